@@ -86,11 +86,25 @@ fn benchmark_pair_read(c: &mut Criterion) {
     });
 }
 
+fn benchmark_pair_transfer(c: &mut Criterion) {
+    let (mut pipe1, mut pipe2) = MockPipe::pair(65536);
+
+    c.bench_function("pair_transfer_64k", |b| {
+        let data = vec![0u8; 65536];
+        let mut buffer = vec![0u8; 65536];
+        b.iter(|| {
+            pipe1.write_all(black_box(&data)).unwrap();
+            pipe2.read_exact(black_box(&mut buffer)).unwrap();
+        })
+    });
+}
+
 criterion_group!(
     benches,
     benchmark_loopback_write,
     benchmark_loopback_read,
     benchmark_pair_write,
-    benchmark_pair_read
+    benchmark_pair_read,
+    benchmark_pair_transfer
 );
 criterion_main!(benches);
@@ -0,0 +1,68 @@
+/*
+ * C API of the mockpipe crate, available with its `ffi` feature.
+ *
+ * Reads and writes return the number of bytes transferred, or one of the
+ * negative MOCKPIPE_ERR_* codes on failure.
+ */
+
+#ifndef MOCKPIPE_H
+#define MOCKPIPE_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* The operation timed out. */
+#define MOCKPIPE_ERR_TIMED_OUT (-1)
+
+/* The pipe is closed. */
+#define MOCKPIPE_ERR_BROKEN_PIPE (-2)
+
+/* A null pointer or an otherwise invalid argument was passed. */
+#define MOCKPIPE_ERR_INVALID (-3)
+
+/* Any other error, such as an injected one, or a panic. */
+#define MOCKPIPE_ERR_OTHER (-4)
+
+/* Opaque handle of a pipe endpoint. */
+typedef struct MockPipe MockPipe;
+
+/*
+ * Creates a pipe with buffers of the given capacity, storing the handles of its
+ * two endpoints into pipe1 and pipe2. Returns 0 on success. If the pipe can't
+ * be created, both handles are set to NULL.
+ */
+ptrdiff_t mockpipe_create_pair(size_t capacity, MockPipe **pipe1, MockPipe **pipe2);
+
+/* Releases an endpoint handle. Does nothing if pipe is NULL. */
+void mockpipe_destroy(MockPipe *pipe);
+
+/*
+ * Reads up to len bytes into buf, waiting according to the endpoint timeout.
+ * Returns the number of bytes read (0 at the end of stream, or when no data is
+ * available in non-blocking mode), or a negative error code.
+ */
+ptrdiff_t mockpipe_read(MockPipe *pipe, uint8_t *buf, size_t len);
+
+/*
+ * Writes up to len bytes from buf, waiting for buffer space according to the
+ * endpoint timeout. Returns the number of bytes written (0 when the buffer is
+ * full in non-blocking mode), or a negative error code.
+ */
+ptrdiff_t mockpipe_write(MockPipe *pipe, const uint8_t *buf, size_t len);
+
+/*
+ * Sets the timeout of the endpoint operations in milliseconds: a negative value
+ * blocks indefinitely, and 0 (the default) makes the operations non-blocking.
+ * Returns 0 on success.
+ */
+ptrdiff_t mockpipe_set_timeout(MockPipe *pipe, int64_t timeout_ms);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* MOCKPIPE_H */
@@ -0,0 +1,168 @@
+//! Assertion macros for tests using pipes.
+//!
+//! The macros report failures with hexdumps or hex diffs of the data involved:
+//!
+//! - [`assert_pipe_written!`](crate::assert_pipe_written) asserts that the next
+//!   data read from an endpoint is the expected one.
+//! - [`assert_pipe_empty!`](crate::assert_pipe_empty) asserts that there is no
+//!   data left to read from an endpoint.
+//! - [`assert_pipe_times_out!`](crate::assert_pipe_times_out) asserts that an
+//!   I/O operation fails with a `TimedOut` error.
+//!
+//! ```
+//! use std::io::{Read, Write};
+//!
+//! use mockpipe::{assert_pipe_empty, assert_pipe_times_out, assert_pipe_written, MockPipe};
+//!
+//! let (mut device, mut host) = MockPipe::pair(64);
+//!
+//! device.write_all(b"AT\r\n").unwrap();
+//!
+//! assert_pipe_written!(host, b"AT\r\n");
+//! assert_pipe_empty!(host);
+//!
+//! host.set_timeout(Some(std::time::Duration::from_millis(10)));
+//! assert_pipe_times_out!(host.read(&mut [0u8; 1]));
+//! ```
+
+use std::io;
+
+use crate::{
+    hex::{hex_diff, hexdump},
+    MockPipe,
+};
+
+/// Asserts that the next data read from the endpoint is the expected one.
+///
+/// Reads as many bytes as expected, waiting for them according to the
+/// endpoint timeout, and panics with a hex diff of the expected and the
+/// received data if they differ.
+#[macro_export]
+macro_rules! assert_pipe_written {
+    ($pipe:expr, $expected:expr $(,)?) => {
+        $crate::assert::assert_written(&$pipe, ::core::convert::AsRef::<[u8]>::as_ref(&$expected))
+    };
+}
+
+/// Asserts that there is no data left to read from the endpoint, panicking
+/// with a hexdump of the pending data otherwise.
+#[macro_export]
+macro_rules! assert_pipe_empty {
+    ($pipe:expr $(,)?) => {
+        $crate::assert::assert_empty(&$pipe)
+    };
+}
+
+/// Asserts that an expression evaluating to an `io::Result` fails with a
+/// `TimedOut` error.
+#[macro_export]
+macro_rules! assert_pipe_times_out {
+    ($result:expr $(,)?) => {
+        $crate::assert::assert_times_out($result)
+    };
+}
+
+#[doc(hidden)]
+#[track_caller]
+pub fn assert_written(pipe: &MockPipe, expected: &[u8]) {
+    let mut received = vec![0u8; expected.len()];
+    let mut filled = 0;
+    let mut error = None;
+
+    while filled < received.len() {
+        match pipe.read_with_timeout(&mut received[filled..], pipe.timeout()) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(err) => {
+                error = Some(err);
+                break;
+            }
+        }
+    }
+    received.truncate(filled);
+
+    if received != expected {
+        let reason = match error {
+            Some(err) => format!(" (read failed: {})", err),
+            None if filled < expected.len() => " (end of stream)".to_string(),
+            None => String::new(),
+        };
+
+        panic!(
+            "pipe data mismatch{}\n{}",
+            reason,
+            hex_diff(expected, &received)
+        );
+    }
+}
+
+#[doc(hidden)]
+#[track_caller]
+pub fn assert_empty(pipe: &MockPipe) {
+    let mut pending = vec![0u8; pipe.read_buffer_len()];
+    if pending.is_empty() {
+        return;
+    }
+
+    let n = pipe
+        .read_buffer
+        .read(&mut pending, Some(std::time::Duration::ZERO))
+        .unwrap_or(0);
+
+    if n > 0 {
+        panic!(
+            "pipe is not empty, {} bytes pending:\n{}",
+            n,
+            hexdump(&pending[0..n])
+        );
+    }
+}
+
+#[doc(hidden)]
+#[track_caller]
+pub fn assert_times_out<T: std::fmt::Debug>(result: io::Result<T>) {
+    match result {
+        Err(err) if err.kind() == io::ErrorKind::TimedOut => {}
+        Err(err) => panic!("expected a timeout, got error: {}", err),
+        Ok(value) => panic!("expected a timeout, got Ok({:?})", value),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Read, Write},
+        panic::{self, AssertUnwindSafe},
+        time::Duration,
+    };
+
+    use crate::MockPipe;
+
+    #[test]
+    fn test_assert_macros() {
+        let (mut device, mut host) = MockPipe::pair(64);
+        host.set_timeout(Some(Duration::from_millis(10)));
+
+        device.write_all(b"hello").unwrap();
+        assert_pipe_written!(host, b"hel");
+        assert_pipe_written!(host, "lo");
+        assert_pipe_empty!(host);
+        assert_pipe_times_out!(host.read(&mut [0u8; 1]));
+
+        device.write_all(b"hello").unwrap();
+        let message =
+            *panic::catch_unwind(AssertUnwindSafe(|| assert_pipe_written!(host, b"help")))
+                .unwrap_err()
+                .downcast::<String>()
+                .unwrap();
+        assert!(message.contains("|help|"));
+        assert!(message.contains("|hell|"));
+
+        let message = *panic::catch_unwind(AssertUnwindSafe(|| assert_pipe_empty!(host)))
+            .unwrap_err()
+            .downcast::<String>()
+            .unwrap();
+        assert!(message.contains("1 bytes pending"));
+        assert!(message.contains("|o|"));
+    }
+}
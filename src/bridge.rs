@@ -0,0 +1,590 @@
+//! Bridges connecting a `MockPipe` endpoint to real I/O resources.
+//!
+//! A bridge spawns pump threads copying data in both directions between a
+//! pipe endpoint and an external resource, so a component wired to the other
+//! end of the pipe can be exercised against an actual service.
+
+use std::{
+    io::{self, Read, Write},
+    net::{Shutdown, TcpStream},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use crate::{MockPipe, Transport};
+
+#[cfg(all(windows, feature = "bridge"))]
+mod named_pipe;
+
+/// Interval at which the pump threads check whether the bridge was stopped.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Size of the intermediate buffer used by the pump threads.
+const CHUNK_SIZE: usize = 4096;
+
+/// A running bridge. Dropping it stops the pump threads.
+pub struct Bridge {
+    running: Arc<AtomicBool>,
+    threads: Vec<JoinHandle<io::Result<()>>>,
+
+    /// Results of the pump threads blocking on readers that can't be
+    /// interrupted, which are not waited for when stopping.
+    detached: Vec<Receiver<io::Result<()>>>,
+}
+
+impl Bridge {
+    /// Creates a bridge from its pump threads, which clear `running` when they
+    /// stop by themselves.
+    pub(crate) fn new(running: Arc<AtomicBool>, threads: Vec<JoinHandle<io::Result<()>>>) -> Self {
+        Self {
+            running,
+            threads,
+            detached: Vec::new(),
+        }
+    }
+
+    /// Returns `true` while data is being pumped in both directions, i.e. until
+    /// the bridge is stopped or one of the sides fails or reaches end of stream.
+    pub fn is_running(&self) -> bool {
+        self.running.load(Ordering::SeqCst)
+    }
+
+    /// Waits until the bridge stops by itself, i.e. until one of the sides
+    /// fails or reaches end of stream.
+    ///
+    /// Returns the first error encountered by the pump threads, if any.
+    pub fn wait(mut self) -> io::Result<()> {
+        while self.is_running() {
+            thread::sleep(POLL_INTERVAL);
+        }
+
+        self.shutdown()
+    }
+
+    /// Stops the bridge and waits for the pump threads to finish.
+    ///
+    /// Returns the first error encountered by the pump threads, if any.
+    pub fn stop(mut self) -> io::Result<()> {
+        self.shutdown()
+    }
+
+    fn shutdown(&mut self) -> io::Result<()> {
+        self.running.store(false, Ordering::SeqCst);
+
+        let joined = self
+            .threads
+            .drain(..)
+            .map(|thread| thread.join().expect("bridge pump thread panicked"));
+
+        let detached = self
+            .detached
+            .drain(..)
+            .map(|result| result.try_recv().unwrap_or(Ok(())));
+
+        joined.chain(detached).fold(Ok(()), Result::and)
+    }
+}
+
+impl Drop for Bridge {
+    fn drop(&mut self) {
+        let _ = self.shutdown();
+    }
+}
+
+/// Connects the pipe endpoint to a TCP stream: data written to the peer of the
+/// pipe is sent to the stream, and data received from the stream can be read
+/// from the peer.
+///
+/// The bridge stops when either side reaches end of stream or fails. The end of
+/// stream is propagated: closing the pipe shuts down the sending side of the
+/// stream, and the stream reaching end of stream closes the pipe.
+pub fn to_tcp(pipe: MockPipe, stream: TcpStream) -> io::Result<Bridge> {
+    stream.set_read_timeout(Some(POLL_INTERVAL))?;
+
+    let running = Arc::new(AtomicBool::new(true));
+
+    let mut stream_writer = stream.try_clone()?;
+    let mut stream_reader = stream;
+
+    let pipe_reader = pipe.clone();
+    let pipe_writer = pipe;
+
+    let outbound = {
+        let running = running.clone();
+        thread::spawn(move || {
+            let result = pump_from_pipe(&pipe_reader, &mut stream_writer, &running);
+            running.store(false, Ordering::SeqCst);
+
+            // The connection may be gone already
+            let _ = stream_writer.shutdown(Shutdown::Write);
+            result
+        })
+    };
+
+    let inbound = {
+        let running = running.clone();
+        thread::spawn(move || {
+            let result = pump_into_pipe(&mut stream_reader, &pipe_writer, &running);
+            running.store(false, Ordering::SeqCst);
+            result
+        })
+    };
+
+    Ok(Bridge {
+        running,
+        threads: vec![outbound, inbound],
+        detached: Vec::new(),
+    })
+}
+
+/// Connects the pipe endpoint to the standard input and output of the process,
+/// for interacting with an emulated device by hand: lines typed into the
+/// console can be read from the peer of the pipe, and data written to the peer
+/// is printed.
+///
+/// See [`to_io`] for how the bridge stops.
+pub fn to_stdio(pipe: MockPipe) -> Bridge {
+    to_io(pipe, io::stdin(), io::stdout())
+}
+
+/// Connects the pipe endpoint to arbitrary I/O handles: data written to the
+/// peer of the pipe is written to `writer`, and data read from `reader` can be
+/// read from the peer.
+///
+/// The bridge stops when either side reaches end of stream or one of the
+/// handles fails: `reader` reaching end of stream closes the pipe. As a
+/// blocking read can't be interrupted, stopping the bridge does not
+/// wait for a pending read from `reader`: data it returns afterwards is
+/// discarded.
+pub fn to_io<R, W>(pipe: MockPipe, mut reader: R, mut writer: W) -> Bridge
+where
+    R: Read + Send + 'static,
+    W: Write + Send + 'static,
+{
+    let running = Arc::new(AtomicBool::new(true));
+
+    let pipe_reader = pipe.clone();
+    let pipe_writer = pipe;
+
+    let outbound = {
+        let running = running.clone();
+        thread::spawn(move || {
+            let result = pump_from_pipe(&pipe_reader, &mut writer, &running);
+            running.store(false, Ordering::SeqCst);
+            result
+        })
+    };
+
+    let (inbound_result, inbound) = mpsc::channel();
+    {
+        let running = running.clone();
+        thread::spawn(move || {
+            let result = pump_into_pipe(&mut reader, &pipe_writer, &running);
+            running.store(false, Ordering::SeqCst);
+            let _ = inbound_result.send(result);
+        });
+    }
+
+    Bridge {
+        running,
+        threads: vec![outbound],
+        detached: vec![inbound],
+    }
+}
+
+/// Creates the named pipe `\\.\pipe\<name>` and connects the pipe endpoint to
+/// the first local client opening it: data written to the peer of the pipe is
+/// sent to the client, and data received from the client can be read from the
+/// peer. Available on Windows only, with the `bridge` feature.
+///
+/// The named pipe exists once the function returns, so native tools can
+/// connect to it right away. Creating it fails if the name is already taken.
+/// The bridge stops when the client disconnects or the connection fails.
+#[cfg(all(windows, feature = "bridge"))]
+pub fn to_windows_named_pipe(pipe: MockPipe, name: &str) -> io::Result<Bridge> {
+    let server = named_pipe::NamedPipe::create(name)?;
+
+    let running = Arc::new(AtomicBool::new(true));
+
+    let thread = {
+        let running = running.clone();
+        thread::spawn(move || {
+            let result = serve_named_pipe(server, pipe, &running);
+            running.store(false, Ordering::SeqCst);
+            result
+        })
+    };
+
+    Ok(Bridge {
+        running,
+        threads: vec![thread],
+        detached: Vec::new(),
+    })
+}
+
+/// Waits for a client of the named pipe and pumps data in both directions
+/// between it and the pipe endpoint while the bridge is running.
+#[cfg(all(windows, feature = "bridge"))]
+fn serve_named_pipe(
+    server: named_pipe::NamedPipe,
+    pipe: MockPipe,
+    running: &Arc<AtomicBool>,
+) -> io::Result<()> {
+    if !server.wait_for_client(running)? {
+        return Ok(());
+    }
+
+    let mut client_writer = server.try_clone()?;
+    let mut client_reader = server;
+
+    let pipe_reader = pipe.clone();
+    let pipe_writer = pipe;
+
+    let outbound = {
+        let running = running.clone();
+        thread::spawn(move || {
+            let result = pump_from_pipe(&pipe_reader, &mut client_writer, &running);
+            running.store(false, Ordering::SeqCst);
+            result
+        })
+    };
+
+    let inbound = pump_into_pipe(&mut client_reader, &pipe_writer, running);
+    running.store(false, Ordering::SeqCst);
+
+    outbound
+        .join()
+        .expect("bridge pump thread panicked")
+        .and(inbound)
+}
+
+/// Pumps data in both directions between two transports (pipe endpoints or
+/// real streams) on background threads, the building block of bridge and proxy
+/// fixtures.
+///
+/// Each transport is shared by the two pump threads, so its timeout is set to a
+/// short polling interval to keep either direction from holding it for long.
+/// When one side reaches end of stream, the other one is closed and the bridge
+/// stops; it also stops when either side fails.
+pub fn copy_bidirectional<A, B>(mut a: A, mut b: B) -> io::Result<Bridge>
+where
+    A: Transport + Send + 'static,
+    B: Transport + Send + 'static,
+{
+    a.set_timeout(Some(POLL_INTERVAL))?;
+    b.set_timeout(Some(POLL_INTERVAL))?;
+
+    let running = Arc::new(AtomicBool::new(true));
+
+    let a = Arc::new(Mutex::new(a));
+    let b = Arc::new(Mutex::new(b));
+
+    let a_to_b = {
+        let (a, b, running) = (a.clone(), b.clone(), running.clone());
+        thread::spawn(move || {
+            let result = pump_between(&a, &b, &running);
+            running.store(false, Ordering::SeqCst);
+            result
+        })
+    };
+
+    let b_to_a = {
+        let running = running.clone();
+        thread::spawn(move || {
+            let result = pump_between(&b, &a, &running);
+            running.store(false, Ordering::SeqCst);
+            result
+        })
+    };
+
+    Ok(Bridge {
+        running,
+        threads: vec![a_to_b, b_to_a],
+        detached: Vec::new(),
+    })
+}
+
+/// Copies data read from `reader` into `writer` while the bridge is running,
+/// closing `writer` once `reader` reaches end of stream.
+///
+/// Both transports are expected to time out periodically, so that neither lock
+/// is held for long and stopping the bridge is noticed.
+fn pump_between<R: Transport, W: Transport>(
+    reader: &Mutex<R>,
+    writer: &Mutex<W>,
+    running: &AtomicBool,
+) -> io::Result<()> {
+    let mut buf = [0u8; CHUNK_SIZE];
+
+    while running.load(Ordering::SeqCst) {
+        let result = reader.lock().unwrap().read(&mut buf);
+        let n = match result {
+            Ok(0) if reader.lock().unwrap().is_end_of_stream() => {
+                return writer.lock().unwrap().close()
+            }
+            // Woken up by a clear
+            Ok(0) => continue,
+            Ok(n) => n,
+            Err(err) if is_transient(&err) => continue,
+            Err(err) => return Err(err),
+        };
+
+        let mut data = &buf[0..n];
+
+        while !data.is_empty() && running.load(Ordering::SeqCst) {
+            let result = writer.lock().unwrap().write(data);
+            match result {
+                Ok(0) => return Err(io::Error::from(io::ErrorKind::WriteZero)),
+                Ok(written) => data = &data[written..],
+                Err(err) if is_transient(&err) => continue,
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns `true` if the error only means that the operation should be retried.
+fn is_transient(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut | io::ErrorKind::Interrupted
+    )
+}
+
+/// Copies data read from the pipe into `writer` while the bridge is running,
+/// until the pipe is closed and drained.
+fn pump_from_pipe<W: Write>(
+    pipe: &MockPipe,
+    writer: &mut W,
+    running: &AtomicBool,
+) -> io::Result<()> {
+    let mut buf = [0u8; CHUNK_SIZE];
+
+    while running.load(Ordering::SeqCst) {
+        match pipe.read_buffer.read(&mut buf, Some(POLL_INTERVAL)) {
+            Ok(0) if pipe.read_buffer.is_closed() => break,
+            // Woken up by a clear
+            Ok(0) => continue,
+            Ok(n) => {
+                writer.write_all(&buf[0..n])?;
+                writer.flush()?;
+            }
+            Err(err) if err.kind() == io::ErrorKind::TimedOut => continue,
+            Err(err) => return Err(err),
+        }
+    }
+
+    Ok(())
+}
+
+/// Copies data read from `reader` into the pipe while the bridge is running,
+/// closing the pipe once `reader` reaches end of stream.
+///
+/// `reader` is expected to time out periodically, so that stopping the bridge
+/// is noticed.
+fn pump_into_pipe<R: Read>(
+    reader: &mut R,
+    pipe: &MockPipe,
+    running: &AtomicBool,
+) -> io::Result<()> {
+    let mut buf = [0u8; CHUNK_SIZE];
+
+    while running.load(Ordering::SeqCst) {
+        let n = match reader.read(&mut buf) {
+            Ok(0) => {
+                pipe.close();
+                break;
+            }
+            Ok(n) => n,
+            Err(err) if is_transient(&err) => continue,
+            Err(err) => return Err(err),
+        };
+
+        write_into_pipe(pipe, &buf[0..n], running)?;
+    }
+
+    Ok(())
+}
+
+/// Writes `data` into the pipe, waiting for space while the bridge is running.
+pub(crate) fn write_into_pipe(
+    pipe: &MockPipe,
+    mut data: &[u8],
+    running: &AtomicBool,
+) -> io::Result<()> {
+    while !data.is_empty() && running.load(Ordering::SeqCst) {
+        match pipe.write_buffer.write(data, Some(POLL_INTERVAL)) {
+            Ok(0) => return Err(io::Error::from(io::ErrorKind::WriteZero)),
+            Ok(written) => data = &data[written..],
+            Err(err) if err.kind() == io::ErrorKind::TimedOut => continue,
+            Err(err) => return Err(err),
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+
+    use super::*;
+    use crate::ClearWakeup;
+
+    #[test]
+    fn test_bridge_to_tcp_echo() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        // Echo server
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut buf = [0u8; 64];
+            loop {
+                match stream.read(&mut buf).unwrap() {
+                    0 => break,
+                    n => stream.write_all(&buf[0..n]).unwrap(),
+                }
+            }
+        });
+
+        let (mut component, pipe) = MockPipe::pair(1024);
+        component.set_timeout(Some(Duration::from_millis(1000)));
+
+        let bridge = to_tcp(pipe, TcpStream::connect(addr).unwrap()).unwrap();
+        assert!(bridge.is_running());
+
+        component.write_all(b"ping").unwrap();
+
+        let mut read_data = [0u8; 4];
+        component.read_exact(&mut read_data).unwrap();
+        assert_eq!(&read_data, b"ping");
+
+        // Stopping the bridge closes the connection, which ends the server
+        bridge.stop().unwrap();
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_bridge_to_tcp_end_of_stream() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        // Closing the pipe ends the stream of the server
+        let (component, pipe) = MockPipe::pair(1024);
+        let bridge = to_tcp(pipe, TcpStream::connect(addr).unwrap()).unwrap();
+        let (mut server, _) = listener.accept().unwrap();
+
+        component.close();
+        let mut read_data = [0u8; 4];
+        assert_eq!(server.read(&mut read_data).unwrap(), 0);
+        bridge.wait().unwrap();
+
+        // The client disconnecting ends the stream of the peer of the pipe
+        let (mut component, pipe) = MockPipe::pair(1024);
+        component.set_timeout(Some(Duration::from_millis(1000)));
+        let client = TcpStream::connect(addr).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        let bridge = to_tcp(pipe, server).unwrap();
+
+        drop(client);
+        assert_eq!(component.read(&mut read_data).unwrap(), 0);
+        bridge.wait().unwrap();
+    }
+
+    #[test]
+    fn test_copy_bidirectional() {
+        let (mut client, proxy_client_side) = MockPipe::pair(64);
+        let (proxy_server_side, mut server) = MockPipe::pair(64);
+        proxy_client_side.set_clear_wakeup(ClearWakeup::EndOfStream);
+        client.set_timeout(Some(Duration::from_millis(1000)));
+        server.set_timeout(Some(Duration::from_millis(1000)));
+
+        let proxy = copy_bidirectional(proxy_client_side, proxy_server_side).unwrap();
+
+        client.write_all(b"ping").unwrap();
+
+        let mut read_data = [0u8; 4];
+        server.read_exact(&mut read_data).unwrap();
+        assert_eq!(&read_data, b"ping");
+
+        server.write_all(b"pong").unwrap();
+        client.read_exact(&mut read_data).unwrap();
+        assert_eq!(&read_data, b"pong");
+
+        // Waking up the proxy with a clear does not end the stream
+        for _ in 0..5 {
+            thread::sleep(Duration::from_millis(20));
+            client.clear_write();
+        }
+        assert!(proxy.is_running());
+        assert!(!server.is_closed());
+
+        // The end of stream is propagated to the other side
+        client.close();
+        proxy.wait().unwrap();
+        assert_eq!(server.read(&mut read_data).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_bridge_to_io() {
+        // Console input that never arrives, and the console output
+        let (_keyboard, keyboard_input) = MockPipe::pair(64);
+        let (mut screen, screen_output) = MockPipe::pair(64);
+        keyboard_input.set_timeout(None);
+        screen.set_timeout(Some(Duration::from_millis(1000)));
+
+        let (mut component, pipe) = MockPipe::pair(64);
+        let bridge = to_io(pipe, keyboard_input, screen_output);
+
+        component.write_all(b"> ").unwrap();
+
+        let mut read_data = [0u8; 2];
+        screen.read_exact(&mut read_data).unwrap();
+        assert_eq!(&read_data, b"> ");
+
+        // Stopping does not wait for the blocked console read
+        bridge.stop().unwrap();
+    }
+
+    #[cfg(all(windows, feature = "bridge"))]
+    #[test]
+    fn test_bridge_to_windows_named_pipe() {
+        use std::fs::OpenOptions;
+
+        let name = format!("mockpipe-test-{}", std::process::id());
+
+        let (mut component, pipe) = MockPipe::pair(1024);
+        component.set_timeout(Some(Duration::from_millis(1000)));
+
+        let bridge = to_windows_named_pipe(pipe, &name).unwrap();
+
+        // A native client on the other side of the named pipe
+        let mut client = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(format!(r"\\.\pipe\{}", name))
+            .unwrap();
+
+        client.write_all(b"ping").unwrap();
+
+        let mut read_data = [0u8; 4];
+        component.read_exact(&mut read_data).unwrap();
+        assert_eq!(&read_data, b"ping");
+
+        component.write_all(b"pong").unwrap();
+        client.read_exact(&mut read_data).unwrap();
+        assert_eq!(&read_data, b"pong");
+
+        // The client disconnecting stops the bridge
+        drop(client);
+        bridge.stop().unwrap();
+    }
+}
@@ -0,0 +1,171 @@
+//! Server end of a Windows named pipe for the bridge.
+
+use std::{
+    ffi::OsStr,
+    fs::File,
+    io::{self, Read, Write},
+    iter,
+    os::windows::{
+        ffi::OsStrExt,
+        io::{AsRawHandle, FromRawHandle, OwnedHandle},
+    },
+    ptr,
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+    time::{Duration, Instant},
+};
+
+use windows_sys::Win32::{
+    Foundation::{ERROR_NO_DATA, ERROR_PIPE_CONNECTED, ERROR_PIPE_LISTENING, INVALID_HANDLE_VALUE},
+    Storage::FileSystem::{FILE_FLAG_FIRST_PIPE_INSTANCE, PIPE_ACCESS_DUPLEX},
+    System::Pipes::{
+        ConnectNamedPipe, CreateNamedPipeW, PeekNamedPipe, SetNamedPipeHandleState, PIPE_NOWAIT,
+        PIPE_READMODE_BYTE, PIPE_REJECT_REMOTE_CLIENTS, PIPE_TYPE_BYTE, PIPE_WAIT,
+    },
+};
+
+use super::{CHUNK_SIZE, POLL_INTERVAL};
+
+/// Interval at which a read checks whether the client has sent data.
+const PEEK_INTERVAL: Duration = Duration::from_millis(1);
+
+/// A single-instance, local-only named pipe server.
+///
+/// Reads time out after `POLL_INTERVAL` if the client sends nothing, so the
+/// pump threads notice when the bridge is stopped.
+pub(super) struct NamedPipe {
+    file: File,
+}
+
+impl NamedPipe {
+    /// Creates the named pipe `\\.\pipe\<name>`, failing if it already exists.
+    pub(super) fn create(name: &str) -> io::Result<Self> {
+        let path: Vec<u16> = OsStr::new(r"\\.\pipe\")
+            .encode_wide()
+            .chain(OsStr::new(name).encode_wide())
+            .chain(iter::once(0))
+            .collect();
+
+        // SAFETY: `path` is a NUL-terminated wide string. The pipe starts in
+        // non-blocking mode, so waiting for a client can be interrupted.
+        let handle = unsafe {
+            CreateNamedPipeW(
+                path.as_ptr(),
+                PIPE_ACCESS_DUPLEX | FILE_FLAG_FIRST_PIPE_INSTANCE,
+                PIPE_TYPE_BYTE | PIPE_READMODE_BYTE | PIPE_NOWAIT | PIPE_REJECT_REMOTE_CLIENTS,
+                1,
+                CHUNK_SIZE as u32,
+                CHUNK_SIZE as u32,
+                0,
+                ptr::null(),
+            )
+        };
+        if handle == INVALID_HANDLE_VALUE {
+            return Err(io::Error::last_os_error());
+        }
+
+        // SAFETY: `handle` is a valid handle owned by nothing else.
+        let handle = unsafe { OwnedHandle::from_raw_handle(handle) };
+
+        Ok(Self {
+            file: File::from(handle),
+        })
+    }
+
+    /// Waits for a client to connect while the bridge is running, then
+    /// switches the pipe to blocking mode.
+    ///
+    /// Returns `false` if the bridge was stopped first.
+    pub(super) fn wait_for_client(&self, running: &AtomicBool) -> io::Result<bool> {
+        while running.load(Ordering::SeqCst) {
+            // SAFETY: the handle is valid, and the pipe is non-blocking.
+            if unsafe { ConnectNamedPipe(self.file.as_raw_handle(), ptr::null_mut()) } != 0 {
+                continue;
+            }
+
+            let err = io::Error::last_os_error();
+            match err.raw_os_error().map(|code| code as u32) {
+                Some(ERROR_PIPE_LISTENING) => thread::sleep(POLL_INTERVAL),
+                // A client that already disconnected may have left data behind
+                Some(ERROR_PIPE_CONNECTED) | Some(ERROR_NO_DATA) => {
+                    let mode = PIPE_READMODE_BYTE | PIPE_WAIT;
+
+                    // SAFETY: the handle is valid, and `mode` outlives the call.
+                    let result = unsafe {
+                        SetNamedPipeHandleState(
+                            self.file.as_raw_handle(),
+                            &mode,
+                            ptr::null(),
+                            ptr::null(),
+                        )
+                    };
+                    if result == 0 {
+                        return Err(io::Error::last_os_error());
+                    }
+
+                    return Ok(true);
+                }
+                _ => return Err(err),
+            }
+        }
+
+        Ok(false)
+    }
+
+    /// Returns a second handle to the pipe.
+    pub(super) fn try_clone(&self) -> io::Result<Self> {
+        Ok(Self {
+            file: self.file.try_clone()?,
+        })
+    }
+
+    /// Returns the number of bytes sent by the client and not read yet.
+    fn available(&self) -> io::Result<u32> {
+        let mut available = 0;
+
+        // SAFETY: the handle is valid, and `available` outlives the call.
+        let result = unsafe {
+            PeekNamedPipe(
+                self.file.as_raw_handle(),
+                ptr::null_mut(),
+                0,
+                ptr::null_mut(),
+                &mut available,
+                ptr::null_mut(),
+            )
+        };
+        if result == 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(available)
+    }
+}
+
+impl Read for NamedPipe {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let start = Instant::now();
+
+        loop {
+            match self.available() {
+                Ok(0) if start.elapsed() < POLL_INTERVAL => thread::sleep(PEEK_INTERVAL),
+                Ok(0) => return Err(io::Error::from(io::ErrorKind::TimedOut)),
+                // Data is pending, so the blocking read returns right away
+                Ok(_) => return self.file.read(buf),
+                // The client disconnected
+                Err(err) if err.kind() == io::ErrorKind::BrokenPipe => return Ok(0),
+                Err(err) => return Err(err),
+            }
+        }
+    }
+}
+
+impl Write for NamedPipe {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
@@ -0,0 +1,312 @@
+//! One writer, many independent readers.
+//!
+//! See [`MockPipe::broadcast`](crate::MockPipe::broadcast).
+
+use std::{
+    io,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::buffer::SyncBuffer;
+
+/// What the broadcast writer does when a reader's buffer is full.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Backpressure {
+    /// Blocks the writer (subject to its timeout) until the reader has room,
+    /// so the reader never misses data.
+    #[default]
+    Block,
+
+    /// Discards the bytes which do not fit into the reader's buffer.
+    DropNewest,
+
+    /// Discards the oldest unread bytes of the reader's buffer to make room.
+    DropOldest,
+}
+
+/// A reader buffer of the broadcast together with its backpressure policy.
+struct Subscriber {
+    buffer: Arc<SyncBuffer>,
+    backpressure: Backpressure,
+}
+
+/// The writing endpoint of a broadcast created with
+/// [`MockPipe::broadcast`](crate::MockPipe::broadcast).
+///
+/// Every byte written is delivered to each reader's own buffer.
+pub struct BroadcastWriter {
+    subscribers: Vec<Subscriber>,
+    timeout: Mutex<Option<Duration>>,
+    closed: AtomicBool,
+}
+
+impl BroadcastWriter {
+    /// Creates a writer delivering data into the given reader buffers.
+    pub(crate) fn new(buffers: Vec<Arc<SyncBuffer>>) -> Self {
+        Self {
+            subscribers: buffers
+                .into_iter()
+                .map(|buffer| Subscriber {
+                    buffer,
+                    backpressure: Backpressure::default(),
+                })
+                .collect(),
+            // Non-blocking by default, as `MockPipe`
+            timeout: Mutex::new(Some(Duration::ZERO)),
+            closed: AtomicBool::new(false),
+        }
+    }
+
+    /// Returns the number of readers.
+    pub fn readers(&self) -> usize {
+        self.subscribers.len()
+    }
+
+    /// Gets the backpressure policy of the reader with the given index.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `reader` is out of bounds.
+    pub fn backpressure(&self, reader: usize) -> Backpressure {
+        self.subscribers[reader].backpressure
+    }
+
+    /// Sets the backpressure policy of the reader with the given index.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `reader` is out of bounds.
+    pub fn set_backpressure(&mut self, reader: usize, backpressure: Backpressure) {
+        self.subscribers[reader].backpressure = backpressure;
+    }
+
+    /// Gets the current timeout duration for write operations.
+    pub fn timeout(&self) -> Option<Duration> {
+        *self.timeout.lock().unwrap()
+    }
+
+    /// Sets the timeout duration for write operations.
+    ///
+    /// Only readers with the `Backpressure::Block` policy can make the writer
+    /// wait, and the timeout applies to the whole write, however many of them
+    /// it waits for. `None` means the operation blocks indefinitely.
+    /// `Some(Duration::ZERO)` means the operation is non-blocking.
+    pub fn set_timeout(&self, timeout: Option<Duration>) {
+        *self.timeout.lock().unwrap() = timeout;
+    }
+
+    /// Sets the timeout duration for write operations and returns the modified
+    /// writer.
+    pub fn with_timeout(self, timeout: Option<Duration>) -> Self {
+        self.set_timeout(timeout);
+        self
+    }
+
+    /// Closes the broadcast, simulating a closed connection.
+    ///
+    /// Once the data already buffered has been consumed, reads on every reader
+    /// return `Ok(0)` (end of stream) instead of blocking, and writes fail with
+    /// a `BrokenPipe` error.
+    pub fn close(&self) {
+        self.closed.store(true, Ordering::SeqCst);
+        for subscriber in &self.subscribers {
+            subscriber.buffer.close();
+        }
+    }
+
+    /// Returns `true` if the broadcast was closed.
+    pub fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::SeqCst)
+    }
+
+    /// Returns the number of bytes queued in the buffer of the reader with the
+    /// given index.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `reader` is out of bounds.
+    pub fn buffer_len(&self, reader: usize) -> usize {
+        self.subscribers[reader].buffer.len()
+    }
+}
+
+impl io::Write for BroadcastWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.is_closed() {
+            return Err(io::Error::from(io::ErrorKind::BrokenPipe));
+        }
+
+        let timeout = self.timeout();
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+
+        // Wait until every blocking reader has room, until a deadline shared by
+        // all of them; as there is a single writer, the room can only grow
+        // until the data is delivered below.
+        let mut bytes_to_write = buf.len();
+
+        for subscriber in &self.subscribers {
+            if subscriber.backpressure == Backpressure::Block {
+                // What remains of the timeout, keeping it positive once the
+                // deadline passed so the write times out instead of turning
+                // non-blocking
+                let timeout = timeout.zip(deadline).map(|(timeout, deadline)| {
+                    match deadline.saturating_duration_since(Instant::now()) {
+                        Duration::ZERO if timeout > Duration::ZERO => Duration::from_nanos(1),
+                        remaining => remaining,
+                    }
+                });
+                let (state_guard, bytes_available) =
+                    subscriber.buffer.wait_for_space(bytes_to_write, timeout)?;
+
+                drop(state_guard);
+
+                bytes_to_write = bytes_available;
+            }
+        }
+
+        let data = &buf[0..bytes_to_write];
+
+        for subscriber in &self.subscribers {
+            offer(&subscriber.buffer, data, subscriber.backpressure);
+        }
+
+        Ok(bytes_to_write)
+    }
+
+    /// Waits until all readers have consumed the data written so far.
+    fn flush(&mut self) -> io::Result<()> {
+        self.subscribers
+            .iter()
+            .try_for_each(|subscriber| subscriber.buffer.flush(None))
+    }
+}
+
+/// Puts `data` into `buffer` without waiting, according to the backpressure
+/// policy: as much as fits with `Block` and `DropNewest`, or all of it with
+/// `DropOldest`, discarding the oldest unread data to make room.
+pub(crate) fn offer(buffer: &SyncBuffer, data: &[u8], backpressure: Backpressure) {
+    let mut state_guard = buffer.state.lock().unwrap();
+
+    let capacity = state_guard.capacity;
+    let space = state_guard.space();
+
+    let bytes_put = match backpressure {
+        Backpressure::Block | Backpressure::DropNewest => {
+            buffer.put(&mut state_guard, &data[0..data.len().min(space)])
+        }
+        Backpressure::DropOldest => {
+            let data = &data[data.len().saturating_sub(capacity)..];
+            let excess = data.len().saturating_sub(space);
+            state_guard.discard_oldest(excess);
+            buffer.put(&mut state_guard, data)
+        }
+    };
+
+    let deferred = state_guard.defers_delivery();
+    drop(state_guard);
+
+    if (bytes_put > 0) && !deferred {
+        buffer.raise_rx_interrupt();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+
+    use super::*;
+    use crate::MockPipe;
+
+    #[test]
+    fn test_broadcast_full_copies() {
+        let (mut writer, mut readers) = MockPipe::broadcast(16, 3);
+
+        assert_eq!(writer.readers(), 3);
+
+        writer.write_all(b"hello").unwrap();
+
+        for reader in &mut readers {
+            let mut read_data = [0u8; 5];
+            reader.read_exact(&mut read_data).unwrap();
+            assert_eq!(&read_data, b"hello");
+        }
+
+        // Readers can't write back
+        assert_eq!(
+            readers[0].write_all(b"x").unwrap_err().kind(),
+            io::ErrorKind::WriteZero
+        );
+    }
+
+    #[test]
+    fn test_broadcast_backpressure() {
+        let (mut writer, mut readers) = MockPipe::broadcast(4, 3);
+
+        writer.set_backpressure(1, Backpressure::DropNewest);
+        writer.set_backpressure(2, Backpressure::DropOldest);
+
+        writer.write_all(b"abc").unwrap();
+
+        // The blocking reader limits the write to its free space
+        assert_eq!(writer.write(b"def").unwrap(), 1);
+
+        // Now the blocking reader is full
+        assert_eq!(writer.write(b"g").unwrap(), 0);
+
+        let mut read_data = [0u8; 4];
+        readers[0].read_exact(&mut read_data).unwrap();
+        assert_eq!(&read_data, b"abcd");
+
+        writer.write_all(b"ef").unwrap();
+
+        // The first reader got everything, the second missed the newest data,
+        // the third lost the oldest data
+        readers[0].read_exact(&mut read_data[0..2]).unwrap();
+        assert_eq!(&read_data[0..2], b"ef");
+
+        readers[1].read_exact(&mut read_data).unwrap();
+        assert_eq!(&read_data, b"abcd");
+        assert_eq!(readers[1].read_buffer_len(), 0);
+
+        readers[2].read_exact(&mut read_data).unwrap();
+        assert_eq!(&read_data, b"cdef");
+    }
+
+    #[test]
+    fn test_broadcast_close() {
+        let (mut writer, mut readers) = MockPipe::broadcast(4, 2);
+        writer.write_all(b"ab").unwrap();
+        writer.close();
+
+        assert!(writer.is_closed());
+        assert_eq!(
+            writer.write(b"c").unwrap_err().kind(),
+            io::ErrorKind::BrokenPipe
+        );
+
+        for reader in &mut readers {
+            let mut read_data = Vec::new();
+            reader.read_to_end(&mut read_data).unwrap();
+            assert_eq!(read_data, b"ab");
+        }
+    }
+
+    #[test]
+    fn test_broadcast_shared_deadline() {
+        let (mut writer, _readers) = MockPipe::broadcast(1, 4);
+        writer.write_all(b"x").unwrap();
+        writer.set_timeout(Some(Duration::from_millis(100)));
+
+        // Every reader is full, but the write waits for the timeout once
+        let start = Instant::now();
+        assert_eq!(
+            writer.write(b"y").unwrap_err().kind(),
+            io::ErrorKind::TimedOut
+        );
+        assert!(start.elapsed() < Duration::from_millis(300));
+    }
+}
@@ -0,0 +1,138 @@
+//! Memory budget shared by the buffers of a set of pipes.
+
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+/// Cap on the total number of bytes buffered across a set of pipes, so stress
+/// tests with pipes of huge capacities can't silently consume gigabytes of
+/// memory.
+///
+/// The budget is shared by cloning it and assigning it to the pipes with
+/// [`MockPipe::set_memory_budget`](crate::MockPipe::set_memory_budget). Every
+/// byte written and not read yet counts against it, in whichever buffer it is
+/// waiting, in flight over a simulated link or staged. Once the budget is
+/// exhausted, writes block, according to their timeout, until data is read
+/// from any of the pipes, or fail with a [`Full`](crate::Error::Full) error if
+/// the budget is set to fail when exhausted.
+///
+/// Writers blocked by the budget check it periodically, as the pipes freeing
+/// it don't notify them. Async writers are only woken up by their own pipe.
+///
+/// ```
+/// use std::io::Write;
+///
+/// use mockpipe::{MemoryBudget, MockPipe};
+///
+/// let budget = MemoryBudget::new(1024).with_fail_when_exhausted(true);
+///
+/// let (mut host, _device) = MockPipe::pair(1 << 30);
+/// let (mut other, _peer) = MockPipe::pair(1 << 30);
+/// host.set_memory_budget(Some(budget.clone()));
+/// other.set_memory_budget(Some(budget.clone()));
+///
+/// host.write_all(&[0u8; 1000]).unwrap();
+/// assert_eq!(other.write(&[0u8; 100]).unwrap(), 24);
+/// assert!(other.write(&[0u8; 100]).is_err());
+/// assert_eq!(budget.used(), 1024);
+/// ```
+#[derive(Clone)]
+pub struct MemoryBudget {
+    inner: Arc<BudgetInner>,
+}
+
+struct BudgetInner {
+    limit: usize,
+
+    /// Number of bytes buffered, or reserved for a write in progress.
+    used: AtomicUsize,
+
+    /// Whether writes fail instead of blocking once the budget is exhausted.
+    fail_when_exhausted: AtomicBool,
+}
+
+impl MemoryBudget {
+    /// Creates a budget of `limit` bytes, blocking the writes once exhausted.
+    pub fn new(limit: usize) -> Self {
+        Self {
+            inner: Arc::new(BudgetInner {
+                limit,
+                used: AtomicUsize::new(0),
+                fail_when_exhausted: AtomicBool::new(false),
+            }),
+        }
+    }
+
+    /// Returns the maximum number of bytes buffered across the pipes.
+    pub fn limit(&self) -> usize {
+        self.inner.limit
+    }
+
+    /// Returns the number of bytes currently buffered across the pipes.
+    pub fn used(&self) -> usize {
+        self.inner.used.load(Ordering::SeqCst)
+    }
+
+    /// Returns the number of bytes that can still be buffered.
+    pub fn available(&self) -> usize {
+        self.inner.limit.saturating_sub(self.used())
+    }
+
+    /// Sets whether writes fail with a `Full` error instead of blocking once
+    /// the budget is exhausted.
+    pub fn set_fail_when_exhausted(&self, fail: bool) {
+        self.inner.fail_when_exhausted.store(fail, Ordering::SeqCst);
+    }
+
+    /// Sets whether writes fail once the budget is exhausted and returns the
+    /// modified budget.
+    pub fn with_fail_when_exhausted(self, fail: bool) -> Self {
+        self.set_fail_when_exhausted(fail);
+        self
+    }
+
+    /// Returns `true` if writes fail instead of blocking once the budget is
+    /// exhausted.
+    pub fn fail_when_exhausted(&self) -> bool {
+        self.inner.fail_when_exhausted.load(Ordering::SeqCst)
+    }
+
+    /// Reserves up to `n` bytes of the budget, returning the number of bytes
+    /// reserved.
+    pub(crate) fn reserve(&self, n: usize) -> usize {
+        let mut reserved = 0;
+
+        let _ = self
+            .inner
+            .used
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |used| {
+                reserved = n.min(self.inner.limit.saturating_sub(used));
+                Some(used + reserved)
+            });
+
+        reserved
+    }
+
+    /// Adjusts the bytes used by a buffer from `from` to `to`.
+    pub(crate) fn adjust(&self, from: usize, to: usize) {
+        if to > from {
+            self.inner.used.fetch_add(to - from, Ordering::SeqCst);
+        } else {
+            self.inner.used.fetch_sub(from - to, Ordering::SeqCst);
+        }
+    }
+}
+
+impl fmt::Debug for MemoryBudget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MemoryBudget")
+            .field("limit", &self.limit())
+            .field("used", &self.used())
+            .field("fail_when_exhausted", &self.fail_when_exhausted())
+            .finish()
+    }
+}
@@ -0,0 +1,1901 @@
+//! The thread-safe circular buffer underlying every pipe direction.
+
+use std::{
+    borrow::Cow,
+    collections::VecDeque,
+    io, mem,
+    sync::Arc,
+    task::Waker,
+    thread::{self, ThreadId},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    budget::MemoryBudget,
+    hang,
+    link::{LinkConfig, TokenBucket},
+    rng::Rng,
+    sampler::OccupancyLog,
+    signal::SignalLane,
+    sync::{AtomicUsize, Condvar, Mutex, MutexGuard, Ordering},
+    ClearWakeup, Error, Interleaving,
+};
+
+#[cfg(any(feature = "embedded-io-async", feature = "tokio"))]
+use std::task::{Context, Poll};
+
+/// Interval at which writers blocked by a memory budget check it, as the other
+/// buffers freeing it don't notify them.
+const BUDGET_POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+/// Async tasks waiting for the buffer to become readable or writable.
+#[derive(Default)]
+struct Wakers {
+    read: Vec<Waker>,
+    write: Vec<Waker>,
+
+    /// Readiness callbacks of the endpoints registered in a `mio::Poll` for
+    /// readability (of the reading endpoint) and writability (of the writing
+    /// endpoint), which unlike task wakers stay installed.
+    #[cfg(feature = "mio")]
+    mio_read: Option<ReadinessNotify>,
+    #[cfg(feature = "mio")]
+    mio_write: Option<ReadinessNotify>,
+}
+
+/// Callback notified when a buffer registered in a `mio::Poll` may have
+/// become ready.
+#[cfg(feature = "mio")]
+pub(crate) type ReadinessNotify = Arc<dyn Fn() + Send + Sync>;
+
+/// Callback notified when the buffer occupancy crosses a watermark, with `true`
+/// for the high and `false` for the low watermark.
+pub(crate) type WatermarkNotify = Arc<dyn Fn(bool) + Send + Sync>;
+
+/// Occupancy thresholds of a buffer.
+struct Watermarks {
+    low: usize,
+    high: usize,
+    above: bool,
+    notify: WatermarkNotify,
+}
+
+/// Storage of the data available to read.
+#[cfg(not(feature = "ring-buffer"))]
+pub(crate) type Storage = crate::ring::SmallDeque;
+
+/// Storage of the data available to read, preallocated.
+#[cfg(feature = "ring-buffer")]
+pub(crate) type Storage = crate::ring::RingBuffer;
+
+/// The data of a `SyncBuffer` and its state, protected by the buffer mutex.
+pub(crate) struct BufferState {
+    /// Data available to read.
+    pub(crate) data: Storage,
+
+    /// Number of bytes available to read, mirrored after every change so it
+    /// can be queried without locking the buffer.
+    occupancy: Arc<AtomicUsize>,
+
+    /// Maximum number of bytes the buffer can hold.
+    pub(crate) capacity: usize,
+
+    /// Whether the buffer was closed: readers get end of stream once the
+    /// remaining data is consumed, writers get a `BrokenPipe` error.
+    pub(crate) closed: bool,
+
+    /// Total number of bytes consumed from the buffer so far.
+    consumed: u64,
+
+    /// Errors injected into the stream, with the stream offset at which they
+    /// are reported to the reader.
+    errors: VecDeque<(u64, io::ErrorKind)>,
+
+    /// Out-of-band messages not received yet.
+    oob: VecDeque<Vec<u8>>,
+
+    /// Occupancy thresholds to notify about, if any.
+    watermarks: Option<Watermarks>,
+
+    /// Written data not yet delivered to the reader, in pump mode or with
+    /// manual delivery.
+    staged: VecDeque<u8>,
+
+    /// Whether written data is staged until pumped, and no operation blocks.
+    pump_mode: bool,
+
+    /// Whether written data is staged until explicitly delivered.
+    manual_delivery: bool,
+
+    /// Whether written data is staged until the writer flushes.
+    flush_delivery: bool,
+
+    /// Whether consumed data keeps counting against the capacity until
+    /// acknowledged by the reader.
+    ack_mode: bool,
+
+    /// Number of bytes consumed and not acknowledged yet.
+    unacked: usize,
+
+    /// Number of bytes the reader allows to be written, with credit-based flow
+    /// control.
+    credit: Option<usize>,
+
+    /// Whether each write replaces the data not read yet.
+    latest_value: bool,
+
+    /// Whether a write not fitting into the buffer fails, like on a hardware
+    /// FIFO.
+    error_on_full: bool,
+
+    /// Size of the quanta the written data is delivered in, if fixed.
+    quantum: Option<usize>,
+
+    /// Policy for interleaving the data of concurrent writers.
+    interleaving: Interleaving,
+
+    /// Writer threads waiting for their turn, with round-robin interleaving.
+    turns: VecDeque<ThreadId>,
+
+    /// How blocked readers react to the buffer being cleared.
+    clear_wakeup: ClearWakeup,
+
+    /// Number of times the buffer was cleared, telling blocked readers it was.
+    clears: u64,
+
+    /// Duration after which a blocked operation is reported, if any.
+    suspicious_wait: Option<Duration>,
+
+    /// Whether written data is discarded, as by a null sink.
+    discard: bool,
+
+    /// Simulated characteristics of the link carrying the written data.
+    link: LinkConfig,
+
+    /// Generator driving the random faults of the link.
+    rng: Rng,
+
+    /// Token bucket of a link with a limited bandwidth.
+    link_tokens: TokenBucket,
+
+    /// End of the current stall of the link, if stalled.
+    stalled_until: Option<Instant>,
+
+    /// Written data on its way over the link, with the time it becomes
+    /// readable.
+    in_flight: VecDeque<(Instant, Vec<u8>)>,
+
+    /// Number of bytes in flight.
+    in_flight_len: usize,
+
+    /// Whether the thread delivering the data in flight was started.
+    courier_started: bool,
+
+    /// Memory budget shared with other buffers, if any.
+    budget: Option<MemoryBudget>,
+
+    /// Number of bytes charged to the memory budget.
+    charged: usize,
+
+    /// Number of endpoints able to write into the buffer, if known: buffers
+    /// fed by other means, like a thread reading a file, do not count them.
+    writers: Option<usize>,
+
+    /// Log of the occupancy changes, if sampled on every change.
+    occupancy_log: Option<Arc<OccupancyLog>>,
+}
+
+impl BufferState {
+    /// Returns the number of bytes that can be written without blocking.
+    pub(crate) fn space(&self) -> usize {
+        // Latest-value writes replace the pending data regardless of the
+        // unacknowledged data, so the buffer can be overcommitted
+        let space = self
+            .capacity
+            .saturating_sub(self.data.len())
+            .saturating_sub(self.staged.len())
+            .saturating_sub(self.in_flight_len)
+            .saturating_sub(self.unacked);
+        space
+            .min(self.credit.unwrap_or(usize::MAX))
+            .min(self.budget_available())
+    }
+
+    /// Returns the data written and not read yet: the data available to read,
+    /// then the data in flight over the simulated link and the staged data.
+    fn unread(&mut self) -> Cow<'_, [u8]> {
+        if self.in_flight.is_empty() && self.staged.is_empty() {
+            return Cow::Borrowed(self.data.make_contiguous());
+        }
+
+        let mut unread = self.data.make_contiguous().to_vec();
+        for (_, chunk) in &self.in_flight {
+            unread.extend_from_slice(chunk);
+        }
+        unread.extend(&self.staged);
+        Cow::Owned(unread)
+    }
+
+    /// Returns the number of bytes that can be buffered without exceeding the
+    /// memory budget, if any.
+    fn budget_available(&self) -> usize {
+        self.budget
+            .as_ref()
+            .map_or(usize::MAX, |budget| budget.available())
+    }
+
+    /// Charges the bytes written and not consumed yet to the memory budget, if
+    /// any, which releases the bytes reserved but not written.
+    fn settle_budget(&mut self) {
+        if let Some(budget) = &self.budget {
+            let pending = self.pending();
+            budget.adjust(self.charged, pending);
+            self.charged = pending;
+        }
+    }
+
+    /// Returns `true` if all written data, including staged data, has been
+    /// consumed and acknowledged.
+    fn is_drained(&self) -> bool {
+        self.data.is_empty()
+            && self.staged.is_empty()
+            && self.in_flight.is_empty()
+            && (self.unacked == 0)
+    }
+
+    /// Returns the number of bytes written and not consumed yet.
+    fn pending(&self) -> usize {
+        self.data.len() + self.in_flight_len + self.staged.len()
+    }
+
+    /// Discards the oldest `n` bytes, delivered, in flight or staged.
+    pub(crate) fn discard_oldest(&mut self, n: usize) {
+        let from_data = n.min(self.data.len());
+        self.data.drain(0..from_data);
+        self.update_occupancy();
+
+        let mut n = n - from_data;
+        while n > 0 {
+            let chunk = match self.in_flight.front_mut() {
+                Some((_, chunk)) => chunk,
+                None => break,
+            };
+
+            let from_chunk = n.min(chunk.len());
+            chunk.drain(0..from_chunk);
+            if chunk.is_empty() {
+                self.in_flight.pop_front();
+            }
+
+            self.in_flight_len -= from_chunk;
+            n -= from_chunk;
+        }
+
+        self.staged.drain(0..n);
+        self.settle_budget();
+    }
+
+    /// Returns `true` if written data is staged instead of being delivered to
+    /// the reader right away.
+    pub(crate) fn is_staging(&self) -> bool {
+        self.pump_mode || self.manual_delivery || self.flush_delivery || self.quantum.is_some()
+    }
+
+    /// Returns the space needed to write `n` bytes: all of them (up to the
+    /// capacity) with atomic interleaving, any otherwise.
+    fn space_required(&self, n: usize) -> usize {
+        match self.interleaving {
+            Interleaving::Atomic => n.min(self.capacity),
+            _ => 1,
+        }
+    }
+
+    /// Returns `true` if `space` bytes can be written, by the writer thread
+    /// `writer` if given, whose turn it then has to be.
+    fn may_write(&self, space: usize, writer: Option<ThreadId>) -> bool {
+        let turn = match writer {
+            Some(writer) if self.interleaving == Interleaving::RoundRobin => {
+                self.turns.front() == Some(&writer)
+            }
+            _ => true,
+        };
+
+        turn && (self.space() >= space)
+    }
+
+    /// Returns the number of staged bytes to deliver right away: whole quanta
+    /// with a fixed delivery quantum, nothing while another staging mode is
+    /// enabled.
+    fn ready_len(&self) -> usize {
+        if self.pump_mode || self.manual_delivery || self.flush_delivery {
+            return 0;
+        }
+
+        match self.quantum {
+            Some(quantum) => self.staged.len() / quantum * quantum,
+            None => self.staged.len(),
+        }
+    }
+
+    /// Returns `true` if written data does not become readable right away, as
+    /// it is staged or travels over a simulated link.
+    pub(crate) fn defers_delivery(&self) -> bool {
+        self.is_staging() || !self.in_flight.is_empty()
+    }
+
+    /// Returns the number of bytes that can be read before the next injected
+    /// error is due.
+    fn readable(&self) -> usize {
+        match self.errors.front() {
+            Some(&(offset, _)) => self.data.len().min((offset - self.consumed) as usize),
+            None => self.data.len(),
+        }
+    }
+
+    /// Returns `true` if an injected error must be reported to the next reader.
+    fn error_due(&self) -> bool {
+        matches!(self.errors.front(), Some(&(offset, _)) if offset == self.consumed)
+    }
+
+    /// Returns `true` if a read would not block: there is data to read, an
+    /// error to report or the buffer was closed (with no data in flight).
+    fn can_read(&self) -> bool {
+        (self.readable() > 0) || self.error_due() || (self.closed && self.in_flight.is_empty())
+    }
+
+    /// Returns `true` if no more data can become readable: no endpoint can
+    /// write into the buffer anymore, and no written data is on its way.
+    fn is_orphaned(&self) -> bool {
+        (self.writers == Some(0)) && self.staged.is_empty() && self.in_flight.is_empty()
+    }
+
+    /// Returns `true` if the link is stalled.
+    fn is_stalled(&self) -> bool {
+        self.stalled_until
+            .map_or(false, |until| Instant::now() < until)
+    }
+
+    /// Returns the time data in flight due at `due` becomes readable, once the
+    /// link is no longer stalled.
+    fn release_at(&self, due: Instant) -> Instant {
+        self.stalled_until.map_or(due, |until| due.max(until))
+    }
+
+    /// Sends data over the link: appends it to the readable data right away on
+    /// an ideal link, or applies the simulated faults and delays otherwise.
+    /// Returns `true` if the data became readable.
+    fn transmit(&mut self, data: impl IntoIterator<Item = u8>) -> bool {
+        if self.link.is_ideal() && self.in_flight.is_empty() && !self.is_stalled() {
+            self.data.extend(data);
+            self.update_occupancy();
+            return true;
+        }
+
+        let link = self.link;
+        let mut chunk: Vec<u8> = data.into_iter().collect();
+
+        if self.rng.chance(link.loss) {
+            return false;
+        }
+
+        if link.deletion > 0.0 {
+            let rng = &mut self.rng;
+            chunk.retain(|_| !rng.chance(link.deletion));
+            if chunk.is_empty() {
+                return false;
+            }
+        }
+
+        if link.corruption > 0.0 {
+            for byte in &mut chunk {
+                if self.rng.chance(link.corruption) {
+                    *byte ^= 1 << (self.rng.next_u64() % 8);
+                }
+            }
+        }
+
+        if !link.delays() && self.in_flight.is_empty() && !self.is_stalled() {
+            self.data.extend(chunk);
+            self.update_occupancy();
+            return true;
+        }
+
+        let now = Instant::now();
+        let delay = link.delay(&mut self.rng);
+
+        // The occupancy when the data is written sets the bandwidth of a
+        // congested link
+        let occupancy = if self.capacity > 0 {
+            self.pending() as f64 / self.capacity as f64
+        } else {
+            0.0
+        };
+
+        match link.effective_bandwidth(occupancy) {
+            Some(bandwidth) => {
+                let rate = bandwidth as f64;
+                let tokens = self.link_tokens.refill(bandwidth, link.burst, now);
+
+                // Deliver the data exceeding the available tokens in slices of
+                // 10 ms worth of transmission
+                let slice_len = (bandwidth / 100).max(1) as usize;
+                let mut sent = 0;
+
+                for slice in chunk.chunks(slice_len) {
+                    sent += slice.len();
+                    let wait = (sent as f64 - tokens).max(0.0) / rate;
+                    self.queue_in_flight(
+                        now + Duration::from_secs_f64(wait) + delay,
+                        slice.to_vec(),
+                    );
+                }
+
+                self.link_tokens.spend(sent);
+            }
+            None => self.queue_in_flight(now + delay, chunk),
+        }
+
+        false
+    }
+
+    /// Queues data in flight until the given time, or after the data queued
+    /// before it, as data never overtakes the data sent before it.
+    fn queue_in_flight(&mut self, due: Instant, chunk: Vec<u8>) {
+        let due = match self.in_flight.back() {
+            Some(&(last_due, _)) => due.max(last_due),
+            None => due,
+        };
+
+        self.in_flight_len += chunk.len();
+        self.in_flight.push_back((due, chunk));
+    }
+
+    /// Mirrors the number of bytes available to read into the occupancy, and
+    /// logs it if it changed and is sampled.
+    fn update_occupancy(&self) {
+        let len = self.data.len();
+        let previous = self.occupancy.swap(len, Ordering::AcqRel);
+
+        if let Some(log) = &self.occupancy_log {
+            if len != previous {
+                log.record(len);
+            }
+        }
+    }
+
+    /// Notifies about the occupancy reaching the high watermark, or falling to
+    /// the low one after that.
+    fn check_watermarks(&mut self) {
+        let len = self.data.len();
+
+        if let Some(watermarks) = &mut self.watermarks {
+            if !watermarks.above && (len >= watermarks.high) {
+                watermarks.above = true;
+                (watermarks.notify)(true);
+            } else if watermarks.above && (len <= watermarks.low) {
+                watermarks.above = false;
+                (watermarks.notify)(false);
+            }
+        }
+    }
+}
+
+/// Registration of an endpoint able to write into a buffer. Dropping the last
+/// one wakes up the readers waiting for data forever, to report the deadlock.
+pub(crate) struct WriterHandle {
+    buffer: Arc<SyncBuffer>,
+}
+
+impl Drop for WriterHandle {
+    fn drop(&mut self) {
+        let mut state = self.buffer.state.lock().unwrap();
+        state.writers = state.writers.map(|writers| writers - 1);
+
+        if state.writers == Some(0) {
+            self.buffer.can_read.notify_all();
+        }
+    }
+}
+
+impl Drop for BufferState {
+    fn drop(&mut self) {
+        if let Some(budget) = &self.budget {
+            budget.adjust(self.charged, 0);
+        }
+    }
+}
+
+/// Callback emulating a receive interrupt, invoked when data is put into the
+/// buffer.
+pub(crate) type RxInterrupt = Arc<dyn Fn() + Send + Sync>;
+
+/// Callback notified whether a read from the buffer would not block, whenever
+/// that may have changed.
+pub(crate) type ReadReadiness = Arc<dyn Fn(bool) + Send + Sync>;
+
+/// A thread-safe circular buffer with synchronization primitives.
+pub(crate) struct SyncBuffer {
+    pub(crate) state: Mutex<BufferState>,
+    pub(crate) can_read: Condvar,
+    pub(crate) can_write: Condvar,
+
+    /// Signaled when data is sent over a simulated link, for the thread
+    /// delivering it.
+    in_flight: Condvar,
+
+    wakers: Mutex<Wakers>,
+    rx_interrupt: Mutex<Option<RxInterrupt>>,
+    read_readiness: Mutex<Option<ReadReadiness>>,
+
+    /// Sideband signals raised by the writer.
+    pub(crate) signals: Mutex<SignalLane>,
+
+    /// Number of bytes available to read, shared with the state.
+    occupancy: Arc<AtomicUsize>,
+}
+
+impl SyncBuffer {
+    /// Creates a new `SyncBuffer` with the specified capacity.
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self::with_storage(Storage::with_capacity(capacity), capacity)
+    }
+
+    /// Creates a new `SyncBuffer` storing its data in `storage`, whose length
+    /// is the capacity.
+    pub(crate) fn from_static(storage: &'static mut [u8]) -> Self {
+        let capacity = storage.len();
+        Self::with_storage(Storage::from_static(storage), capacity)
+    }
+
+    fn with_storage(data: Storage, capacity: usize) -> Self {
+        let occupancy = Arc::new(AtomicUsize::new(0));
+
+        SyncBuffer {
+            state: Mutex::new(BufferState {
+                data,
+                occupancy: occupancy.clone(),
+                capacity,
+                closed: false,
+                consumed: 0,
+                errors: VecDeque::new(),
+                oob: VecDeque::new(),
+                watermarks: None,
+                staged: VecDeque::new(),
+                pump_mode: false,
+                manual_delivery: false,
+                flush_delivery: false,
+                ack_mode: false,
+                unacked: 0,
+                credit: None,
+                latest_value: false,
+                error_on_full: false,
+                quantum: None,
+                interleaving: Interleaving::Arrival,
+                turns: VecDeque::new(),
+                clear_wakeup: ClearWakeup::KeepWaiting,
+                clears: 0,
+                suspicious_wait: None,
+                discard: false,
+                link: LinkConfig::default(),
+                rng: Rng::new(0),
+                link_tokens: TokenBucket::default(),
+                stalled_until: None,
+                in_flight: VecDeque::new(),
+                in_flight_len: 0,
+                courier_started: false,
+                budget: None,
+                charged: 0,
+                writers: None,
+                occupancy_log: None,
+            }),
+            can_read: Condvar::new(),
+            can_write: Condvar::new(),
+            in_flight: Condvar::new(),
+            wakers: Mutex::new(Wakers::default()),
+            signals: Mutex::new(SignalLane::default()),
+            rx_interrupt: Mutex::new(None),
+            read_readiness: Mutex::new(None),
+            occupancy,
+        }
+    }
+
+    /// Creates a null sink: a `SyncBuffer` accepting any amount of written
+    /// data and discarding it.
+    pub(crate) fn null() -> Self {
+        let buffer = Self::new(0);
+        {
+            let mut state = buffer.state.lock().unwrap();
+            state.capacity = usize::MAX;
+            state.discard = true;
+        }
+        buffer
+    }
+
+    /// Waits until the condition function returns false.
+    ///
+    /// If successful, returns a new locked guard to the buffer state.
+    /// If a timeout is specified, returns a `TimedOut` error if the condition
+    /// is not met within the timeout duration.
+    fn wait_while<'a, F>(
+        mut state_guard: MutexGuard<'a, BufferState>,
+        condvar: &Condvar,
+        timeout: Option<Duration>,
+        mut condition: F,
+    ) -> io::Result<MutexGuard<'a, BufferState>>
+    where
+        F: FnMut(&mut BufferState) -> bool,
+    {
+        // Without threads nothing could change the state while waiting, so
+        // every operation is non-blocking
+        #[cfg(all(target_arch = "wasm32", not(target_feature = "atomics")))]
+        let timeout = {
+            let _ = timeout;
+            Some(Duration::ZERO)
+        };
+
+        // In pump mode nothing progresses while waiting either
+        let mut timeout = if state_guard.pump_mode {
+            Some(Duration::ZERO)
+        } else {
+            timeout
+        };
+
+        if condition(&mut state_guard) {
+            #[cfg(feature = "trace")]
+            let _wait = (timeout != Some(Duration::ZERO)).then(crate::trace::WaitSpan::start);
+
+            // Report the waits longer than the suspicious wait threshold, then
+            // keep waiting
+            let threshold = state_guard
+                .suspicious_wait
+                .filter(|&threshold| timeout.map_or(true, |timeout| timeout > threshold));
+            if let Some(threshold) = threshold {
+                let (new_guard, timeout_result) = condvar
+                    .wait_timeout_while(state_guard, threshold, &mut condition)
+                    .map_err(|_| io::Error::from(Error::Poisoned))?;
+
+                if !timeout_result.timed_out() {
+                    return Ok(new_guard);
+                }
+
+                hang::report_wait(threshold);
+                state_guard = new_guard;
+                timeout = timeout.map(|timeout| timeout - threshold);
+            }
+
+            state_guard = match timeout {
+                Some(Duration::ZERO) => state_guard,
+                Some(timeout) => {
+                    let (new_guard, timeout_result) = condvar
+                        .wait_timeout_while(state_guard, timeout, condition)
+                        .map_err(|_| io::Error::from(Error::Poisoned))?;
+
+                    if timeout_result.timed_out() {
+                        return Err(io::Error::from(Error::TimedOut { partial: 0 }));
+                    }
+
+                    new_guard
+                }
+                None => condvar
+                    .wait_while(state_guard, condition)
+                    .map_err(|_| io::Error::from(Error::Poisoned))?,
+            };
+        }
+
+        Ok(state_guard)
+    }
+
+    /// Waits like [`wait_while`](SyncBuffer::wait_while), checking the
+    /// condition periodically too, for the changes not notified on `condvar`.
+    fn poll_while<'a, F>(
+        &'a self,
+        mut state_guard: MutexGuard<'a, BufferState>,
+        condvar: &Condvar,
+        timeout: Option<Duration>,
+        mut condition: F,
+    ) -> io::Result<MutexGuard<'a, BufferState>>
+    where
+        F: FnMut(&mut BufferState) -> bool,
+    {
+        if timeout == Some(Duration::ZERO) {
+            return Self::wait_while(state_guard, condvar, timeout, condition);
+        }
+
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+
+        loop {
+            let interval = deadline.map_or(BUDGET_POLL_INTERVAL, |deadline| {
+                deadline
+                    .saturating_duration_since(Instant::now())
+                    .min(BUDGET_POLL_INTERVAL)
+            });
+            if interval == Duration::ZERO {
+                return Err(io::Error::from(Error::TimedOut { partial: 0 }));
+            }
+
+            match Self::wait_while(state_guard, condvar, Some(interval), &mut condition) {
+                Err(err) if err.kind() == io::ErrorKind::TimedOut => {
+                    state_guard = self.state.lock().unwrap();
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Waits until there is space in the buffer for writing, and until it is
+    /// the turn of the calling thread with round-robin interleaving.
+    ///
+    /// If successful, returns a locked state guard and the number of bytes
+    /// (up to `bytes_required`) that can be written. If a timeout is specified,
+    /// returns a `TimedOut` error if no space becomes available within the
+    /// timeout duration. Returns a `BrokenPipe` error if the buffer is closed,
+    /// or a `Full` error if the memory budget is exhausted and set to fail.
+    pub(crate) fn wait_for_space(
+        &self,
+        bytes_required: usize,
+        timeout: Option<Duration>,
+    ) -> io::Result<(MutexGuard<'_, BufferState>, usize)> {
+        let mut state_guard = self.state.lock().unwrap();
+
+        if !state_guard.closed && ((bytes_required == 0) || (state_guard.capacity == 0)) {
+            return Ok((state_guard, 0));
+        }
+
+        let space_required = state_guard.space_required(bytes_required);
+
+        let writer =
+            (state_guard.interleaving == Interleaving::RoundRobin).then(|| thread::current().id());
+        if let Some(writer) = writer {
+            if !state_guard.turns.contains(&writer) {
+                state_guard.turns.push_back(writer);
+            }
+        }
+
+        let condition =
+            |state: &mut BufferState| !state.may_write(space_required, writer) && !state.closed;
+
+        let result = match state_guard.budget.clone() {
+            Some(budget)
+                if budget.fail_when_exhausted()
+                    && (budget.available() < space_required)
+                    && !state_guard.closed =>
+            {
+                drop(state_guard);
+                Err(io::Error::from(Error::Full))
+            }
+            Some(_) => self.poll_while(state_guard, &self.can_write, timeout, condition),
+            None => Self::wait_while(state_guard, &self.can_write, timeout, condition),
+        };
+
+        state_guard = match result {
+            Ok(state_guard) => state_guard,
+            Err(err) => {
+                if let Some(writer) = writer {
+                    self.end_turn(&mut self.state.lock().unwrap(), writer);
+                }
+                return Err(err);
+            }
+        };
+
+        let may_write = state_guard.may_write(space_required, writer);
+        if let Some(writer) = writer {
+            self.end_turn(&mut state_guard, writer);
+        }
+
+        if state_guard.closed {
+            return Err(io::Error::from(Error::Closed));
+        }
+
+        let mut bytes_available = if may_write {
+            bytes_required.min(state_guard.space())
+        } else {
+            0
+        };
+
+        // Reserve the space in the budget, as writers to other buffers may be
+        // taking it too
+        if let Some(budget) = &state_guard.budget {
+            bytes_available = budget.reserve(bytes_available);
+            state_guard.charged += bytes_available;
+        }
+
+        Ok((state_guard, bytes_available))
+    }
+
+    /// Removes `writer` from the writer threads waiting for their turn and
+    /// wakes the others up.
+    fn end_turn(&self, state: &mut BufferState, writer: ThreadId) {
+        state.turns.retain(|&waiting| waiting != writer);
+        self.can_write.notify_all();
+    }
+
+    /// Reads data from the buffer.
+    ///
+    /// Blocks until some data is available or the timeout is reached.
+    /// Returns the number of bytes read if successful, or `0` if the buffer is
+    /// closed and drained.
+    pub(crate) fn read(&self, buf: &mut [u8], timeout: Option<Duration>) -> io::Result<usize> {
+        let state_guard = self.state.lock().unwrap();
+
+        if buf.is_empty() || (state_guard.capacity == 0) {
+            return Ok(0);
+        }
+
+        match self.wait_readable(state_guard, timeout)? {
+            Some(mut state_guard) => self.read_locked(&mut state_guard, buf),
+            None => Ok(0),
+        }
+    }
+
+    /// Passes up to `limit` bytes of the data available to read to `sink`,
+    /// straight from the buffer, consuming the bytes it accepts, until it
+    /// accepts none. Waits for data as [`read`](SyncBuffer::read) does.
+    ///
+    /// Returns the number of bytes consumed, or the error of `sink` if it
+    /// fails before accepting any.
+    pub(crate) fn drain_into<F>(
+        &self,
+        limit: usize,
+        timeout: Option<Duration>,
+        mut sink: F,
+    ) -> io::Result<usize>
+    where
+        F: FnMut(&[u8]) -> io::Result<usize>,
+    {
+        let state_guard = self.state.lock().unwrap();
+
+        if (limit == 0) || (state_guard.capacity == 0) {
+            return Ok(0);
+        }
+
+        let mut state_guard = match self.wait_readable(state_guard, timeout)? {
+            Some(state_guard) => state_guard,
+            None => return Ok(0),
+        };
+
+        if let Some(err) = self.take_due_error(&mut state_guard) {
+            return Err(err);
+        }
+
+        let limit = limit.min(state_guard.readable());
+        let mut drained = 0;
+
+        while drained < limit {
+            let (front, _) = state_guard.data.as_slices();
+            let chunk = &front[0..front.len().min(limit - drained)];
+
+            match sink(chunk) {
+                Ok(0) => break,
+                Ok(n) => {
+                    self.consume(&mut state_guard, n);
+                    drained += n;
+                }
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) if drained == 0 => return Err(err),
+                Err(_) => break,
+            }
+        }
+
+        Ok(drained)
+    }
+
+    /// Waits until a read would not block, according to the timeout. Returns
+    /// `None` if a clear of the buffer ended the wait with end of stream, as
+    /// configured by its `ClearWakeup`.
+    fn wait_readable<'a>(
+        &self,
+        state_guard: MutexGuard<'a, BufferState>,
+        timeout: Option<Duration>,
+    ) -> io::Result<Option<MutexGuard<'a, BufferState>>> {
+        let clears = state_guard.clears;
+        let cleared = |state: &BufferState| {
+            (state.clear_wakeup != ClearWakeup::KeepWaiting) && (state.clears != clears)
+        };
+
+        // Waiting forever for data nothing can write would hang
+        let deadlocked = |state: &BufferState| timeout.is_none() && state.is_orphaned();
+
+        let state_guard = Self::wait_while(state_guard, &self.can_read, timeout, |state| {
+            !state.can_read() && !cleared(state) && !deadlocked(state)
+        })?;
+
+        if !state_guard.can_read() && deadlocked(&state_guard) {
+            return Err(io::Error::from(Error::Deadlock));
+        }
+
+        if !state_guard.can_read() && cleared(&state_guard) {
+            return match state_guard.clear_wakeup {
+                ClearWakeup::WouldBlock => Err(io::Error::from(Error::WouldBlock)),
+                _ => Ok(None),
+            };
+        }
+
+        Ok(Some(state_guard))
+    }
+
+    /// Reads as much data as is available from the locked buffer without
+    /// waiting, reporting a due injected error instead, if any.
+    fn read_locked(&self, state: &mut BufferState, buf: &mut [u8]) -> io::Result<usize> {
+        if let Some(err) = self.take_due_error(state) {
+            return Err(err);
+        }
+
+        let bytes_to_read = buf.len().min(state.readable());
+
+        Ok(self.take(state, &mut buf[0..bytes_to_read]))
+    }
+
+    /// Removes the injected error due to be reported to the reader, if any.
+    fn take_due_error(&self, state: &mut BufferState) -> Option<io::Error> {
+        if !state.error_due() {
+            return None;
+        }
+
+        let (_, kind) = state.errors.pop_front().unwrap();
+        self.report_readiness(state);
+        Some(io::Error::from(Error::Injected(kind)))
+    }
+
+    /// Moves `buf.len()` bytes from the locked buffer into `buf` and notifies
+    /// waiting writers. Returns the number of bytes moved.
+    pub(crate) fn take(&self, state: &mut BufferState, buf: &mut [u8]) -> usize {
+        if !buf.is_empty() {
+            let n = buf.len();
+            let (front, back) = state.data.as_slices();
+            let from_front = n.min(front.len());
+            buf[0..from_front].copy_from_slice(&front[0..from_front]);
+            buf[from_front..].copy_from_slice(&back[0..(n - from_front)]);
+            self.consume(state, n);
+        }
+
+        buf.len()
+    }
+
+    /// Removes the first `n` bytes of the locked buffer, which were read, and
+    /// notifies waiting writers.
+    fn consume(&self, state: &mut BufferState, n: usize) {
+        state.data.drain(0..n);
+        state.update_occupancy();
+        state.settle_budget();
+
+        state.consumed += n as u64;
+        if state.ack_mode {
+            state.unacked += n;
+        }
+        state.check_watermarks();
+        self.report_readiness(state);
+
+        // Notify the writer that space is available, or all of them if the
+        // one woken up may be unable to take it: with atomic interleaving it
+        // may need more space, with round-robin it may not be its turn
+        if state.interleaving == Interleaving::Arrival {
+            self.can_write.notify_one();
+        } else {
+            self.can_write.notify_all();
+        }
+        self.wake_writers();
+    }
+
+    /// Writes data into the buffer.
+    ///
+    /// Blocks if there is not enough space until some space becomes available
+    /// or the timeout is reached. Returns the number of bytes written if successful.
+    pub(crate) fn write(&self, buf: &[u8], timeout: Option<Duration>) -> io::Result<usize> {
+        if self.latest_value() {
+            return self.overwrite(buf);
+        }
+
+        if self.error_on_full() {
+            return self.write_or_fail(buf);
+        }
+
+        let (mut state_guard, bytes_to_write) = self.wait_for_space(buf.len(), timeout)?;
+
+        let readable = state_guard.data.len();
+        let bytes_written = self.put(&mut state_guard, &buf[0..bytes_to_write]);
+        let delivered = state_guard.data.len() > readable;
+        drop(state_guard);
+
+        if delivered {
+            self.raise_rx_interrupt();
+        }
+
+        Ok(bytes_written)
+    }
+
+    /// Appends `buf` to the locked buffer and notifies waiting readers, sends
+    /// it over the simulated link, or stages it in pump mode. Returns the
+    /// number of bytes appended.
+    pub(crate) fn put(&self, state: &mut BufferState, buf: &[u8]) -> usize {
+        if let Some(credit) = &mut state.credit {
+            *credit = credit.saturating_sub(buf.len());
+        }
+
+        if state.discard {
+            return buf.len();
+        }
+
+        if state.is_staging() {
+            state.staged.extend(buf);
+
+            let ready = state.ready_len();
+            self.release(state, ready);
+
+            // Notify the threads waiting for specific data, which includes the
+            // staged data
+            self.can_read.notify_all();
+        } else if !buf.is_empty() {
+            if state.transmit(buf.iter().copied()) {
+                state.check_watermarks();
+                self.report_readiness(state);
+
+                // Notify the readers that data is available, including the
+                // threads waiting for specific data
+                self.can_read.notify_all();
+                self.wake_readers();
+            } else {
+                self.in_flight.notify_one();
+                self.can_read.notify_all();
+            }
+        }
+
+        state.settle_budget();
+        buf.len()
+    }
+
+    /// Waits until all data has been written from the buffer (blocks until the buffer is empty
+    /// or the operation times out, if a timeout is specified).
+    pub(crate) fn flush(&self, timeout: Option<Duration>) -> io::Result<()> {
+        if self.deliver_on_flush() {
+            return Ok(());
+        }
+
+        // Wait until the write buffer is empty.
+        Self::wait_while(
+            self.state.lock().unwrap(),
+            &self.can_write,
+            timeout,
+            |state| !state.is_drained() && !state.closed,
+        )
+        .map(|_| ())
+    }
+
+    /// Waits until the data written and not read yet, including the data in
+    /// flight and staged, satisfies `predicate`, without consuming it. Returns
+    /// a `TimedOut` error if it does not within the timeout, or a `BrokenPipe`
+    /// error if the buffer is closed first.
+    pub(crate) fn wait_for_data<F>(
+        &self,
+        timeout: Option<Duration>,
+        mut predicate: F,
+    ) -> io::Result<()>
+    where
+        F: FnMut(&[u8]) -> bool,
+    {
+        let deadlocked = |state: &BufferState| timeout.is_none() && state.is_orphaned();
+
+        let mut satisfied = false;
+        let state_guard = Self::wait_while(
+            self.state.lock().unwrap(),
+            &self.can_read,
+            timeout,
+            |state| {
+                satisfied = predicate(&state.unread());
+                !satisfied && !state.closed && !deadlocked(state)
+            },
+        )?;
+
+        if satisfied {
+            Ok(())
+        } else if state_guard.closed {
+            Err(io::Error::from(Error::Closed))
+        } else if deadlocked(&state_guard) {
+            Err(io::Error::from(Error::Deadlock))
+        } else {
+            // Not waiting at all, as in pump mode
+            Err(io::Error::from(Error::TimedOut { partial: 0 }))
+        }
+    }
+
+    /// Clears the buffer, discarding all pending data (and injected errors)
+    /// and notifying waiting writers.
+    pub(crate) fn clear(&self) {
+        {
+            let mut state = self.state.lock().unwrap();
+            state.data.clear();
+            state.update_occupancy();
+            state.staged.clear();
+            state.in_flight.clear();
+            state.in_flight_len = 0;
+            state.settle_budget();
+            state.unacked = 0;
+            state.errors.clear();
+            state.oob.clear();
+            state.clears = state.clears.wrapping_add(1);
+            state.check_watermarks();
+            self.report_readiness(&state);
+
+            if state.clear_wakeup != ClearWakeup::KeepWaiting {
+                self.can_read.notify_all();
+            }
+        }
+        self.can_write.notify_all();
+        self.wake_writers();
+    }
+
+    /// Queues an out-of-band message, ahead of the stream data. Returns a
+    /// `BrokenPipe` error if the buffer is closed.
+    pub(crate) fn send_oob(&self, message: &[u8]) -> io::Result<()> {
+        {
+            let mut state = self.state.lock().unwrap();
+            if state.closed {
+                return Err(io::Error::from(Error::Closed));
+            }
+            state.oob.push_back(message.to_vec());
+        }
+        self.can_read.notify_all();
+        Ok(())
+    }
+
+    /// Receives the next out-of-band message into `buf`, truncating it if it
+    /// does not fit. Blocks until a message is available or the timeout is
+    /// reached. Returns `0` if the buffer is closed and no message is left.
+    pub(crate) fn recv_oob(&self, buf: &mut [u8], timeout: Option<Duration>) -> io::Result<usize> {
+        let mut state_guard = Self::wait_while(
+            self.state.lock().unwrap(),
+            &self.can_read,
+            timeout,
+            |state| state.oob.is_empty() && !state.closed,
+        )?;
+
+        Ok(match state_guard.oob.pop_front() {
+            Some(message) => {
+                let len = buf.len().min(message.len());
+                buf[0..len].copy_from_slice(&message[0..len]);
+                len
+            }
+            None => 0,
+        })
+    }
+
+    /// Returns the number of out-of-band messages not received yet.
+    pub(crate) fn oob_len(&self) -> usize {
+        self.state.lock().unwrap().oob.len()
+    }
+
+    /// Closes the buffer and wakes up all waiting readers and writers.
+    pub(crate) fn close(&self) {
+        {
+            let mut state = self.state.lock().unwrap();
+            state.closed = true;
+            self.report_readiness(&state);
+        }
+        self.can_read.notify_all();
+        self.can_write.notify_all();
+        self.wake_readers();
+        self.wake_writers();
+    }
+
+    /// Returns `true` if the buffer was closed.
+    pub(crate) fn is_closed(&self) -> bool {
+        self.state.lock().unwrap().closed
+    }
+
+    /// Returns `true` if flushing would not wait: all written data has been
+    /// consumed and acknowledged, or the buffer was closed.
+    #[cfg(feature = "embedded-hal")]
+    pub(crate) fn is_flushed(&self) -> bool {
+        let state = self.state.lock().unwrap();
+        state.is_drained() || state.closed
+    }
+
+    /// Queues an error to be reported to the reader once the data currently in
+    /// the buffer has been consumed.
+    pub(crate) fn inject_error(&self, kind: io::ErrorKind) {
+        {
+            let mut state = self.state.lock().unwrap();
+            let offset = state.consumed + state.pending() as u64;
+            state.errors.push_back((offset, kind));
+            self.report_readiness(&state);
+        }
+        self.can_read.notify_all();
+        self.wake_readers();
+    }
+
+    /// Sets the occupancy thresholds to notify about, or removes them.
+    pub(crate) fn set_watermarks(&self, watermarks: Option<(usize, usize, WatermarkNotify)>) {
+        let mut state = self.state.lock().unwrap();
+
+        let above = state.data.len() >= watermarks.as_ref().map_or(0, |w| w.1);
+        state.watermarks = watermarks.map(|(low, high, notify)| Watermarks {
+            low,
+            high,
+            above,
+            notify,
+        });
+    }
+
+    /// Enables or disables the pump mode. Disabling it delivers all staged data,
+    /// unless manual delivery is enabled.
+    pub(crate) fn set_pump_mode(&self, enabled: bool) {
+        let ready = {
+            let mut state = self.state.lock().unwrap();
+            state.pump_mode = enabled;
+            state.ready_len()
+        };
+
+        self.deliver(ready);
+    }
+
+    /// Enables or disables manual delivery. Disabling it delivers all staged
+    /// data, unless the pump mode is enabled.
+    pub(crate) fn set_manual_delivery(&self, enabled: bool) {
+        let ready = {
+            let mut state = self.state.lock().unwrap();
+            state.manual_delivery = enabled;
+            state.ready_len()
+        };
+
+        self.deliver(ready);
+    }
+
+    /// Returns `true` if manual delivery is enabled.
+    pub(crate) fn manual_delivery(&self) -> bool {
+        self.state.lock().unwrap().manual_delivery
+    }
+
+    /// Enables or disables delivery on flush. Disabling it delivers all staged
+    /// data, unless another staging mode is enabled.
+    pub(crate) fn set_flush_delivery(&self, enabled: bool) {
+        let ready = {
+            let mut state = self.state.lock().unwrap();
+            state.flush_delivery = enabled;
+            state.ready_len()
+        };
+
+        self.deliver(ready);
+    }
+
+    /// Returns `true` if delivery on flush is enabled.
+    pub(crate) fn flush_delivery(&self) -> bool {
+        self.state.lock().unwrap().flush_delivery
+    }
+
+    /// Delivers all staged data if delivery on flush is enabled, or the
+    /// partial quantum held back with a fixed delivery quantum. Returns `true`
+    /// if delivery on flush is enabled, in which case flushing does not wait
+    /// for the reader.
+    pub(crate) fn deliver_on_flush(&self) -> bool {
+        let (flush_delivery, partial_quantum) = {
+            let state = self.state.lock().unwrap();
+            let partial_quantum =
+                state.quantum.is_some() && !state.pump_mode && !state.manual_delivery;
+            (state.flush_delivery, partial_quantum)
+        };
+
+        if flush_delivery || partial_quantum {
+            self.deliver(usize::MAX);
+        }
+
+        flush_delivery
+    }
+
+    /// Sets the size of the quanta written data is delivered in, or `None` to
+    /// deliver it as written. Removing the quantum delivers all staged data,
+    /// unless another staging mode is enabled.
+    pub(crate) fn set_delivery_quantum(&self, quantum: Option<usize>) {
+        let ready = {
+            let mut state = self.state.lock().unwrap();
+            state.quantum = quantum.filter(|&quantum| quantum > 0);
+            state.ready_len()
+        };
+
+        self.deliver(ready);
+    }
+
+    /// Returns the size of the quanta written data is delivered in, if fixed.
+    pub(crate) fn delivery_quantum(&self) -> Option<usize> {
+        self.state.lock().unwrap().quantum
+    }
+
+    /// Enables or disables the acknowledgment of consumed data. Disabling it
+    /// acknowledges all consumed data.
+    pub(crate) fn set_ack_mode(&self, enabled: bool) {
+        let mut state = self.state.lock().unwrap();
+        state.ack_mode = enabled;
+        if !enabled {
+            let unacked = state.unacked;
+            self.acknowledge(&mut state, unacked);
+        }
+    }
+
+    /// Returns `true` if consumed data has to be acknowledged.
+    pub(crate) fn ack_mode(&self) -> bool {
+        self.state.lock().unwrap().ack_mode
+    }
+
+    /// Acknowledges up to `n` consumed bytes, making room for writers. Returns
+    /// the number of bytes acknowledged.
+    pub(crate) fn ack(&self, n: usize) -> usize {
+        let mut state = self.state.lock().unwrap();
+        let n = n.min(state.unacked);
+        self.acknowledge(&mut state, n);
+        n
+    }
+
+    /// Acknowledges `n` consumed bytes of the locked buffer and notifies
+    /// waiting writers.
+    fn acknowledge(&self, state: &mut BufferState, n: usize) {
+        if n > 0 {
+            state.unacked -= n;
+            self.report_readiness(state);
+
+            self.can_write.notify_all();
+            self.wake_writers();
+        }
+    }
+
+    /// Returns the number of bytes consumed and not acknowledged yet.
+    pub(crate) fn unacked_len(&self) -> usize {
+        self.state.lock().unwrap().unacked
+    }
+
+    /// Enables or disables credit-based flow control. Enabling it starts with
+    /// no credit, disabling it lets writers use all buffer space again.
+    pub(crate) fn set_credit_flow(&self, enabled: bool) {
+        let mut state = self.state.lock().unwrap();
+        if enabled {
+            state.credit.get_or_insert(0);
+        } else if state.credit.take().is_some() {
+            self.report_readiness(&state);
+            self.can_write.notify_all();
+            self.wake_writers();
+        }
+    }
+
+    /// Returns the remaining credit, or `None` without credit-based flow
+    /// control.
+    pub(crate) fn credit(&self) -> Option<usize> {
+        self.state.lock().unwrap().credit
+    }
+
+    /// Grants writers `n` more bytes of credit, if credit-based flow control is
+    /// enabled.
+    pub(crate) fn grant_credit(&self, n: usize) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(credit) = &mut state.credit {
+            *credit = credit.saturating_add(n);
+            self.report_readiness(&state);
+            self.can_write.notify_all();
+            self.wake_writers();
+        }
+    }
+
+    /// Enables or disables the latest-value mode.
+    pub(crate) fn set_latest_value(&self, enabled: bool) {
+        self.state.lock().unwrap().latest_value = enabled;
+    }
+
+    /// Returns `true` if the buffer is in latest-value mode.
+    pub(crate) fn latest_value(&self) -> bool {
+        self.state.lock().unwrap().latest_value
+    }
+
+    /// Enables or disables the error-on-full write policy.
+    pub(crate) fn set_error_on_full(&self, enabled: bool) {
+        self.state.lock().unwrap().error_on_full = enabled;
+    }
+
+    /// Returns `true` if writes not fitting into the buffer fail.
+    pub(crate) fn error_on_full(&self) -> bool {
+        self.state.lock().unwrap().error_on_full
+    }
+
+    /// Sets the memory budget the buffered data is charged to, moving the data
+    /// already buffered to the new budget, and notifies waiting writers.
+    pub(crate) fn set_memory_budget(&self, budget: Option<MemoryBudget>) {
+        {
+            let mut state = self.state.lock().unwrap();
+            if let Some(old) = state.budget.take() {
+                old.adjust(state.charged, 0);
+            }
+            state.charged = 0;
+            state.budget = budget;
+            state.settle_budget();
+        }
+        self.can_write.notify_all();
+        self.wake_writers();
+    }
+
+    /// Logs the occupancy on every change into `log`, starting with the current
+    /// one, replacing the previous log, if any.
+    pub(crate) fn set_occupancy_log(&self, log: Arc<OccupancyLog>) {
+        let mut state = self.state.lock().unwrap();
+        log.record(state.data.len());
+        state.occupancy_log = Some(log);
+    }
+
+    /// Stops logging the occupancy into `log`, if it is the current log.
+    pub(crate) fn remove_occupancy_log(&self, log: &Arc<OccupancyLog>) {
+        let mut state = self.state.lock().unwrap();
+        if matches!(&state.occupancy_log, Some(current) if Arc::ptr_eq(current, log)) {
+            state.occupancy_log = None;
+        }
+    }
+
+    /// Registers an endpoint able to write into the buffer, until the returned
+    /// handle is dropped.
+    pub(crate) fn register_writer(self: &Arc<Self>) -> WriterHandle {
+        let mut state = self.state.lock().unwrap();
+        state.writers = Some(state.writers.unwrap_or(0) + 1);
+
+        WriterHandle {
+            buffer: self.clone(),
+        }
+    }
+
+    /// Returns the memory budget the buffered data is charged to, if any.
+    pub(crate) fn memory_budget(&self) -> Option<MemoryBudget> {
+        self.state.lock().unwrap().budget.clone()
+    }
+
+    /// Writes all of `buf` without blocking, or nothing if it does not fit into
+    /// the buffer. Returns the length of `buf`, a `Full` error if it does not
+    /// fit, or a `BrokenPipe` error if the buffer is closed.
+    fn write_or_fail(&self, buf: &[u8]) -> io::Result<usize> {
+        let mut state_guard = self.state.lock().unwrap();
+
+        if state_guard.closed {
+            return Err(io::Error::from(Error::Closed));
+        }
+
+        if state_guard.space() < buf.len() {
+            return Err(io::Error::from(Error::Full));
+        }
+
+        let readable = state_guard.data.len();
+        self.put(&mut state_guard, buf);
+        let delivered = state_guard.data.len() > readable;
+        drop(state_guard);
+
+        if delivered {
+            self.raise_rx_interrupt();
+        }
+
+        Ok(buf.len())
+    }
+
+    /// Replaces the data not read yet with `buf`, or with its end if it does
+    /// not fit into the buffer, without blocking. Returns the length of `buf`,
+    /// or a `BrokenPipe` error if the buffer is closed.
+    fn overwrite(&self, buf: &[u8]) -> io::Result<usize> {
+        let mut state_guard = self.state.lock().unwrap();
+
+        if state_guard.closed {
+            return Err(io::Error::from(Error::Closed));
+        }
+
+        let pending = state_guard.pending();
+        state_guard.discard_oldest(pending);
+
+        let value = &buf[buf.len().saturating_sub(state_guard.capacity)..];
+        self.put(&mut state_guard, value);
+        state_guard.check_watermarks();
+        self.report_readiness(&state_guard);
+        let delivered = !state_guard.data.is_empty();
+        drop(state_guard);
+
+        if delivered {
+            self.raise_rx_interrupt();
+        }
+
+        Ok(buf.len())
+    }
+
+    /// Sets the policy for interleaving the data of concurrent writers.
+    pub(crate) fn set_interleaving(&self, interleaving: Interleaving) {
+        let mut state = self.state.lock().unwrap();
+        state.interleaving = interleaving;
+        self.can_write.notify_all();
+    }
+
+    /// Returns the policy for interleaving the data of concurrent writers.
+    pub(crate) fn interleaving(&self) -> Interleaving {
+        self.state.lock().unwrap().interleaving
+    }
+
+    /// Sets how blocked readers react to the buffer being cleared.
+    pub(crate) fn set_clear_wakeup(&self, clear_wakeup: ClearWakeup) {
+        self.state.lock().unwrap().clear_wakeup = clear_wakeup;
+    }
+
+    /// Returns how blocked readers react to the buffer being cleared.
+    pub(crate) fn clear_wakeup(&self) -> ClearWakeup {
+        self.state.lock().unwrap().clear_wakeup
+    }
+
+    /// Sets the duration after which a blocked operation is reported, if any.
+    pub(crate) fn set_suspicious_wait(&self, threshold: Option<Duration>) {
+        self.state.lock().unwrap().suspicious_wait = threshold;
+    }
+
+    /// Returns the duration after which a blocked operation is reported, if
+    /// any.
+    pub(crate) fn suspicious_wait(&self) -> Option<Duration> {
+        self.state.lock().unwrap().suspicious_wait
+    }
+
+    /// Returns the number of staged bytes.
+    pub(crate) fn staged_len(&self) -> usize {
+        self.state.lock().unwrap().staged.len()
+    }
+
+    /// Returns `true` if the buffer is in pump mode.
+    pub(crate) fn pump_mode(&self) -> bool {
+        self.state.lock().unwrap().pump_mode
+    }
+
+    /// Moves up to `max` bytes of staged data into the buffer, making them
+    /// available to the reader. Returns the number of bytes moved.
+    pub(crate) fn deliver(&self, max: usize) -> usize {
+        let (bytes_delivered, readable) = {
+            let mut state = self.state.lock().unwrap();
+            let n = max.min(state.staged.len());
+            (n, self.release(&mut state, n))
+        };
+
+        if readable {
+            self.raise_rx_interrupt();
+        }
+
+        bytes_delivered
+    }
+
+    /// Moves the oldest `n` staged bytes of the locked buffer to the reader, or
+    /// over the simulated link. Returns `true` if they became readable.
+    fn release(&self, state: &mut BufferState, n: usize) -> bool {
+        if n == 0 {
+            return false;
+        }
+
+        let staged: Vec<u8> = state.staged.drain(0..n).collect();
+        let delivered = state.transmit(staged);
+        state.settle_budget();
+
+        if delivered {
+            state.check_watermarks();
+            self.report_readiness(state);
+            self.can_read.notify_all();
+            self.wake_readers();
+            true
+        } else {
+            self.in_flight.notify_one();
+            false
+        }
+    }
+
+    /// Sets the simulated link characteristics, reseeding the generator of the
+    /// random faults. Returns `true` if the thread delivering the data in
+    /// flight has to be started.
+    pub(crate) fn set_link(&self, link: LinkConfig) -> bool {
+        let mut state = self.state.lock().unwrap();
+        state.link = link;
+        state.rng = link.rng();
+
+        let start_courier = link.delays() && !state.courier_started;
+        state.courier_started |= start_courier;
+        start_courier
+    }
+
+    /// Stops the link from delivering data for the given duration. Returns
+    /// `true` if the thread delivering the data in flight has to be started.
+    pub(crate) fn stall(&self, duration: Duration) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let until = Instant::now() + duration;
+        state.stalled_until = Some(
+            state
+                .stalled_until
+                .map_or(until, |stalled| stalled.max(until)),
+        );
+
+        Self::claim_courier(&mut state)
+    }
+
+    /// Writes data to become readable at the given time, bypassing the
+    /// simulated link and the staging area, but never overtaking the data
+    /// written before it. Returns the number of bytes written, and whether the
+    /// thread delivering the data in flight has to be started.
+    pub(crate) fn write_at(
+        &self,
+        due: Instant,
+        buf: &[u8],
+        timeout: Option<Duration>,
+    ) -> io::Result<(usize, bool)> {
+        let (mut state, bytes_to_write) = self.wait_for_space(buf.len(), timeout)?;
+
+        if bytes_to_write > 0 {
+            state.queue_in_flight(due, buf[0..bytes_to_write].to_vec());
+            self.in_flight.notify_one();
+        }
+        state.settle_budget();
+
+        Ok((bytes_to_write, Self::claim_courier(&mut state)))
+    }
+
+    /// Marks the thread delivering the data in flight as started. Returns
+    /// `true` if it has to be started.
+    fn claim_courier(state: &mut BufferState) -> bool {
+        let start_courier = !state.courier_started;
+        state.courier_started = true;
+        start_courier
+    }
+
+    /// Returns the simulated link characteristics.
+    pub(crate) fn link(&self) -> LinkConfig {
+        self.state.lock().unwrap().link
+    }
+
+    /// Waits up to `max_wait` for data in flight to become due, then makes the
+    /// due data readable.
+    pub(crate) fn deliver_due(&self, max_wait: Duration) {
+        let mut state_guard = self.state.lock().unwrap();
+
+        let wait = match state_guard.in_flight.front() {
+            Some(&(due, _)) => state_guard
+                .release_at(due)
+                .saturating_duration_since(Instant::now()),
+            None => max_wait,
+        };
+        if !wait.is_zero() {
+            state_guard = self
+                .in_flight
+                .wait_timeout(state_guard, wait.min(max_wait))
+                .unwrap()
+                .0;
+        }
+
+        let bytes_delivered = {
+            let state = &mut *state_guard;
+            let now = Instant::now();
+            let mut n = 0;
+
+            while matches!(state.in_flight.front(), Some(&(due, _)) if state.release_at(due) <= now)
+            {
+                let (_, chunk) = state.in_flight.pop_front().unwrap();
+                state.in_flight_len -= chunk.len();
+                n += chunk.len();
+                state.data.extend(chunk);
+                state.update_occupancy();
+            }
+
+            if n > 0 {
+                state.check_watermarks();
+                self.report_readiness(state);
+                self.can_read.notify_all();
+                self.wake_readers();
+            }
+
+            n
+        };
+        drop(state_guard);
+
+        if bytes_delivered > 0 {
+            self.raise_rx_interrupt();
+        }
+    }
+
+    /// Sets or removes the receive interrupt callback.
+    pub(crate) fn set_rx_interrupt(&self, rx_interrupt: Option<RxInterrupt>) {
+        *self.rx_interrupt.lock().unwrap() = rx_interrupt;
+    }
+
+    /// Invokes the receive interrupt callback, if any. Must be called without
+    /// the state lock held, so the callback can read from the buffer.
+    pub(crate) fn raise_rx_interrupt(&self) {
+        let rx_interrupt = self.rx_interrupt.lock().unwrap().clone();
+        if let Some(rx_interrupt) = rx_interrupt {
+            rx_interrupt();
+        }
+    }
+
+    /// Sets or removes the read readiness callback, notifying it of the current
+    /// readiness right away.
+    pub(crate) fn set_read_readiness(&self, read_readiness: Option<ReadReadiness>) {
+        let state = self.state.lock().unwrap();
+        *self.read_readiness.lock().unwrap() = read_readiness;
+        self.report_readiness(&state);
+    }
+
+    /// Notifies the read readiness callback, if any, of the readiness of the
+    /// locked buffer. Called with the state lock held, so that concurrent
+    /// notifications are not reordered.
+    fn report_readiness(&self, state: &BufferState) {
+        if let Some(read_readiness) = &*self.read_readiness.lock().unwrap() {
+            read_readiness(state.can_read());
+        }
+    }
+
+    /// Wakes all async tasks waiting for data to become available.
+    fn wake_readers(&self) {
+        let wakers = mem::take(&mut self.wakers.lock().unwrap().read);
+        wakers.into_iter().for_each(Waker::wake);
+
+        #[cfg(feature = "mio")]
+        {
+            let notify = self.wakers.lock().unwrap().mio_read.clone();
+            if let Some(notify) = notify {
+                notify();
+            }
+        }
+    }
+
+    /// Wakes all async tasks waiting for space to become available.
+    fn wake_writers(&self) {
+        let wakers = mem::take(&mut self.wakers.lock().unwrap().write);
+        wakers.into_iter().for_each(Waker::wake);
+
+        #[cfg(feature = "mio")]
+        {
+            let notify = self.wakers.lock().unwrap().mio_write.clone();
+            if let Some(notify) = notify {
+                notify();
+            }
+        }
+    }
+
+    /// Sets or removes the callback notified when the buffer becomes readable.
+    #[cfg(feature = "mio")]
+    pub(crate) fn set_mio_read_notify(&self, notify: Option<ReadinessNotify>) {
+        self.wakers.lock().unwrap().mio_read = notify;
+    }
+
+    /// Sets or removes the callback notified when the buffer becomes writable.
+    #[cfg(feature = "mio")]
+    pub(crate) fn set_mio_write_notify(&self, notify: Option<ReadinessNotify>) {
+        self.wakers.lock().unwrap().mio_write = notify;
+    }
+
+    /// Registers a waker in the given list unless an equivalent one is already there.
+    #[cfg(any(feature = "embedded-io-async", feature = "tokio"))]
+    fn register(wakers: &mut Vec<Waker>, waker: &Waker) {
+        if !wakers.iter().any(|w| w.will_wake(waker)) {
+            wakers.push(waker.clone());
+        }
+    }
+
+    /// Attempts to read data from the buffer without blocking, registering the
+    /// current task to be woken when data becomes available.
+    #[cfg(any(feature = "embedded-io-async", feature = "tokio"))]
+    pub(crate) fn poll_read(
+        &self,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let mut state_guard = self.state.lock().unwrap();
+
+        if buf.is_empty() || (state_guard.capacity == 0) {
+            return Poll::Ready(Ok(0));
+        }
+
+        if !state_guard.can_read() {
+            Self::register(&mut self.wakers.lock().unwrap().read, cx.waker());
+            return Poll::Pending;
+        }
+
+        Poll::Ready(self.read_locked(&mut state_guard, buf))
+    }
+
+    /// Attempts to write data into the buffer without blocking, registering the
+    /// current task to be woken when space becomes available.
+    #[cfg(any(feature = "embedded-io-async", feature = "tokio"))]
+    pub(crate) fn poll_write(&self, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        if self.latest_value() {
+            return Poll::Ready(self.overwrite(buf));
+        }
+
+        if self.error_on_full() {
+            return Poll::Ready(self.write_or_fail(buf));
+        }
+
+        let mut state_guard = self.state.lock().unwrap();
+
+        if state_guard.closed {
+            return Poll::Ready(Err(io::Error::from(Error::Closed)));
+        }
+
+        if buf.is_empty() || (state_guard.capacity == 0) {
+            return Poll::Ready(Ok(0));
+        }
+
+        let space_required = state_guard.space_required(buf.len());
+        if !state_guard.may_write(space_required, None) {
+            Self::register(&mut self.wakers.lock().unwrap().write, cx.waker());
+            return Poll::Pending;
+        }
+
+        let bytes_to_write = buf.len().min(state_guard.space());
+
+        let readable = state_guard.data.len();
+        let bytes_written = self.put(&mut state_guard, &buf[0..bytes_to_write]);
+        let delivered = state_guard.data.len() > readable;
+        drop(state_guard);
+
+        if delivered {
+            self.raise_rx_interrupt();
+        }
+
+        Poll::Ready(Ok(bytes_written))
+    }
+
+    /// Checks whether the buffer has been drained, registering the current task
+    /// to be woken when data is read from it otherwise.
+    #[cfg(feature = "embedded-io-async")]
+    pub(crate) fn poll_flush(&self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        if self.deliver_on_flush() {
+            return Poll::Ready(Ok(()));
+        }
+
+        let state_guard = self.state.lock().unwrap();
+
+        if state_guard.is_drained() || state_guard.closed {
+            return Poll::Ready(Ok(()));
+        }
+
+        Self::register(&mut self.wakers.lock().unwrap().write, cx.waker());
+        Poll::Pending
+    }
+
+    /// Checks whether there is space in the buffer for writing, registering the
+    /// current task to be woken when space becomes available otherwise.
+    /// Returns the number of bytes that can be written, or a `BrokenPipe`
+    /// error if the buffer is closed.
+    #[cfg(feature = "tokio")]
+    pub(crate) fn poll_space(&self, cx: &mut Context<'_>) -> Poll<io::Result<usize>> {
+        let state_guard = self.state.lock().unwrap();
+
+        if state_guard.closed {
+            return Poll::Ready(Err(io::Error::from(Error::Closed)));
+        }
+
+        if (state_guard.space() == 0) && (state_guard.capacity > 0) {
+            Self::register(&mut self.wakers.lock().unwrap().write, cx.waker());
+            return Poll::Pending;
+        }
+
+        Poll::Ready(Ok(state_guard.space()))
+    }
+
+    /// Returns the number of bytes available to read, without locking the
+    /// buffer.
+    pub(crate) fn len(&self) -> usize {
+        self.occupancy.load(Ordering::Acquire)
+    }
+
+    /// Returns `true` if the data available to read starts with `prefix`.
+    pub(crate) fn starts_with(&self, prefix: &[u8]) -> bool {
+        let state = self.state.lock().unwrap();
+        let (front, back) = state.data.as_slices();
+        (state.data.len() >= prefix.len())
+            && front.iter().chain(back).zip(prefix).all(|(a, b)| a == b)
+    }
+
+    /// Returns the offset of the first occurrence of `needle` in the data
+    /// available to read, if any.
+    pub(crate) fn position_of(&self, needle: &[u8]) -> Option<usize> {
+        let mut state = self.state.lock().unwrap();
+        if needle.is_empty() {
+            return Some(0);
+        }
+
+        state
+            .data
+            .make_contiguous()
+            .windows(needle.len())
+            .position(|window| window == needle)
+    }
+
+    /// Returns the number of bytes that can be written without blocking.
+    pub(crate) fn space(&self) -> usize {
+        self.state.lock().unwrap().space()
+    }
+}
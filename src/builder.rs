@@ -0,0 +1,298 @@
+//! Builder of pipes starting from realistic device presets.
+
+use std::time::Duration;
+
+use crate::{link::LinkConfig, rng::Rng, Direction, MockPipe};
+
+/// Buffer capacity of a builder without preset.
+const DEFAULT_CAPACITY: usize = 1024;
+
+/// Class of device whose typical behavior a [`MockPipeBuilder`] starts from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Preset {
+    /// UART at 115200 baud with the 8N1 character format: 11520 bytes per
+    /// second, a 256-byte driver buffer and a 100 ms timeout.
+    Uart115200,
+
+    /// TCP connection over a LAN: 1 ms latency, 64 KiB socket buffers and a
+    /// 1 s timeout.
+    Tcp,
+
+    /// USB CDC serial port at full speed: data delivered in 64-byte packets (a
+    /// partial packet only once flushed), 1 ms frame latency, about 1 MB per
+    /// second, a 4 KiB buffer and a 100 ms timeout.
+    UsbCdc,
+
+    /// Bluetooth Low Energy serial service: 30 ms connection interval latency,
+    /// about 2.5 KB per second, a 512-byte buffer and a 1 s timeout.
+    Ble,
+}
+
+/// Builder of pipes with a buffer capacity, timeout, link characteristics
+/// and delivery quantum set at once, from scratch or from a [`Preset`], so
+/// tests can start from realistic behavior instead of an idealized pipe:
+///
+/// ```
+/// use std::time::Duration;
+///
+/// use mockpipe::{MockPipeBuilder, Preset};
+///
+/// let (host, device) = MockPipeBuilder::preset(Preset::Uart115200)
+///     .with_timeout(Some(Duration::from_millis(500)))
+///     .pair();
+/// assert_eq!(host.timeout(), Some(Duration::from_millis(500)));
+/// ```
+///
+/// The link characteristics apply to both directions. Unless seeded
+/// explicitly, the random faults of the links derive from the master seed of
+/// the builder (see [`with_seed`](MockPipeBuilder::with_seed)), so a test
+/// can replay them exactly by reusing it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MockPipeBuilder {
+    capacity: usize,
+    timeout: Option<Duration>,
+    link: LinkConfig,
+    quantum: Option<usize>,
+    suspicious_wait: Option<Duration>,
+    seed: Option<u64>,
+}
+
+impl MockPipeBuilder {
+    /// Creates a builder of ideal pipes, as created by [`MockPipe::pair`]: a
+    /// 1 KiB buffer, non-blocking endpoints and an ideal link.
+    pub fn new() -> Self {
+        Self {
+            capacity: DEFAULT_CAPACITY,
+            timeout: Some(Duration::ZERO),
+            link: LinkConfig::new(),
+            quantum: None,
+            suspicious_wait: None,
+            seed: None,
+        }
+    }
+
+    /// Creates a builder of pipes behaving like the given class of device.
+    pub fn preset(preset: Preset) -> Self {
+        let ms = Duration::from_millis;
+        let builder = Self::new();
+
+        match preset {
+            Preset::Uart115200 => builder
+                .with_capacity(256)
+                .with_timeout(Some(ms(100)))
+                .with_link(LinkConfig::new().with_bandwidth(Some(115_200 / 10))),
+            Preset::Tcp => builder
+                .with_capacity(64 * 1024)
+                .with_timeout(Some(ms(1000)))
+                .with_link(LinkConfig::new().with_latency(ms(1))),
+            Preset::UsbCdc => builder
+                .with_capacity(4096)
+                .with_timeout(Some(ms(100)))
+                .with_delivery_quantum(Some(64))
+                .with_link(
+                    LinkConfig::new()
+                        .with_latency(ms(1))
+                        .with_bandwidth(Some(1_000_000)),
+                ),
+            Preset::Ble => builder
+                .with_capacity(512)
+                .with_timeout(Some(ms(1000)))
+                .with_link(
+                    LinkConfig::new()
+                        .with_latency(ms(30))
+                        .with_bandwidth(Some(2500)),
+                ),
+        }
+    }
+
+    /// Sets the capacity of the buffers and returns the modified builder.
+    pub fn with_capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity;
+        self
+    }
+
+    /// Sets the timeout of the endpoints and returns the modified builder.
+    pub fn with_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Sets the link characteristics of both directions and returns the
+    /// modified builder.
+    pub fn with_link(mut self, link: LinkConfig) -> Self {
+        self.link = link;
+        self
+    }
+
+    /// Sets the size of the quanta the data is delivered in, if fixed, and
+    /// returns the modified builder.
+    pub fn with_delivery_quantum(mut self, quantum: Option<usize>) -> Self {
+        self.quantum = quantum;
+        self
+    }
+
+    /// Sets the duration after which a blocked operation is reported as
+    /// possibly hanging, if any (see [`MockPipe::set_suspicious_wait`]), and
+    /// returns the modified builder.
+    pub fn with_suspicious_wait(mut self, threshold: Option<Duration>) -> Self {
+        self.suspicious_wait = threshold;
+        self
+    }
+
+    /// Sets the master seed from which the random faults of the links derive
+    /// and returns the modified builder.
+    ///
+    /// Without a master seed, the generator of every link gets a distinct seed
+    /// derived from the global master seed (see [`set_seed`](crate::set_seed)).
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    /// Returns the capacity of the buffers.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Returns the timeout of the endpoints.
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+
+    /// Returns the link characteristics of both directions.
+    pub fn link(&self) -> LinkConfig {
+        self.link
+    }
+
+    /// Returns the size of the quanta the data is delivered in, if fixed.
+    pub fn delivery_quantum(&self) -> Option<usize> {
+        self.quantum
+    }
+
+    /// Returns the duration after which a blocked operation is reported as
+    /// possibly hanging, if any.
+    pub fn suspicious_wait(&self) -> Option<Duration> {
+        self.suspicious_wait
+    }
+
+    /// Returns the master seed from which the random faults of the links
+    /// derive, if set.
+    pub fn seed(&self) -> Option<u64> {
+        self.seed
+    }
+
+    /// Creates a pair of connected endpoints (see [`MockPipe::pair`]).
+    pub fn pair(self) -> (MockPipe, MockPipe) {
+        let (pipe1, pipe2) = MockPipe::pair(self.capacity);
+        pipe1.set_link(Direction::Inbound, self.seeded_link("link::inbound"));
+        pipe1.set_link(Direction::Outbound, self.seeded_link("link::outbound"));
+
+        (self.configure(pipe1), self.configure(pipe2))
+    }
+
+    /// Creates an endpoint in loopback mode (see [`MockPipe::loopback`]).
+    pub fn loopback(self) -> MockPipe {
+        let pipe = MockPipe::loopback(self.capacity);
+        pipe.set_link(Direction::Outbound, self.seeded_link("link::outbound"));
+
+        self.configure(pipe)
+    }
+
+    /// Returns the link characteristics of a direction, seeded from the master
+    /// seed and the given label unless seeded explicitly.
+    fn seeded_link(&self, label: &str) -> LinkConfig {
+        match (self.link.seed, self.seed) {
+            (None, Some(seed)) => self.link.with_seed(Rng::derive_seed_from(seed, label)),
+            _ => self.link,
+        }
+    }
+
+    /// Applies the settings of each endpoint.
+    fn configure(&self, pipe: MockPipe) -> MockPipe {
+        pipe.set_delivery_quantum(self.quantum);
+        pipe.set_suspicious_wait(self.suspicious_wait);
+        pipe.with_timeout(self.timeout)
+    }
+}
+
+impl Default for MockPipeBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Read, Write},
+        thread,
+        time::Instant,
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_presets() {
+        let (host, device) = MockPipeBuilder::new().pair();
+        assert_eq!(host.link(Direction::Outbound), LinkConfig::new());
+        assert_eq!(device.timeout(), Some(Duration::ZERO));
+        assert_eq!(device.suspicious_wait(), None);
+
+        let threshold = Some(Duration::from_secs(1));
+        let pipe = MockPipeBuilder::new()
+            .with_suspicious_wait(threshold)
+            .loopback();
+        assert_eq!(pipe.suspicious_wait(), threshold);
+
+        let (mut host, mut device) = MockPipeBuilder::preset(Preset::UsbCdc).pair();
+        host.set_timeout(Some(Duration::from_secs(5)));
+        assert_eq!(device.delivery_quantum(), Some(64));
+        assert_eq!(
+            host.link(Direction::Inbound).latency,
+            Duration::from_millis(1)
+        );
+
+        // A partial packet is delivered once flushed
+        let reader = thread::spawn(move || {
+            let mut data = [0u8; 4];
+            host.read_exact(&mut data).unwrap();
+            data
+        });
+        device.write_all(b"OK\r\n").unwrap();
+        device.flush().unwrap();
+        assert_eq!(&reader.join().unwrap(), b"OK\r\n");
+
+        // 256 bytes at 11520 bytes per second take about 22 ms
+        let (mut host, mut device) = MockPipeBuilder::preset(Preset::Uart115200).pair();
+        let start = Instant::now();
+        host.write_all(&[0x55; 256]).unwrap();
+        let mut data = [0u8; 256];
+        device.read_exact(&mut data).unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(20));
+
+        let mut pipe = MockPipeBuilder::preset(Preset::Tcp).loopback();
+        pipe.write_all(b"ping").unwrap();
+        pipe.read_exact(&mut data[0..4]).unwrap();
+        assert_eq!(&data[0..4], b"ping");
+    }
+
+    #[test]
+    fn test_seed() {
+        let received = |builder: MockPipeBuilder| {
+            let (host, mut device) = builder.with_link(LinkConfig::new().with_loss(0.5)).pair();
+            for byte in 0..64u8 {
+                host.write_with_timeout(&[byte], None).unwrap();
+            }
+
+            let mut received = vec![0u8; device.read_buffer_len()];
+            device.read_exact(&mut received).unwrap();
+            received
+        };
+
+        // The same master seed replays the same losses
+        let builder = MockPipeBuilder::new().with_seed(7);
+        assert_eq!(builder.seed(), Some(7));
+        assert_eq!(received(builder), received(builder));
+        assert_ne!(received(builder), received(builder.with_seed(8)));
+    }
+}
@@ -0,0 +1,320 @@
+//! Multi-drop bus simulation (RS-485 / CAN style), where many endpoints share
+//! a single medium.
+//!
+//! Every write made by a `BusEndpoint` is delivered to the receive buffers of
+//! all other endpoints attached to the same `Bus`, optionally filtered per
+//! receiver. Writes from different endpoints that follow each other within
+//! the configured collision window are treated as colliding and delivered
+//! garbled.
+
+use std::{
+    io,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::buffer::SyncBuffer;
+
+/// Receive filter deciding whether a write should be delivered to an endpoint.
+type Filter = Arc<dyn Fn(&[u8]) -> bool + Send + Sync>;
+
+/// An endpoint attached to the bus.
+struct Member {
+    id: usize,
+    rx_buffer: Arc<SyncBuffer>,
+    filter: Option<Filter>,
+}
+
+/// Shared state of the bus.
+struct BusState {
+    members: Vec<Member>,
+    next_id: usize,
+    collision_window: Option<Duration>,
+    last_write: Option<(usize, Instant)>,
+    collisions: usize,
+}
+
+struct BusInner {
+    buffer_capacity: usize,
+    state: Mutex<BusState>,
+}
+
+/// A shared medium connecting any number of `BusEndpoint`s.
+///
+/// Writes never block: bytes that do not fit into a receiver's buffer are
+/// dropped for that receiver, as with a UART overrun.
+#[derive(Clone)]
+pub struct Bus {
+    inner: Arc<BusInner>,
+}
+
+impl Bus {
+    /// Creates a new bus; each endpoint gets a receive buffer of the specified
+    /// capacity.
+    pub fn new(buffer_capacity: usize) -> Self {
+        Self {
+            inner: Arc::new(BusInner {
+                buffer_capacity,
+                state: Mutex::new(BusState {
+                    members: Vec::new(),
+                    next_id: 0,
+                    collision_window: None,
+                    last_write: None,
+                    collisions: 0,
+                }),
+            }),
+        }
+    }
+
+    /// Attaches a new endpoint to the bus.
+    pub fn endpoint(&self) -> BusEndpoint {
+        let rx_buffer = Arc::new(SyncBuffer::new(self.inner.buffer_capacity));
+
+        let mut state = self.inner.state.lock().unwrap();
+
+        let id = state.next_id;
+        state.next_id += 1;
+
+        state.members.push(Member {
+            id,
+            rx_buffer: rx_buffer.clone(),
+            filter: None,
+        });
+
+        BusEndpoint {
+            id,
+            bus: self.inner.clone(),
+            rx_buffer,
+            // Non-blocking by default, as `MockPipe`
+            timeout: Mutex::new(Some(Duration::ZERO)),
+        }
+    }
+
+    /// Gets the current collision window.
+    pub fn collision_window(&self) -> Option<Duration> {
+        self.inner.state.lock().unwrap().collision_window
+    }
+
+    /// Sets the collision window.
+    ///
+    /// When set, a write from one endpoint made within the window after a write
+    /// from another endpoint collides with it, and its bytes are delivered
+    /// inverted. `None` (the default) disables collision simulation.
+    pub fn set_collision_window(&self, window: Option<Duration>) {
+        self.inner.state.lock().unwrap().collision_window = window;
+    }
+
+    /// Returns the number of collisions that occurred on the bus.
+    pub fn collisions(&self) -> usize {
+        self.inner.state.lock().unwrap().collisions
+    }
+}
+
+/// An endpoint attached to a `Bus`.
+///
+/// Data written to the endpoint is received by all other endpoints of the bus;
+/// the endpoint itself does not receive its own writes.
+pub struct BusEndpoint {
+    id: usize,
+    bus: Arc<BusInner>,
+    rx_buffer: Arc<SyncBuffer>,
+    timeout: Mutex<Option<Duration>>,
+}
+
+impl BusEndpoint {
+    /// Gets the current timeout duration for read operations.
+    pub fn timeout(&self) -> Option<Duration> {
+        *self.timeout.lock().unwrap()
+    }
+
+    /// Sets the timeout duration for read operations.
+    ///
+    /// `None` means the operation blocks indefinitely. `Some(Duration::ZERO)`
+    /// means the operation is non-blocking.
+    pub fn set_timeout(&self, timeout: Option<Duration>) {
+        *self.timeout.lock().unwrap() = timeout;
+    }
+
+    /// Sets the timeout duration for read operations and returns the modified
+    /// endpoint.
+    pub fn with_timeout(self, timeout: Option<Duration>) -> Self {
+        self.set_timeout(timeout);
+        self
+    }
+
+    /// Sets a filter deciding which writes are delivered to this endpoint.
+    ///
+    /// The filter is called with the data of every write made by other
+    /// endpoints, which makes it possible to implement addressing, e.g. only
+    /// accepting Modbus RTU frames whose first byte matches the device address:
+    ///
+    /// ```
+    /// use mockpipe::bus::Bus;
+    ///
+    /// let bus = Bus::new(256);
+    /// let device = bus.endpoint();
+    /// device.set_filter(|data| data.first() == Some(&0x11));
+    /// ```
+    pub fn set_filter<F>(&self, filter: F)
+    where
+        F: Fn(&[u8]) -> bool + Send + Sync + 'static,
+    {
+        self.set_member_filter(Some(Arc::new(filter)));
+    }
+
+    /// Removes the filter, so all writes are delivered to this endpoint again.
+    pub fn clear_filter(&self) {
+        self.set_member_filter(None);
+    }
+
+    fn set_member_filter(&self, filter: Option<Filter>) {
+        let mut state = self.bus.state.lock().unwrap();
+
+        if let Some(member) = state.members.iter_mut().find(|m| m.id == self.id) {
+            member.filter = filter;
+        }
+    }
+
+    /// Returns the number of bytes currently available to read.
+    pub fn read_buffer_len(&self) -> usize {
+        self.rx_buffer.len()
+    }
+
+    /// Clears the receive buffer, discarding all pending data.
+    pub fn clear(&self) {
+        self.rx_buffer.clear();
+    }
+}
+
+impl Drop for BusEndpoint {
+    fn drop(&mut self) {
+        self.bus
+            .state
+            .lock()
+            .unwrap()
+            .members
+            .retain(|member| member.id != self.id);
+    }
+}
+
+impl io::Read for BusEndpoint {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.rx_buffer.read(buf, self.timeout())
+    }
+}
+
+impl io::Write for BusEndpoint {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let mut state = self.bus.state.lock().unwrap();
+
+        let now = Instant::now();
+
+        let collided = match (state.collision_window, state.last_write) {
+            (Some(window), Some((id, time))) => (id != self.id) && (now - time < window),
+            _ => false,
+        };
+
+        state.last_write = Some((self.id, now));
+
+        let data = if collided {
+            state.collisions += 1;
+            buf.iter().map(|byte| !byte).collect()
+        } else {
+            buf.to_vec()
+        };
+
+        for member in state.members.iter().filter(|m| m.id != self.id) {
+            if member.filter.as_ref().map_or(true, |filter| filter(&data)) {
+                member.rx_buffer.write(&data, Some(Duration::ZERO))?;
+            }
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+
+    use super::*;
+
+    #[test]
+    fn test_bus_delivers_to_all_others() {
+        let bus = Bus::new(64);
+
+        let mut master = bus.endpoint();
+        let mut slave1 = bus.endpoint();
+        let mut slave2 = bus.endpoint();
+
+        master.write_all(b"hello").unwrap();
+
+        assert_eq!(master.read_buffer_len(), 0);
+
+        let mut read_data = [0u8; 5];
+        slave1.read_exact(&mut read_data).unwrap();
+        assert_eq!(&read_data, b"hello");
+        slave2.read_exact(&mut read_data).unwrap();
+        assert_eq!(&read_data, b"hello");
+
+        slave1.write_all(b"ok").unwrap();
+
+        assert_eq!(master.read_buffer_len(), 2);
+        assert_eq!(slave2.read_buffer_len(), 2);
+        assert_eq!(slave1.read_buffer_len(), 0);
+    }
+
+    #[test]
+    fn test_bus_filter() {
+        let bus = Bus::new(64);
+
+        let mut master = bus.endpoint();
+        let slave1 = bus.endpoint();
+        let slave2 = bus.endpoint();
+
+        slave1.set_filter(|data| data.first() == Some(&1));
+        slave2.set_filter(|data| data.first() == Some(&2));
+
+        master.write_all(&[1, 0xaa]).unwrap();
+        master.write_all(&[2, 0xbb, 0xcc]).unwrap();
+
+        assert_eq!(slave1.read_buffer_len(), 2);
+        assert_eq!(slave2.read_buffer_len(), 3);
+
+        slave1.clear_filter();
+        master.write_all(&[2]).unwrap();
+
+        assert_eq!(slave1.read_buffer_len(), 3);
+    }
+
+    #[test]
+    fn test_bus_collision() {
+        let bus = Bus::new(64);
+        bus.set_collision_window(Some(Duration::from_secs(60)));
+
+        let mut endpoint1 = bus.endpoint();
+        let mut endpoint2 = bus.endpoint();
+        let mut observer = bus.endpoint();
+
+        endpoint1.write_all(&[0x01]).unwrap();
+        endpoint2.write_all(&[0x02]).unwrap();
+
+        assert_eq!(bus.collisions(), 1);
+
+        let mut read_data = [0u8; 2];
+        observer.read_exact(&mut read_data).unwrap();
+        assert_eq!(read_data, [0x01, !0x02]);
+
+        // Subsequent writes from the same endpoint do not collide
+        endpoint2.write_all(&[0x03]).unwrap();
+        assert_eq!(bus.collisions(), 1);
+    }
+}
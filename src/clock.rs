@@ -0,0 +1,168 @@
+//! Skewed and drifting endpoint clocks.
+//!
+//! Every endpoint runs on its own [`Clock`], real time by default. Setting an
+//! offset or a drift with [`MockPipe::set_clock`] makes the endpoint time out
+//! according to its clock rather than real time, and the code under test can
+//! read the clock for its timestamps, so time synchronization and timestamp
+//! validation can be tested against clocks drifting apart:
+//!
+//! ```
+//! use std::time::Duration;
+//!
+//! use mockpipe::{clock::Clock, MockPipe};
+//!
+//! let (host, device) = MockPipe::pair(64);
+//!
+//! // The device clock is 5 s ahead and gains 100 µs per second
+//! device.set_clock(
+//!     Clock::new()
+//!         .with_offset(Duration::from_secs(5))
+//!         .with_drift_ppm(100.0),
+//! );
+//!
+//! assert!(device.clock().now() >= host.clock().now() + Duration::from_secs(5));
+//! ```
+//!
+//! There is no virtual clock: the endpoint clocks derive from the real one.
+
+use std::time::{Duration, Instant};
+
+use crate::MockPipe;
+
+/// The clock of an endpoint: the time elapsed since its creation, plus an
+/// offset, running at a rate drifting from real time.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Clock {
+    /// Real time of the creation of the clock.
+    epoch: Instant,
+
+    /// Reading of the clock at its creation.
+    offset: Duration,
+
+    /// Drift from real time, in parts per million.
+    drift_ppm: f64,
+}
+
+impl Default for Clock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock {
+    /// Creates a clock reading zero now and running at the real time rate.
+    pub fn new() -> Self {
+        Self {
+            epoch: Instant::now(),
+            offset: Duration::ZERO,
+            drift_ppm: 0.0,
+        }
+    }
+
+    /// Sets the reading of the clock at its creation and returns the modified
+    /// clock.
+    pub fn with_offset(mut self, offset: Duration) -> Self {
+        self.offset = offset;
+        self
+    }
+
+    /// Sets the drift from real time in parts per million (positive for a
+    /// clock running fast) and returns the modified clock.
+    ///
+    /// The drift is limited to `-999_999.0`, as the clock can't stop.
+    pub fn with_drift_ppm(mut self, drift_ppm: f64) -> Self {
+        self.drift_ppm = drift_ppm.max(-999_999.0);
+        self
+    }
+
+    /// Returns the reading of the clock at its creation.
+    pub fn offset(&self) -> Duration {
+        self.offset
+    }
+
+    /// Returns the drift from real time in parts per million.
+    pub fn drift_ppm(&self) -> f64 {
+        self.drift_ppm
+    }
+
+    /// Returns the current reading of the clock.
+    pub fn now(&self) -> Duration {
+        self.offset + self.epoch.elapsed().mul_f64(self.rate())
+    }
+
+    /// Returns the real time it takes the clock to advance by `duration`.
+    pub fn to_real(&self, duration: Duration) -> Duration {
+        duration.div_f64(self.rate())
+    }
+
+    /// Returns the rate of the clock relative to real time.
+    fn rate(&self) -> f64 {
+        1.0 + self.drift_ppm / 1_000_000.0
+    }
+}
+
+impl MockPipe {
+    /// Sets the clock of the endpoint.
+    ///
+    /// The timeouts of the endpoint operations are measured on its clock, so
+    /// with a clock running fast they elapse early. Clones of an endpoint share
+    /// its clock.
+    pub fn set_clock(&self, clock: Clock) {
+        *self.clock.lock().unwrap() = Some(clock);
+    }
+
+    /// Sets the clock of the endpoint and returns the modified `MockPipe`.
+    pub fn with_clock(self, clock: Clock) -> Self {
+        self.set_clock(clock);
+        self
+    }
+
+    /// Returns the clock of the endpoint.
+    ///
+    /// Unless set, the clock runs in real time, reading zero when first
+    /// queried.
+    pub fn clock(&self) -> Clock {
+        *self.clock.lock().unwrap().get_or_insert_with(Clock::new)
+    }
+
+    /// Converts a timeout measured on the endpoint clock to real time.
+    pub(crate) fn real_timeout(&self, timeout: Option<Duration>) -> Option<Duration> {
+        match *self.clock.lock().unwrap() {
+            Some(clock) => timeout.map(|timeout| clock.to_real(timeout)),
+            None => timeout,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use super::*;
+
+    #[test]
+    fn test_drifting_clock() {
+        let (host, mut device) = MockPipe::pair(64);
+        let clock = Clock::new()
+            .with_offset(Duration::from_secs(1))
+            .with_drift_ppm(1_000_000.0);
+        device.set_clock(clock);
+
+        assert!(device.clock().now() >= Duration::from_secs(1));
+        assert_eq!(
+            clock.to_real(Duration::from_millis(100)),
+            Duration::from_millis(50)
+        );
+
+        // The device clock runs twice as fast, so its timeout elapses early
+        device.set_timeout(Some(Duration::from_millis(200)));
+        let start = Instant::now();
+        let err = device.read(&mut [0u8; 4]).unwrap_err();
+        let elapsed = start.elapsed();
+
+        assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+        assert!(elapsed >= Duration::from_millis(100));
+        assert!(elapsed < Duration::from_millis(200));
+        assert!(host.clock().now() < device.clock().now());
+    }
+}
@@ -0,0 +1,323 @@
+//! DMA-style bulk transfers on pipe endpoints.
+//!
+//! [`MockPipe::start_dma_read`] and [`MockPipe::start_dma_write`] start a
+//! transfer that proceeds in the background, as a DMA channel does, and return
+//! a [`DmaHandle`] through which the driver under test polls the progress,
+//! waits for completion, aborts the transfer or registers a completion
+//! callback (the "transfer complete interrupt").
+//!
+//! ```
+//! use std::io::Write;
+//!
+//! use mockpipe::MockPipe;
+//!
+//! let (uart, mut device) = MockPipe::pair(64);
+//!
+//! let rx = uart.start_dma_read(5);
+//! device.write_all(b"hello").unwrap();
+//!
+//! assert_eq!(rx.wait().unwrap(), b"hello");
+//! ```
+
+use std::{
+    io,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Condvar, Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+use crate::{stats::OpTimer, MockPipe};
+
+/// Interval at which transfers check whether they were aborted.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Callback invoked when a transfer completes.
+type Callback = Box<dyn FnOnce() + Send>;
+
+/// Completion state of a transfer.
+struct Completion<T> {
+    result: Option<io::Result<T>>,
+    callback: Option<Callback>,
+
+    /// Whether the result was taken already.
+    taken: bool,
+}
+
+impl<T> Completion<T> {
+    fn is_complete(&self) -> bool {
+        self.result.is_some() || self.taken
+    }
+
+    /// Takes the result of the transfer, if it has completed and was not taken
+    /// already.
+    fn take(&mut self) -> Option<io::Result<T>> {
+        let result = self.result.take();
+        self.taken |= result.is_some();
+        result
+    }
+}
+
+/// State shared between a handle and its transfer thread.
+struct Transfer<T> {
+    completion: Mutex<Completion<T>>,
+    completed: Condvar,
+    transferred: AtomicUsize,
+    aborted: AtomicBool,
+}
+
+impl<T> Transfer<T> {
+    /// Stores the result of the transfer and signals its completion.
+    fn complete(&self, result: io::Result<T>) {
+        let callback = {
+            let mut completion = self.completion.lock().unwrap();
+            completion.result = Some(result);
+            completion.callback.take()
+        };
+
+        self.completed.notify_all();
+
+        if let Some(callback) = callback {
+            callback();
+        }
+    }
+}
+
+/// A handle to a DMA-style transfer running in the background.
+///
+/// The transfer produces a `Vec<u8>` with the received data for reads and the
+/// number of bytes written for writes.
+pub struct DmaHandle<T> {
+    transfer: Arc<Transfer<T>>,
+}
+
+impl<T: Send + 'static> DmaHandle<T> {
+    /// Starts a transfer running `f` on a background thread.
+    fn start<F>(f: F) -> Self
+    where
+        F: FnOnce(&Transfer<T>) -> io::Result<T> + Send + 'static,
+    {
+        let transfer = Arc::new(Transfer {
+            completion: Mutex::new(Completion {
+                result: None,
+                callback: None,
+                taken: false,
+            }),
+            completed: Condvar::new(),
+            transferred: AtomicUsize::new(0),
+            aborted: AtomicBool::new(false),
+        });
+
+        let thread_transfer = transfer.clone();
+        thread::spawn(move || {
+            let result = f(&thread_transfer);
+            thread_transfer.complete(result);
+        });
+
+        Self { transfer }
+    }
+}
+
+impl<T> DmaHandle<T> {
+    /// Returns `true` once the transfer has completed (successfully or not).
+    pub fn is_complete(&self) -> bool {
+        self.transfer.completion.lock().unwrap().is_complete()
+    }
+
+    /// Returns the number of bytes transferred so far.
+    pub fn transferred(&self) -> usize {
+        self.transfer.transferred.load(Ordering::SeqCst)
+    }
+
+    /// Aborts the transfer. It completes with the data transferred so far, as
+    /// when a DMA channel is disabled mid-transfer (e.g. on an idle line).
+    pub fn abort(&self) {
+        self.transfer.aborted.store(true, Ordering::SeqCst);
+    }
+
+    /// Sets a callback invoked on the transfer thread when the transfer
+    /// completes, emulating a transfer complete interrupt. If the transfer has
+    /// already completed, the callback is invoked immediately.
+    pub fn on_complete<F>(&self, callback: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let mut completion = self.transfer.completion.lock().unwrap();
+
+        if completion.is_complete() {
+            drop(completion);
+            callback();
+        } else {
+            completion.callback = Some(Box::new(callback));
+        }
+    }
+
+    /// Takes the result of the transfer if it has completed, without blocking.
+    /// Returns `None` once the result was taken.
+    pub fn try_take(&self) -> Option<io::Result<T>> {
+        self.transfer.completion.lock().unwrap().take()
+    }
+
+    /// Blocks until the transfer completes and returns its result.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the result was already taken with
+    /// [`try_take`](DmaHandle::try_take).
+    pub fn wait(self) -> io::Result<T> {
+        let completion = self.transfer.completion.lock().unwrap();
+
+        let mut completion = self
+            .transfer
+            .completed
+            .wait_while(completion, |completion| !completion.is_complete())
+            .unwrap();
+
+        completion
+            .take()
+            .expect("the result of the DMA transfer was already taken with `try_take`")
+    }
+}
+
+impl MockPipe {
+    /// Starts receiving `len` bytes from the endpoint in the background.
+    ///
+    /// The transfer completes once `len` bytes have been received or it is
+    /// aborted, and fails with `UnexpectedEof` if the pipe is closed before.
+    pub fn start_dma_read(&self, len: usize) -> DmaHandle<Vec<u8>> {
+        let pipe = self.clone();
+
+        DmaHandle::start(move |transfer| {
+            let mut data = vec![0u8; len];
+            let mut filled = 0;
+
+            while (filled < len) && !transfer.aborted.load(Ordering::SeqCst) {
+                let timer = OpTimer::start();
+                match pipe
+                    .read_buffer
+                    .read(&mut data[filled..], Some(POLL_INTERVAL))
+                {
+                    Ok(0) => return Err(io::Error::from(io::ErrorKind::UnexpectedEof)),
+                    Ok(n) => {
+                        pipe.finish_read(&data[filled..], &Ok(n), timer);
+                        filled += n;
+                        transfer.transferred.store(filled, Ordering::SeqCst);
+                    }
+                    Err(err) if err.kind() == io::ErrorKind::TimedOut => {}
+                    Err(err) => return Err(err),
+                }
+            }
+
+            data.truncate(filled);
+            Ok(data)
+        })
+    }
+
+    /// Starts sending `data` to the endpoint in the background.
+    ///
+    /// The data is copied, as a DMA controller would read it from memory. The
+    /// transfer completes with the number of bytes written once all of them
+    /// fit into the pipe buffer or it is aborted.
+    pub fn start_dma_write(&self, data: &[u8]) -> DmaHandle<usize> {
+        let pipe = self.clone();
+        let data = data.to_vec();
+
+        DmaHandle::start(move |transfer| {
+            let mut written = 0;
+
+            while (written < data.len()) && !transfer.aborted.load(Ordering::SeqCst) {
+                let timer = OpTimer::start();
+                match pipe
+                    .write_buffer
+                    .write(&data[written..], Some(POLL_INTERVAL))
+                {
+                    Ok(0) => return Err(io::Error::from(io::ErrorKind::WriteZero)),
+                    Ok(n) => {
+                        pipe.finish_write(&data[written..], &Ok(n), timer);
+                        written += n;
+                        transfer.transferred.store(written, Ordering::SeqCst);
+                    }
+                    Err(err) if err.kind() == io::ErrorKind::TimedOut => {}
+                    Err(err) => return Err(err),
+                }
+            }
+
+            Ok(written)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Read, Write},
+        sync::mpsc,
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_dma_read() {
+        let (uart, mut device) = MockPipe::pair(64);
+
+        let rx = uart.start_dma_read(8);
+
+        let (sender, receiver) = mpsc::channel();
+        rx.on_complete(move || sender.send(()).unwrap());
+
+        device.write_all(b"hello").unwrap();
+        while rx.transferred() < 5 {
+            thread::yield_now();
+        }
+        assert!(!rx.is_complete());
+
+        device.write_all(b"!!!").unwrap();
+        receiver.recv_timeout(Duration::from_secs(1)).unwrap();
+
+        assert!(rx.is_complete());
+        assert_eq!(rx.try_take().unwrap().unwrap(), b"hello!!!");
+        assert!(rx.try_take().is_none());
+        assert!(rx.is_complete());
+
+        // An aborted transfer completes with the data received so far
+        let rx = uart.start_dma_read(8);
+        device.write_all(b"hi").unwrap();
+        while rx.transferred() < 2 {
+            thread::yield_now();
+        }
+        rx.abort();
+        assert_eq!(rx.wait().unwrap(), b"hi");
+    }
+
+    #[test]
+    fn test_dma_write() {
+        let (uart, mut device) = MockPipe::pair(4);
+        device.set_timeout(Some(Duration::from_secs(1)));
+
+        let tx = uart.start_dma_write(b"hello");
+
+        let mut read_data = [0u8; 5];
+        device.read_exact(&mut read_data).unwrap();
+        assert_eq!(&read_data, b"hello");
+
+        assert_eq!(tx.wait().unwrap(), 5);
+        assert_eq!(uart.stats().bytes_written, 5);
+    }
+
+    #[test]
+    #[should_panic(expected = "already taken")]
+    fn test_dma_wait_after_take() {
+        let (uart, mut device) = MockPipe::pair(4);
+        let rx = uart.start_dma_read(2);
+        device.write_all(b"hi").unwrap();
+
+        while rx.try_take().is_none() {
+            thread::yield_now();
+        }
+
+        // Must not block forever
+        let _ = rx.wait();
+    }
+}
@@ -0,0 +1,90 @@
+//! `embedded-io` trait implementations for `MockPipe`.
+//!
+//! The implementations follow the same timeout rules as the `std::io` ones:
+//! with the default non-blocking timeout an empty read buffer yields `Ok(0)`,
+//! which `embedded-io` treats as end of stream, so blocking consumers should
+//! set a timeout (or `None`) on the pipe first.
+
+use std::io;
+
+use embedded_io::{ErrorType, Read, ReadReady, Write, WriteReady};
+
+use crate::MockPipe;
+
+impl ErrorType for MockPipe {
+    type Error = io::Error;
+}
+
+impl Read for MockPipe {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        self.read_with_timeout(buf, self.timeout())
+    }
+}
+
+impl Write for MockPipe {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        self.write_with_timeout(buf, self.timeout())
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        self.flush_write_buffer()
+    }
+}
+
+impl ReadReady for MockPipe {
+    /// Returns `true` if a read would not block: there is data to read, or the
+    /// pipe was closed and a read reports the end of stream.
+    fn read_ready(&mut self) -> Result<bool, Self::Error> {
+        Ok((self.read_buffer.len() > 0) || self.read_buffer.is_closed())
+    }
+}
+
+impl WriteReady for MockPipe {
+    fn write_ready(&mut self) -> Result<bool, Self::Error> {
+        Ok(self.write_buffer.space() > 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn test_embedded_io_pair() {
+        let (mut pipe1, mut pipe2) = MockPipe::pair(4);
+
+        assert!(!ReadReady::read_ready(&mut pipe2).unwrap());
+        assert!(WriteReady::write_ready(&mut pipe1).unwrap());
+
+        Write::write_all(&mut pipe1, b"ping").unwrap();
+
+        assert!(!WriteReady::write_ready(&mut pipe1).unwrap());
+        assert!(ReadReady::read_ready(&mut pipe2).unwrap());
+
+        let mut read_data = [0u8; 4];
+        Read::read_exact(&mut pipe2, &mut read_data).unwrap();
+        assert_eq!(&read_data, b"ping");
+
+        Write::flush(&mut pipe1).unwrap();
+
+        // The end of stream is ready to be read
+        assert!(!ReadReady::read_ready(&mut pipe2).unwrap());
+        pipe1.close();
+        assert!(ReadReady::read_ready(&mut pipe2).unwrap());
+        assert_eq!(Read::read(&mut pipe2, &mut read_data).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_embedded_io_timeout() {
+        let mut pipe = MockPipe::loopback(4).with_timeout(Some(Duration::from_millis(50)));
+
+        let mut read_data = [0u8; 1];
+        let err = Read::read(&mut pipe, &mut read_data).unwrap_err();
+        assert_eq!(
+            embedded_io::Error::kind(&err),
+            embedded_io::ErrorKind::TimedOut
+        );
+    }
+}
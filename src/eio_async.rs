@@ -0,0 +1,113 @@
+//! `embedded-io-async` trait implementations for `MockPipe`.
+//!
+//! Async operations never block the executor: a task waiting for data (or for
+//! buffer space) is suspended and woken when the peer side makes progress.
+//! The pipe timeout is not applied here; use the executor's timer facilities
+//! (e.g. `embassy_time::with_timeout`) to bound waits instead.
+//!
+//! Async functions in traits require Rust 1.75, so this feature has a higher
+//! MSRV than the rest of the crate.
+
+#![allow(clippy::incompatible_msrv)]
+
+use std::future::poll_fn;
+
+use embedded_io_async::{Read, Write};
+
+use crate::{stats::OpTimer, MockPipe};
+
+impl Read for MockPipe {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        let timer = OpTimer::start();
+        let result = poll_fn(|cx| self.read_buffer.poll_read(cx, buf)).await;
+        self.finish_read(buf, &result, timer);
+        result
+    }
+}
+
+impl Write for MockPipe {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        let timer = OpTimer::start();
+        let result = poll_fn(|cx| self.write_buffer.poll_write(cx, buf)).await;
+        self.finish_write(buf, &result, timer);
+        result
+    }
+
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        poll_fn(|cx| self.write_buffer.poll_flush(cx)).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        future::Future,
+        pin::pin,
+        sync::Arc,
+        task::{Context, Poll, Wake, Waker},
+        thread::{self, Thread},
+        time::Duration,
+    };
+
+    use super::*;
+
+    struct ThreadWaker(Thread);
+
+    impl Wake for ThreadWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    /// Minimal executor driving a single future on the current thread.
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let mut future = pin!(future);
+        let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+
+        loop {
+            match future.as_mut().poll(&mut cx) {
+                Poll::Ready(output) => return output,
+                Poll::Pending => thread::park(),
+            }
+        }
+    }
+
+    #[test]
+    fn test_async_read_waits_for_data() {
+        let (mut pipe1, mut pipe2) = MockPipe::pair(16);
+
+        let writer = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(100));
+            std::io::Write::write_all(&mut pipe1, b"hello").unwrap();
+        });
+
+        let mut read_data = [0u8; 5];
+        block_on(pipe2.read_exact(&mut read_data)).unwrap();
+        assert_eq!(&read_data, b"hello");
+
+        writer.join().unwrap();
+    }
+
+    #[test]
+    fn test_async_write_waits_for_space() {
+        let (mut pipe1, mut pipe2) = MockPipe::pair(2);
+
+        let reader = thread::spawn(move || {
+            pipe2.set_timeout(Some(Duration::from_millis(1000)));
+
+            let mut read_data = [0u8; 4];
+            std::io::Read::read_exact(&mut pipe2, &mut read_data).unwrap();
+            assert_eq!(&read_data, b"ping");
+        });
+
+        block_on(async {
+            pipe1.write_all(b"ping").await.unwrap();
+            pipe1.flush().await.unwrap();
+        });
+
+        assert_eq!(pipe1.write_buffer_len(), 0);
+
+        reader.join().unwrap();
+    }
+}
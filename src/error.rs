@@ -0,0 +1,161 @@
+//! Typed errors of pipe operations.
+
+use std::{error, fmt, io};
+
+/// Cause of a failed pipe operation.
+///
+/// Pipe operations return `io::Error`s to fit the `Read` and `Write` traits,
+/// converted from this type. [`Error::from_io`] recovers it from them, so tests
+/// can match precisely on the cause of a failure:
+///
+/// ```
+/// use std::{io::Read, time::Duration};
+///
+/// use mockpipe::{Error, MockPipe};
+///
+/// let (_host, mut device) = MockPipe::pair(16);
+/// device.set_timeout(Some(Duration::from_millis(10)));
+///
+/// let err = device.read(&mut [0u8; 4]).unwrap_err();
+/// assert_eq!(Error::from_io(&err), Some(Error::TimedOut { partial: 0 }));
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Error {
+    /// The operation timed out after transferring `partial` bytes.
+    ///
+    /// A single read or write transfers nothing once it times out, while
+    /// `read_exact` and `write_all` report the bytes transferred by their
+    /// previous steps.
+    TimedOut { partial: usize },
+
+    /// The pipe was closed.
+    Closed,
+
+    /// The data did not fit into the buffer, with the error-on-full write
+    /// policy (see [`MockPipe::set_error_on_full`](crate::MockPipe::set_error_on_full)).
+    ///
+    /// It converts to an `io::Error` of the `Other` kind, as `StorageFull` is
+    /// not available on the minimum supported Rust version.
+    Full,
+
+    /// The operation would have blocked in non-blocking mode.
+    WouldBlock,
+
+    /// A thread panicked while holding the lock of the pipe.
+    Poisoned,
+
+    /// An error of the given kind was injected into the stream with
+    /// [`MockPipe::inject_error`](crate::MockPipe::inject_error).
+    Injected(io::ErrorKind),
+
+    /// The operation would have blocked forever: it waits with no timeout for
+    /// data no endpoint can write anymore, as all of them were dropped.
+    Deadlock,
+}
+
+impl Error {
+    /// Returns the kind of the `io::Error` the error converts to.
+    pub fn kind(&self) -> io::ErrorKind {
+        match self {
+            Self::TimedOut { .. } => io::ErrorKind::TimedOut,
+            Self::Closed => io::ErrorKind::BrokenPipe,
+            Self::Full => io::ErrorKind::Other,
+            Self::WouldBlock => io::ErrorKind::WouldBlock,
+            Self::Poisoned => io::ErrorKind::Other,
+            Self::Injected(kind) => *kind,
+            Self::Deadlock => io::ErrorKind::Other,
+        }
+    }
+
+    /// Returns the error an `io::Error` was converted from, if any.
+    pub fn from_io(err: &io::Error) -> Option<Self> {
+        err.get_ref()?.downcast_ref::<Self>().copied()
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::TimedOut { partial } => {
+                write!(f, "operation timed out after transferring {partial} bytes")
+            }
+            Self::Closed => write!(f, "pipe closed"),
+            Self::Full => write!(f, "pipe buffer full"),
+            Self::WouldBlock => write!(f, "operation would block"),
+            Self::Poisoned => write!(f, "pipe lock poisoned"),
+            Self::Injected(kind) => write!(f, "injected error: {kind}"),
+            Self::Deadlock => write!(f, "operation would block forever: no writer left"),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+impl From<Error> for io::Error {
+    fn from(err: Error) -> Self {
+        io::Error::new(err.kind(), err)
+    }
+}
+
+/// Adds the `partial` bytes transferred by the previous steps of an operation
+/// to a `TimedOut` error, returning other errors unchanged.
+pub(crate) fn timed_out_after(err: io::Error, partial: usize) -> io::Error {
+    match Error::from_io(&err) {
+        Some(Error::TimedOut { partial: n }) => io::Error::from(Error::TimedOut {
+            partial: partial + n,
+        }),
+        _ => err,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Read, Write},
+        time::Duration,
+    };
+
+    use super::*;
+    use crate::MockPipe;
+
+    #[test]
+    fn test_error_interop() {
+        let (pipe1, pipe2) = MockPipe::pair(4);
+
+        pipe2.inject_error(io::ErrorKind::ConnectionReset);
+        let err = pipe1.read_with_timeout(&mut [0u8; 4], None).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionReset);
+        assert_eq!(
+            Error::from_io(&err),
+            Some(Error::Injected(io::ErrorKind::ConnectionReset))
+        );
+
+        pipe1.close();
+        let err = pipe2.write_with_timeout(b"data", None).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+        assert_eq!(Error::from_io(&err), Some(Error::Closed));
+
+        // Errors from other sources are not recovered
+        assert_eq!(Error::from_io(&io::Error::from(io::ErrorKind::Other)), None);
+    }
+
+    #[test]
+    fn test_timed_out_partial() {
+        let (mut pipe1, mut pipe2) = MockPipe::pair(4);
+        pipe1.set_timeout(Some(Duration::from_millis(10)));
+        pipe2.set_timeout(Some(Duration::from_millis(10)));
+
+        // The bytes transferred before the timeout are reported
+        let err = pipe1.write_all(b"abcdef").unwrap_err();
+        assert_eq!(Error::from_io(&err), Some(Error::TimedOut { partial: 4 }));
+
+        let mut read_data = [0u8; 6];
+        let err = pipe2.read_exact(&mut read_data).unwrap_err();
+        assert_eq!(Error::from_io(&err), Some(Error::TimedOut { partial: 4 }));
+        assert_eq!(&read_data[0..4], b"abcd");
+
+        let err = pipe2.read(&mut read_data).unwrap_err();
+        assert_eq!(Error::from_io(&err), Some(Error::TimedOut { partial: 0 }));
+    }
+}
@@ -0,0 +1,295 @@
+//! Notifications about the activity of pipe endpoints.
+
+use std::{
+    io::Write,
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Arc, Mutex,
+    },
+};
+
+use crate::Direction;
+
+/// An event occurring on a pipe endpoint.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum PipeEvent {
+    /// Data was written to the endpoint.
+    DataWritten {
+        /// Number of bytes written.
+        n: usize,
+    },
+
+    /// Data was read from the endpoint.
+    DataRead {
+        /// Number of bytes read.
+        n: usize,
+    },
+
+    /// A write left the write buffer full (or found it full).
+    BufferFull,
+
+    /// Pending data of the endpoint was discarded.
+    Cleared,
+
+    /// The endpoint was closed.
+    Closed,
+
+    /// A read, write or flush operation timed out.
+    TimedOut,
+
+    /// The write buffer occupancy reached the high watermark.
+    HighWatermark,
+
+    /// The write buffer occupancy fell to the low watermark after reaching
+    /// the high one.
+    LowWatermark,
+}
+
+/// Channels of the subscribers to the events of an endpoint.
+#[derive(Default)]
+pub(crate) struct Subscribers {
+    senders: Mutex<Vec<Sender<PipeEvent>>>,
+}
+
+impl Subscribers {
+    /// Adds a new subscriber, returning the receiving end of its channel.
+    pub(crate) fn subscribe(&self) -> Receiver<PipeEvent> {
+        let (sender, receiver) = mpsc::channel();
+        self.senders.lock().unwrap().push(sender);
+        receiver
+    }
+
+    /// Sends the event to all subscribers, dropping those that have gone away.
+    pub(crate) fn emit(&self, event: PipeEvent) {
+        self.senders
+            .lock()
+            .unwrap()
+            .retain(|sender| sender.send(event).is_ok());
+    }
+}
+
+/// Callback invoked with the data transferred through an endpoint.
+pub(crate) type Hook = Arc<dyn Fn(&[u8]) + Send + Sync>;
+
+/// A sink mirroring the data transferred through an endpoint in the given
+/// direction (or both, if `None`).
+struct Tee {
+    direction: Option<Direction>,
+    writer: Box<dyn Write + Send>,
+}
+
+/// Callbacks and sinks installed on an endpoint.
+#[derive(Default)]
+pub(crate) struct Hooks {
+    read: Mutex<Option<Hook>>,
+    write: Mutex<Option<Hook>>,
+    tees: Mutex<Vec<Tee>>,
+}
+
+impl Hooks {
+    /// Sets or removes the read hook.
+    pub(crate) fn set_read(&self, hook: Option<Hook>) {
+        *self.read.lock().unwrap() = hook;
+    }
+
+    /// Sets or removes the write hook.
+    pub(crate) fn set_write(&self, hook: Option<Hook>) {
+        *self.write.lock().unwrap() = hook;
+    }
+
+    /// Adds a sink mirroring the data transferred in the given direction (or
+    /// both, if `None`).
+    pub(crate) fn add_tee(&self, direction: Option<Direction>, writer: Box<dyn Write + Send>) {
+        self.tees.lock().unwrap().push(Tee { direction, writer });
+    }
+
+    /// Removes all sinks.
+    pub(crate) fn clear_tees(&self) {
+        self.tees.lock().unwrap().clear();
+    }
+
+    /// Invokes the read hook, if any, with the data read, and mirrors the data.
+    pub(crate) fn on_read(&self, data: &[u8]) {
+        // The lock is not held during the call, so the hook may use the pipe
+        let hook = self.read.lock().unwrap().clone();
+        if let Some(hook) = hook {
+            hook(data);
+        }
+
+        self.mirror(Direction::Inbound, data);
+    }
+
+    /// Invokes the write hook, if any, with the data written, and mirrors the
+    /// data.
+    pub(crate) fn on_write(&self, data: &[u8]) {
+        let hook = self.write.lock().unwrap().clone();
+        if let Some(hook) = hook {
+            hook(data);
+        }
+
+        self.mirror(Direction::Outbound, data);
+    }
+
+    /// Copies the data into the sinks for the given direction, dropping those
+    /// that fail.
+    fn mirror(&self, direction: Direction, data: &[u8]) {
+        self.tees.lock().unwrap().retain_mut(|tee| {
+            if tee.direction.map_or(true, |d| d == direction) {
+                tee.writer.write_all(data).is_ok()
+            } else {
+                true
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{self, Read, Write},
+        time::Duration,
+    };
+
+    use super::*;
+    use crate::MockPipe;
+
+    #[test]
+    fn test_subscribe() {
+        let (mut pipe1, mut pipe2) = MockPipe::pair(4);
+        pipe1.set_timeout(Some(Duration::from_millis(10)));
+
+        let events = pipe1.subscribe();
+
+        let mut read_data = [0u8; 4];
+        assert_eq!(
+            pipe1.read(&mut read_data).unwrap_err().kind(),
+            io::ErrorKind::TimedOut
+        );
+
+        pipe1.write_all(b"ab").unwrap();
+        pipe1.write_all(b"cd").unwrap();
+
+        pipe2.read_exact(&mut read_data).unwrap();
+
+        pipe2.write_all(b"ok").unwrap();
+        pipe1.read_exact(&mut read_data[0..2]).unwrap();
+
+        pipe1.clear();
+        pipe1.close();
+
+        assert_eq!(
+            events.try_iter().collect::<Vec<_>>(),
+            [
+                PipeEvent::TimedOut,
+                PipeEvent::DataWritten { n: 2 },
+                PipeEvent::DataWritten { n: 2 },
+                PipeEvent::BufferFull,
+                PipeEvent::DataRead { n: 2 },
+                PipeEvent::Cleared,
+                PipeEvent::Closed,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_subscribe_clone() {
+        let pipe = MockPipe::loopback(64);
+        let events = pipe.subscribe();
+
+        // Clones of an endpoint share its subscribers
+        let mut clone = pipe.clone();
+        clone.write_all(b"hello").unwrap();
+        assert_eq!(events.try_recv(), Ok(PipeEvent::DataWritten { n: 5 }));
+
+        // Subscribers that have gone away are dropped
+        drop(events);
+        clone.write_all(b"hello").unwrap();
+        assert_eq!(pipe.subscribers.senders.lock().unwrap().len(), 0);
+    }
+
+    #[test]
+    fn test_hooks() {
+        let (mut pipe1, mut pipe2) = MockPipe::pair(64);
+
+        let written = Arc::new(Mutex::new(Vec::new()));
+        let read = Arc::new(Mutex::new(Vec::new()));
+
+        let written_clone = written.clone();
+        pipe1.set_write_hook(move |data| written_clone.lock().unwrap().extend_from_slice(data));
+        let read_clone = read.clone();
+        pipe2.set_read_hook(move |data| read_clone.lock().unwrap().extend_from_slice(data));
+
+        pipe1.write_all(b"hello").unwrap();
+
+        let mut read_data = [0u8; 3];
+        pipe2.read_exact(&mut read_data).unwrap();
+
+        assert_eq!(*written.lock().unwrap(), b"hello");
+        assert_eq!(*read.lock().unwrap(), b"hel");
+
+        pipe1.clear_write_hook();
+        pipe1.write_all(b"!").unwrap();
+        assert_eq!(*written.lock().unwrap(), b"hello");
+    }
+
+    #[test]
+    fn test_watermarks() {
+        let (mut pipe1, mut pipe2) = MockPipe::pair(8);
+        pipe1.set_watermarks(2, 6);
+
+        let events = pipe1.subscribe();
+        let watermark_events = || {
+            events
+                .try_iter()
+                .filter(|event| matches!(event, PipeEvent::HighWatermark | PipeEvent::LowWatermark))
+                .collect::<Vec<_>>()
+        };
+
+        pipe1.write_all(b"hello").unwrap();
+        assert_eq!(watermark_events(), []);
+
+        pipe1.write_all(b"!").unwrap();
+        assert_eq!(watermark_events(), [PipeEvent::HighWatermark]);
+
+        // Draining by the other end is reported as well
+        let mut read_data = [0u8; 3];
+        pipe2.read_exact(&mut read_data).unwrap();
+        assert_eq!(watermark_events(), []);
+        pipe2.read_exact(&mut read_data[0..1]).unwrap();
+        assert_eq!(watermark_events(), [PipeEvent::LowWatermark]);
+
+        pipe1.clear_watermarks();
+        pipe1.write_all(b"hello").unwrap();
+        assert_eq!(watermark_events(), []);
+    }
+
+    #[test]
+    fn test_tee() {
+        let (mut pipe1, mut pipe2) = MockPipe::pair(64);
+
+        // Any `Write` can be used as a sink, including another pipe
+        let mut all = MockPipe::loopback(64);
+        let mut written = MockPipe::loopback(64);
+        pipe1.tee(all.clone());
+        pipe1.tee_direction(Direction::Outbound, written.clone());
+
+        pipe1.write_all(b"ping").unwrap();
+
+        let mut read_data = [0u8; 4];
+        pipe2.read_exact(&mut read_data).unwrap();
+        pipe2.write_all(b"pong").unwrap();
+        pipe1.read_exact(&mut read_data).unwrap();
+
+        let mut mirrored = [0u8; 8];
+        all.read_exact(&mut mirrored).unwrap();
+        assert_eq!(&mirrored, b"pingpong");
+
+        written.read_exact(&mut mirrored[0..4]).unwrap();
+        assert_eq!(&mirrored[0..4], b"ping");
+        assert_eq!(written.read_buffer_len(), 0);
+
+        pipe1.clear_tee();
+        pipe1.write_all(b"ping").unwrap();
+        assert_eq!(all.read_buffer_len(), 0);
+    }
+}
@@ -0,0 +1,281 @@
+//! Minimal C API for cross-language test harnesses.
+//!
+//! C and C++ code handles pipe endpoints through opaque `MockPipe` pointers
+//! (see `include/mockpipe.h`), so firmware test harnesses written in C can
+//! exchange data with Rust test drivers through the same pipe. A Rust driver
+//! creates the pipe and hands one endpoint over with [`into_handle`], or takes
+//! over an endpoint created by C code with [`from_handle`].
+//!
+//! The functions are exported unmangled, so a `staticlib` or `cdylib` crate
+//! depending on `mockpipe` with this feature links them into C code.
+//!
+//! Reads and writes return the number of bytes transferred, or one of the
+//! negative `MOCKPIPE_ERR_*` codes on failure. The timeout is set in
+//! milliseconds, with a negative value blocking indefinitely and `0` (the
+//! default) making the endpoint non-blocking.
+//!
+//! Panics never unwind into the C code, which is undefined behavior: the
+//! functions catch them and report them as `MOCKPIPE_ERR_OTHER` instead.
+
+use std::{
+    io,
+    panic::{self, AssertUnwindSafe},
+    ptr, slice,
+    time::Duration,
+};
+
+use crate::MockPipe;
+
+/// The operation timed out.
+pub const MOCKPIPE_ERR_TIMED_OUT: isize = -1;
+
+/// The pipe is closed.
+pub const MOCKPIPE_ERR_BROKEN_PIPE: isize = -2;
+
+/// A null pointer or an otherwise invalid argument was passed.
+pub const MOCKPIPE_ERR_INVALID: isize = -3;
+
+/// Any other error, such as an injected one, or a panic.
+pub const MOCKPIPE_ERR_OTHER: isize = -4;
+
+/// Turns an endpoint into a handle that can be passed to C code.
+///
+/// The handle must be released with [`mockpipe_destroy`] (or taken back with
+/// [`from_handle`]).
+pub fn into_handle(pipe: MockPipe) -> *mut MockPipe {
+    Box::into_raw(Box::new(pipe))
+}
+
+/// Takes back an endpoint from a handle.
+///
+/// # Safety
+///
+/// `pipe` must be a handle created by this module and not released yet. It
+/// must not be used after the call.
+pub unsafe fn from_handle(pipe: *mut MockPipe) -> MockPipe {
+    *Box::from_raw(pipe)
+}
+
+/// Runs `f`, reporting a panic as `MOCKPIPE_ERR_OTHER` instead of unwinding
+/// into the calling C code.
+fn catch_panic<F: FnOnce() -> isize>(f: F) -> isize {
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or(MOCKPIPE_ERR_OTHER)
+}
+
+/// Maps the result of an I/O operation to a byte count or an error code.
+fn to_status(result: io::Result<usize>) -> isize {
+    match result {
+        Ok(n) => n as isize,
+        Err(err) => match err.kind() {
+            io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => MOCKPIPE_ERR_TIMED_OUT,
+            io::ErrorKind::BrokenPipe => MOCKPIPE_ERR_BROKEN_PIPE,
+            io::ErrorKind::InvalidInput => MOCKPIPE_ERR_INVALID,
+            _ => MOCKPIPE_ERR_OTHER,
+        },
+    }
+}
+
+/// Creates a pipe with buffers of the given capacity, storing the handles of
+/// its two endpoints into `pipe1` and `pipe2`.
+///
+/// Returns `0` on success, `MOCKPIPE_ERR_INVALID` if a pointer is null, or
+/// `MOCKPIPE_ERR_OTHER` if the pipe could not be created, e.g. because of an
+/// overflowing capacity, in which case both handles are set to null.
+///
+/// # Safety
+///
+/// `pipe1` and `pipe2` must be null or valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn mockpipe_create_pair(
+    capacity: usize,
+    pipe1: *mut *mut MockPipe,
+    pipe2: *mut *mut MockPipe,
+) -> isize {
+    if pipe1.is_null() || pipe2.is_null() {
+        return MOCKPIPE_ERR_INVALID;
+    }
+
+    ptr::write(pipe1, ptr::null_mut());
+    ptr::write(pipe2, ptr::null_mut());
+
+    catch_panic(|| {
+        let (endpoint1, endpoint2) = MockPipe::pair(capacity);
+        ptr::write(pipe1, into_handle(endpoint1));
+        ptr::write(pipe2, into_handle(endpoint2));
+
+        0
+    })
+}
+
+/// Releases an endpoint handle. Does nothing if `pipe` is null.
+///
+/// # Safety
+///
+/// `pipe` must be null or a handle not released yet. It must not be used after
+/// the call.
+#[no_mangle]
+pub unsafe extern "C" fn mockpipe_destroy(pipe: *mut MockPipe) {
+    if !pipe.is_null() {
+        catch_panic(|| {
+            drop(from_handle(pipe));
+            0
+        });
+    }
+}
+
+/// Reads up to `len` bytes into `buf`, waiting according to the endpoint
+/// timeout.
+///
+/// Returns the number of bytes read (`0` at the end of stream, or when no data
+/// is available in non-blocking mode), or a negative error code.
+///
+/// # Safety
+///
+/// `pipe` must be null or a valid handle, and `buf` must be valid for writes
+/// of `len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn mockpipe_read(pipe: *mut MockPipe, buf: *mut u8, len: usize) -> isize {
+    let pipe = match pipe.as_ref() {
+        Some(pipe) => pipe,
+        None => return MOCKPIPE_ERR_INVALID,
+    };
+    if buf.is_null() {
+        return if len == 0 { 0 } else { MOCKPIPE_ERR_INVALID };
+    }
+
+    let buf = slice::from_raw_parts_mut(buf, len);
+    catch_panic(|| to_status(pipe.read_with_timeout(buf, pipe.timeout())))
+}
+
+/// Writes up to `len` bytes from `buf`, waiting for buffer space according to
+/// the endpoint timeout.
+///
+/// Returns the number of bytes written (`0` when the buffer is full in
+/// non-blocking mode), or a negative error code.
+///
+/// # Safety
+///
+/// `pipe` must be null or a valid handle, and `buf` must be valid for reads of
+/// `len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn mockpipe_write(pipe: *mut MockPipe, buf: *const u8, len: usize) -> isize {
+    let pipe = match pipe.as_ref() {
+        Some(pipe) => pipe,
+        None => return MOCKPIPE_ERR_INVALID,
+    };
+    if buf.is_null() {
+        return if len == 0 { 0 } else { MOCKPIPE_ERR_INVALID };
+    }
+
+    let buf = slice::from_raw_parts(buf, len);
+    catch_panic(|| to_status(pipe.write_with_timeout(buf, pipe.timeout())))
+}
+
+/// Sets the timeout of the endpoint operations in milliseconds: a negative
+/// value blocks indefinitely, and `0` makes the operations non-blocking.
+///
+/// Returns `0` on success, or `MOCKPIPE_ERR_INVALID` if `pipe` is null.
+///
+/// # Safety
+///
+/// `pipe` must be null or a valid handle.
+#[no_mangle]
+pub unsafe extern "C" fn mockpipe_set_timeout(pipe: *mut MockPipe, timeout_ms: i64) -> isize {
+    let pipe = match pipe.as_ref() {
+        Some(pipe) => pipe,
+        None => return MOCKPIPE_ERR_INVALID,
+    };
+
+    let timeout = u64::try_from(timeout_ms).ok().map(Duration::from_millis);
+
+    catch_panic(|| {
+        pipe.set_timeout(timeout);
+        0
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Read, thread};
+
+    use super::*;
+
+    #[test]
+    fn test_ffi_exchange() {
+        let mut pipe1 = ptr::null_mut();
+        let mut pipe2 = ptr::null_mut();
+
+        unsafe {
+            assert_eq!(mockpipe_create_pair(16, &mut pipe1, &mut pipe2), 0);
+            assert_eq!(mockpipe_set_timeout(pipe1, -1), 0);
+            assert_eq!(mockpipe_set_timeout(pipe2, 10), 0);
+
+            let mut buf = [0u8; 4];
+            assert_eq!(
+                mockpipe_read(pipe2, buf.as_mut_ptr(), buf.len()),
+                MOCKPIPE_ERR_TIMED_OUT
+            );
+            assert_eq!(mockpipe_write(pipe2, b"ping".as_ptr(), 4), 4);
+
+            // The Rust side of the harness takes over one endpoint
+            let mut rust_side = from_handle(pipe1);
+            let reader = thread::spawn(move || {
+                let mut buf = [0u8; 4];
+                rust_side.read_exact(&mut buf).unwrap();
+                buf
+            });
+            assert_eq!(&reader.join().unwrap(), b"ping");
+
+            assert_eq!(
+                mockpipe_write(ptr::null_mut(), b"x".as_ptr(), 1),
+                MOCKPIPE_ERR_INVALID
+            );
+            mockpipe_destroy(pipe2);
+        }
+    }
+
+    #[test]
+    fn test_ffi_panics() {
+        let mut pipe1 = ptr::null_mut();
+        let mut pipe2 = ptr::null_mut();
+
+        // A sink panicking on every write
+        struct PanickingSink;
+
+        impl io::Write for PanickingSink {
+            fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+                panic!("panicking sink");
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        unsafe {
+            assert_eq!(mockpipe_create_pair(16, &mut pipe1, &mut pipe2), 0);
+
+            // A panic in a hook is reported as an error
+            (*pipe2).tee(PanickingSink);
+            assert_eq!(mockpipe_write(pipe2, b"x".as_ptr(), 1), MOCKPIPE_ERR_OTHER);
+
+            // A panic while holding the timeout lock poisons it, so the
+            // operations using it panic too
+            let _ = panic::catch_unwind(AssertUnwindSafe(|| {
+                let _guard = (*pipe1).timeout.lock().unwrap();
+                panic!("poisoning the timeout");
+            }));
+
+            let mut buf = [0u8; 4];
+            assert_eq!(mockpipe_set_timeout(pipe1, 10), MOCKPIPE_ERR_OTHER);
+            assert_eq!(
+                mockpipe_read(pipe1, buf.as_mut_ptr(), buf.len()),
+                MOCKPIPE_ERR_OTHER
+            );
+            assert_eq!(mockpipe_write(pipe1, b"x".as_ptr(), 1), MOCKPIPE_ERR_OTHER);
+
+            mockpipe_destroy(pipe1);
+            mockpipe_destroy(pipe2);
+        }
+    }
+}
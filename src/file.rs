@@ -0,0 +1,222 @@
+//! Endpoints streaming data from and into files and other readers and writers.
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Read, Write},
+    path::Path,
+    sync::{Arc, Weak},
+    thread,
+    time::Duration,
+};
+
+use crate::{buffer::SyncBuffer, link::LinkConfig, Direction, MockPipe};
+
+/// Capacity of the buffer of an endpoint fed from a reader or drained into a
+/// writer.
+const FEED_CAPACITY: usize = 4096;
+
+/// Interval at which a feeder or drainer checks whether the endpoint was
+/// dropped while waiting.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+impl MockPipe {
+    /// Creates an endpoint whose read side streams the data of `reader`, which
+    /// a background thread reads as the endpoint consumes it. Writes to the
+    /// endpoint are discarded as by a [`null`](MockPipe::null) sink.
+    ///
+    /// The data is carried over a link simulated according to `link` (see
+    /// [`link`](crate::link)), so it can be paced by a bandwidth or latency;
+    /// [`LinkConfig::new`] delivers it as fast as it is read. Only a buffer of
+    /// 4 KiB is held in memory, so large payloads need not be loaded by the
+    /// test.
+    ///
+    /// Once `reader` is exhausted, the endpoint reads end of stream. An error
+    /// reading `reader` is reported by the endpoint after the data read before
+    /// it.
+    pub fn feed_from_reader<R: Read + Send + 'static>(reader: R, link: LinkConfig) -> Self {
+        let buffer = Arc::new(SyncBuffer::new(FEED_CAPACITY));
+        let pipe = Self::from_buffers(buffer.clone(), Arc::new(SyncBuffer::null()));
+        pipe.set_link(Direction::Inbound, link);
+
+        start_feeder(&buffer, reader);
+
+        pipe
+    }
+
+    /// Creates an endpoint whose read side streams the contents of the file at
+    /// `path`, as [`feed_from_reader`](MockPipe::feed_from_reader) does.
+    ///
+    /// Returns an error if the file can't be opened.
+    pub fn from_file(path: impl AsRef<Path>, link: LinkConfig) -> io::Result<Self> {
+        Ok(Self::feed_from_reader(File::open(path)?, link))
+    }
+
+    /// Creates an endpoint whose written data is written to `writer` by a
+    /// background thread, e.g. to capture long sessions for later analysis.
+    /// Reads from the endpoint return end of stream.
+    ///
+    /// Flushing the endpoint waits until the thread took all data written so
+    /// far. The thread flushes `writer` and stops once the endpoint is closed,
+    /// or all its clones are dropped, and all written data was passed on. If
+    /// writing to `writer` fails, the endpoint gets closed, so later writes
+    /// fail with a `BrokenPipe` error.
+    pub fn drain_into_writer<W: Write + Send + 'static>(writer: W) -> Self {
+        let buffer = Arc::new(SyncBuffer::new(FEED_CAPACITY));
+        start_drainer(buffer.clone(), writer);
+
+        Self::from_buffers(Arc::new(SyncBuffer::new(0)), buffer)
+    }
+
+    /// Creates an endpoint whose written data is appended to the file at
+    /// `path`, as [`drain_into_writer`](MockPipe::drain_into_writer) does. The
+    /// file is created if it does not exist.
+    ///
+    /// Returns an error if the file can't be opened.
+    pub fn sink_to_file(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self::drain_into_writer(file))
+    }
+}
+
+/// Starts the thread writing the data of `reader` into `buffer` until it is
+/// exhausted, or the buffer is dropped or closed.
+fn start_feeder<R: Read + Send + 'static>(buffer: &Arc<SyncBuffer>, mut reader: R) {
+    let buffer: Weak<SyncBuffer> = Arc::downgrade(buffer);
+
+    thread::spawn(move || {
+        let mut chunk = vec![0u8; FEED_CAPACITY];
+
+        loop {
+            let n = match reader.read(&mut chunk) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => {
+                    if let Some(buffer) = buffer.upgrade() {
+                        buffer.inject_error(err.kind());
+                    }
+                    break;
+                }
+            };
+
+            if !feed(&buffer, &chunk[0..n]) {
+                return;
+            }
+        }
+
+        if let Some(buffer) = buffer.upgrade() {
+            buffer.close();
+        }
+    });
+}
+
+/// Writes all of `data` into `buffer`, waiting for space. Returns `false` if
+/// the buffer was dropped or closed in the meantime.
+fn feed(buffer: &Weak<SyncBuffer>, mut data: &[u8]) -> bool {
+    while !data.is_empty() {
+        let buffer = match buffer.upgrade() {
+            Some(buffer) => buffer,
+            None => return false,
+        };
+
+        match buffer.write(data, Some(POLL_INTERVAL)) {
+            Ok(n) => data = &data[n..],
+            Err(err) if err.kind() == io::ErrorKind::TimedOut => {}
+            Err(_) => return false,
+        }
+    }
+
+    true
+}
+
+/// Starts the thread writing the data written into `buffer` to `writer` until
+/// the buffer is closed or only referenced by the thread, and drained.
+fn start_drainer<W: Write + Send + 'static>(buffer: Arc<SyncBuffer>, mut writer: W) {
+    thread::spawn(move || {
+        let mut chunk = vec![0u8; FEED_CAPACITY];
+
+        loop {
+            let n = match buffer.read(&mut chunk, Some(POLL_INTERVAL)) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(err)
+                    if (err.kind() == io::ErrorKind::TimedOut)
+                        && (Arc::strong_count(&buffer) == 1) =>
+                {
+                    break
+                }
+                // Timed out, or an error injected into the stream
+                Err(_) => continue,
+            };
+
+            if writer.write_all(&chunk[0..n]).is_err() {
+                buffer.close();
+                return;
+            }
+        }
+
+        let _ = writer.flush();
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, process, time::Instant};
+
+    use super::*;
+
+    #[test]
+    fn test_from_file() {
+        let path = env::temp_dir().join(format!("mockpipe-feed-{}.bin", process::id()));
+        let contents: Vec<u8> = (0..10_000u32).map(|i| i as u8).collect();
+        fs::write(&path, &contents).unwrap();
+
+        let mut pipe = MockPipe::from_file(&path, LinkConfig::new()).unwrap();
+        pipe.set_timeout(None);
+        let mut data = Vec::new();
+        pipe.read_to_end(&mut data).unwrap();
+        assert_eq!(data, contents);
+
+        let link = LinkConfig::new().with_bandwidth(Some(10_000));
+        let mut pipe = MockPipe::from_file(&path, link).unwrap();
+        pipe.set_timeout(None);
+        let start = Instant::now();
+        let mut data = [0u8; 2000];
+        pipe.read_exact(&mut data).unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(100));
+
+        fs::remove_file(&path).unwrap();
+        assert!(MockPipe::from_file(&path, LinkConfig::new()).is_err());
+    }
+
+    #[test]
+    fn test_sink_to_file() {
+        let path = env::temp_dir().join(format!("mockpipe-sink-{}.bin", process::id()));
+        let contents: Vec<u8> = (0..10_000u32).map(|i| (i * 7) as u8).collect();
+
+        // Waits for the background thread to write `expected` into the file
+        let wait_for_file = |expected: &[u8]| {
+            let deadline = Instant::now() + Duration::from_secs(5);
+            while (fs::read(&path).unwrap() != expected) && (Instant::now() < deadline) {
+                thread::sleep(POLL_INTERVAL);
+            }
+            assert_eq!(fs::read(&path).unwrap(), expected);
+        };
+
+        // The data left in the buffer of a dropped endpoint is written too
+        let mut pipe = MockPipe::sink_to_file(&path).unwrap();
+        pipe.set_timeout(None);
+        pipe.write_all(&contents[0..6000]).unwrap();
+        drop(pipe);
+        wait_for_file(&contents[0..6000]);
+
+        let mut pipe = MockPipe::sink_to_file(&path).unwrap();
+        pipe.set_timeout(None);
+        pipe.write_all(&contents[6000..]).unwrap();
+        pipe.close();
+        wait_for_file(&contents);
+
+        assert_eq!(pipe.read(&mut [0u8; 4]).unwrap(), 0);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,390 @@
+//! Fixed-capacity pipes without heap allocation, for bare-metal targets.
+//!
+//! The buffers of a [`FixedMockPipe`] are stored inline, those of a
+//! [`StaticMockPipe`] in caller-provided static memory. Both are protected with
+//! [`critical_section`] instead of `std::sync::Mutex`, so they can be shared
+//! between interrupt handlers and the main loop, e.g. to simulate a UART in
+//! on-target tests. The platform must provide a `critical-section`
+//! implementation (most HALs and `cortex-m` do, and there is a `std` one for
+//! host tests).
+//!
+//! All operations are non-blocking: a read from an empty buffer returns `0`,
+//! as does a write into a full one.
+//!
+//! The backend does not use atomic operations, so it also works on targets
+//! without compare-and-swap support, such as `thumbv6m-none-eabi`. The other
+//! backends need `std`, whose `Arc` requires pointer-sized compare-and-swap
+//! anyway, and use no wider atomics, so the crate has no `portable-atomic`
+//! fallback: on such targets, use this backend.
+//!
+//! ```
+//! use mockpipe::fixed::FixedMockPipe;
+//!
+//! static PIPE: FixedMockPipe<64> = FixedMockPipe::new();
+//!
+//! let (uart, device) = PIPE.endpoints();
+//!
+//! assert_eq!(uart.write(b"hello"), 5);
+//!
+//! let mut read_data = [0u8; 5];
+//! assert_eq!(device.read(&mut read_data), 5);
+//! assert_eq!(&read_data, b"hello");
+//! ```
+
+use core::cell::RefCell;
+
+use critical_section::Mutex;
+
+/// A circular buffer over the storage `S`.
+struct Ring<S> {
+    data: S,
+    start: usize,
+    len: usize,
+}
+
+impl<S: AsRef<[u8]> + AsMut<[u8]>> Ring<S> {
+    /// Appends as much of `buf` as fits, returning the number of bytes appended.
+    fn push(&mut self, buf: &[u8]) -> usize {
+        let capacity = self.data.as_ref().len();
+        let n = buf.len().min(capacity - self.len);
+
+        let data = self.data.as_mut();
+        for (i, &byte) in buf[0..n].iter().enumerate() {
+            data[(self.start + self.len + i) % capacity] = byte;
+        }
+        self.len += n;
+
+        n
+    }
+
+    /// Removes up to `buf.len()` bytes into `buf`, returning the number of
+    /// bytes removed.
+    fn pop(&mut self, buf: &mut [u8]) -> usize {
+        let capacity = self.data.as_ref().len();
+        let n = buf.len().min(self.len);
+
+        if n > 0 {
+            let data = self.data.as_ref();
+            for (i, byte) in buf[0..n].iter_mut().enumerate() {
+                *byte = data[(self.start + i) % capacity];
+            }
+            self.start = (self.start + n) % capacity;
+            self.len -= n;
+        }
+
+        n
+    }
+}
+
+/// A buffer carrying data in one direction, protected by a critical section.
+///
+/// The storage is either inline ([`FixedBuffer`]) or caller-provided static
+/// memory ([`StaticBuffer`]).
+pub struct RingBuffer<S> {
+    ring: Mutex<RefCell<Ring<S>>>,
+}
+
+/// A buffer storing up to `N` bytes inline.
+pub type FixedBuffer<const N: usize> = RingBuffer<[u8; N]>;
+
+/// A buffer backed by caller-provided static storage.
+pub type StaticBuffer = RingBuffer<&'static mut [u8]>;
+
+impl<const N: usize> Default for FixedBuffer<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> FixedBuffer<N> {
+    /// Creates an empty buffer. This is a `const fn`, so the buffer can be
+    /// placed in a `static`.
+    pub const fn new() -> Self {
+        Self::with_storage([0; N])
+    }
+}
+
+impl StaticBuffer {
+    /// Creates an empty buffer using `storage` to hold the data, so its
+    /// capacity is the length of `storage`.
+    pub fn from_static(storage: &'static mut [u8]) -> Self {
+        Self::with_storage(storage)
+    }
+}
+
+impl<S: AsRef<[u8]> + AsMut<[u8]>> RingBuffer<S> {
+    /// Creates an empty buffer over the given storage.
+    const fn with_storage(data: S) -> Self {
+        Self {
+            ring: Mutex::new(RefCell::new(Ring {
+                data,
+                start: 0,
+                len: 0,
+            })),
+        }
+    }
+
+    /// Returns the maximum number of bytes the buffer can hold.
+    pub fn capacity(&self) -> usize {
+        critical_section::with(|cs| self.ring.borrow_ref(cs).data.as_ref().len())
+    }
+
+    /// Returns the number of bytes currently stored in the buffer.
+    pub fn len(&self) -> usize {
+        critical_section::with(|cs| self.ring.borrow_ref(cs).len)
+    }
+
+    /// Returns `true` if the buffer is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Writes as much of `buf` as fits into the buffer, returning the number of
+    /// bytes written.
+    pub fn write(&self, buf: &[u8]) -> usize {
+        critical_section::with(|cs| self.ring.borrow_ref_mut(cs).push(buf))
+    }
+
+    /// Reads up to `buf.len()` bytes from the buffer, returning the number of
+    /// bytes read.
+    pub fn read(&self, buf: &mut [u8]) -> usize {
+        critical_section::with(|cs| self.ring.borrow_ref_mut(cs).pop(buf))
+    }
+
+    /// Discards all data stored in the buffer.
+    pub fn clear(&self) {
+        critical_section::with(|cs| {
+            let mut ring = self.ring.borrow_ref_mut(cs);
+            ring.start = 0;
+            ring.len = 0;
+        })
+    }
+}
+
+/// A pair of fixed-capacity buffers connecting two endpoints, each buffer
+/// holding up to `N` bytes.
+pub struct FixedMockPipe<const N: usize> {
+    buffer1: FixedBuffer<N>,
+    buffer2: FixedBuffer<N>,
+}
+
+impl<const N: usize> Default for FixedMockPipe<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> FixedMockPipe<N> {
+    /// Creates a pipe with empty buffers. This is a `const fn`, so the pipe can
+    /// be placed in a `static` and its endpoints shared with interrupt
+    /// handlers.
+    pub const fn new() -> Self {
+        Self {
+            buffer1: FixedBuffer::new(),
+            buffer2: FixedBuffer::new(),
+        }
+    }
+
+    /// Returns the two linked endpoints of the pipe: data written to one of
+    /// them can be read from the other.
+    pub fn endpoints(&self) -> (FixedEndpoint<'_, [u8; N]>, FixedEndpoint<'_, [u8; N]>) {
+        (
+            FixedEndpoint::new(&self.buffer1, &self.buffer2),
+            FixedEndpoint::new(&self.buffer2, &self.buffer1),
+        )
+    }
+}
+
+/// A pair of buffers connecting two endpoints, backed by caller-provided static
+/// storage instead of the heap.
+///
+/// This allows a pipe to live in the `static` resources of RTIC or Embassy
+/// test firmware, with the storage obtained e.g. from `cortex_m::singleton!` or
+/// `static_cell::StaticCell`.
+///
+/// ```
+/// use mockpipe::fixed::StaticMockPipe;
+///
+/// let storage: &'static mut [u8] = Box::leak(Box::new([0u8; 128]));
+///
+/// let pipe = StaticMockPipe::from_static(storage);
+/// let (uart, device) = pipe.endpoints();
+///
+/// assert_eq!(uart.write(b"hello"), 5);
+/// ```
+pub struct StaticMockPipe {
+    buffer1: StaticBuffer,
+    buffer2: StaticBuffer,
+}
+
+impl StaticMockPipe {
+    /// Creates a pipe using `storage` for its buffers. The storage is split
+    /// evenly between the two directions.
+    pub fn from_static(storage: &'static mut [u8]) -> Self {
+        let (storage1, storage2) = storage.split_at_mut(storage.len() / 2);
+
+        Self {
+            buffer1: StaticBuffer::from_static(storage1),
+            buffer2: StaticBuffer::from_static(storage2),
+        }
+    }
+
+    /// Returns the two linked endpoints of the pipe: data written to one of
+    /// them can be read from the other.
+    pub fn endpoints(
+        &self,
+    ) -> (
+        FixedEndpoint<'_, &'static mut [u8]>,
+        FixedEndpoint<'_, &'static mut [u8]>,
+    ) {
+        (
+            FixedEndpoint::new(&self.buffer1, &self.buffer2),
+            FixedEndpoint::new(&self.buffer2, &self.buffer1),
+        )
+    }
+}
+
+/// An endpoint of a [`FixedMockPipe`] or [`StaticMockPipe`], reading from one
+/// buffer and writing to another.
+///
+/// Endpoints are cheap to copy, so e.g. an interrupt handler and the main loop
+/// can each hold one.
+pub struct FixedEndpoint<'a, S> {
+    read_buffer: &'a RingBuffer<S>,
+    write_buffer: &'a RingBuffer<S>,
+}
+
+impl<S> Clone for FixedEndpoint<'_, S> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<S> Copy for FixedEndpoint<'_, S> {}
+
+impl<'a, S: AsRef<[u8]> + AsMut<[u8]>> FixedEndpoint<'a, S> {
+    /// Creates an endpoint from separate read and write buffers. Passing the
+    /// same buffer twice gives a loopback endpoint.
+    pub const fn new(read_buffer: &'a RingBuffer<S>, write_buffer: &'a RingBuffer<S>) -> Self {
+        Self {
+            read_buffer,
+            write_buffer,
+        }
+    }
+
+    /// Reads up to `buf.len()` bytes, returning the number of bytes read (`0`
+    /// if no data is available).
+    pub fn read(&self, buf: &mut [u8]) -> usize {
+        self.read_buffer.read(buf)
+    }
+
+    /// Writes as much of `buf` as fits, returning the number of bytes written
+    /// (`0` if the buffer is full).
+    pub fn write(&self, buf: &[u8]) -> usize {
+        self.write_buffer.write(buf)
+    }
+
+    /// Returns the number of bytes currently available to read.
+    pub fn read_buffer_len(&self) -> usize {
+        self.read_buffer.len()
+    }
+
+    /// Returns the number of bytes written but not yet read by the other end.
+    pub fn write_buffer_len(&self) -> usize {
+        self.write_buffer.len()
+    }
+
+    /// Clears both read and write buffers, discarding all pending data.
+    pub fn clear(&self) {
+        self.read_buffer.clear();
+        self.write_buffer.clear();
+    }
+}
+
+#[cfg(feature = "std")]
+impl<S: AsRef<[u8]> + AsMut<[u8]>> std::io::Read for FixedEndpoint<'_, S> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        Ok(FixedEndpoint::read(self, buf))
+    }
+}
+
+#[cfg(feature = "std")]
+impl<S: AsRef<[u8]> + AsMut<[u8]>> std::io::Write for FixedEndpoint<'_, S> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        Ok(FixedEndpoint::write(self, buf))
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fixed_pipe() {
+        static PIPE: FixedMockPipe<4> = FixedMockPipe::new();
+        let (endpoint1, endpoint2) = PIPE.endpoints();
+
+        assert_eq!(endpoint1.write(b"abc"), 3);
+        assert_eq!(endpoint1.write(b"def"), 1);
+        assert_eq!(endpoint1.write_buffer_len(), 4);
+
+        let mut read_data = [0u8; 3];
+        assert_eq!(endpoint2.read(&mut read_data), 3);
+        assert_eq!(&read_data, b"abc");
+
+        // Wrapping around the end of the storage
+        assert_eq!(endpoint1.write(b"gh"), 2);
+        assert_eq!(endpoint2.read(&mut read_data), 3);
+        assert_eq!(&read_data, b"dgh");
+        assert_eq!(endpoint2.read(&mut read_data), 0);
+
+        endpoint2.write(b"ok");
+        assert_eq!(endpoint1.read_buffer_len(), 2);
+        endpoint1.clear();
+        assert_eq!(endpoint1.read_buffer_len(), 0);
+    }
+
+    #[test]
+    fn test_static_pipe() {
+        let storage: &'static mut [u8] = Box::leak(Box::new([0u8; 8]));
+
+        let pipe = StaticMockPipe::from_static(storage);
+        let (endpoint1, endpoint2) = pipe.endpoints();
+
+        assert_eq!(endpoint1.write(b"hello"), 4);
+        assert_eq!(endpoint2.write(b"ok"), 2);
+
+        let mut read_data = [0u8; 4];
+        assert_eq!(endpoint2.read(&mut read_data), 4);
+        assert_eq!(&read_data, b"hell");
+        assert_eq!(endpoint1.read(&mut read_data), 2);
+        assert_eq!(&read_data[0..2], b"ok");
+    }
+
+    #[test]
+    fn test_fixed_loopback_threads() {
+        let buffer = FixedBuffer::<16>::new();
+        let endpoint = FixedEndpoint::new(&buffer, &buffer);
+
+        std::thread::scope(|scope| {
+            scope.spawn(|| {
+                for byte in 0..100u8 {
+                    while endpoint.write(&[byte]) == 0 {}
+                }
+            });
+
+            let mut expected = 0u8;
+            while expected < 100 {
+                let mut byte = [0u8; 1];
+                if endpoint.read(&mut byte) == 1 {
+                    assert_eq!(byte[0], expected);
+                    expected += 1;
+                }
+            }
+        });
+    }
+}
@@ -0,0 +1,153 @@
+//! Golden-file comparison of written traffic.
+//!
+//! A [`Capture`] collects everything the code under test writes to its
+//! endpoint, and [`Capture::assert_matches_golden`] compares it against a
+//! stored golden byte file, giving snapshot-testing ergonomics for binary
+//! protocols.
+//!
+//! When the `MOCKPIPE_UPDATE_GOLDEN` environment variable is set (to anything
+//! but `0`), the golden files are (re)generated from the captured data instead.
+//!
+//! ```no_run
+//! use std::io::Write;
+//!
+//! use mockpipe::MockPipe;
+//!
+//! let (mut device, _host) = MockPipe::pair(64);
+//! let capture = device.capture_written();
+//!
+//! device.write_all(b"\x01\x03\x00\x00").unwrap();
+//!
+//! capture.assert_matches_golden("tests/golden/read_request.bin");
+//! ```
+
+use std::{
+    env, fs,
+    io::{self, Write},
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use crate::{hex::hex_diff, Direction, MockPipe};
+
+/// Environment variable requesting the golden files to be regenerated.
+pub const UPDATE_ENV_VAR: &str = "MOCKPIPE_UPDATE_GOLDEN";
+
+/// Data written to an endpoint, collected for comparison.
+#[derive(Clone, Default)]
+pub struct Capture {
+    data: Arc<Mutex<Vec<u8>>>,
+}
+
+impl Capture {
+    /// Returns a copy of the data captured so far.
+    pub fn data(&self) -> Vec<u8> {
+        self.data.lock().unwrap().clone()
+    }
+
+    /// Asserts that the data captured so far matches the golden file at
+    /// `path`, or regenerates the file if requested.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the data does not match, or if the golden file cannot be read
+    /// (or written).
+    pub fn assert_matches_golden<P: AsRef<Path>>(&self, path: P) {
+        assert_matches_golden(&self.data(), path);
+    }
+}
+
+impl Write for Capture {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.data.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl MockPipe {
+    /// Starts capturing all data written to the endpoint from now on.
+    ///
+    /// The capture is installed as a tee, so [`MockPipe::clear_tee`] stops it.
+    pub fn capture_written(&self) -> Capture {
+        let capture = Capture::default();
+        self.tee_direction(Direction::Outbound, capture.clone());
+        capture
+    }
+}
+
+/// Asserts that `data` matches the golden file at `path`, or (re)generates the
+/// file from `data` if the `MOCKPIPE_UPDATE_GOLDEN` environment variable is
+/// set.
+///
+/// # Panics
+///
+/// Panics if the data does not match, or if the golden file cannot be read (or
+/// written).
+pub fn assert_matches_golden<P: AsRef<Path>>(data: &[u8], path: P) {
+    let path = path.as_ref();
+
+    if update_requested() {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).unwrap_or_else(|err| {
+                panic!("failed to create directory {}: {}", parent.display(), err)
+            });
+        }
+
+        fs::write(path, data).unwrap_or_else(|err| {
+            panic!("failed to write golden file {}: {}", path.display(), err)
+        });
+
+        return;
+    }
+
+    let golden = fs::read(path).unwrap_or_else(|err| {
+        panic!(
+            "failed to read golden file {}: {} (set {}=1 to generate it)",
+            path.display(),
+            err,
+            UPDATE_ENV_VAR
+        )
+    });
+
+    if golden != data {
+        panic!(
+            "data does not match golden file {}\n{}\n(set {}=1 to update the golden file)",
+            path.display(),
+            hex_diff(&golden, data),
+            UPDATE_ENV_VAR
+        );
+    }
+}
+
+/// Returns `true` if the golden files are to be regenerated.
+fn update_requested() -> bool {
+    env::var_os(UPDATE_ENV_VAR).map_or(false, |value| !value.is_empty() && value != "0")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_capture_matches_golden() {
+        let path = env::temp_dir().join(format!("mockpipe-golden-{}.bin", std::process::id()));
+        fs::write(&path, b"hello, world").unwrap();
+
+        let (mut device, _host) = MockPipe::pair(64);
+        let capture = device.capture_written();
+
+        device.write_all(b"hello, ").unwrap();
+        device.write_all(b"world").unwrap();
+        capture.assert_matches_golden(&path);
+
+        device.write_all(b"!").unwrap();
+        let result = std::panic::catch_unwind(|| capture.assert_matches_golden(&path));
+        fs::remove_file(&path).unwrap();
+
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,146 @@
+//! Adapter implementing the `nb`-based `embedded_hal::serial` traits on top of
+//! `MockPipe`, so drivers written against `embedded-hal` can be tested on the
+//! host.
+
+use std::{io, time::Duration};
+
+use embedded_hal::serial;
+
+use crate::MockPipe;
+
+/// A serial port adapter over a `MockPipe` endpoint implementing the
+/// `embedded_hal::serial::{Read, Write}` traits.
+///
+/// All operations are non-blocking regardless of the pipe timeout: an empty
+/// read buffer or a full write buffer is reported as `nb::Error::WouldBlock`,
+/// so the usual `nb::block!` loops work as expected. Once the pipe is closed
+/// and drained, reads fail with an `UnexpectedEof` error instead, as the
+/// traits have no other way to report the end of stream.
+#[derive(Clone)]
+pub struct NbSerial {
+    pipe: MockPipe,
+}
+
+impl NbSerial {
+    /// Creates a new `NbSerial` adapter over the given pipe endpoint.
+    pub fn new(pipe: MockPipe) -> Self {
+        Self { pipe }
+    }
+
+    /// Returns a reference to the underlying pipe.
+    pub fn pipe(&self) -> &MockPipe {
+        &self.pipe
+    }
+
+    /// Consumes the adapter, returning the underlying pipe.
+    pub fn into_inner(self) -> MockPipe {
+        self.pipe
+    }
+}
+
+impl From<MockPipe> for NbSerial {
+    fn from(pipe: MockPipe) -> Self {
+        Self::new(pipe)
+    }
+}
+
+impl serial::Read<u8> for NbSerial {
+    type Error = io::Error;
+
+    fn read(&mut self) -> nb::Result<u8, Self::Error> {
+        let mut byte = [0u8; 1];
+        match self
+            .pipe
+            .read_with_timeout(&mut byte, Some(Duration::ZERO))?
+        {
+            0 if self.pipe.read_buffer.is_closed() => {
+                Err(nb::Error::Other(io::ErrorKind::UnexpectedEof.into()))
+            }
+            0 => Err(nb::Error::WouldBlock),
+            _ => Ok(byte[0]),
+        }
+    }
+}
+
+impl serial::Write<u8> for NbSerial {
+    type Error = io::Error;
+
+    fn write(&mut self, word: u8) -> nb::Result<(), Self::Error> {
+        match self
+            .pipe
+            .write_with_timeout(&[word], Some(Duration::ZERO))?
+        {
+            0 => Err(nb::Error::WouldBlock),
+            _ => Ok(()),
+        }
+    }
+
+    /// Completes once the written data, staged or unacknowledged included,
+    /// has been read, as `MockPipe::flush` does.
+    fn flush(&mut self) -> nb::Result<(), Self::Error> {
+        let buffer = &self.pipe.write_buffer;
+        if buffer.deliver_on_flush() || buffer.is_flushed() {
+            Ok(())
+        } else {
+            Err(nb::Error::WouldBlock)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embedded_hal::serial::{Read, Write};
+
+    use super::*;
+
+    #[test]
+    fn test_nb_serial_pair() {
+        let (pipe1, pipe2) = MockPipe::pair(2);
+        let mut serial1 = NbSerial::new(pipe1);
+        let mut serial2 = NbSerial::new(pipe2);
+
+        assert!(matches!(serial2.read(), Err(nb::Error::WouldBlock)));
+
+        nb::block!(serial1.write(b'o')).unwrap();
+        nb::block!(serial1.write(b'k')).unwrap();
+
+        // The buffer is full now
+        assert!(matches!(serial1.write(b'!'), Err(nb::Error::WouldBlock)));
+        assert!(matches!(serial1.flush(), Err(nb::Error::WouldBlock)));
+
+        assert_eq!(nb::block!(serial2.read()).unwrap(), b'o');
+        assert_eq!(nb::block!(serial2.read()).unwrap(), b'k');
+
+        nb::block!(serial1.flush()).unwrap();
+    }
+
+    #[test]
+    fn test_nb_serial_end_of_stream() {
+        let (pipe1, pipe2) = MockPipe::pair(4);
+        pipe1.set_ack_mode(true);
+        let mut serial1 = NbSerial::new(pipe1);
+        let mut serial2 = NbSerial::new(pipe2);
+
+        // Flushing waits for the data to be acknowledged
+        nb::block!(serial1.write(b'x')).unwrap();
+        assert_eq!(nb::block!(serial2.read()).unwrap(), b'x');
+        assert!(matches!(serial1.flush(), Err(nb::Error::WouldBlock)));
+        serial2.pipe().ack(1);
+        nb::block!(serial1.flush()).unwrap();
+
+        serial1.pipe().close();
+        match nb::block!(serial2.read()) {
+            Err(err) => assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof),
+            Ok(byte) => panic!("unexpected byte {}", byte),
+        }
+    }
+
+    #[test]
+    fn test_nb_serial_ignores_timeout() {
+        let pipe = MockPipe::loopback(1).with_timeout(None);
+        let mut serial = NbSerial::new(pipe);
+
+        // Must not block even though the pipe itself blocks indefinitely
+        assert!(matches!(serial.read(), Err(nb::Error::WouldBlock)));
+    }
+}
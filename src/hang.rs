@@ -0,0 +1,23 @@
+//! Detection of the operations hanging a test.
+
+use std::{thread, time::Duration};
+
+/// Reports the calling thread as blocked for `waited`, as a warning with the
+/// `log` feature, or on the standard error otherwise.
+pub(crate) fn report_wait(waited: Duration) {
+    let thread = thread::current();
+    let name = thread.name().unwrap_or("<unnamed>");
+
+    #[cfg(feature = "log")]
+    log::warn!(
+        "thread '{}' blocked on a pipe for {:?}, possibly hanging",
+        name,
+        waited
+    );
+
+    #[cfg(not(feature = "log"))]
+    eprintln!(
+        "mockpipe: thread '{}' blocked on a pipe for {:?}, possibly hanging",
+        name, waited
+    );
+}
@@ -0,0 +1,198 @@
+//! Hex formatting of transferred data for logs and failure messages.
+
+use std::fmt::Write as _;
+
+/// Number of bytes per hexdump line.
+const BYTES_PER_LINE: usize = 16;
+
+/// Number of bytes per line and side of a hex diff.
+const BYTES_PER_DIFF_LINE: usize = 8;
+
+/// Number of hex diff lines shown before and after the first difference.
+const DIFF_CONTEXT_LINES: usize = 3;
+
+/// Width of the offset column of a hex diff, including the difference marker.
+const DIFF_OFFSET_WIDTH: usize = 7;
+
+/// Width of one side of a hex diff.
+const DIFF_SIDE_WIDTH: usize = BYTES_PER_DIFF_LINE * 4 + 3;
+
+/// Formats data as a hexdump with offsets, hex bytes and printable ASCII
+/// characters.
+pub(crate) fn hexdump(data: &[u8]) -> String {
+    let mut dump = String::new();
+
+    for (line, chunk) in data.chunks(BYTES_PER_LINE).enumerate() {
+        if line > 0 {
+            dump.push('\n');
+        }
+
+        write!(dump, "{:04x} ", line * BYTES_PER_LINE).unwrap();
+
+        for i in 0..BYTES_PER_LINE {
+            match chunk.get(i) {
+                Some(byte) => write!(dump, " {:02x}", byte).unwrap(),
+                None => dump.push_str("   "),
+            }
+        }
+
+        dump.push_str("  |");
+        dump.extend(chunk.iter().map(|&byte| printable(byte)));
+        dump.push('|');
+    }
+
+    dump
+}
+
+/// Returns the offset of the first byte that differs between `a` and `b`, if
+/// they are not equal.
+pub(crate) fn first_difference(a: &[u8], b: &[u8]) -> Option<usize> {
+    a.iter()
+        .zip(b)
+        .position(|(x, y)| x != y)
+        .or_else(|| (a.len() != b.len()).then(|| a.len().min(b.len())))
+}
+
+/// Formats a side-by-side hex and ASCII diff of the expected and the actual
+/// data around their first difference.
+///
+/// Lines which differ are marked with `!`, and the first divergent byte is
+/// pointed at with carets.
+pub(crate) fn hex_diff(expected: &[u8], actual: &[u8]) -> String {
+    let offset = match first_difference(expected, actual) {
+        Some(offset) => offset,
+        None => return format!("no difference ({} bytes)", expected.len()),
+    };
+
+    let mut diff = format!(
+        "first difference at offset {:#06x} ({} bytes expected, {} bytes actual)\n",
+        offset,
+        expected.len(),
+        actual.len()
+    );
+
+    let lines = (expected.len().max(actual.len()) + BYTES_PER_DIFF_LINE - 1) / BYTES_PER_DIFF_LINE;
+    let first_line = offset / BYTES_PER_DIFF_LINE;
+    let start = first_line.saturating_sub(DIFF_CONTEXT_LINES);
+    let end = lines.min(first_line + DIFF_CONTEXT_LINES + 1);
+
+    writeln!(
+        diff,
+        "{:offset_width$}{:side_width$}  actual",
+        "",
+        "expected",
+        offset_width = DIFF_OFFSET_WIDTH,
+        side_width = DIFF_SIDE_WIDTH
+    )
+    .unwrap();
+
+    if start > 0 {
+        diff.push_str("...\n");
+    }
+
+    for line in start..end {
+        let expected_chunk = diff_chunk(expected, line);
+        let actual_chunk = diff_chunk(actual, line);
+
+        let marker = if expected_chunk == actual_chunk {
+            ' '
+        } else {
+            '!'
+        };
+
+        let diff_line = format!(
+            "{:04x}{}  {}  {}",
+            line * BYTES_PER_DIFF_LINE,
+            marker,
+            diff_side(expected_chunk),
+            diff_side(actual_chunk)
+        );
+        diff.push_str(diff_line.trim_end());
+        diff.push('\n');
+
+        if line == first_line {
+            let column = DIFF_OFFSET_WIDTH + (offset % BYTES_PER_DIFF_LINE) * 3;
+            writeln!(
+                diff,
+                "{:column$}^^{:gap$}^^",
+                "",
+                "",
+                column = column,
+                gap = DIFF_SIDE_WIDTH
+            )
+            .unwrap();
+        }
+    }
+
+    if end < lines {
+        diff.push_str("...\n");
+    }
+
+    diff.pop();
+    diff
+}
+
+/// Returns the bytes of `data` on the given hex diff line.
+fn diff_chunk(data: &[u8], line: usize) -> &[u8] {
+    let start = (line * BYTES_PER_DIFF_LINE).min(data.len());
+    &data[start..data.len().min(start + BYTES_PER_DIFF_LINE)]
+}
+
+/// Formats one side of a hex diff line, padded to a fixed width.
+fn diff_side(chunk: &[u8]) -> String {
+    let mut side = String::with_capacity(DIFF_SIDE_WIDTH);
+
+    for i in 0..BYTES_PER_DIFF_LINE {
+        match chunk.get(i) {
+            Some(byte) => write!(side, "{:02x} ", byte).unwrap(),
+            None => side.push_str("   "),
+        }
+    }
+
+    side.push_str(" |");
+    side.extend(chunk.iter().map(|&byte| printable(byte)));
+    side.push('|');
+
+    format!("{:width$}", side, width = DIFF_SIDE_WIDTH)
+}
+
+/// Returns the character representing a byte in the ASCII column.
+fn printable(byte: u8) -> char {
+    if byte.is_ascii_graphic() || byte == b' ' {
+        byte as char
+    } else {
+        '.'
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hexdump() {
+        assert_eq!(
+            hexdump(b"hello, world!\r\n\x00\x01"),
+            "0000  68 65 6c 6c 6f 2c 20 77 6f 72 6c 64 21 0d 0a 00  |hello, world!...|\n\
+             0010  01                                               |.|"
+        );
+    }
+
+    #[test]
+    fn test_hex_diff() {
+        assert_eq!(first_difference(b"abc", b"abd"), Some(2));
+        assert_eq!(first_difference(b"abc", b"abcd"), Some(3));
+        assert_eq!(first_difference(b"abc", b"abc"), None);
+
+        assert_eq!(
+            hex_diff(b"hello, world!", b"hello, World"),
+            concat!(
+                "first difference at offset 0x0007 (13 bytes expected, 12 bytes actual)\n",
+                "       expected                             actual\n",
+                "0000!  68 65 6c 6c 6f 2c 20 77  |hello, w|  68 65 6c 6c 6f 2c 20 57  |hello, W|\n",
+                "                            ^^                                   ^^\n",
+                "0008!  6f 72 6c 64 21           |orld!|     6f 72 6c 64              |orld|",
+            )
+        );
+    }
+}
@@ -0,0 +1,766 @@
+//! End-to-end integrity checking of the data sent through a pipe.
+//!
+//! An [`IntegrityWriter`] tags every write with a sequence number and
+//! checksums, and an [`IntegrityReader`] on the other end verifies them,
+//! passing only the intact data on and reporting exactly where data was lost,
+//! corrupted or reordered on the way. This makes soak tests combining several
+//! fault injectors (see [`link`](crate::link)) self-checking:
+//!
+//! ```
+//! use std::io::{Read, Write};
+//!
+//! use mockpipe::{
+//!     integrity::{IntegrityFault, IntegrityReader, IntegrityWriter},
+//!     link::LinkConfig,
+//!     Direction, MockPipe,
+//! };
+//!
+//! let (host, device) = MockPipe::pair(1024);
+//! host.set_link(Direction::Outbound, LinkConfig::new().with_loss(1.0));
+//!
+//! let mut writer = IntegrityWriter::new(host.clone());
+//! let mut reader = IntegrityReader::new(device);
+//!
+//! writer.write_all(b"lost").unwrap();
+//! host.set_link(Direction::Outbound, LinkConfig::new());
+//! writer.write_all(b"kept").unwrap();
+//! host.close();
+//!
+//! let mut received = Vec::new();
+//! reader.read_to_end(&mut received).unwrap();
+//! assert_eq!(received, b"kept");
+//! assert_eq!(
+//!     reader.faults(),
+//!     &[IntegrityFault::Lost { sequence: 0, count: 1, offset: 0 }]
+//! );
+//! ```
+//!
+//! Each write becomes a frame: a magic number, the sequence number, the length
+//! of the data, a header checksum and a data checksum, followed by up to
+//! [`MAX_FRAME_DATA`] bytes of data. The reader skips invalid frames, resyncing
+//! on the next valid one. The frames are encoded by a [`FrameEncoder`] and
+//! verified by a [`FrameDecoder`], which can also be used on their own, e.g.
+//! over other transports; both reuse their buffers, so frame-heavy soak tests
+//! do not allocate per frame.
+//!
+//! A [`Sequenced`] wrapper combines both ends over any endpoint, a `MockPipe`
+//! as well as a real connection, so protocol code reading and writing through
+//! it transparently gets its chunks tagged and verified in both directions.
+//! The integrity writer and reader are its one-way forms for pipe endpoints.
+
+use std::{
+    io::{self, Read, Write},
+    time::Duration,
+};
+
+use crate::{MockPipe, Transport};
+
+/// Maximum number of data bytes carried by a frame.
+pub const MAX_FRAME_DATA: usize = 4096;
+
+/// Marker starting every frame.
+const MAGIC: [u8; 2] = [0xa5, 0x5a];
+
+/// Size of the frame header: magic, sequence number, data length, header
+/// checksum and data checksum.
+const HEADER_LEN: usize = 14;
+
+/// A problem detected by an [`IntegrityReader`]. Offsets are positions in the
+/// raw received stream, frame headers included.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum IntegrityFault {
+    /// `count` frames starting at sequence number `sequence` never arrived,
+    /// as found at the frame received at `offset`.
+    Lost {
+        /// Sequence number of the first missing frame.
+        sequence: u32,
+        /// Number of missing frames.
+        count: u32,
+        /// Offset of the frame received after the missing ones.
+        offset: u64,
+    },
+
+    /// The frame with sequence number `sequence`, received at `offset`,
+    /// arrived after a later one (or twice).
+    OutOfOrder {
+        /// Sequence number of the frame.
+        sequence: u32,
+        /// Offset of the frame.
+        offset: u64,
+    },
+
+    /// The `len` bytes received at `offset` did not form a valid frame, having
+    /// been corrupted or truncated, and were skipped.
+    Corrupted {
+        /// Offset of the first skipped byte.
+        offset: u64,
+        /// Number of skipped bytes.
+        len: u64,
+    },
+}
+
+/// Encoder of the frames of an integrity-checked stream.
+///
+/// The frames are built in a scratch buffer reused across calls, so encoding
+/// does not allocate once the buffer has grown to the largest frame.
+#[derive(Clone, Debug, Default)]
+pub struct FrameEncoder {
+    sequence: u32,
+
+    /// Last encoded frame.
+    frame: Vec<u8>,
+}
+
+impl FrameEncoder {
+    /// Creates an encoder starting at sequence number zero.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the sequence number of the next frame.
+    pub fn sequence(&self) -> u32 {
+        self.sequence
+    }
+
+    /// Encodes up to [`MAX_FRAME_DATA`] bytes of `data` into the next frame,
+    /// returning it. The frame is only valid until the next call.
+    pub fn encode(&mut self, data: &[u8]) -> &[u8] {
+        let data = &data[0..data.len().min(MAX_FRAME_DATA)];
+
+        self.frame.clear();
+        write_frame(&mut self.frame, self.sequence, data);
+        self.sequence = self.sequence.wrapping_add(1);
+
+        &self.frame
+    }
+}
+
+/// Decoder of the frames of an integrity-checked stream, verifying them.
+///
+/// The received data is parsed in a scratch buffer reused across calls, so
+/// decoding does not allocate once the buffer has grown to the largest chunk
+/// of data pending. The detected faults are collected, in the order they were
+/// found, and available through [`FrameDecoder::faults`].
+#[derive(Clone, Debug, Default)]
+pub struct FrameDecoder {
+    /// Received data, parsed up to `start`.
+    raw: Vec<u8>,
+
+    /// Position in `raw` of the first byte not parsed yet.
+    start: usize,
+
+    /// Offset of the first byte not parsed yet in the received stream.
+    offset: u64,
+
+    /// Sequence number of the next expected frame.
+    expected: u32,
+
+    /// Number of valid frames received.
+    frames: u64,
+
+    /// Offset and length of the invalid data being skipped.
+    skipped: Option<(u64, u64)>,
+
+    faults: Vec<IntegrityFault>,
+}
+
+impl FrameDecoder {
+    /// Creates a decoder expecting sequence number zero first.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the faults detected so far.
+    pub fn faults(&self) -> &[IntegrityFault] {
+        &self.faults
+    }
+
+    /// Returns `true` if no fault was detected so far.
+    pub fn is_intact(&self) -> bool {
+        self.faults.is_empty()
+    }
+
+    /// Returns the number of valid frames received so far.
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// Decodes the next chunk of the received stream, appending the data of
+    /// the valid frames completed by it to `data`.
+    pub fn decode(&mut self, raw: &[u8], data: &mut Vec<u8>) {
+        // Drop the data parsed by the previous calls, keeping the allocation
+        self.raw.drain(0..self.start);
+        self.start = 0;
+
+        self.raw.extend_from_slice(raw);
+        self.parse(data);
+    }
+
+    /// Ends the stream, reporting the remains of an incomplete frame as
+    /// corrupted.
+    pub fn finish(&mut self) {
+        let len = self.pending().len();
+        self.skip(len);
+        self.end_skip();
+    }
+
+    /// Returns the received data not parsed yet.
+    fn pending(&self) -> &[u8] {
+        &self.raw[self.start..]
+    }
+
+    /// Parses the received data into frames, until more data is needed.
+    fn parse(&mut self, data: &mut Vec<u8>) {
+        loop {
+            let start = find_magic(self.pending());
+            self.skip(start);
+
+            let pending = self.pending();
+            if pending.len() < HEADER_LEN {
+                return;
+            }
+
+            let header = &pending[0..HEADER_LEN];
+            let sequence = u32::from_le_bytes(header[2..6].try_into().unwrap());
+            let len = u16::from_le_bytes(header[6..8].try_into().unwrap()) as usize;
+            let header_check = u16::from_le_bytes(header[8..10].try_into().unwrap());
+            let data_check = u32::from_le_bytes(header[10..14].try_into().unwrap());
+
+            if (header_check != checksum(&header[2..8]) as u16) || (len > MAX_FRAME_DATA) {
+                self.skip(1);
+                continue;
+            }
+
+            let frame_len = HEADER_LEN + len;
+            if pending.len() < frame_len {
+                return;
+            }
+
+            if data_check != checksum(&pending[HEADER_LEN..frame_len]) {
+                self.skip(1);
+                continue;
+            }
+
+            data.extend_from_slice(&pending[HEADER_LEN..frame_len]);
+            self.end_skip();
+            self.check_sequence(sequence);
+            self.frames += 1;
+            self.consume(frame_len);
+        }
+    }
+
+    /// Checks the sequence number of a valid frame starting at `offset`.
+    fn check_sequence(&mut self, sequence: u32) {
+        let offset = self.offset;
+
+        match sequence.wrapping_sub(self.expected) as i32 {
+            0 => {}
+            ahead if ahead > 0 => self.faults.push(IntegrityFault::Lost {
+                sequence: self.expected,
+                count: ahead as u32,
+                offset,
+            }),
+            _ => {
+                self.faults
+                    .push(IntegrityFault::OutOfOrder { sequence, offset });
+                return;
+            }
+        }
+
+        self.expected = sequence.wrapping_add(1);
+    }
+
+    /// Skips the next `n` bytes as invalid.
+    fn skip(&mut self, n: usize) {
+        if n == 0 {
+            return;
+        }
+
+        match &mut self.skipped {
+            Some((_, len)) => *len += n as u64,
+            None => self.skipped = Some((self.offset, n as u64)),
+        }
+        self.consume(n);
+    }
+
+    /// Reports the invalid data skipped so far.
+    fn end_skip(&mut self) {
+        if let Some((offset, len)) = self.skipped.take() {
+            self.faults.push(IntegrityFault::Corrupted { offset, len });
+        }
+    }
+
+    /// Marks the next `n` bytes as parsed.
+    fn consume(&mut self, n: usize) {
+        self.start += n;
+        self.offset += n as u64;
+    }
+}
+
+/// Writing end of an integrity-checked stream, wrapping a pipe endpoint.
+///
+/// Every write sends one frame, carrying up to [`MAX_FRAME_DATA`] bytes. A
+/// frame is written whole, waiting for room in the pipe regardless of its
+/// timeout, so that the other end can tell a partial frame from a damaged one.
+pub struct IntegrityWriter {
+    inner: Sequenced<BlockingWriter>,
+}
+
+impl IntegrityWriter {
+    /// Wraps the pipe endpoint, starting at sequence number zero.
+    pub fn new(pipe: MockPipe) -> Self {
+        Self {
+            inner: Sequenced::new(BlockingWriter(pipe)),
+        }
+    }
+
+    /// Returns a reference to the wrapped pipe endpoint.
+    pub fn pipe(&self) -> &MockPipe {
+        &self.inner.get_ref().0
+    }
+
+    /// Consumes the writer, returning the wrapped pipe endpoint.
+    pub fn into_inner(self) -> MockPipe {
+        self.inner.into_inner().0
+    }
+
+    /// Returns the sequence number of the next frame.
+    pub fn sequence(&self) -> u32 {
+        self.inner.sequence()
+    }
+}
+
+impl Write for IntegrityWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Pipe endpoint whose writes wait for room regardless of its timeout.
+struct BlockingWriter(MockPipe);
+
+impl Write for BlockingWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write_with_timeout(buf, None)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+/// Reading end of an integrity-checked stream, wrapping a pipe endpoint.
+///
+/// Reads return the data of the valid frames only, waiting according to the
+/// pipe timeout. The detected faults are collected, in the order they were
+/// found, and available through [`IntegrityReader::faults`].
+pub struct IntegrityReader {
+    inner: Sequenced<MockPipe>,
+}
+
+impl IntegrityReader {
+    /// Wraps the pipe endpoint, expecting sequence number zero first.
+    pub fn new(pipe: MockPipe) -> Self {
+        Self {
+            inner: Sequenced::new(pipe),
+        }
+    }
+
+    /// Returns a reference to the wrapped pipe endpoint.
+    pub fn pipe(&self) -> &MockPipe {
+        self.inner.get_ref()
+    }
+
+    /// Consumes the reader, returning the wrapped pipe endpoint.
+    pub fn into_inner(self) -> MockPipe {
+        self.inner.into_inner()
+    }
+
+    /// Returns the faults detected so far.
+    pub fn faults(&self) -> &[IntegrityFault] {
+        self.inner.faults()
+    }
+
+    /// Returns `true` if no fault was detected so far.
+    pub fn is_intact(&self) -> bool {
+        self.inner.is_intact()
+    }
+
+    /// Returns the number of valid frames received so far.
+    pub fn frames(&self) -> u64 {
+        self.inner.frames()
+    }
+}
+
+impl Read for IntegrityReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+
+        // The remains of an incomplete frame at the end of stream
+        if (n == 0) && !buf.is_empty() && self.inner.get_ref().read_buffer.is_closed() {
+            self.inner.finish();
+        }
+
+        Ok(n)
+    }
+}
+
+/// Sequence-tagging wrapper of a bidirectional endpoint, such as a pipe
+/// endpoint, a [`Transport`] or any `Read` and `Write`
+/// implementation.
+///
+/// Every write sends one frame, carrying up to [`MAX_FRAME_DATA`] bytes, and
+/// reads return the data of the valid frames only, so the wrapper can stand
+/// in for the endpoint in protocol code on both ends of a faulty link. The
+/// gaps and reorderings of the received frames are collected with their
+/// sequence numbers and offsets, in the order they were found, and available
+/// through [`Sequenced::faults`].
+///
+/// ```
+/// use std::io::{Read, Write};
+///
+/// use mockpipe::{
+///     integrity::{IntegrityFault, Sequenced},
+///     link::LinkConfig,
+///     Direction, MockPipe,
+/// };
+///
+/// let (host, device) = MockPipe::pair(1024);
+/// let mut host = Sequenced::new(host);
+/// let mut device = Sequenced::new(device);
+///
+/// host.write_all(b"ping").unwrap();
+/// host.get_ref().set_link(Direction::Outbound, LinkConfig::new().with_loss(1.0));
+/// host.write_all(b"lost").unwrap();
+/// host.get_ref().set_link(Direction::Outbound, LinkConfig::new());
+/// host.write_all(b"pong").unwrap();
+///
+/// let mut received = [0u8; 8];
+/// device.read_exact(&mut received).unwrap();
+/// assert_eq!(&received, b"pingpong");
+/// assert_eq!(
+///     device.faults(),
+///     &[IntegrityFault::Lost { sequence: 1, count: 1, offset: 18 }]
+/// );
+/// ```
+///
+/// A frame is written with `write_all`: if the endpoint fails in the middle of
+/// it, e.g. with a timeout, the other end reports the part sent as corrupted.
+pub struct Sequenced<T> {
+    inner: T,
+    encoder: FrameEncoder,
+    decoder: FrameDecoder,
+
+    /// Data of the valid frames not read yet, from `start`.
+    data: Vec<u8>,
+    start: usize,
+}
+
+impl<T> Sequenced<T> {
+    /// Wraps the endpoint, starting at sequence number zero in both
+    /// directions.
+    pub fn new(inner: T) -> Self {
+        Self {
+            inner,
+            encoder: FrameEncoder::new(),
+            decoder: FrameDecoder::new(),
+            data: Vec::new(),
+            start: 0,
+        }
+    }
+
+    /// Returns a reference to the wrapped endpoint.
+    pub fn get_ref(&self) -> &T {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the wrapped endpoint. Reading or writing
+    /// through it directly desynchronizes the streams.
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+
+    /// Consumes the wrapper, returning the wrapped endpoint.
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+
+    /// Returns the sequence number of the next frame sent.
+    pub fn sequence(&self) -> u32 {
+        self.encoder.sequence()
+    }
+
+    /// Returns the number of valid frames received so far.
+    pub fn frames(&self) -> u64 {
+        self.decoder.frames()
+    }
+
+    /// Returns the faults detected in the received frames so far.
+    pub fn faults(&self) -> &[IntegrityFault] {
+        self.decoder.faults()
+    }
+
+    /// Returns `true` if no fault was detected in the received frames so far.
+    pub fn is_intact(&self) -> bool {
+        self.decoder.is_intact()
+    }
+
+    /// Ends the received stream, reporting the remains of an incomplete frame
+    /// as corrupted. Reads returning `Ok(0)` do not end it, as pipes also
+    /// return it when woken up by a clear.
+    pub fn finish(&mut self) {
+        self.decoder.finish();
+    }
+}
+
+impl<T: Read> Read for Sequenced<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let pending = &self.data[self.start..];
+            if !pending.is_empty() || buf.is_empty() {
+                let n = buf.len().min(pending.len());
+                buf[0..n].copy_from_slice(&pending[0..n]);
+                self.start += n;
+                return Ok(n);
+            }
+
+            self.data.clear();
+            self.start = 0;
+
+            let mut chunk = [0u8; 1024];
+            match self.inner.read(&mut chunk)? {
+                0 => return Ok(0),
+                n => self.decoder.decode(&chunk[0..n], &mut self.data),
+            }
+        }
+    }
+}
+
+impl<T: Write> Write for Sequenced<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let frame = self.encoder.encode(buf);
+        self.inner.write_all(frame)?;
+        Ok(frame.len() - HEADER_LEN)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<T: Transport> Transport for Sequenced<T> {
+    fn set_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        self.inner.set_timeout(timeout)
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        self.inner.close()
+    }
+
+    fn is_end_of_stream(&self) -> bool {
+        self.inner.is_end_of_stream()
+    }
+}
+
+/// Appends a frame carrying `data` with the given sequence number to `frame`.
+fn write_frame(frame: &mut Vec<u8>, sequence: u32, data: &[u8]) {
+    let start = frame.len();
+    frame.extend_from_slice(&MAGIC);
+    frame.extend_from_slice(&sequence.to_le_bytes());
+    frame.extend_from_slice(&(data.len() as u16).to_le_bytes());
+    let header_check = checksum(&frame[(start + 2)..(start + 8)]) as u16;
+    frame.extend_from_slice(&header_check.to_le_bytes());
+    frame.extend_from_slice(&checksum(data).to_le_bytes());
+    frame.extend_from_slice(data);
+}
+
+/// Returns the number of bytes before the first possible frame start.
+fn find_magic(raw: &[u8]) -> usize {
+    match raw.windows(2).position(|window| window == MAGIC) {
+        Some(start) => start,
+        // The last byte may start a frame
+        None if raw.last() == Some(&MAGIC[0]) => raw.len() - 1,
+        None => raw.len(),
+    }
+}
+
+/// Computes the 32-bit FNV-1a hash of the data.
+fn checksum(data: &[u8]) -> u32 {
+    data.iter().fold(0x811c_9dc5, |hash, &byte| {
+        (hash ^ u32::from(byte)).wrapping_mul(0x0100_0193)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{link::LinkConfig, Direction};
+
+    #[test]
+    fn test_integrity_faults() {
+        let (host, device) = MockPipe::pair(1 << 16);
+        host.set_link(
+            Direction::Outbound,
+            LinkConfig::new()
+                .with_loss(0.05)
+                .with_deletion(0.002)
+                .with_corruption(0.002)
+                .with_seed(3),
+        );
+
+        let mut writer = IntegrityWriter::new(host.clone());
+        for frame in 0..200u32 {
+            writer.write_all(&[frame as u8; 32]).unwrap();
+        }
+        assert_eq!(writer.sequence(), 200);
+        host.close();
+
+        let mut reader = IntegrityReader::new(device);
+        let mut received = Vec::new();
+        reader.read_to_end(&mut received).unwrap();
+
+        // Only whole, intact frames are passed on
+        assert!(!reader.is_intact());
+        assert_eq!(received.len() as u64, reader.frames() * 32);
+        assert!(received
+            .chunks(32)
+            .all(|frame| frame.iter().all(|&byte| byte == frame[0])));
+
+        let lost: u32 = reader
+            .faults()
+            .iter()
+            .map(|fault| match fault {
+                IntegrityFault::Lost { count, .. } => *count,
+                _ => 0,
+            })
+            .sum();
+        assert!(reader
+            .faults()
+            .iter()
+            .any(|fault| matches!(fault, IntegrityFault::Corrupted { .. })));
+        assert!(reader.frames() + u64::from(lost) <= 200);
+    }
+
+    #[test]
+    fn test_frame_codec() {
+        let mut encoder = FrameEncoder::new();
+        let mut stream = Vec::new();
+        let mut scratch = None;
+
+        for frame in 0..100u32 {
+            let encoded = encoder.encode(&[frame as u8; 64]);
+            stream.extend_from_slice(encoded);
+
+            // The scratch buffer is reused
+            assert_eq!(*scratch.get_or_insert(encoded.as_ptr()), encoded.as_ptr());
+        }
+        assert_eq!(encoder.sequence(), 100);
+
+        let mut decoder = FrameDecoder::new();
+        let mut data = Vec::new();
+        for chunk in stream.chunks(100) {
+            decoder.decode(chunk, &mut data);
+        }
+        decoder.decode(&MAGIC, &mut data);
+        decoder.finish();
+
+        assert_eq!(decoder.frames(), 100);
+        assert_eq!(data.len(), 6400);
+        assert_eq!(
+            decoder.faults(),
+            &[IntegrityFault::Corrupted {
+                offset: stream.len() as u64,
+                len: 2
+            }]
+        );
+    }
+
+    #[test]
+    fn test_sequenced() {
+        // Frames tagged over any writer, reordered on the way
+        let mut sender = Sequenced::new(Vec::new());
+        for chunk in [&b"one"[..], b"two", b"three"] {
+            sender.write_all(chunk).unwrap();
+        }
+        assert_eq!(sender.sequence(), 3);
+
+        let stream = sender.into_inner();
+        let reordered = [&stream[0..17], &stream[34..], &stream[17..34]].concat();
+
+        let mut receiver = Sequenced::new(&reordered[..]);
+        let mut received = String::new();
+        receiver.read_to_string(&mut received).unwrap();
+        receiver.finish();
+
+        assert_eq!(received, "onethreetwo");
+        assert_eq!(receiver.frames(), 3);
+        assert_eq!(
+            receiver.faults(),
+            &[
+                IntegrityFault::Lost {
+                    sequence: 1,
+                    count: 1,
+                    offset: 17
+                },
+                IntegrityFault::OutOfOrder {
+                    sequence: 1,
+                    offset: 36
+                },
+            ]
+        );
+
+        // Both directions of a pipe
+        let (host, device) = MockPipe::pair(1024);
+        let (mut host, mut device) = (Sequenced::new(host), Sequenced::new(device));
+        host.write_all(b"ping").unwrap();
+        device.write_all(b"pong").unwrap();
+
+        let mut reply = [0u8; 4];
+        device.read_exact(&mut reply).unwrap();
+        assert_eq!(&reply, b"ping");
+        host.read_exact(&mut reply).unwrap();
+        assert_eq!(&reply, b"pong");
+        assert!(host.is_intact() && device.is_intact());
+    }
+
+    #[test]
+    fn test_integrity_out_of_order() {
+        let (mut host, device) = MockPipe::pair(1024);
+        let mut reader = IntegrityReader::new(device);
+
+        let mut frames = Vec::new();
+        write_frame(&mut frames, 0, b"a");
+        write_frame(&mut frames, 2, b"c");
+        write_frame(&mut frames, 1, b"b");
+        host.write_all(&frames).unwrap();
+        host.write_all(&[0x00, 0xa5]).unwrap();
+        host.close();
+
+        let mut received = Vec::new();
+        reader.read_to_end(&mut received).unwrap();
+        assert_eq!(received, b"acb");
+        assert_eq!(
+            reader.faults(),
+            &[
+                IntegrityFault::Lost {
+                    sequence: 1,
+                    count: 1,
+                    offset: 15
+                },
+                IntegrityFault::OutOfOrder {
+                    sequence: 1,
+                    offset: 30
+                },
+                IntegrityFault::Corrupted { offset: 45, len: 2 },
+            ]
+        );
+    }
+}
@@ -20,167 +20,232 @@
 //!
 //! assert_eq!(&read_data, write_data);
 //! ```
+//!
+//! # Optional features
+//!
+//! - `std` (enabled by default): `MockPipe` and everything built on it. Without
+//!   it the crate is `no_std`, and only the `fixed` backend is available.
+//! - `bridge`: `bridge::to_windows_named_pipe` on Windows, exposing an
+//!   endpoint to native tooling as a named pipe server.
+//! - `critical-section`: `fixed::FixedMockPipe`, a fixed-capacity pipe
+//!   without heap allocation protected by `critical-section`, for bare-metal
+//!   targets, and `fixed::StaticMockPipe` backed by caller-provided static
+//!   storage.
+//! - `embedded-hal`: `hal::NbSerial` adapter implementing the `nb`-based
+//!   `embedded_hal::serial::{Read, Write}` traits.
+//! - `embedded-io`: `embedded_io::{Read, Write, ReadReady, WriteReady}`
+//!   implementations for `MockPipe`.
+//! - `embedded-io-async`: `embedded_io_async::{Read, Write}` implementations
+//!   for `MockPipe`, for testing async (e.g. Embassy-based) drivers.
+//! - `ffi`: `ffi` module with a minimal C API over `MockPipe` endpoints, for
+//!   C/C++ test harnesses.
+//! - `json`, `yaml`, `toml`: loading [`script::Script`]s from scenario files in
+//!   the corresponding format.
+//! - `mio`: `mio::event::Source` implementation for `MockPipe`, for testing
+//!   poll-based code with the pipe registered in a `mio::Poll`.
+//! - `proptest`: `strategy` module with `proptest` strategies generating
+//!   sequences of pipe operations.
+//! - `serde`: `serde::Serialize` implementation for [`stats::PipeStats`].
+//! - `shuttle`: `shuttle` synchronization primitives in the pipe buffers, for
+//!   testing reader/writer interleavings under shuttle's randomized scheduling.
+//! - `stats`: histograms of the operation sizes and wait durations in
+//!   [`stats::PipeStats`], and statistics per thread in `stats::ThreadStats`.
+//! - `tokio`: `tokio_serial::SerialStream`, an async serial port adapter
+//!   implementing `tokio::io::{AsyncRead, AsyncWrite}` with the
+//!   `tokio_serial::SerialStream` setters.
+//! - `trace`: `trace::TraceRecorder`, exporting the operations of pipes as a
+//!   Chrome tracing / Perfetto timeline.
+//! - `log`: debug-level hexdumps of the transferred data through the `log`
+//!   crate, enabled per pipe with `MockPipe::set_log_data`.
+
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
 
 // To run doc tests on examples from README.md and verify their correctness
-#[cfg(doctest)]
+#[cfg(all(doctest, feature = "std"))]
 #[doc = include_str!("../README.md")]
 struct ReadMe;
 
+#[cfg(feature = "std")]
 use std::{
-    collections::VecDeque,
-    io,
-    sync::{Arc, Condvar, Mutex, MutexGuard},
-    time::Duration,
+    io, mem,
+    sync::{mpsc::Receiver, Arc, Mutex},
+    time::{Duration, Instant},
 };
 
-/// A thread-safe circular buffer with synchronization primitives.
-struct SyncBuffer {
-    data: Mutex<VecDeque<u8>>,
-    can_read: Condvar,
-    can_write: Condvar,
+#[cfg(feature = "log")]
+use std::sync::atomic::{AtomicBool, Ordering};
+
+#[cfg(feature = "std")]
+use broadcast::BroadcastWriter;
+#[cfg(feature = "std")]
+use buffer::{SyncBuffer, WriterHandle};
+#[cfg(feature = "std")]
+use clock::Clock;
+#[cfg(feature = "std")]
+use event::{Hooks, PipeEvent, Subscribers};
+#[cfg(feature = "std")]
+use simplex::{MockReader, MockWriter};
+#[cfg(feature = "std")]
+use stats::{EndpointStats, OpTimer, PipeStats};
+
+#[cfg(feature = "std")]
+pub use bridge::copy_bidirectional;
+#[cfg(feature = "std")]
+pub use budget::MemoryBudget;
+#[cfg(feature = "std")]
+pub use builder::{MockPipeBuilder, Preset};
+#[cfg(feature = "std")]
+pub use error::Error;
+#[cfg(feature = "std")]
+pub use rng::{seed, set_seed, SEED_ENV_VAR};
+#[cfg(feature = "std")]
+pub use transport::Transport;
+
+#[cfg(feature = "std")]
+pub mod assert;
+#[cfg(feature = "std")]
+pub mod bridge;
+#[cfg(feature = "std")]
+pub mod broadcast;
+#[cfg(feature = "std")]
+mod budget;
+#[cfg(feature = "std")]
+mod buffer;
+#[cfg(feature = "std")]
+mod builder;
+#[cfg(feature = "std")]
+pub mod bus;
+#[cfg(feature = "std")]
+pub mod clock;
+#[cfg(feature = "std")]
+pub mod dma;
+#[cfg(feature = "embedded-io")]
+mod eio;
+#[cfg(feature = "embedded-io-async")]
+mod eio_async;
+#[cfg(feature = "std")]
+mod error;
+#[cfg(feature = "std")]
+pub mod event;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(feature = "std")]
+mod file;
+#[cfg(feature = "critical-section")]
+pub mod fixed;
+#[cfg(feature = "std")]
+pub mod golden;
+#[cfg(feature = "embedded-hal")]
+pub mod hal;
+#[cfg(feature = "std")]
+mod hang;
+#[cfg(feature = "std")]
+mod hex;
+#[cfg(feature = "std")]
+pub mod integrity;
+#[cfg(feature = "std")]
+pub mod link;
+#[cfg(feature = "log")]
+mod logging;
+#[cfg(feature = "std")]
+pub mod net;
+#[cfg(feature = "std")]
+mod oob;
+#[cfg(feature = "std")]
+pub mod pcap;
+#[cfg(feature = "std")]
+pub mod pipeline;
+#[cfg(feature = "mio")]
+mod poll;
+#[cfg(feature = "std")]
+pub mod probe;
+#[cfg(feature = "std")]
+pub mod record;
+#[cfg(feature = "std")]
+mod ring;
+#[cfg(feature = "std")]
+mod rng;
+#[cfg(feature = "std")]
+pub mod sampler;
+#[cfg(feature = "std")]
+pub mod script;
+#[cfg(feature = "std")]
+pub mod serial;
+#[cfg(feature = "std")]
+pub mod serial2;
+#[cfg(feature = "std")]
+mod shadow;
+#[cfg(feature = "std")]
+pub mod signal;
+#[cfg(feature = "std")]
+pub mod simplex;
+#[cfg(feature = "std")]
+pub mod source;
+#[cfg(feature = "std")]
+pub mod stats;
+#[cfg(feature = "proptest")]
+pub mod strategy;
+#[cfg(feature = "std")]
+pub mod stress;
+#[cfg(feature = "std")]
+mod sync;
+#[cfg(feature = "std")]
+pub mod text;
+#[cfg(feature = "std")]
+pub mod throttle;
+#[cfg(feature = "tokio")]
+pub mod tokio_serial;
+#[cfg(feature = "trace")]
+pub mod trace;
+#[cfg(feature = "std")]
+pub mod transport;
+#[cfg(feature = "std")]
+pub mod typed;
+
+/// Direction of a data transfer, relative to an endpoint.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Direction {
+    /// Data read from the endpoint.
+    Inbound,
+
+    /// Data written to the endpoint.
+    Outbound,
 }
 
-impl SyncBuffer {
-    /// Creates a new `SyncBuffer` with the specified capacity.
-    fn new(capacity: usize) -> Self {
-        SyncBuffer {
-            data: Mutex::new(VecDeque::with_capacity(capacity)),
-            can_read: Condvar::new(),
-            can_write: Condvar::new(),
-        }
-    }
-
-    /// Waits until the condition function returns false.
-    ///
-    /// If successful, returns a new locked guard to the data buffer.
-    /// If a timeout is specified, returns a `TimedOut` error if the condition
-    /// is not met within the timeout duration.
-    fn wait_while<'a, F>(
-        mut data_guard: MutexGuard<'a, VecDeque<u8>>,
-        condvar: &Condvar,
-        timeout: Option<Duration>,
-        condition: F,
-    ) -> io::Result<MutexGuard<'a, VecDeque<u8>>>
-    where
-        F: Fn(&mut VecDeque<u8>) -> bool,
-    {
-        if condition(&mut data_guard) {
-            data_guard = match timeout {
-                Some(Duration::ZERO) => data_guard,
-                Some(timeout) => {
-                    let (new_guard, timeout_result) = condvar
-                        .wait_timeout_while(data_guard, timeout, condition)
-                        .map_err(|_| io::Error::from(io::ErrorKind::Other))?;
-
-                    if timeout_result.timed_out() {
-                        return Err(io::Error::from(io::ErrorKind::TimedOut));
-                    }
-
-                    new_guard
-                }
-                None => condvar
-                    .wait_while(data_guard, condition)
-                    .map_err(|_| io::Error::from(io::ErrorKind::Other))?,
-            };
-        }
-
-        Ok(data_guard)
-    }
-
-    /// Waits until the required number of bytes are available in the buffer for
-    /// reading or writing.
-    ///
-    /// If successful, returns a locked data guard and the number of bytes available.
-    /// If a timeout is specified, returns a `TimedOut` error if the required bytes
-    /// are not available within the timeout duration.
-    fn wait_for_bytes_available<F>(
-        &self,
-        bytes_required: usize,
-        condvar: &Condvar,
-        timeout: Option<Duration>,
-        get_bytes_available: F,
-    ) -> io::Result<(MutexGuard<VecDeque<u8>>, usize)>
-    where
-        F: Fn(&VecDeque<u8>) -> usize,
-    {
-        let mut data_guard = self.data.lock().unwrap();
-
-        if (bytes_required == 0) || (data_guard.capacity() == 0) {
-            return Ok((data_guard, 0));
-        }
-
-        data_guard = Self::wait_while(data_guard, condvar, timeout, |data| {
-            get_bytes_available(data) == 0
-        })?;
-
-        let bytes_available = bytes_required.min(get_bytes_available(&data_guard));
-
-        Ok((data_guard, bytes_available))
-    }
-
-    /// Reads data from the buffer.
-    ///
-    /// Blocks until the specified amount of data is available or the timeout is reached.
-    /// Returns the number of bytes read if successful.
-    fn read(&self, buf: &mut [u8], timeout: Option<Duration>) -> io::Result<usize> {
-        let (mut data_guard, bytes_to_read) =
-            self.wait_for_bytes_available(buf.len(), &self.can_read, timeout, |guard| guard.len())?;
-
-        if bytes_to_read > 0 {
-            for byte in &mut buf[0..bytes_to_read] {
-                *byte = data_guard.pop_front().unwrap();
-            }
-
-            // Notify the writer that space is available
-            self.can_write.notify_one();
-        }
-
-        Ok(bytes_to_read)
-    }
-
-    /// Writes data into the buffer.
-    ///
-    /// Blocks if there is not enough space until some space becomes available
-    /// or the timeout is reached. Returns the number of bytes written if successful.
-    fn write(&self, buf: &[u8], timeout: Option<Duration>) -> io::Result<usize> {
-        let (mut data_guard, bytes_to_write) =
-            self.wait_for_bytes_available(buf.len(), &self.can_write, timeout, |guard| {
-                guard.capacity() - guard.len()
-            })?;
-
-        if bytes_to_write > 0 {
-            data_guard.extend(&buf[0..bytes_to_write]);
-
-            // Notify the reader that data is available
-            self.can_read.notify_one();
-        }
-
-        Ok(bytes_to_write)
-    }
-
-    /// Waits until all data has been written from the buffer (blocks until the buffer is empty
-    /// or the operation times out, if a timeout is specified).
-    fn flush(&self, timeout: Option<Duration>) -> io::Result<()> {
-        // Wait until the write buffer is empty.
-        Self::wait_while(
-            self.data.lock().unwrap(),
-            &self.can_write,
-            timeout,
-            |data| !data.is_empty(),
-        )
-        .map(|_| ())
-    }
-
-    /// Clears the buffer, discarding all pending data and notifying waiting writers.
-    fn clear(&self) {
-        self.data.lock().unwrap().clear();
-        self.can_write.notify_all();
-    }
+/// Policy for interleaving the data of endpoint clones writing concurrently.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Interleaving {
+    /// Each write stores as much data as fits as soon as there is space, so a
+    /// write may be split by the writes of other clones.
+    #[default]
+    Arrival,
+
+    /// Each write waits for space for all its data (up to the buffer capacity)
+    /// and stores it in one piece, so a message written at once is never split
+    /// by other clones.
+    Atomic,
+
+    /// Writer threads waiting for space take turns: a thread that just wrote
+    /// queues behind the threads already waiting, so a fast writer can't starve
+    /// the others. Async writes are not queued.
+    RoundRobin,
+}
 
-    /// Returns the number of bytes available to read.
-    fn len(&self) -> usize {
-        self.data.lock().unwrap().len()
-    }
+/// How the readers blocked on an endpoint react to its read buffer being
+/// cleared.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum ClearWakeup {
+    /// The readers keep waiting for data, as if nothing happened, so clearing
+    /// is a silent reset.
+    #[default]
+    KeepWaiting,
+
+    /// The readers wake up with a `WouldBlock` error, so clearing aborts their
+    /// reads.
+    WouldBlock,
+
+    /// The readers wake up with `Ok(0)`, as at end of stream.
+    EndOfStream,
 }
 
 /// A bidirectional data pipe that exchanges datausing internal circular buffers.
@@ -190,6 +255,7 @@ impl SyncBuffer {
 /// This structure is intended for implementing virtual sockets, pipes, serial
 /// ports, or similar communication mechanisms, abstracting away the details of
 /// buffer management and synchronization.
+#[cfg(feature = "std")]
 #[derive(Clone)]
 pub struct MockPipe {
     /// Timeout duration for read and write operations.
@@ -199,21 +265,65 @@ pub struct MockPipe {
     /// - `Some(Duration)` sets a specific timeout duration.
     timeout: Arc<Mutex<Option<Duration>>>,
 
+    /// Clock the timeouts are measured on, created when first used.
+    clock: Arc<Mutex<Option<Clock>>>,
+
     /// Buffer used for reading data.
     read_buffer: Arc<SyncBuffer>,
 
     /// Buffer used for writing data.
     write_buffer: Arc<SyncBuffer>,
+
+    /// Registration of the endpoint as a writer into its write buffer, until
+    /// the endpoint and all its clones are dropped.
+    _writer: Arc<WriterHandle>,
+
+    /// Transfer statistics of the endpoint.
+    stats: Arc<Mutex<EndpointStats>>,
+
+    /// Subscribers to the events of the endpoint.
+    subscribers: Arc<Subscribers>,
+
+    /// Callbacks invoked with the transferred data.
+    hooks: Arc<Hooks>,
+
+    /// Whether the transferred data is logged.
+    #[cfg(feature = "log")]
+    log_data: Arc<AtomicBool>,
+
+    /// Shadow socket signaling the readiness of the endpoint, if enabled.
+    shadow: Arc<Mutex<Option<Arc<shadow::Shadow>>>>,
+
+    /// Readiness signal of the endpoint, if registered in a `mio::Poll`.
+    #[cfg(feature = "mio")]
+    mio_signal: Arc<Mutex<Option<Arc<poll::Signal>>>>,
+
+    /// Recorder of the operations of the endpoint, if attached to one.
+    #[cfg(feature = "trace")]
+    trace: Arc<Mutex<Option<trace::Target>>>,
 }
 
+#[cfg(feature = "std")]
 impl MockPipe {
     /// Creates a `MockPipe` instance from separate read and write buffers.
     fn from_buffers(read_buffer: Arc<SyncBuffer>, write_buffer: Arc<SyncBuffer>) -> Self {
         Self {
             // Non-blocking by default
             timeout: Arc::new(Mutex::new(Some(Duration::ZERO))),
+            clock: Arc::new(Mutex::new(None)),
             read_buffer,
+            _writer: Arc::new(write_buffer.register_writer()),
             write_buffer,
+            stats: Arc::new(Mutex::new(EndpointStats::default())),
+            subscribers: Arc::new(Subscribers::default()),
+            hooks: Arc::new(Hooks::default()),
+            #[cfg(feature = "log")]
+            log_data: Arc::new(AtomicBool::new(false)),
+            shadow: Arc::new(Mutex::new(None)),
+            #[cfg(feature = "mio")]
+            mio_signal: Arc::new(Mutex::new(None)),
+            #[cfg(feature = "trace")]
+            trace: Arc::new(Mutex::new(None)),
         }
     }
 
@@ -239,6 +349,72 @@ impl MockPipe {
         (pipe1, pipe2)
     }
 
+    /// Creates a linked pair of `MockPipe` instances like
+    /// [`pair`](MockPipe::pair), storing the buffered data in `storage`
+    /// instead of allocating it, so large buffers can live in the `static`
+    /// resources of RTIC or Embassy test firmware. The storage is split evenly
+    /// between the two directions.
+    ///
+    /// Only the data is stored there: the endpoints still allocate their
+    /// shared state. Without `std`, `fixed::StaticMockPipe` (with the
+    /// `critical-section` feature) uses static storage without any heap
+    /// allocation.
+    pub fn from_static(storage: &'static mut [u8]) -> (Self, Self) {
+        let (storage1, storage2) = storage.split_at_mut(storage.len() / 2);
+        let buffer1 = Arc::new(SyncBuffer::from_static(storage1));
+        let buffer2 = Arc::new(SyncBuffer::from_static(storage2));
+
+        let pipe1 = Self::from_buffers(buffer1.clone(), buffer2.clone());
+        let pipe2 = Self::from_buffers(buffer2, buffer1);
+
+        (pipe1, pipe2)
+    }
+
+    /// Creates a null sink: an endpoint accepting any amount of written data
+    /// right away and discarding it, while reads return end of stream, like
+    /// `/dev/null`. This spares tests of send-only components a thread
+    /// draining a real buffer.
+    pub fn null() -> Self {
+        Self::from_buffers(Arc::new(SyncBuffer::new(0)), Arc::new(SyncBuffer::null()))
+    }
+
+    /// Creates a broadcast: one writer endpoint and `readers` reader endpoints,
+    /// each receiving a full copy of the written data into its own buffer of
+    /// the specified capacity. This simulates fan-out links such as telemetry
+    /// feeds with several consumers.
+    ///
+    /// What happens when a reader falls behind is controlled per reader with
+    /// [`BroadcastWriter::set_backpressure`](broadcast::BroadcastWriter::set_backpressure).
+    /// The reader endpoints can't write: writing to them fails with `WriteZero`.
+    pub fn broadcast(buffer_capacity: usize, readers: usize) -> (BroadcastWriter, Vec<Self>) {
+        let buffers: Vec<_> = (0..readers)
+            .map(|_| Arc::new(SyncBuffer::new(buffer_capacity)))
+            .collect();
+
+        let no_write_buffer = Arc::new(SyncBuffer::new(0));
+
+        let pipes = buffers
+            .iter()
+            .map(|buffer| Self::from_buffers(buffer.clone(), no_write_buffer.clone()))
+            .collect();
+
+        (BroadcastWriter::new(buffers), pipes)
+    }
+
+    /// Creates a simplex pipe: a writing end and a reading end sharing one
+    /// buffer of the specified capacity, like `tokio::io::simplex`. This
+    /// simulates strictly one-directional links, such as a transmit-only
+    /// serial line, where the types rule out writing from the reading side.
+    pub fn simplex(buffer_capacity: usize) -> (MockWriter, MockReader) {
+        let buffer = Arc::new(SyncBuffer::new(buffer_capacity));
+        let no_buffer = Arc::new(SyncBuffer::new(0));
+
+        let writer = Self::from_buffers(no_buffer.clone(), buffer.clone());
+        let reader = Self::from_buffers(buffer, no_buffer);
+
+        (MockWriter::new(writer), MockReader::new(reader))
+    }
+
     /// Gets the current timeout duration for read/write operations.
     pub fn timeout(&self) -> Option<Duration> {
         *self.timeout.lock().unwrap()
@@ -259,50 +435,884 @@ impl MockPipe {
         self
     }
 
+    /// Reads until `buf` is filled, the timeout expires or the end of stream is
+    /// reached, returning the number of bytes read into `buf`. Unlike
+    /// `read_exact`, a timeout does not lose the bytes obtained before it,
+    /// which is what most serial port consumers want.
+    ///
+    /// The timeout applies to the whole operation, instead of the timeout of
+    /// the endpoint. `None` means waiting until `buf` is filled or the end of
+    /// stream is reached. Other errors, such as injected ones, are returned,
+    /// the bytes read before them being lost.
+    pub fn read_exact_or_partial(
+        &self,
+        buf: &mut [u8],
+        timeout: Option<Duration>,
+    ) -> Result<usize, Error> {
+        let deadline = self
+            .real_timeout(timeout)
+            .map(|timeout| Instant::now() + timeout);
+        let mut partial = 0;
+
+        while partial < buf.len() {
+            let timeout =
+                deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+
+            match self.read_with_real_timeout(&mut buf[partial..], timeout) {
+                // End of stream, or nothing to read right away
+                Ok(0) => break,
+                Ok(n) => partial += n,
+                Err(err) if err.kind() == io::ErrorKind::TimedOut => break,
+                Err(err) => return Err(Error::from_io(&err).unwrap_or(Error::Injected(err.kind()))),
+            }
+        }
+
+        Ok(partial)
+    }
+
+    /// Writes up to `limit` bytes of the data available to read straight from
+    /// the buffer into `writer`, without an intermediate buffer, e.g. to relay
+    /// the traffic of a mock into a file or a socket. Waits for data according
+    /// to the timeout of the endpoint, as a read does.
+    ///
+    /// Returns the number of bytes drained, which is the number of bytes
+    /// `writer` accepted: `0` at end of stream or if `writer` accepts nothing.
+    /// An error of `writer` is returned if it fails before accepting any
+    /// data, the data then staying in the buffer.
+    ///
+    /// The buffer stays locked while `writer` is called, so `writer` must not
+    /// read from this pipe, nor write into it in loopback mode.
+    pub fn drain_into<W: io::Write + ?Sized>(
+        &self,
+        writer: &mut W,
+        limit: usize,
+    ) -> io::Result<usize> {
+        let timer = OpTimer::start();
+
+        let result =
+            self.read_buffer
+                .drain_into(limit, self.real_timeout(self.timeout()), |chunk| {
+                    let n = writer.write(chunk)?;
+                    self.observe_read(&chunk[0..n]);
+                    Ok(n)
+                });
+
+        self.account_read(limit, &result, timer);
+        result
+    }
+
     /// Returns the number of bytes currently available to read from the buffer.
+    ///
+    /// The buffer is not locked, so polling the length in a loop does not slow
+    /// down concurrent transfers.
     pub fn read_buffer_len(&self) -> usize {
         self.read_buffer.len()
     }
 
-    /// Returns the number of bytes currently queued to write in the buffer.
-    pub fn write_buffer_len(&self) -> usize {
-        self.write_buffer.len()
+    /// Returns the number of bytes currently queued to write in the buffer,
+    /// without locking it either.
+    pub fn write_buffer_len(&self) -> usize {
+        self.write_buffer.len()
+    }
+
+    /// Returns `true` if the data currently available to read starts with
+    /// `prefix`. The data is not consumed, so tests can check for markers
+    /// without taking data the code under test still needs.
+    pub fn buffered_starts_with(&self, prefix: &[u8]) -> bool {
+        self.read_buffer.starts_with(prefix)
+    }
+
+    /// Returns `true` if the data currently available to read contains
+    /// `needle`, without consuming it.
+    pub fn buffered_contains(&self, needle: &[u8]) -> bool {
+        self.position_of(needle).is_some()
+    }
+
+    /// Returns the offset of the first occurrence of `needle` in the data
+    /// currently available to read, without consuming it.
+    pub fn position_of(&self, needle: &[u8]) -> Option<usize> {
+        self.read_buffer.position_of(needle)
+    }
+
+    /// Waits until the data written to the endpoint and not read yet by the
+    /// other end satisfies `predicate`, e.g. until it contains a terminator,
+    /// so tests can verify what a driver sent without sleep-and-check loops.
+    /// The data is not consumed. The predicate is called with all such data,
+    /// including the data in flight over a simulated link or staged (see
+    /// [`set_pump_mode`](MockPipe::set_pump_mode)), each time more arrives.
+    ///
+    /// `None` means waiting indefinitely. Returns a `TimedOut` error if the
+    /// predicate is not satisfied within the timeout, or a `BrokenPipe` error
+    /// if the pipe is closed first.
+    pub fn wait_until_written<F>(&self, timeout: Option<Duration>, predicate: F) -> io::Result<()>
+    where
+        F: FnMut(&[u8]) -> bool,
+    {
+        self.write_buffer
+            .wait_for_data(self.real_timeout(timeout), predicate)
+    }
+
+    /// Clears the read buffer, discarding all pending data.
+    pub fn clear_read(&self) {
+        self.read_buffer.clear();
+        self.subscribers.emit(PipeEvent::Cleared);
+    }
+
+    /// Clears the write buffer, discarding all pending data.
+    pub fn clear_write(&self) {
+        self.write_buffer.clear();
+        self.subscribers.emit(PipeEvent::Cleared);
+    }
+
+    /// Clears both read and write buffers, discarding all pending data.
+    pub fn clear(&self) {
+        self.read_buffer.clear();
+        self.write_buffer.clear();
+        self.subscribers.emit(PipeEvent::Cleared);
+    }
+
+    /// Closes the pipe in both directions, simulating a closed connection.
+    ///
+    /// Once the data already buffered has been consumed, reads on either end
+    /// return `Ok(0)` (end of stream) instead of blocking, and writes on either
+    /// end fail with a `BrokenPipe` error.
+    pub fn close(&self) {
+        self.read_buffer.close();
+        self.write_buffer.close();
+        self.subscribers.emit(PipeEvent::Closed);
+    }
+
+    /// Returns `true` if the pipe was closed (by either end).
+    pub fn is_closed(&self) -> bool {
+        self.read_buffer.is_closed() || self.write_buffer.is_closed()
+    }
+
+    /// Injects an error into the outgoing data stream: once the data written so
+    /// far has been read, the next read on the other end (or on this end in
+    /// loopback mode) fails with an error of the given kind. Reads after that
+    /// proceed normally.
+    pub fn inject_error(&self, kind: io::ErrorKind) {
+        self.write_buffer.inject_error(kind);
+    }
+
+    /// Enables or disables the pump mode, for deterministic single-threaded
+    /// harnesses such as fuzz targets.
+    ///
+    /// In pump mode no operation blocks, regardless of the timeout, and written
+    /// data is staged until [`MockPipe::pump`] is called instead of becoming
+    /// readable right away. The staged data still counts against the buffer
+    /// capacity. Disabling the mode delivers all staged data.
+    ///
+    /// The mode applies to both buffers of the endpoint, so it is shared with
+    /// the other end of the pipe.
+    pub fn set_pump_mode(&self, enabled: bool) {
+        self.read_buffer.set_pump_mode(enabled);
+        self.write_buffer.set_pump_mode(enabled);
+    }
+
+    /// Enables or disables the pump mode and returns the modified `MockPipe`.
+    pub fn with_pump_mode(self, enabled: bool) -> Self {
+        self.set_pump_mode(enabled);
+        self
+    }
+
+    /// Returns `true` if the pipe is in pump mode.
+    pub fn pump_mode(&self) -> bool {
+        self.write_buffer.pump_mode()
+    }
+
+    /// Delivers the data staged in pump mode in both directions, making it
+    /// readable. Returns the number of bytes delivered.
+    pub fn pump(&self) -> usize {
+        self.pump_bytes(usize::MAX)
+    }
+
+    /// Delivers up to `n` bytes of the data staged in pump mode in each
+    /// direction, so the test controls how much of it the other ends observe
+    /// at a time. Returns the number of bytes delivered, in both directions:
+    /// up to `2 * n` for a pair.
+    pub fn pump_bytes(&self, n: usize) -> usize {
+        let delivered = self.read_buffer.deliver(n);
+
+        if Arc::ptr_eq(&self.read_buffer, &self.write_buffer) {
+            delivered
+        } else {
+            delivered + self.write_buffer.deliver(n)
+        }
+    }
+
+    /// Enables or disables manual delivery of the data written to the endpoint.
+    ///
+    /// With manual delivery, written data sits in a staging area until the test
+    /// moves it to the other end with [`MockPipe::deliver`] or
+    /// [`MockPipe::deliver_all`], giving precise control over interleaving
+    /// without sleeps or timing races. Unlike in pump mode, operations still
+    /// block according to the timeout, e.g. a reader on the other end waits
+    /// until data is delivered. The staged data counts against the buffer
+    /// capacity. Disabling manual delivery delivers all staged data.
+    pub fn set_manual_delivery(&self, enabled: bool) {
+        self.write_buffer.set_manual_delivery(enabled);
+    }
+
+    /// Enables or disables manual delivery and returns the modified `MockPipe`.
+    pub fn with_manual_delivery(self, enabled: bool) -> Self {
+        self.set_manual_delivery(enabled);
+        self
+    }
+
+    /// Returns `true` if manual delivery is enabled for the endpoint.
+    pub fn manual_delivery(&self) -> bool {
+        self.write_buffer.manual_delivery()
+    }
+
+    /// Enables or disables delivery of the data written to the endpoint on
+    /// flush.
+    ///
+    /// With delivery on flush, written data accumulates in a staging area and
+    /// only becomes readable by the other end when the endpoint is flushed,
+    /// like with a buffered transport, so tests can verify that the code under
+    /// test flushes at protocol boundaries. Flushing then does not wait for the
+    /// other end to read the data. The staged data counts against the buffer
+    /// capacity. Disabling delivery on flush delivers all staged data.
+    pub fn set_flush_delivery(&self, enabled: bool) {
+        self.write_buffer.set_flush_delivery(enabled);
+    }
+
+    /// Enables or disables delivery on flush and returns the modified
+    /// `MockPipe`.
+    pub fn with_flush_delivery(self, enabled: bool) -> Self {
+        self.set_flush_delivery(enabled);
+        self
+    }
+
+    /// Returns `true` if delivery on flush is enabled for the endpoint.
+    pub fn flush_delivery(&self) -> bool {
+        self.write_buffer.flush_delivery()
+    }
+
+    /// Returns the number of bytes written to the endpoint but not yet
+    /// delivered to the other end.
+    pub fn staged_len(&self) -> usize {
+        self.write_buffer.staged_len()
+    }
+
+    /// Delivers up to `n` bytes of the staged data written to the endpoint to
+    /// the other end. Returns the number of bytes delivered.
+    pub fn deliver(&self, n: usize) -> usize {
+        self.write_buffer.deliver(n)
+    }
+
+    /// Delivers all staged data written to the endpoint to the other end.
+    /// Returns the number of bytes delivered.
+    pub fn deliver_all(&self) -> usize {
+        self.write_buffer.deliver(usize::MAX)
+    }
+
+    /// Enables or disables the acknowledgment of the data written to the
+    /// endpoint.
+    ///
+    /// With acknowledgments, the data read by the other end keeps counting
+    /// against the buffer capacity until the other end acknowledges it with
+    /// [`ack`](MockPipe::ack), like the send window of a credit or ack-based
+    /// transport: writes block once the capacity is taken by unacknowledged
+    /// data, and flushing waits for all data to be acknowledged. Disabling
+    /// acknowledgments acknowledges all data read so far.
+    pub fn set_ack_mode(&self, enabled: bool) {
+        self.write_buffer.set_ack_mode(enabled);
+    }
+
+    /// Enables or disables acknowledgments and returns the modified
+    /// `MockPipe`.
+    pub fn with_ack_mode(self, enabled: bool) -> Self {
+        self.set_ack_mode(enabled);
+        self
+    }
+
+    /// Returns `true` if the data written to the endpoint has to be
+    /// acknowledged.
+    pub fn ack_mode(&self) -> bool {
+        self.write_buffer.ack_mode()
+    }
+
+    /// Acknowledges up to `n` bytes read from the endpoint, freeing buffer
+    /// space for the other end. Returns the number of bytes acknowledged.
+    pub fn ack(&self, n: usize) -> usize {
+        self.read_buffer.ack(n)
+    }
+
+    /// Returns the number of bytes written to the endpoint, read by the other
+    /// end and not acknowledged yet.
+    pub fn unacked_len(&self) -> usize {
+        self.write_buffer.unacked_len()
+    }
+
+    /// Enables or disables credit-based flow control of the data written to
+    /// the endpoint.
+    ///
+    /// With credit-based flow control, the endpoint can only write as many
+    /// bytes as the other end allowed with
+    /// [`grant_credit`](MockPipe::grant_credit), regardless of the buffer
+    /// space, like with the application-level flow control of some protocols.
+    /// Writes block once the credit is exhausted. The credit is zero when flow
+    /// control is enabled.
+    pub fn set_credit_flow(&self, enabled: bool) {
+        self.write_buffer.set_credit_flow(enabled);
+    }
+
+    /// Enables or disables credit-based flow control and returns the modified
+    /// `MockPipe`.
+    pub fn with_credit_flow(self, enabled: bool) -> Self {
+        self.set_credit_flow(enabled);
+        self
+    }
+
+    /// Returns the number of bytes the endpoint is still allowed to write, or
+    /// `None` if credit-based flow control is disabled.
+    pub fn credit(&self) -> Option<usize> {
+        self.write_buffer.credit()
+    }
+
+    /// Allows the other end to write `n` more bytes, if it has credit-based
+    /// flow control enabled.
+    pub fn grant_credit(&self, n: usize) {
+        self.read_buffer.grant_credit(n);
+    }
+
+    /// Enables or disables the latest-value mode for the data written to the
+    /// endpoint.
+    ///
+    /// In latest-value mode, each write replaces the data the other end has not
+    /// read yet, so only the most recent message is retained, like a sensor
+    /// register or a status endpoint. Writes never block: a message larger
+    /// than the buffer capacity is cut to its end.
+    pub fn set_latest_value(&self, enabled: bool) {
+        self.write_buffer.set_latest_value(enabled);
+    }
+
+    /// Enables or disables the latest-value mode and returns the modified
+    /// `MockPipe`.
+    pub fn with_latest_value(self, enabled: bool) -> Self {
+        self.set_latest_value(enabled);
+        self
+    }
+
+    /// Returns `true` if the endpoint is in latest-value mode.
+    pub fn latest_value(&self) -> bool {
+        self.write_buffer.latest_value()
+    }
+
+    /// Enables or disables the error-on-full write policy for the data written
+    /// to the endpoint.
+    ///
+    /// With this policy, writes never block nor write partially: a write not
+    /// fitting into the free buffer space fails right away with an
+    /// [`Error::Full`] error, writing nothing. This models fixed hardware
+    /// FIFOs, whose overflow is an error condition the driver must handle.
+    pub fn set_error_on_full(&self, enabled: bool) {
+        self.write_buffer.set_error_on_full(enabled);
+    }
+
+    /// Enables or disables the error-on-full write policy and returns the
+    /// modified `MockPipe`.
+    pub fn with_error_on_full(self, enabled: bool) -> Self {
+        self.set_error_on_full(enabled);
+        self
+    }
+
+    /// Returns `true` if writes not fitting into the buffer of the endpoint
+    /// fail.
+    pub fn error_on_full(&self) -> bool {
+        self.write_buffer.error_on_full()
+    }
+
+    /// Sets the [`MemoryBudget`] the data written to the endpoint and not read
+    /// yet is charged to, or `None` (the default) for no budget.
+    ///
+    /// Sharing a budget between several pipes caps the total memory they use,
+    /// whatever their capacities. The data already buffered is charged to the
+    /// new budget, even if that exceeds it.
+    pub fn set_memory_budget(&self, budget: Option<MemoryBudget>) {
+        self.write_buffer.set_memory_budget(budget);
+    }
+
+    /// Sets the memory budget and returns the modified `MockPipe`.
+    pub fn with_memory_budget(self, budget: Option<MemoryBudget>) -> Self {
+        self.set_memory_budget(budget);
+        self
+    }
+
+    /// Returns the memory budget the data written to the endpoint is charged
+    /// to, if any.
+    pub fn memory_budget(&self) -> Option<MemoryBudget> {
+        self.write_buffer.memory_budget()
+    }
+
+    /// Sets the size of the quanta the data written to the endpoint is
+    /// delivered in, or `None` (the default) to deliver it as written.
+    ///
+    /// With a fixed delivery quantum, like the 64-byte packets of USB CDC,
+    /// written data only becomes readable by the other end a whole quantum at a
+    /// time: a partial quantum is held back until filled, or until the
+    /// endpoint is flushed. The held back data counts against the buffer
+    /// capacity, so the quantum should not exceed it. Removing the quantum
+    /// delivers the held back data.
+    pub fn set_delivery_quantum(&self, quantum: Option<usize>) {
+        self.write_buffer.set_delivery_quantum(quantum);
+    }
+
+    /// Sets the delivery quantum and returns the modified `MockPipe`.
+    pub fn with_delivery_quantum(self, quantum: Option<usize>) -> Self {
+        self.set_delivery_quantum(quantum);
+        self
+    }
+
+    /// Returns the size of the quanta the data written to the endpoint is
+    /// delivered in, if fixed.
+    pub fn delivery_quantum(&self) -> Option<usize> {
+        self.write_buffer.delivery_quantum()
+    }
+
+    /// Sets how the data of the endpoint clones writing concurrently
+    /// interleaves, [`Interleaving::Arrival`] by default.
+    pub fn set_interleaving(&self, interleaving: Interleaving) {
+        self.write_buffer.set_interleaving(interleaving);
+    }
+
+    /// Sets the interleaving policy and returns the modified `MockPipe`.
+    pub fn with_interleaving(self, interleaving: Interleaving) -> Self {
+        self.set_interleaving(interleaving);
+        self
+    }
+
+    /// Returns the interleaving policy of the endpoint clones writing
+    /// concurrently.
+    pub fn interleaving(&self) -> Interleaving {
+        self.write_buffer.interleaving()
+    }
+
+    /// Sets how the blocking reads of the endpoint react to its read buffer
+    /// being cleared, with [`clear_read`](MockPipe::clear_read) or
+    /// [`clear`](MockPipe::clear), or by the other end: they keep waiting by
+    /// default ([`ClearWakeup::KeepWaiting`]), or can be woken up, so a test
+    /// harness can use clearing as an abort mechanism.
+    pub fn set_clear_wakeup(&self, clear_wakeup: ClearWakeup) {
+        self.read_buffer.set_clear_wakeup(clear_wakeup);
+    }
+
+    /// Sets how blocked reads react to the read buffer being cleared and
+    /// returns the modified `MockPipe`.
+    pub fn with_clear_wakeup(self, clear_wakeup: ClearWakeup) -> Self {
+        self.set_clear_wakeup(clear_wakeup);
+        self
+    }
+
+    /// Returns how the blocking reads of the endpoint react to its read buffer
+    /// being cleared.
+    pub fn clear_wakeup(&self) -> ClearWakeup {
+        self.read_buffer.clear_wakeup()
+    }
+
+    /// Sets the duration after which an operation blocked on the endpoint is
+    /// reported as possibly hanging, or `None` (the default) to never report
+    /// them.
+    ///
+    /// The operation keeps waiting afterwards: the report only helps finding
+    /// which thread is stuck when a test hangs. It is logged as a warning with
+    /// the `log` feature, and written to the standard error otherwise. Reads
+    /// waiting with no timeout for data no endpoint can write anymore do not
+    /// hang, but fail with an [`Error::Deadlock`] error.
+    ///
+    /// The threshold applies to both buffers of the endpoint, so it is shared
+    /// with the other end of the pipe.
+    pub fn set_suspicious_wait(&self, threshold: Option<Duration>) {
+        self.read_buffer.set_suspicious_wait(threshold);
+        self.write_buffer.set_suspicious_wait(threshold);
+    }
+
+    /// Sets the duration after which a blocked operation is reported and
+    /// returns the modified `MockPipe`.
+    pub fn with_suspicious_wait(self, threshold: Option<Duration>) -> Self {
+        self.set_suspicious_wait(threshold);
+        self
+    }
+
+    /// Returns the duration after which an operation blocked on the endpoint is
+    /// reported as possibly hanging, if any.
+    pub fn suspicious_wait(&self) -> Option<Duration> {
+        self.read_buffer.suspicious_wait()
+    }
+
+    /// Returns the transfer statistics of the endpoint: the number of read and
+    /// write operations, bytes transferred, timeouts and errors.
+    ///
+    /// Clones of an endpoint share its statistics.
+    pub fn stats(&self) -> PipeStats {
+        self.stats.lock().unwrap().total
+    }
+
+    /// Returns the transfer statistics of the endpoint and resets them, e.g. to
+    /// collect statistics per test phase. The statistics per thread are reset
+    /// too.
+    pub fn take_stats(&self) -> PipeStats {
+        mem::take(&mut *self.stats.lock().unwrap()).total
+    }
+
+    /// Returns the transfer statistics of the endpoint per thread, in the order
+    /// the threads first used the endpoint, to tell which of the threads
+    /// sharing clones of the endpoint wrote or consumed what.
+    #[cfg(feature = "stats")]
+    pub fn thread_stats(&self) -> Vec<stats::ThreadStats> {
+        self.stats.lock().unwrap().threads.clone()
+    }
+
+    /// Subscribes to the events of the endpoint: data transfers, the write
+    /// buffer filling up or crossing its watermarks, clearing, closing and
+    /// timeouts.
+    ///
+    /// Events are reported for the operations made through this endpoint (or
+    /// its clones), and are delivered to all subscribers. Dropping the receiver
+    /// cancels the subscription.
+    pub fn subscribe(&self) -> Receiver<PipeEvent> {
+        self.subscribers.subscribe()
+    }
+
+    /// Sets a callback emulating a UART receive interrupt: it is invoked
+    /// whenever new data becomes available to read from the endpoint, on the
+    /// thread that wrote the data.
+    ///
+    /// The callback may read from the endpoint, so interrupt-driven drivers
+    /// can be unit tested against the pipe.
+    pub fn set_rx_interrupt<F>(&self, rx_interrupt: F)
+    where
+        F: Fn() + Send + Sync + 'static,
+    {
+        self.read_buffer
+            .set_rx_interrupt(Some(Arc::new(rx_interrupt)));
+    }
+
+    /// Removes the receive interrupt callback.
+    pub fn clear_rx_interrupt(&self) {
+        self.read_buffer.set_rx_interrupt(None);
+    }
+
+    /// Sets occupancy watermarks on the outgoing data (the write buffer), in
+    /// bytes.
+    ///
+    /// Subscribers receive a [`PipeEvent::HighWatermark`] event when the amount
+    /// of data not yet read by the other end reaches `high`, and a
+    /// [`PipeEvent::LowWatermark`] event when it then falls to `low` or below,
+    /// e.g. to drive flow-control logic under test.
+    pub fn set_watermarks(&self, low: usize, high: usize) {
+        let subscribers = self.subscribers.clone();
+
+        self.write_buffer.set_watermarks(Some((
+            low,
+            high,
+            Arc::new(move |above| {
+                subscribers.emit(if above {
+                    PipeEvent::HighWatermark
+                } else {
+                    PipeEvent::LowWatermark
+                })
+            }),
+        )));
+    }
+
+    /// Removes the occupancy watermarks.
+    pub fn clear_watermarks(&self) {
+        self.write_buffer.set_watermarks(None);
+    }
+
+    /// Sets a callback invoked with the data of every successful read from the
+    /// endpoint, e.g. for custom validation, mirroring or latency measurement.
+    ///
+    /// The hook is called on the reading thread, after the data has been
+    /// removed from the buffer. Clones of an endpoint share its hooks.
+    pub fn set_read_hook<F>(&self, hook: F)
+    where
+        F: Fn(&[u8]) + Send + Sync + 'static,
+    {
+        self.hooks.set_read(Some(Arc::new(hook)));
+    }
+
+    /// Removes the read hook.
+    pub fn clear_read_hook(&self) {
+        self.hooks.set_read(None);
+    }
+
+    /// Sets a callback invoked with the data of every successful write to the
+    /// endpoint.
+    ///
+    /// The hook is called on the writing thread, after the data has been
+    /// placed into the buffer. Clones of an endpoint share its hooks.
+    pub fn set_write_hook<F>(&self, hook: F)
+    where
+        F: Fn(&[u8]) + Send + Sync + 'static,
+    {
+        self.hooks.set_write(Some(Arc::new(hook)));
+    }
+
+    /// Removes the write hook.
+    pub fn clear_write_hook(&self) {
+        self.hooks.set_write(None);
+    }
+
+    /// Mirrors all data read from and written to the endpoint into `writer`
+    /// (e.g. a file, stderr or another pipe), so sessions can be archived without
+    /// modifying the code under test.
+    ///
+    /// The data is written to the sink on the thread performing the transfer.
+    /// A sink that fails to write is removed. Clones of an endpoint share its
+    /// sinks.
+    pub fn tee<W>(&self, writer: W)
+    where
+        W: io::Write + Send + 'static,
+    {
+        self.hooks.add_tee(None, Box::new(writer));
+    }
+
+    /// Mirrors the data transferred in the given direction only (read from the
+    /// endpoint if `Inbound`, written to it if `Outbound`) into `writer`.
+    pub fn tee_direction<W>(&self, direction: Direction, writer: W)
+    where
+        W: io::Write + Send + 'static,
+    {
+        self.hooks.add_tee(Some(direction), Box::new(writer));
+    }
+
+    /// Removes all sinks added with [`tee`](MockPipe::tee) or
+    /// [`tee_direction`](MockPipe::tee_direction).
+    pub fn clear_tee(&self) {
+        self.hooks.clear_tees();
+    }
+
+    /// Returns `true` if the transferred data is logged.
+    #[cfg(feature = "log")]
+    pub fn log_data(&self) -> bool {
+        self.log_data.load(Ordering::Relaxed)
+    }
+
+    /// Sets whether every chunk of data read from or written to the endpoint
+    /// is logged as a hexdump at debug level.
+    ///
+    /// Clones of an endpoint share this setting.
+    #[cfg(feature = "log")]
+    pub fn set_log_data(&self, enabled: bool) {
+        self.log_data.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Sets whether the transferred data is logged and returns the modified
+    /// `MockPipe`.
+    #[cfg(feature = "log")]
+    pub fn with_log_data(self, enabled: bool) -> Self {
+        self.set_log_data(enabled);
+        self
+    }
+
+    /// Reads data from the read buffer with the given timeout.
+    pub(crate) fn read_with_timeout(
+        &self,
+        buf: &mut [u8],
+        timeout: Option<Duration>,
+    ) -> io::Result<usize> {
+        self.read_with_real_timeout(buf, self.real_timeout(timeout))
+    }
+
+    /// Reads data from the read buffer with the given timeout in real time,
+    /// rather than on the clock of the endpoint.
+    pub(crate) fn read_with_real_timeout(
+        &self,
+        buf: &mut [u8],
+        timeout: Option<Duration>,
+    ) -> io::Result<usize> {
+        let timer = OpTimer::start();
+        let result = self.read_buffer.read(buf, timeout);
+        self.finish_read(buf, &result, timer);
+        result
+    }
+
+    /// Writes data into the write buffer with the given timeout.
+    pub(crate) fn write_with_timeout(
+        &self,
+        buf: &[u8],
+        timeout: Option<Duration>,
+    ) -> io::Result<usize> {
+        let timer = OpTimer::start();
+        let result = self.write_buffer.write(buf, self.real_timeout(timeout));
+        self.finish_write(buf, &result, timer);
+        result
+    }
+
+    /// Accounts for a completed read operation into `buf` started at `timer`.
+    pub(crate) fn finish_read(&self, buf: &[u8], result: &io::Result<usize>, timer: OpTimer) {
+        if let Ok(n) = result {
+            self.observe_read(&buf[0..*n]);
+        }
+        self.account_read(buf.len(), result, timer);
+    }
+
+    /// Passes data read from the endpoint on to the hooks and the log.
+    fn observe_read(&self, data: &[u8]) {
+        if !data.is_empty() {
+            self.hooks.on_read(data);
+        }
+
+        #[cfg(feature = "log")]
+        if self.log_data() {
+            logging::log_data(Direction::Inbound, data);
+        }
+    }
+
+    /// Records a completed read operation started at `timer` into the
+    /// statistics and notifies the subscribers.
+    fn account_read(&self, requested: usize, result: &io::Result<usize>, timer: OpTimer) {
+        #[cfg(feature = "trace")]
+        self.trace_op("read", requested, result, &timer);
+        #[cfg(not(feature = "trace"))]
+        let _ = requested;
+
+        self.stats.lock().unwrap().record_read(result, timer);
+
+        match result {
+            Ok(0) => {}
+            Ok(n) => self.subscribers.emit(PipeEvent::DataRead { n: *n }),
+            Err(err) => self.emit_error(err),
+        }
+    }
+
+    /// Accounts for a completed write operation from `buf` started at `timer`.
+    pub(crate) fn finish_write(&self, buf: &[u8], result: &io::Result<usize>, timer: OpTimer) {
+        #[cfg(feature = "trace")]
+        self.trace_op("write", buf.len(), result, &timer);
+
+        self.stats.lock().unwrap().record_write(result, timer);
+
+        match result {
+            Ok(n) => {
+                if *n > 0 {
+                    self.hooks.on_write(&buf[0..*n]);
+                    self.subscribers.emit(PipeEvent::DataWritten { n: *n });
+                }
+                if !buf.is_empty() && (self.write_buffer.space() == 0) {
+                    self.subscribers.emit(PipeEvent::BufferFull);
+                }
+            }
+            Err(err) => self.emit_error(err),
+        }
+
+        #[cfg(feature = "log")]
+        if let Ok(n) = result {
+            if self.log_data() {
+                logging::log_data(Direction::Outbound, &buf[0..*n]);
+            }
+        }
     }
 
-    /// Clears the read buffer, discarding all pending data.
-    pub fn clear_read(&self) {
-        self.read_buffer.clear();
+    /// Waits until the written data has been read, updating the statistics.
+    pub(crate) fn flush_write_buffer(&self) -> io::Result<()> {
+        let result = self.write_buffer.flush(None);
+        if let Err(err) = &result {
+            self.stats.lock().unwrap().record_error(err);
+            self.emit_error(err);
+        }
+        result
     }
 
-    /// Clears the write buffer, discarding all pending data.
-    pub fn clear_write(&self) {
-        self.write_buffer.clear();
+    /// Records an operation started at `timer` into the trace, if any.
+    #[cfg(feature = "trace")]
+    fn trace_op(&self, name: &str, requested: usize, result: &io::Result<usize>, timer: &OpTimer) {
+        let target = self.trace.lock().unwrap().clone();
+        if let Some(target) = target {
+            target.record_op(name, timer.started(), requested, result);
+        }
     }
 
-    /// Clears both read and write buffers, discarding all pending data.
-    pub fn clear(&self) {
-        self.clear_read();
-        self.clear_write();
+    /// Notifies the subscribers of a failed operation.
+    fn emit_error(&self, err: &io::Error) {
+        if err.kind() == io::ErrorKind::TimedOut {
+            self.subscribers.emit(PipeEvent::TimedOut);
+        }
     }
 }
 
+#[cfg(feature = "std")]
 impl io::Read for MockPipe {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        self.read_buffer.read(buf, self.timeout())
+        #[cfg(feature = "mio")]
+        if self.is_registered() {
+            return self.read_nonblocking(buf);
+        }
+
+        self.read_with_timeout(buf, self.timeout())
+    }
+
+    /// Reads exactly `buf.len()` bytes, like the default implementation, but a
+    /// timeout reports the number of bytes read before it (see
+    /// [`Error::TimedOut`]).
+    fn read_exact(&mut self, buf: &mut [u8]) -> io::Result<()> {
+        let mut partial = 0;
+        while partial < buf.len() {
+            match self.read(&mut buf[partial..]) {
+                Ok(0) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "failed to fill whole buffer",
+                    ))
+                }
+                Ok(n) => partial += n,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(error::timed_out_after(err, partial)),
+            }
+        }
+        Ok(())
     }
 }
 
+#[cfg(feature = "std")]
 impl io::Write for MockPipe {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        self.write_buffer.write(buf, self.timeout())
+        #[cfg(feature = "mio")]
+        if self.is_registered() {
+            return self.write_nonblocking(buf);
+        }
+
+        self.write_with_timeout(buf, self.timeout())
+    }
+
+    /// Writes all of `buf`, like the default implementation, but a timeout
+    /// reports the number of bytes written before it (see
+    /// [`Error::TimedOut`]).
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        let mut partial = 0;
+        while partial < buf.len() {
+            match self.write(&buf[partial..]) {
+                Ok(0) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::WriteZero,
+                        "failed to write whole buffer",
+                    ))
+                }
+                Ok(n) => partial += n,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(error::timed_out_after(err, partial)),
+            }
+        }
+        Ok(())
     }
 
     fn flush(&mut self) -> io::Result<()> {
-        self.write_buffer.flush(None)
+        // Like a socket, a registered endpoint has nothing to flush
+        #[cfg(feature = "mio")]
+        if self.is_registered() {
+            self.write_buffer.deliver_on_flush();
+            return Ok(());
+        }
+
+        self.flush_write_buffer()
     }
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "std"))]
 mod tests {
     use std::io::{Read, Write};
 
@@ -354,6 +1364,25 @@ mod tests {
         assert_eq!(&read_data, write_data);
     }
 
+    #[test]
+    fn test_from_static() {
+        let storage: &'static mut [u8] = Box::leak(Box::new([0u8; 10]));
+        let (mut pipe1, mut pipe2) = MockPipe::from_static(storage);
+
+        // Each direction holds half of the storage, wrapping around it
+        assert_eq!(pipe1.write(b"hello, world").unwrap(), 5);
+        assert_eq!(pipe2.write(b"ok").unwrap(), 2);
+
+        let mut read_data = [0u8; 5];
+        pipe2.read_exact(&mut read_data[0..3]).unwrap();
+        pipe1.write_all(b"abc").unwrap();
+        pipe2.read_exact(&mut read_data).unwrap();
+        assert_eq!(&read_data, b"loabc");
+
+        pipe1.read_exact(&mut read_data[0..2]).unwrap();
+        assert_eq!(&read_data[0..2], b"ok");
+    }
+
     #[test]
     fn test_bidirectional_exchange() {
         let (mut pipe1, mut pipe2) = MockPipe::pair(1024);
@@ -471,6 +1500,724 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_clear_wakeup() {
+        use std::thread;
+
+        for (clear_wakeup, expected) in [
+            (ClearWakeup::WouldBlock, Err(io::ErrorKind::WouldBlock)),
+            (ClearWakeup::EndOfStream, Ok(0)),
+            (ClearWakeup::KeepWaiting, Ok(2)),
+        ] {
+            let (mut pipe1, pipe2) = MockPipe::pair(16);
+            let pipe2 = pipe2.with_clear_wakeup(clear_wakeup).with_timeout(None);
+            assert_eq!(pipe2.clear_wakeup(), clear_wakeup);
+
+            let mut reader = pipe2.clone();
+            let reader =
+                thread::spawn(move || reader.read(&mut [0u8; 4]).map_err(|err| err.kind()));
+
+            thread::sleep(Duration::from_millis(20));
+            pipe2.clear();
+
+            // A reader kept waiting gets the data written after the clear
+            thread::sleep(Duration::from_millis(20));
+            pipe1.write_all(b"ok").unwrap();
+
+            assert_eq!(reader.join().unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn test_read_exact_or_partial() {
+        let (mut pipe1, pipe2) = MockPipe::pair(16);
+        let mut buf = [0u8; 8];
+
+        pipe1.write_all(b"OK\r\n").unwrap();
+        let n = pipe2
+            .read_exact_or_partial(&mut buf, Some(Duration::from_millis(20)))
+            .unwrap();
+        assert_eq!(&buf[0..n], b"OK\r\n");
+
+        pipe1.write_all(b"+CSQ: 21").unwrap();
+        assert_eq!(pipe2.read_exact_or_partial(&mut buf, None), Ok(8));
+        assert_eq!(&buf, b"+CSQ: 21");
+
+        pipe1.inject_error(io::ErrorKind::ConnectionReset);
+        assert_eq!(
+            pipe2.read_exact_or_partial(&mut buf, None),
+            Err(Error::Injected(io::ErrorKind::ConnectionReset))
+        );
+
+        pipe1.write_all(b"!").unwrap();
+        pipe1.close();
+        assert_eq!(pipe2.read_exact_or_partial(&mut buf, None), Ok(1));
+    }
+
+    #[test]
+    fn test_buffered_queries() {
+        let (mut device, mut host) = MockPipe::pair(8);
+
+        // Wrap the data around the end of the circular buffer
+        device.write_all(b"xxxx").unwrap();
+        host.read_exact(&mut [0u8; 4]).unwrap();
+        device.write_all(b"OK\r\nRI").unwrap();
+
+        assert!(host.buffered_starts_with(b"OK"));
+        assert!(!host.buffered_starts_with(b"OK\r\nRING"));
+        assert!(host.buffered_contains(b"\r\nR"));
+        assert!(!host.buffered_contains(b"RING"));
+        assert_eq!(host.position_of(b"\r\n"), Some(2));
+        assert_eq!(host.position_of(b"ERROR"), None);
+
+        // Nothing was consumed
+        assert_eq!(host.read_buffer_len(), 6);
+    }
+
+    #[test]
+    fn test_drain_into() {
+        /// Writer accepting up to 3 bytes per write, then failing once full.
+        struct Narrow(Vec<u8>);
+
+        impl Write for Narrow {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                if self.0.len() >= 6 {
+                    return Err(io::Error::from(io::ErrorKind::Other));
+                }
+                let n = buf.len().min(3);
+                self.0.extend_from_slice(&buf[0..n]);
+                Ok(n)
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let (mut device, host) = MockPipe::pair(8);
+
+        // Wrap the data around the end of the circular buffer
+        device.write_all(b"xxxxxx").unwrap();
+        host.drain_into(&mut io::sink(), 4).unwrap();
+        device.write_all(b"abcdef").unwrap();
+
+        let mut relayed = Vec::new();
+        assert_eq!(host.drain_into(&mut relayed, 3).unwrap(), 3);
+        assert_eq!(host.drain_into(&mut relayed, 100).unwrap(), 5);
+        assert_eq!(relayed, b"xxabcdef");
+        assert_eq!(host.stats().bytes_read, 12);
+
+        device.write_all(b"01234567").unwrap();
+        let mut narrow = Narrow(Vec::new());
+        assert_eq!(host.drain_into(&mut narrow, 100).unwrap(), 6);
+        assert_eq!(narrow.0, b"012345");
+        assert!(host.drain_into(&mut narrow, 100).is_err());
+        assert_eq!(host.read_buffer_len(), 2);
+
+        device.close();
+        assert_eq!(host.drain_into(&mut relayed, 100).unwrap(), 2);
+        assert_eq!(host.drain_into(&mut relayed, 100).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_deadlock_detection() {
+        use std::thread;
+
+        let (host, mut device) = MockPipe::pair(16);
+        device.set_timeout(None);
+        device.set_suspicious_wait(Some(Duration::from_millis(10)));
+        assert_eq!(host.suspicious_wait(), Some(Duration::from_millis(10)));
+
+        // A clone of the writer keeps the reader waiting
+        let writer = host.clone();
+        drop(host);
+        let reader = thread::spawn(move || {
+            let mut data = [0u8; 4];
+            let n = device.read(&mut data).unwrap();
+            (device.read(&mut data), data[0..n].to_vec())
+        });
+
+        thread::sleep(Duration::from_millis(30));
+        writer.write_with_timeout(b"ok", None).unwrap();
+        thread::sleep(Duration::from_millis(30));
+        drop(writer);
+
+        let (result, data) = reader.join().unwrap();
+        assert_eq!(data, b"ok");
+        assert_eq!(Error::from_io(&result.unwrap_err()), Some(Error::Deadlock));
+    }
+
+    #[test]
+    fn test_memory_budget() {
+        use std::thread;
+
+        let budget = MemoryBudget::new(100);
+        let (mut host, mut device) = MockPipe::pair(1000);
+        let (mut other, mut peer) = MockPipe::pair(1000);
+        host.set_memory_budget(Some(budget.clone()));
+        other.set_memory_budget(Some(budget.clone()));
+        other.set_timeout(Some(Duration::from_millis(10)));
+
+        host.write_all(&[1u8; 60]).unwrap();
+        assert_eq!(other.write(&[2u8; 60]).unwrap(), 40);
+        assert_eq!(budget.used(), 100);
+        assert_eq!(other.write_buffer.space(), 0);
+        let err = other.write(&[2u8; 1]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+
+        // Reading from one pipe unblocks the writers of the other
+        other.set_timeout(None);
+        let writer = thread::spawn(move || other.write_all(&[3u8; 30]).map(|_| other));
+        thread::sleep(Duration::from_millis(20));
+        let mut data = [0u8; 50];
+        device.read_exact(&mut data).unwrap();
+        let mut other = writer.join().unwrap().unwrap();
+        assert_eq!(budget.used(), 80);
+
+        budget.set_fail_when_exhausted(true);
+        assert_eq!(host.write(&[1u8; 30]).unwrap(), 20);
+        let err = other.write(&[2u8; 1]).unwrap_err();
+        assert_eq!(Error::from_io(&err), Some(Error::Full));
+
+        // Dropping the pipes releases their data
+        let mut data = [0u8; 70];
+        peer.read_exact(&mut data).unwrap();
+        assert_eq!(budget.used(), 30);
+        assert_eq!(host.memory_budget().map(|budget| budget.limit()), Some(100));
+        drop((host, device));
+        assert_eq!(budget.used(), 0);
+    }
+
+    #[test]
+    fn test_lock_free_len() {
+        let (mut device, mut host) = MockPipe::pair(8);
+        device.write_all(b"ATZ\r").unwrap();
+
+        // The lengths are available while the buffer is locked
+        let state = host.read_buffer.state.lock().unwrap();
+        assert_eq!(host.read_buffer_len(), 4);
+        assert_eq!(device.write_buffer_len(), 4);
+        drop(state);
+
+        host.read_exact(&mut [0u8; 3]).unwrap();
+        assert_eq!(device.write_buffer_len(), 1);
+        host.clear_read();
+        assert_eq!(host.read_buffer_len(), 0);
+    }
+
+    #[test]
+    fn test_wait_until_written() {
+        use std::thread;
+
+        let (driver, host) = MockPipe::pair(64);
+        let timeout = Some(Duration::from_secs(5));
+
+        let mut writer = driver.clone();
+        let writer = thread::spawn(move || {
+            for chunk in [&b"AT+C"[..], b"SQ", b"\r\n"] {
+                thread::sleep(Duration::from_millis(10));
+                writer.write_all(chunk).unwrap();
+            }
+        });
+
+        driver
+            .wait_until_written(timeout, |data| data.ends_with(b"\r\n"))
+            .unwrap();
+        assert!(host.buffered_starts_with(b"AT+CSQ\r\n"));
+        writer.join().unwrap();
+
+        let err = driver
+            .wait_until_written(Some(Duration::from_millis(10)), |data| data.len() > 8)
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+
+        host.close();
+        let err = driver.wait_until_written(None, |_| false).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+
+        // The data staged or in flight counts as written
+        let (mut driver, host) = MockPipe::pair(64);
+        driver.set_flush_delivery(true);
+        driver.set_link(
+            Direction::Outbound,
+            link::LinkConfig::new().with_latency(Duration::from_secs(10)),
+        );
+        driver.write_all(b"AT").unwrap();
+        driver.flush().unwrap();
+
+        let mut writer = driver.clone();
+        let writer = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(10));
+            writer.write_all(b"I\r\n").unwrap();
+        });
+
+        driver
+            .wait_until_written(timeout, |data| data == b"ATI\r\n")
+            .unwrap();
+        assert_eq!(host.read_buffer_len(), 0);
+        writer.join().unwrap();
+    }
+
+    #[test]
+    fn test_close() {
+        let (mut pipe1, mut pipe2) = MockPipe::pair(1024);
+        pipe2.set_timeout(None);
+
+        pipe1.write_all(b"bye").unwrap();
+        pipe1.close();
+
+        assert!(pipe1.is_closed());
+        assert!(pipe2.is_closed());
+
+        // Buffered data is still delivered, then the end of stream is reported
+        let mut read_data = Vec::new();
+        pipe2.read_to_end(&mut read_data).unwrap();
+        assert_eq!(&read_data, b"bye");
+
+        assert_eq!(
+            pipe2.write_all(b"x").unwrap_err().kind(),
+            io::ErrorKind::BrokenPipe
+        );
+        assert_eq!(
+            pipe1.write_all(b"x").unwrap_err().kind(),
+            io::ErrorKind::BrokenPipe
+        );
+    }
+
+    #[test]
+    fn test_close_wakes_blocked_reader() {
+        use std::thread;
+
+        let (pipe1, mut pipe2) = MockPipe::pair(1024);
+        pipe2.set_timeout(None);
+
+        let reader = thread::spawn(move || pipe2.read(&mut [0u8; 1]).unwrap());
+
+        thread::sleep(Duration::from_millis(100));
+        pipe1.close();
+
+        assert_eq!(reader.join().unwrap(), 0);
+    }
+
+    #[test]
+    fn test_inject_error() {
+        let mut pipe = MockPipe::loopback(1024);
+
+        pipe.write_all(b"ab").unwrap();
+        pipe.inject_error(io::ErrorKind::ConnectionReset);
+        pipe.write_all(b"c").unwrap();
+
+        // Data written before the error is read first
+        let mut read_data = [0u8; 3];
+        assert_eq!(pipe.read(&mut read_data).unwrap(), 2);
+        assert_eq!(&read_data[0..2], b"ab");
+
+        assert_eq!(
+            pipe.read(&mut read_data).unwrap_err().kind(),
+            io::ErrorKind::ConnectionReset
+        );
+
+        assert_eq!(pipe.read(&mut read_data).unwrap(), 1);
+        assert_eq!(&read_data[0..1], b"c");
+    }
+
+    #[test]
+    fn test_stats() {
+        let (mut pipe1, mut pipe2) = MockPipe::pair(4);
+        pipe1.set_timeout(Some(Duration::from_millis(10)));
+
+        pipe1.write_all(b"ab").unwrap();
+        pipe1.write_all(b"cd").unwrap();
+        assert_eq!(
+            pipe1.write(b"e").unwrap_err().kind(),
+            io::ErrorKind::TimedOut
+        );
+
+        let mut read_data = [0u8; 4];
+        assert_eq!(pipe2.read(&mut read_data).unwrap(), 4);
+
+        pipe2.inject_error(io::ErrorKind::ConnectionReset);
+        assert!(pipe1.read(&mut read_data).is_err());
+
+        let stats = pipe1.stats();
+        assert_eq!((stats.reads, stats.writes), (1, 3));
+        assert_eq!((stats.bytes_read, stats.bytes_written), (0, 4));
+        assert_eq!((stats.timeouts, stats.errors), (1, 1));
+
+        let stats = pipe2.stats();
+        assert_eq!((stats.reads, stats.bytes_read), (1, 4));
+
+        assert_eq!(pipe2.take_stats(), stats);
+        assert_eq!(pipe2.stats(), PipeStats::default());
+    }
+
+    #[test]
+    fn test_rx_interrupt() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let (mut pipe1, pipe2) = MockPipe::pair(64);
+
+        // The "interrupt handler" drains the received data as it arrives
+        let received = Arc::new(AtomicUsize::new(0));
+        let handler_pipe = pipe2.clone();
+        let handler_received = received.clone();
+        pipe2.set_rx_interrupt(move || {
+            let mut read_data = [0u8; 64];
+            let n = handler_pipe.read_with_timeout(&mut read_data, Some(Duration::ZERO));
+            handler_received.fetch_add(n.unwrap(), Ordering::SeqCst);
+        });
+
+        pipe1.write_all(b"hello").unwrap();
+        pipe1.write_all(b"!").unwrap();
+
+        assert_eq!(received.load(Ordering::SeqCst), 6);
+        assert_eq!(pipe2.read_buffer_len(), 0);
+
+        pipe2.clear_rx_interrupt();
+        pipe1.write_all(b"hello").unwrap();
+        assert_eq!(pipe2.read_buffer_len(), 5);
+    }
+
+    #[test]
+    fn test_pump_mode() {
+        let (pipe1, pipe2) = MockPipe::pair(4);
+        let mut pipe1 = pipe1.with_timeout(None).with_pump_mode(true);
+        let mut pipe2 = pipe2.with_timeout(None);
+
+        // Nothing blocks, and written data is staged until pumped
+        assert_eq!(pipe1.write(b"hello").unwrap(), 4);
+        assert_eq!(pipe1.write(b"o").unwrap(), 0);
+
+        let mut read_data = [0u8; 4];
+        assert_eq!(pipe2.read(&mut read_data).unwrap(), 0);
+
+        pipe2.write_all(b"ok").unwrap();
+        assert_eq!(pipe2.pump(), 6);
+
+        assert_eq!(pipe2.read(&mut read_data).unwrap(), 4);
+        assert_eq!(&read_data, b"hell");
+        assert_eq!(pipe1.read(&mut read_data).unwrap(), 2);
+        assert_eq!(&read_data[0..2], b"ok");
+
+        // Disabling the mode delivers the staged data
+        pipe1.write_all(b"hi").unwrap();
+        pipe1.set_pump_mode(false);
+        assert_eq!(pipe2.read_buffer_len(), 2);
+    }
+
+    #[test]
+    fn test_pump_bytes() {
+        let (mut pipe1, mut pipe2) = MockPipe::pair(8);
+        pipe1.set_pump_mode(true);
+
+        pipe1.write_all(b"hello").unwrap();
+        pipe2.write_all(b"ok").unwrap();
+
+        // Up to `n` bytes are delivered in each direction
+        assert_eq!(pipe1.pump_bytes(2), 4);
+        assert_eq!(pipe1.read_buffer_len(), 2);
+        assert_eq!(pipe2.read_buffer_len(), 2);
+
+        assert_eq!(pipe2.pump_bytes(2), 2);
+        assert_eq!(pipe2.pump_bytes(usize::MAX), 1);
+        assert_eq!(pipe2.pump_bytes(1), 0);
+
+        let mut read_data = [0u8; 5];
+        pipe2.read_exact(&mut read_data).unwrap();
+        assert_eq!(&read_data, b"hello");
+
+        // A loopback pipe has a single direction
+        let mut pipe = MockPipe::loopback(8).with_pump_mode(true);
+        pipe.write_all(b"hello").unwrap();
+        assert_eq!(pipe.pump_bytes(2), 2);
+        assert_eq!(pipe.read_buffer_len(), 2);
+    }
+
+    #[test]
+    fn test_manual_delivery() {
+        use std::thread;
+
+        let (pipe1, mut pipe2) = MockPipe::pair(8);
+        let mut pipe1 = pipe1.with_manual_delivery(true);
+
+        pipe1.write_all(b"hello").unwrap();
+        assert_eq!(pipe1.staged_len(), 5);
+        assert_eq!(pipe2.read_buffer_len(), 0);
+
+        // A blocked reader receives the data once it is delivered
+        let reader = thread::spawn(move || {
+            pipe2.set_timeout(None);
+            let mut read_data = [0u8; 2];
+            pipe2.read_exact(&mut read_data).unwrap();
+            read_data
+        });
+
+        thread::sleep(Duration::from_millis(10));
+        assert_eq!(pipe1.deliver(2), 2);
+        assert_eq!(&reader.join().unwrap(), b"he");
+
+        assert_eq!(pipe1.deliver_all(), 3);
+        assert_eq!(pipe1.staged_len(), 0);
+    }
+
+    #[test]
+    fn test_flush_delivery() {
+        let (pipe1, mut pipe2) = MockPipe::pair(8);
+        let mut pipe1 = pipe1.with_flush_delivery(true);
+
+        pipe1.write_all(b"req").unwrap();
+        assert_eq!(pipe1.staged_len(), 3);
+        assert_eq!(pipe2.read_buffer_len(), 0);
+
+        // Flushing delivers the data without waiting for the reader
+        pipe1.flush().unwrap();
+        assert_eq!(pipe2.read_buffer_len(), 3);
+
+        pipe1.write_all(b"!").unwrap();
+        pipe1.set_flush_delivery(false);
+        let mut read_data = [0u8; 4];
+        pipe2.read_exact(&mut read_data).unwrap();
+        assert_eq!(&read_data, b"req!");
+    }
+
+    #[test]
+    fn test_ack_mode() {
+        let (pipe1, mut pipe2) = MockPipe::pair(4);
+        let mut pipe1 = pipe1.with_ack_mode(true);
+
+        pipe1.write_all(b"abcd").unwrap();
+        let mut read_data = [0u8; 4];
+        pipe2.read_exact(&mut read_data).unwrap();
+        assert_eq!(pipe1.unacked_len(), 4);
+
+        // The window stays closed until the reader acknowledges the data
+        assert_eq!(pipe1.write(b"ef").unwrap(), 0);
+        assert_eq!(pipe2.ack(3), 3);
+        assert_eq!(pipe1.write(b"ef").unwrap(), 2);
+        assert_eq!(pipe1.unacked_len(), 1);
+
+        pipe2.read_exact(&mut read_data[0..2]).unwrap();
+        assert_eq!(&read_data[0..2], b"ef");
+        assert_eq!(pipe2.ack(10), 3);
+        pipe1.flush().unwrap();
+    }
+
+    #[test]
+    fn test_credit_flow() {
+        use std::thread;
+
+        let (pipe1, mut pipe2) = MockPipe::pair(16);
+        let mut pipe1 = pipe1.with_credit_flow(true);
+        assert_eq!(pipe1.credit(), Some(0));
+        assert_eq!(pipe1.write(b"data").unwrap(), 0);
+
+        // Writes are limited by the credit, not the buffer space
+        pipe2.grant_credit(3);
+        assert_eq!(pipe1.write(b"data").unwrap(), 3);
+        assert_eq!(pipe1.credit(), Some(0));
+
+        let writer = thread::spawn(move || {
+            pipe1.set_timeout(None);
+            pipe1.write_all(b"a").unwrap();
+            pipe1
+        });
+
+        thread::sleep(Duration::from_millis(50));
+        pipe2.grant_credit(8);
+        let pipe1 = writer.join().unwrap();
+        assert_eq!(pipe1.credit(), Some(7));
+
+        let mut read_data = [0u8; 4];
+        pipe2.read_exact(&mut read_data).unwrap();
+        assert_eq!(&read_data, b"data");
+
+        pipe1.set_credit_flow(false);
+        assert_eq!(pipe1.credit(), None);
+    }
+
+    #[test]
+    fn test_latest_value() {
+        let (pipe1, mut pipe2) = MockPipe::pair(4);
+        let mut pipe1 = pipe1.with_latest_value(true);
+
+        pipe1.write_all(b"t=20").unwrap();
+        pipe1.write_all(b"t=21").unwrap();
+        assert_eq!(pipe2.read_buffer_len(), 4);
+
+        let mut read_data = [0u8; 4];
+        pipe2.read_exact(&mut read_data).unwrap();
+        assert_eq!(&read_data, b"t=21");
+
+        // A value larger than the buffer keeps its end
+        assert_eq!(pipe1.write(b"t=22.5").unwrap(), 6);
+        pipe2.read_exact(&mut read_data).unwrap();
+        assert_eq!(&read_data, b"22.5");
+    }
+
+    #[test]
+    fn test_latest_value_ack_mode() {
+        let (pipe1, mut pipe2) = MockPipe::pair(4);
+        let mut pipe1 = pipe1.with_latest_value(true).with_ack_mode(true);
+
+        pipe1.write_all(b"t=20").unwrap();
+        let mut read_data = [0u8; 4];
+        pipe2.read_exact(&mut read_data).unwrap();
+        assert_eq!(pipe1.unacked_len(), 4);
+
+        // The unacknowledged data does not hold back a new value
+        pipe1.write_all(b"t=21").unwrap();
+        assert_eq!(pipe2.read_buffer_len(), 4);
+        assert_eq!(pipe1.write_buffer.space(), 0);
+
+        assert_eq!(pipe2.ack(4), 4);
+        pipe2.read_exact(&mut read_data).unwrap();
+        assert_eq!(&read_data, b"t=21");
+    }
+
+    #[test]
+    fn test_error_on_full() {
+        let (pipe1, mut pipe2) = MockPipe::pair(8);
+        let mut pipe1 = pipe1.with_error_on_full(true).with_timeout(None);
+        assert!(pipe1.error_on_full());
+
+        pipe1.write_all(b"FIFO").unwrap();
+
+        // Nothing is written when the data does not fit
+        let err = pipe1.write(b"12345").unwrap_err();
+        assert_eq!(Error::from_io(&err), Some(Error::Full));
+        assert_eq!(pipe2.read_buffer_len(), 4);
+
+        pipe1.write_all(b"1234").unwrap();
+        let mut read_data = [0u8; 8];
+        pipe2.read_exact(&mut read_data).unwrap();
+        assert_eq!(&read_data, b"FIFO1234");
+    }
+
+    #[test]
+    fn test_delivery_quantum() {
+        use std::thread;
+
+        let (pipe1, mut pipe2) = MockPipe::pair(16);
+        let mut pipe1 = pipe1.with_delivery_quantum(Some(4));
+
+        pipe1.write_all(b"abcdef").unwrap();
+        assert_eq!(pipe2.read_buffer_len(), 4);
+        assert_eq!(pipe1.staged_len(), 2);
+
+        pipe1.write_all(b"gh").unwrap();
+        assert_eq!(pipe2.read_buffer_len(), 8);
+
+        // Flushing delivers the partial quantum
+        pipe1.write_all(b"i").unwrap();
+        pipe2.set_timeout(None);
+        let reader = thread::spawn(move || {
+            let mut read_data = [0u8; 9];
+            pipe2.read_exact(&mut read_data).unwrap();
+            read_data
+        });
+        pipe1.set_timeout(None);
+        pipe1.flush().unwrap();
+        assert_eq!(&reader.join().unwrap(), b"abcdefghi");
+    }
+
+    #[test]
+    fn test_interleaving() {
+        use std::thread;
+
+        let (pipe1, mut pipe2) = MockPipe::pair(8);
+        let pipe1 = pipe1.with_interleaving(Interleaving::Atomic);
+        pipe1.set_timeout(None);
+
+        let writers: Vec<_> = [b"aaaaaa", b"bbbbbb"]
+            .into_iter()
+            .map(|message| {
+                let mut pipe1 = pipe1.clone();
+                thread::spawn(move || pipe1.write_all(message).unwrap())
+            })
+            .collect();
+
+        // Each message is written whole, never split by the other one
+        pipe2.set_timeout(None);
+        let mut read_data = [0u8; 12];
+        pipe2.read_exact(&mut read_data).unwrap();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+
+        assert!([b"aaaaaabbbbbb", b"bbbbbbaaaaaa"].contains(&&read_data));
+
+        // Without space for the whole message nothing is written
+        let mut pipe1 = pipe1;
+        pipe1.set_timeout(Some(Duration::ZERO));
+        pipe1.write_all(b"1234567").unwrap();
+        assert_eq!(pipe1.write(b"89").unwrap(), 0);
+    }
+
+    #[test]
+    fn test_interleaving_wakeup() {
+        use std::thread;
+
+        let (pipe1, mut pipe2) = MockPipe::pair(4);
+        let mut pipe1 = pipe1.with_interleaving(Interleaving::Atomic);
+        pipe1.write_all(b"abcd").unwrap();
+        pipe1.set_timeout(Some(Duration::from_secs(5)));
+
+        let spawn_writer = |message: &'static [u8]| {
+            let mut pipe1 = pipe1.clone();
+            let writer = thread::spawn(move || pipe1.write(message));
+            thread::sleep(Duration::from_millis(50));
+            writer
+        };
+
+        // Reading a byte wakes up the writer it is enough for, not only the
+        // one waiting first for more space
+        let whole = spawn_writer(b"wxyz");
+        let single = spawn_writer(b"1");
+        let start = Instant::now();
+        pipe2.read_exact(&mut [0u8; 1]).unwrap();
+        assert_eq!(single.join().unwrap().unwrap(), 1);
+        assert!(start.elapsed() < Duration::from_secs(1));
+
+        pipe2.set_timeout(Some(Duration::from_secs(5)));
+        let mut read_data = [0u8; 8];
+        pipe2.read_exact(&mut read_data).unwrap();
+        assert_eq!(whole.join().unwrap().unwrap(), 4);
+        assert_eq!(&read_data, b"bcd1wxyz");
+
+        // Every round-robin writer gets its turn as the reader makes room
+        pipe1.set_interleaving(Interleaving::RoundRobin);
+        let writers: Vec<_> = [b'a', b'b', b'c']
+            .into_iter()
+            .map(|byte| {
+                let mut pipe1 = pipe1.clone();
+                thread::spawn(move || pipe1.write_all(&[byte; 50]))
+            })
+            .collect();
+
+        let mut read_data = [0u8; 150];
+        pipe2.read_exact(&mut read_data).unwrap();
+        for writer in writers {
+            writer.join().unwrap().unwrap();
+        }
+        for byte in [b'a', b'b', b'c'] {
+            assert_eq!(read_data.iter().filter(|&&b| b == byte).count(), 50);
+        }
+    }
+
+    #[test]
+    fn test_null() {
+        let mut pipe = MockPipe::null();
+
+        let data = vec![0x55u8; 1 << 20];
+        pipe.write_all(&data).unwrap();
+        pipe.write_all(&data).unwrap();
+        pipe.flush().unwrap();
+        assert_eq!(pipe.write_buffer_len(), 0);
+
+        let mut read_data = [0u8; 4];
+        assert_eq!(pipe.read(&mut read_data).unwrap(), 0);
+    }
+
     #[test]
     fn test_multiple_threads() {
         use std::{thread, time};
@@ -0,0 +1,613 @@
+//! Simulation of the link characteristics of each pipe direction.
+//!
+//! Every direction of a pipe (each buffer) has its own [`LinkConfig`], set from
+//! either endpoint with [`MockPipe::set_link`]: latency, bandwidth, loss, byte
+//! deletion and corruption. Real links are rarely symmetric, so asymmetric
+//! ones such as a satellite connection with a slow downlink can be modeled, or
+//! a test can degrade only one direction:
+//!
+//! ```
+//! use std::time::Duration;
+//!
+//! use mockpipe::{link::LinkConfig, Direction, MockPipe};
+//!
+//! let (ground, satellite) = MockPipe::pair(1024);
+//! ground.set_link(
+//!     Direction::Outbound,
+//!     LinkConfig::new().with_latency(Duration::from_millis(10)),
+//! );
+//! ground.set_link(
+//!     Direction::Inbound,
+//!     LinkConfig::new()
+//!         .with_latency(Duration::from_millis(300))
+//!         .with_bandwidth(Some(9600))
+//!         .with_corruption(0.001),
+//! );
+//! ```
+//!
+//! Data on the way to the reader still counts against the buffer capacity. It
+//! is delivered by a background thread, so delaying links are not available on
+//! targets without threads.
+//!
+//! A limited bandwidth can allow bursts exceeding it (see
+//! [`LinkConfig::burst`]) and shrink as the buffer fills up, following a
+//! [`CongestionCurve`], so adaptive-rate senders see the throughput drop once
+//! they overwhelm the reader.
+//!
+//! [`MockPipe::stall_for`] stops the link entirely for a while, without
+//! losing data, so watchdogs and retry timers can be validated.
+//!
+//! Loss, deletion, corruption and jitter are driven by a seeded pseudo-random
+//! generator per direction, so the faults can be replayed. Unless set with
+//! [`LinkConfig::with_seed`] or derived from the master seed of a
+//! [`MockPipeBuilder`](crate::MockPipeBuilder), the seed derives from the
+//! global master seed (see [`set_seed`](crate::set_seed)) and differs for
+//! every link set, so the faults of different links are independent.
+
+use std::{
+    io,
+    sync::{Arc, Weak},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{buffer::SyncBuffer, rng::Rng, stats::OpTimer, Direction, MockPipe};
+
+/// Maximum time the delivery thread waits before checking whether the buffer
+/// was dropped.
+const IDLE_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Simulated characteristics of one direction of a pipe.
+///
+/// The default is an ideal link, delivering written data right away.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct LinkConfig {
+    /// Time it takes written data to become readable on the other end.
+    pub latency: Duration,
+
+    /// Maximum random delay added to the latency of each write. Data never
+    /// overtakes the data written before it.
+    pub jitter: Duration,
+
+    /// Transfer rate in bytes per second, or `None` for an unlimited one.
+    /// Written data becomes readable progressively as it is transmitted.
+    pub bandwidth: Option<u64>,
+
+    /// Burst size in bytes of a limited bandwidth: the link shapes the traffic
+    /// with a token bucket of this size refilled at the bandwidth rate, so up
+    /// to this many bytes are transmitted at once after an idle period. `0`
+    /// for a constant rate.
+    pub burst: u64,
+
+    /// Shrinking of the bandwidth as the buffer fills up, or `None` for a
+    /// constant bandwidth. Only applies to a limited bandwidth.
+    pub congestion: Option<CongestionCurve>,
+
+    /// Probability (`0.0..=1.0`) of the data of a write being lost entirely.
+    pub loss: f64,
+
+    /// Probability (`0.0..=1.0`) of a byte being dropped silently, leaving a
+    /// gap in the stream.
+    pub deletion: f64,
+
+    /// Probability (`0.0..=1.0`) of a byte getting a bit flipped.
+    pub corruption: f64,
+
+    /// Seed of the generator driving loss, deletion, corruption and jitter, or
+    /// `None` to derive a distinct one from the master seed for every link set.
+    pub seed: Option<u64>,
+}
+
+impl LinkConfig {
+    /// Creates the settings of an ideal link.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the latency and returns the modified settings.
+    pub fn with_latency(mut self, latency: Duration) -> Self {
+        self.latency = latency;
+        self
+    }
+
+    /// Sets the jitter and returns the modified settings.
+    pub fn with_jitter(mut self, jitter: Duration) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// Sets the bandwidth in bytes per second (`None` for unlimited) and
+    /// returns the modified settings.
+    pub fn with_bandwidth(mut self, bandwidth: Option<u64>) -> Self {
+        self.bandwidth = bandwidth;
+        self
+    }
+
+    /// Sets the burst size in bytes and returns the modified settings.
+    pub fn with_burst(mut self, burst: u64) -> Self {
+        self.burst = burst;
+        self
+    }
+
+    /// Sets the congestion curve (`None` for a constant bandwidth) and returns
+    /// the modified settings.
+    pub fn with_congestion(mut self, congestion: Option<CongestionCurve>) -> Self {
+        self.congestion = congestion;
+        self
+    }
+
+    /// Sets the probability of losing the data of a write and returns the
+    /// modified settings.
+    pub fn with_loss(mut self, probability: f64) -> Self {
+        self.loss = probability;
+        self
+    }
+
+    /// Sets the probability of dropping a byte and returns the modified
+    /// settings.
+    pub fn with_deletion(mut self, probability: f64) -> Self {
+        self.deletion = probability;
+        self
+    }
+
+    /// Sets the probability of corrupting a byte and returns the modified
+    /// settings.
+    pub fn with_corruption(mut self, probability: f64) -> Self {
+        self.corruption = probability;
+        self
+    }
+
+    /// Sets the seed of the generator driving the random faults and returns
+    /// the modified settings.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    /// Returns `true` if written data is delivered right away and intact.
+    pub(crate) fn is_ideal(&self) -> bool {
+        !self.delays() && (self.loss <= 0.0) && (self.deletion <= 0.0) && (self.corruption <= 0.0)
+    }
+
+    /// Returns `true` if written data takes time to become readable.
+    pub(crate) fn delays(&self) -> bool {
+        !self.latency.is_zero() || !self.jitter.is_zero() || self.bandwidth().is_some()
+    }
+
+    /// Returns the bandwidth, if limited.
+    pub(crate) fn bandwidth(&self) -> Option<u64> {
+        self.bandwidth.filter(|&bandwidth| bandwidth > 0)
+    }
+
+    /// Returns the bandwidth at the given buffer occupancy (`0.0..=1.0`), if
+    /// limited.
+    pub(crate) fn effective_bandwidth(&self, occupancy: f64) -> Option<u64> {
+        let bandwidth = self.bandwidth()?;
+        let factor = self.congestion.map_or(1.0, |curve| curve.factor(occupancy));
+        Some(((bandwidth as f64 * factor) as u64).max(1))
+    }
+
+    /// Creates the generator driving the random faults. Without a seed, every
+    /// generator created gets its own, so the faults of different links are
+    /// independent.
+    pub(crate) fn rng(&self) -> Rng {
+        Rng::new(self.seed.unwrap_or_else(|| Rng::derive_unique_seed("link")))
+    }
+
+    /// Returns the delay of a write: the latency plus a random jitter.
+    pub(crate) fn delay(&self, rng: &mut Rng) -> Duration {
+        self.latency + self.jitter.mul_f64(rng.next_f64())
+    }
+}
+
+/// How the bandwidth of a congested link shrinks as its buffer fills up.
+///
+/// Below the `onset` occupancy the full bandwidth is available. Above it, the
+/// bandwidth shrinks down to the `floor` fraction of it at a full buffer,
+/// following a power curve of the given `exponent` (`1.0` for a linear one).
+/// A sender adapting its rate to the link throughput thus sees it drop once it
+/// overwhelms the reader, as on a congested network:
+///
+/// ```
+/// use mockpipe::link::{CongestionCurve, LinkConfig};
+///
+/// // Half of the buffer fills up freely, then the bandwidth drops down to a
+/// // tenth, faster at first
+/// let link = LinkConfig::new()
+///     .with_bandwidth(Some(100_000))
+///     .with_congestion(Some(CongestionCurve::linear(0.5, 0.1).with_exponent(0.5)));
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CongestionCurve {
+    /// Buffer occupancy (`0.0..=1.0`) above which the bandwidth starts to
+    /// shrink.
+    pub onset: f64,
+
+    /// Fraction (`0.0..=1.0`) of the bandwidth left at a full buffer.
+    pub floor: f64,
+
+    /// Exponent of the curve between the onset and a full buffer.
+    pub exponent: f64,
+}
+
+impl CongestionCurve {
+    /// Creates a curve shrinking the bandwidth linearly from the `onset`
+    /// occupancy down to the `floor` fraction of it at a full buffer.
+    pub fn linear(onset: f64, floor: f64) -> Self {
+        Self {
+            onset,
+            floor,
+            exponent: 1.0,
+        }
+    }
+
+    /// Sets the exponent of the curve and returns the modified curve.
+    pub fn with_exponent(mut self, exponent: f64) -> Self {
+        self.exponent = exponent;
+        self
+    }
+
+    /// Returns the fraction of the bandwidth available at the given buffer
+    /// occupancy.
+    pub fn factor(&self, occupancy: f64) -> f64 {
+        let onset = self.onset.clamp(0.0, 1.0);
+        let floor = self.floor.clamp(0.0, 1.0);
+
+        if occupancy <= onset {
+            return 1.0;
+        }
+
+        let congestion = if onset < 1.0 {
+            ((occupancy - onset) / (1.0 - onset)).min(1.0)
+        } else {
+            1.0
+        };
+        1.0 - (1.0 - floor) * congestion.powf(self.exponent)
+    }
+}
+
+/// Token bucket shaping the traffic of a link with a limited bandwidth.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct TokenBucket {
+    /// Number of bytes that can be sent right away, negative while previously
+    /// sent data is still being transmitted, and the time it was last updated.
+    /// `None` for a full bucket.
+    tokens: Option<(f64, Instant)>,
+}
+
+impl TokenBucket {
+    /// Refills the bucket at the `bandwidth` rate up to the `burst` size, and
+    /// returns the number of bytes that can be sent at `now`.
+    pub(crate) fn refill(&mut self, bandwidth: u64, burst: u64, now: Instant) -> f64 {
+        let burst = burst as f64;
+        let tokens = match self.tokens {
+            Some((tokens, at)) => {
+                let refill = now.saturating_duration_since(at).as_secs_f64() * bandwidth as f64;
+                (tokens + refill).min(burst)
+            }
+            None => burst,
+        };
+
+        self.tokens = Some((tokens, now));
+        tokens
+    }
+
+    /// Takes the tokens of `len` bytes sent, after a refill.
+    pub(crate) fn spend(&mut self, len: usize) {
+        if let Some((tokens, _)) = &mut self.tokens {
+            *tokens -= len as f64;
+        }
+    }
+}
+
+/// Spawns the thread delivering the data in flight over the link of the buffer
+/// once it is due. The thread exits once the buffer is dropped.
+pub(crate) fn start_courier(buffer: &Arc<SyncBuffer>) {
+    let buffer: Weak<SyncBuffer> = Arc::downgrade(buffer);
+
+    thread::spawn(move || {
+        while let Some(buffer) = buffer.upgrade() {
+            buffer.deliver_due(IDLE_INTERVAL);
+        }
+    });
+}
+
+impl MockPipe {
+    /// Returns the buffer carrying the data in the given direction.
+    fn buffer(&self, direction: Direction) -> &Arc<SyncBuffer> {
+        match direction {
+            Direction::Inbound => &self.read_buffer,
+            Direction::Outbound => &self.write_buffer,
+        }
+    }
+
+    /// Sets the simulated link characteristics of the given direction: of the
+    /// data read from the endpoint if `Inbound`, or written to it if
+    /// `Outbound`.
+    ///
+    /// The settings belong to the direction, so they are shared with the other
+    /// end of the pipe, where the direction is reversed. They apply to the data
+    /// written from then on, including data delivered from the staging area in
+    /// pump mode or with manual delivery.
+    pub fn set_link(&self, direction: Direction, config: LinkConfig) {
+        let buffer = self.buffer(direction);
+        if buffer.set_link(config) {
+            start_courier(buffer);
+        }
+    }
+
+    /// Sets the simulated link characteristics of the given direction and
+    /// returns the modified `MockPipe`.
+    pub fn with_link(self, direction: Direction, config: LinkConfig) -> Self {
+        self.set_link(direction, config);
+        self
+    }
+
+    /// Returns the simulated link characteristics of the given direction.
+    pub fn link(&self, direction: Direction) -> LinkConfig {
+        self.buffer(direction).link()
+    }
+
+    /// Writes data to the endpoint to become readable on the other end at the
+    /// given time, waiting for buffer space according to the timeout. Returns
+    /// the number of bytes written.
+    ///
+    /// The data bypasses the simulated link and the staging area, but never
+    /// overtakes the data written before it. Data due in the past is delivered
+    /// right away. This paces replayed traffic by absolute timestamps, so the
+    /// delays do not add up.
+    pub fn deliver_at(&self, at: Instant, buf: &[u8]) -> io::Result<usize> {
+        self.write_at_with_timeout(at, buf, self.timeout())
+    }
+
+    /// Writes data to become readable at the given time with the given
+    /// timeout.
+    pub(crate) fn write_at_with_timeout(
+        &self,
+        at: Instant,
+        buf: &[u8],
+        timeout: Option<Duration>,
+    ) -> io::Result<usize> {
+        let timer = OpTimer::start();
+        let result = self
+            .write_buffer
+            .write_at(at, buf, self.real_timeout(timeout))
+            .map(|(n, start)| {
+                if start {
+                    start_courier(&self.write_buffer);
+                }
+                n
+            });
+        self.finish_write(buf, &result, timer);
+        result
+    }
+
+    /// Stalls the link in both directions for the given duration.
+    ///
+    /// A stalled link transfers nothing: the data written in the meantime, or
+    /// still in flight, is held back and delivered once the stall is over.
+    /// The held back data counts against the buffer capacity, so writers
+    /// eventually block, and the end of stream waits for the link to resume.
+    pub fn stall_for(&self, duration: Duration) {
+        for direction in [Direction::Inbound, Direction::Outbound] {
+            let buffer = self.buffer(direction);
+            if buffer.stall(duration) {
+                start_courier(buffer);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Read, Write},
+        time::Instant,
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_asymmetric_latency() {
+        let (mut ground, mut satellite) = MockPipe::pair(64);
+        ground.set_link(
+            Direction::Outbound,
+            LinkConfig::new().with_latency(Duration::from_millis(10)),
+        );
+        satellite.set_link(
+            Direction::Outbound,
+            LinkConfig::new().with_latency(Duration::from_millis(100)),
+        );
+        assert_eq!(
+            ground.link(Direction::Inbound).latency,
+            Duration::from_millis(100)
+        );
+
+        ground.set_timeout(None);
+        satellite.set_timeout(None);
+        let mut buf = [0u8; 4];
+
+        // The data is in flight, not readable yet
+        let start = Instant::now();
+        ground.write_all(b"ping").unwrap();
+        assert_eq!(satellite.read_buffer_len(), 0);
+        assert_eq!(ground.write_buffer_len(), 0);
+        satellite.read_exact(&mut buf).unwrap();
+        let uplink = start.elapsed();
+
+        let start = Instant::now();
+        satellite.write_all(b"pong").unwrap();
+        ground.read_exact(&mut buf).unwrap();
+        let downlink = start.elapsed();
+
+        assert_eq!(&buf, b"pong");
+        assert!(uplink >= Duration::from_millis(10));
+        assert!(downlink >= Duration::from_millis(100));
+        assert!(downlink > uplink);
+
+        // The end of stream follows the data in flight
+        satellite.write_all(b"bye").unwrap();
+        satellite.close();
+        let mut rest = Vec::new();
+        ground.read_to_end(&mut rest).unwrap();
+        assert_eq!(rest, b"bye");
+    }
+
+    #[test]
+    fn test_link_faults_per_direction() {
+        let (mut host, mut device) = MockPipe::pair(1024);
+        host.set_link(Direction::Inbound, LinkConfig::new().with_loss(1.0));
+        host.set_link(
+            Direction::Outbound,
+            LinkConfig::new().with_corruption(1.0).with_seed(7),
+        );
+
+        // Only the degraded direction is affected
+        device.write_all(b"lost").unwrap();
+        assert_eq!(host.read_buffer_len(), 0);
+
+        host.write_all(b"data").unwrap();
+        let mut buf = [0u8; 4];
+        device.read_exact(&mut buf).unwrap();
+        for (byte, original) in buf.iter().zip(b"data") {
+            assert_eq!((byte ^ original).count_ones(), 1);
+        }
+
+        // Deleted bytes leave gaps, the rest arrives in order
+        host.set_link(
+            Direction::Outbound,
+            LinkConfig::new().with_deletion(0.5).with_seed(7),
+        );
+        let sent: Vec<u8> = (0..=255).collect();
+        host.write_all(&sent).unwrap();
+        let mut received = vec![0u8; device.read_buffer_len()];
+        device.read_exact(&mut received).unwrap();
+        assert!(!received.is_empty() && (received.len() < sent.len()));
+        assert!(received.windows(2).all(|pair| pair[0] < pair[1]));
+
+        // 200 bytes at 10 kB/s take 20 ms, arriving progressively
+        host.set_link(
+            Direction::Outbound,
+            LinkConfig::new().with_bandwidth(Some(10_000)),
+        );
+        device.set_timeout(None);
+
+        let start = Instant::now();
+        host.write_all(&[0u8; 200]).unwrap();
+        device.read_exact(&mut [0u8; 100]).unwrap();
+        let half = start.elapsed();
+        device.read_exact(&mut [0u8; 100]).unwrap();
+        let full = start.elapsed();
+
+        assert!(half >= Duration::from_millis(10));
+        assert!(full >= Duration::from_millis(20));
+    }
+
+    #[test]
+    fn test_unseeded_links_independent() {
+        let received = || {
+            let (host, mut device) = MockPipe::pair(1024);
+            host.set_link(Direction::Outbound, LinkConfig::new().with_loss(0.5));
+            for byte in 0..64u8 {
+                host.write_with_timeout(&[byte], None).unwrap();
+            }
+
+            let mut received = vec![0u8; device.read_buffer_len()];
+            device.read_exact(&mut received).unwrap();
+            received
+        };
+
+        // The links of different pipes drop different writes
+        assert_ne!(received(), received());
+    }
+
+    #[test]
+    fn test_deliver_at() {
+        let (host, mut device) = MockPipe::pair(64);
+        device.set_timeout(None);
+
+        // Data never overtakes the data scheduled before it
+        let start = Instant::now();
+        host.deliver_at(start + Duration::from_millis(50), b"late")
+            .unwrap();
+        host.deliver_at(start, b"r").unwrap();
+        assert_eq!(device.read_buffer_len(), 0);
+
+        let mut buf = [0u8; 5];
+        device.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"later");
+        assert!(start.elapsed() >= Duration::from_millis(50));
+    }
+
+    #[test]
+    fn test_stall_for() {
+        let (mut host, mut device) = MockPipe::pair(64);
+        host.stall_for(Duration::from_millis(100));
+
+        let start = Instant::now();
+        host.write_all(b"ping").unwrap();
+        device.write_all(b"pong").unwrap();
+        assert_eq!(device.read_buffer_len(), 0);
+        assert_eq!(host.read_buffer_len(), 0);
+
+        // Nothing is lost, the data arrives once the link resumes
+        device.set_timeout(None);
+        let mut buf = [0u8; 4];
+        device.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"ping");
+        assert!(start.elapsed() >= Duration::from_millis(100));
+
+        host.write_all(b"next").unwrap();
+        assert_eq!(device.read_buffer_len(), 4);
+    }
+
+    #[test]
+    fn test_token_bucket_burst() {
+        let (mut sender, mut receiver) = MockPipe::pair(2048);
+        sender.set_link(
+            Direction::Outbound,
+            LinkConfig::new()
+                .with_bandwidth(Some(1000))
+                .with_burst(1000),
+        );
+        receiver.set_timeout(None);
+
+        // A full bucket lets a burst through at once, instead of in 1 s
+        let start = Instant::now();
+        sender.write_all(&[0u8; 1000]).unwrap();
+        receiver.read_exact(&mut [0u8; 1000]).unwrap();
+        assert!(start.elapsed() < Duration::from_millis(500));
+
+        // Once drained, the bucket refills at the bandwidth rate
+        let start = Instant::now();
+        sender.write_all(&[0u8; 200]).unwrap();
+        receiver.read_exact(&mut [0u8; 200]).unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(100));
+    }
+
+    #[test]
+    fn test_congestion_curve() {
+        let curve = CongestionCurve::linear(0.5, 0.2);
+        assert_eq!(curve.factor(0.25), 1.0);
+        assert!((curve.factor(0.75) - 0.6).abs() < 1e-9);
+        assert!((curve.factor(1.0) - 0.2).abs() < 1e-9);
+
+        let (mut sender, mut receiver) = MockPipe::pair(1000);
+        sender.write_all(&[0u8; 800]).unwrap();
+
+        // With 80 % of the buffer occupied, the bandwidth drops to 28 %:
+        // 100 bytes take about 36 ms instead of 10 ms
+        sender.set_link(
+            Direction::Outbound,
+            LinkConfig::new()
+                .with_bandwidth(Some(10_000))
+                .with_congestion(Some(CongestionCurve::linear(0.0, 0.1))),
+        );
+        receiver.set_timeout(None);
+
+        let start = Instant::now();
+        sender.write_all(&[0u8; 100]).unwrap();
+        receiver.read_exact(&mut [0u8; 900]).unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(30));
+    }
+}
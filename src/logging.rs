@@ -0,0 +1,70 @@
+//! Hexdump logging of transferred data through the `log` crate.
+
+use crate::{hex::hexdump, Direction};
+
+/// Logs a hexdump of a chunk of data transferred in the given direction at
+/// debug level.
+pub(crate) fn log_data(direction: Direction, data: &[u8]) {
+    if !data.is_empty() && log::log_enabled!(log::Level::Debug) {
+        let arrow = match direction {
+            Direction::Inbound => "<-",
+            Direction::Outbound => "->",
+        };
+
+        log::debug!("{} {} bytes\n{}", arrow, data.len(), hexdump(data));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Read, Write},
+        sync::Mutex,
+    };
+
+    use super::*;
+    use crate::MockPipe;
+
+    static MESSAGES: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+    struct TestLogger;
+
+    impl log::Log for TestLogger {
+        fn enabled(&self, _: &log::Metadata) -> bool {
+            true
+        }
+
+        fn log(&self, record: &log::Record) {
+            // Other tests may log warnings concurrently
+            if record.target() == "mockpipe::logging" {
+                MESSAGES.lock().unwrap().push(record.args().to_string());
+            }
+        }
+
+        fn flush(&self) {}
+    }
+
+    #[test]
+    fn test_log_data() {
+        log::set_logger(&TestLogger).unwrap();
+        log::set_max_level(log::LevelFilter::Debug);
+
+        let (mut pipe1, mut pipe2) = MockPipe::pair(64);
+        pipe1.set_log_data(true);
+
+        pipe1.write_all(b"hi").unwrap();
+
+        let mut read_data = [0u8; 2];
+        pipe2.read_exact(&mut read_data).unwrap();
+        pipe2.write_all(b"ok").unwrap();
+        pipe1.read_exact(&mut read_data).unwrap();
+
+        assert_eq!(
+            *MESSAGES.lock().unwrap(),
+            [
+                format!("-> 2 bytes\n{}", hexdump(b"hi")),
+                format!("<- 2 bytes\n{}", hexdump(b"ok")),
+            ]
+        );
+    }
+}
@@ -0,0 +1,618 @@
+//! TCP-like connection establishment on top of `MockPipe` pairs and UDP-like
+//! datagram sockets.
+//!
+//! `MockTcpListener::bind` registers a name in a process-wide registry and
+//! `MockTcpStream::connect` looks it up, creating a linked pipe pair whose ends
+//! are handed to the client and (via `accept`) to the server.
+//!
+//! `MockUdpSocket::bind` registers a name in a separate process-wide registry
+//! which `send_to` uses to deliver datagrams to the destination socket.
+
+use std::{
+    collections::VecDeque,
+    io,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Condvar, Mutex, MutexGuard, Weak,
+    },
+    time::Duration,
+};
+
+use crate::{rng::Rng, MockPipe};
+
+/// Default buffer capacity of the pipes created for each connection.
+pub const DEFAULT_BUFFER_CAPACITY: usize = 64 * 1024;
+
+/// Listeners currently bound, by name.
+static LISTENERS: Mutex<Vec<(String, Weak<Backlog>)>> = Mutex::new(Vec::new());
+
+/// Counter used to generate unique client names.
+static NEXT_CLIENT_ID: AtomicUsize = AtomicUsize::new(1);
+
+/// Default maximum number of datagrams queued for a `MockUdpSocket`.
+pub const DEFAULT_DATAGRAM_QUEUE_LEN: usize = 1024;
+
+/// UDP sockets currently bound, by name.
+static UDP_SOCKETS: Mutex<Vec<(String, Weak<Inbox>)>> = Mutex::new(Vec::new());
+
+/// Waits until the queue is not empty and pops its first item.
+///
+/// Returns a `WouldBlock` error in non-blocking mode (`Some(Duration::ZERO)`)
+/// and a `TimedOut` error if no item arrives within the timeout.
+fn wait_for_item<T>(
+    queue: MutexGuard<'_, VecDeque<T>>,
+    condvar: &Condvar,
+    timeout: Option<Duration>,
+) -> io::Result<T> {
+    let mut queue = match timeout {
+        Some(Duration::ZERO) => queue,
+        Some(timeout) => {
+            let (queue, timeout_result) = condvar
+                .wait_timeout_while(queue, timeout, |queue| queue.is_empty())
+                .map_err(|_| io::Error::from(io::ErrorKind::Other))?;
+
+            if timeout_result.timed_out() {
+                return Err(io::Error::from(io::ErrorKind::TimedOut));
+            }
+
+            queue
+        }
+        None => condvar
+            .wait_while(queue, |queue| queue.is_empty())
+            .map_err(|_| io::Error::from(io::ErrorKind::Other))?,
+    };
+
+    queue
+        .pop_front()
+        .ok_or_else(|| io::Error::from(io::ErrorKind::WouldBlock))
+}
+
+/// Queue of connections waiting to be accepted.
+struct Backlog {
+    buffer_capacity: usize,
+    pending: Mutex<VecDeque<(MockTcpStream, String)>>,
+    can_accept: Condvar,
+}
+
+/// A mock TCP listener accepting connections made with
+/// [`MockTcpStream::connect`] to the name it is bound to.
+///
+/// The name is released when the listener is dropped; connections that were
+/// not accepted by then are discarded.
+pub struct MockTcpListener {
+    name: String,
+    backlog: Arc<Backlog>,
+    timeout: Mutex<Option<Duration>>,
+}
+
+impl MockTcpListener {
+    /// Binds a listener to the given name using the default buffer capacity
+    /// for accepted connections.
+    ///
+    /// Returns an `AddrInUse` error if another listener is bound to the name.
+    pub fn bind(name: &str) -> io::Result<Self> {
+        Self::bind_with_capacity(name, DEFAULT_BUFFER_CAPACITY)
+    }
+
+    /// Binds a listener to the given name; each accepted connection uses pipes
+    /// with the specified buffer capacity.
+    pub fn bind_with_capacity(name: &str, buffer_capacity: usize) -> io::Result<Self> {
+        let mut listeners = LISTENERS.lock().unwrap();
+
+        listeners.retain(|(_, backlog)| backlog.strong_count() > 0);
+
+        if listeners.iter().any(|(bound_name, _)| bound_name == name) {
+            return Err(io::Error::from(io::ErrorKind::AddrInUse));
+        }
+
+        let backlog = Arc::new(Backlog {
+            buffer_capacity,
+            pending: Mutex::new(VecDeque::new()),
+            can_accept: Condvar::new(),
+        });
+
+        listeners.push((name.to_owned(), Arc::downgrade(&backlog)));
+
+        Ok(Self {
+            name: name.to_owned(),
+            backlog,
+            // Non-blocking by default, as `MockPipe`
+            timeout: Mutex::new(Some(Duration::ZERO)),
+        })
+    }
+
+    /// Returns the name this listener is bound to.
+    pub fn local_name(&self) -> &str {
+        &self.name
+    }
+
+    /// Gets the current timeout duration for `accept`.
+    pub fn timeout(&self) -> Option<Duration> {
+        *self.timeout.lock().unwrap()
+    }
+
+    /// Sets the timeout duration for `accept`.
+    ///
+    /// `None` means `accept` blocks indefinitely. `Some(Duration::ZERO)` means
+    /// it is non-blocking and fails with `WouldBlock` when no connection is
+    /// pending.
+    pub fn set_timeout(&self, timeout: Option<Duration>) {
+        *self.timeout.lock().unwrap() = timeout;
+    }
+
+    /// Sets the timeout duration for `accept` and returns the modified listener.
+    pub fn with_timeout(self, timeout: Option<Duration>) -> Self {
+        self.set_timeout(timeout);
+        self
+    }
+
+    /// Accepts a pending connection, returning the server side stream and the
+    /// name of the connected client.
+    ///
+    /// Returns a `WouldBlock` error in non-blocking mode and a `TimedOut` error
+    /// if no connection arrives within the timeout.
+    pub fn accept(&self) -> io::Result<(MockTcpStream, String)> {
+        wait_for_item(
+            self.backlog.pending.lock().unwrap(),
+            &self.backlog.can_accept,
+            self.timeout(),
+        )
+    }
+}
+
+impl Drop for MockTcpListener {
+    fn drop(&mut self) {
+        LISTENERS
+            .lock()
+            .unwrap()
+            .retain(|(name, _)| name != &self.name);
+    }
+}
+
+/// A connected mock TCP stream, backed by one end of a `MockPipe` pair.
+#[derive(Clone)]
+pub struct MockTcpStream {
+    pipe: MockPipe,
+    local_name: String,
+    peer_name: String,
+}
+
+impl MockTcpStream {
+    /// Connects to the listener bound to the given name.
+    ///
+    /// Returns a `ConnectionRefused` error if no listener is bound to the name.
+    pub fn connect(name: &str) -> io::Result<Self> {
+        let backlog = LISTENERS
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|(bound_name, _)| bound_name == name)
+            .and_then(|(_, backlog)| backlog.upgrade())
+            .ok_or_else(|| io::Error::from(io::ErrorKind::ConnectionRefused))?;
+
+        let client_name = format!(
+            "{}#{}",
+            name,
+            NEXT_CLIENT_ID.fetch_add(1, Ordering::Relaxed)
+        );
+
+        let (client_pipe, server_pipe) = MockPipe::pair(backlog.buffer_capacity);
+
+        let server = Self {
+            pipe: server_pipe,
+            local_name: name.to_owned(),
+            peer_name: client_name.clone(),
+        };
+
+        backlog
+            .pending
+            .lock()
+            .unwrap()
+            .push_back((server, client_name.clone()));
+        backlog.can_accept.notify_one();
+
+        Ok(Self {
+            pipe: client_pipe,
+            local_name: client_name,
+            peer_name: name.to_owned(),
+        })
+    }
+
+    /// Returns the name of the local end of the connection.
+    pub fn local_name(&self) -> &str {
+        &self.local_name
+    }
+
+    /// Returns the name of the remote end of the connection.
+    pub fn peer_name(&self) -> &str {
+        &self.peer_name
+    }
+
+    /// Returns a reference to the underlying pipe.
+    pub fn pipe(&self) -> &MockPipe {
+        &self.pipe
+    }
+
+    /// Consumes the stream, returning the underlying pipe.
+    pub fn into_inner(self) -> MockPipe {
+        self.pipe
+    }
+
+    /// Gets the current timeout duration for read/write operations.
+    pub fn timeout(&self) -> Option<Duration> {
+        self.pipe.timeout()
+    }
+
+    /// Sets the timeout duration for read/write operations.
+    pub fn set_timeout(&self, timeout: Option<Duration>) {
+        self.pipe.set_timeout(timeout);
+    }
+}
+
+impl io::Read for MockTcpStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.pipe.read(buf)
+    }
+}
+
+impl io::Write for MockTcpStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.pipe.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.pipe.flush()
+    }
+}
+
+/// Queue of datagrams received by a `MockUdpSocket`.
+struct Inbox {
+    queue_len: usize,
+    datagrams: Mutex<VecDeque<(Vec<u8>, String)>>,
+    can_recv: Condvar,
+}
+
+/// Fault simulation settings applied to datagrams sent by a `MockUdpSocket`.
+struct Faults {
+    loss: f64,
+    duplication: f64,
+    seed: u64,
+    rng: Rng,
+}
+
+/// A mock UDP socket exchanging datagrams with other `MockUdpSocket`s bound
+/// in the same process.
+///
+/// As with real UDP, datagrams sent to a name nobody is bound to, or to a
+/// socket whose queue is full, are silently discarded. Outgoing datagrams can
+/// additionally be lost or duplicated with configurable probabilities, driven
+/// by a seeded pseudo-random generator so that failures can be replayed. The
+/// seed derives from the master seed (see [`set_seed`](crate::set_seed)) and the
+/// socket name, unless set with [`MockUdpSocket::set_seed`].
+pub struct MockUdpSocket {
+    name: String,
+    inbox: Arc<Inbox>,
+    timeout: Mutex<Option<Duration>>,
+    faults: Mutex<Faults>,
+}
+
+impl MockUdpSocket {
+    /// Binds a socket to the given name using the default queue length.
+    ///
+    /// Returns an `AddrInUse` error if another socket is bound to the name.
+    pub fn bind(name: &str) -> io::Result<Self> {
+        Self::bind_with_queue_len(name, DEFAULT_DATAGRAM_QUEUE_LEN)
+    }
+
+    /// Binds a socket to the given name, queueing at most `queue_len` received
+    /// datagrams.
+    pub fn bind_with_queue_len(name: &str, queue_len: usize) -> io::Result<Self> {
+        let mut sockets = UDP_SOCKETS.lock().unwrap();
+
+        sockets.retain(|(_, inbox)| inbox.strong_count() > 0);
+
+        if sockets.iter().any(|(bound_name, _)| bound_name == name) {
+            return Err(io::Error::from(io::ErrorKind::AddrInUse));
+        }
+
+        let inbox = Arc::new(Inbox {
+            queue_len,
+            datagrams: Mutex::new(VecDeque::new()),
+            can_recv: Condvar::new(),
+        });
+
+        sockets.push((name.to_owned(), Arc::downgrade(&inbox)));
+
+        let seed = Rng::derive_seed(name);
+
+        Ok(Self {
+            name: name.to_owned(),
+            inbox,
+            // Non-blocking by default, as `MockPipe`
+            timeout: Mutex::new(Some(Duration::ZERO)),
+            faults: Mutex::new(Faults {
+                loss: 0.0,
+                duplication: 0.0,
+                seed,
+                rng: Rng::new(seed),
+            }),
+        })
+    }
+
+    /// Returns the name this socket is bound to.
+    pub fn local_name(&self) -> &str {
+        &self.name
+    }
+
+    /// Gets the current timeout duration for `recv_from`.
+    pub fn timeout(&self) -> Option<Duration> {
+        *self.timeout.lock().unwrap()
+    }
+
+    /// Sets the timeout duration for `recv_from`.
+    ///
+    /// `None` means `recv_from` blocks indefinitely. `Some(Duration::ZERO)`
+    /// means it is non-blocking and fails with `WouldBlock` when no datagram
+    /// is queued.
+    pub fn set_timeout(&self, timeout: Option<Duration>) {
+        *self.timeout.lock().unwrap() = timeout;
+    }
+
+    /// Sets the timeout duration for `recv_from` and returns the modified socket.
+    pub fn with_timeout(self, timeout: Option<Duration>) -> Self {
+        self.set_timeout(timeout);
+        self
+    }
+
+    /// Sets the probability (`0.0..=1.0`) of an outgoing datagram being lost.
+    pub fn set_loss(&self, probability: f64) {
+        self.faults.lock().unwrap().loss = probability;
+    }
+
+    /// Sets the probability (`0.0..=1.0`) of an outgoing datagram being
+    /// delivered twice.
+    pub fn set_duplication(&self, probability: f64) {
+        self.faults.lock().unwrap().duplication = probability;
+    }
+
+    /// Gets the seed of the generator driving loss and duplication.
+    pub fn seed(&self) -> u64 {
+        self.faults.lock().unwrap().seed
+    }
+
+    /// Reseeds the generator driving loss and duplication, making the
+    /// sequence of simulated faults reproducible.
+    pub fn set_seed(&self, seed: u64) {
+        let mut faults = self.faults.lock().unwrap();
+        faults.seed = seed;
+        faults.rng = Rng::new(seed);
+    }
+
+    /// Sends a datagram to the socket bound to the given name.
+    ///
+    /// Returns the number of bytes sent, which is always the whole buffer.
+    pub fn send_to(&self, buf: &[u8], name: &str) -> io::Result<usize> {
+        let copies = {
+            let mut faults = self.faults.lock().unwrap();
+            let Faults {
+                loss,
+                duplication,
+                rng,
+                ..
+            } = &mut *faults;
+
+            if rng.chance(*loss) {
+                0
+            } else if rng.chance(*duplication) {
+                2
+            } else {
+                1
+            }
+        };
+
+        let inbox = UDP_SOCKETS
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|(bound_name, _)| bound_name == name)
+            .and_then(|(_, inbox)| inbox.upgrade());
+
+        if let Some(inbox) = inbox {
+            let mut datagrams = inbox.datagrams.lock().unwrap();
+
+            for _ in 0..copies {
+                if datagrams.len() < inbox.queue_len {
+                    datagrams.push_back((buf.to_vec(), self.name.clone()));
+                    inbox.can_recv.notify_one();
+                }
+            }
+        }
+
+        Ok(buf.len())
+    }
+
+    /// Receives a datagram, returning the number of bytes read and the name of
+    /// the sender.
+    ///
+    /// If the datagram does not fit into `buf`, the excess bytes are discarded.
+    /// Returns a `WouldBlock` error in non-blocking mode and a `TimedOut` error
+    /// if no datagram arrives within the timeout.
+    pub fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, String)> {
+        let (datagram, sender) = wait_for_item(
+            self.inbox.datagrams.lock().unwrap(),
+            &self.inbox.can_recv,
+            self.timeout(),
+        )?;
+
+        let len = buf.len().min(datagram.len());
+        buf[0..len].copy_from_slice(&datagram[0..len]);
+
+        Ok((len, sender))
+    }
+}
+
+impl Drop for MockUdpSocket {
+    fn drop(&mut self) {
+        UDP_SOCKETS
+            .lock()
+            .unwrap()
+            .retain(|(name, _)| name != &self.name);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Read, Write},
+        thread,
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_connect_and_accept() {
+        let listener = MockTcpListener::bind("test_connect_and_accept").unwrap();
+
+        let mut client = MockTcpStream::connect("test_connect_and_accept").unwrap();
+        let (mut server, client_name) = listener.accept().unwrap();
+
+        assert_eq!(client_name, client.local_name());
+        assert_eq!(server.peer_name(), client.local_name());
+        assert_eq!(client.peer_name(), "test_connect_and_accept");
+
+        client.write_all(b"ping").unwrap();
+
+        let mut read_data = [0u8; 4];
+        server.read_exact(&mut read_data).unwrap();
+        assert_eq!(&read_data, b"ping");
+    }
+
+    #[test]
+    fn test_connect_refused() {
+        assert_eq!(
+            MockTcpStream::connect("test_connect_refused")
+                .err()
+                .unwrap()
+                .kind(),
+            io::ErrorKind::ConnectionRefused
+        );
+
+        let listener = MockTcpListener::bind("test_connect_refused").unwrap();
+        drop(listener);
+
+        // The name is released when the listener is dropped
+        assert_eq!(
+            MockTcpStream::connect("test_connect_refused")
+                .err()
+                .unwrap()
+                .kind(),
+            io::ErrorKind::ConnectionRefused
+        );
+    }
+
+    #[test]
+    fn test_bind_in_use() {
+        let _listener = MockTcpListener::bind("test_bind_in_use").unwrap();
+
+        assert_eq!(
+            MockTcpListener::bind("test_bind_in_use")
+                .err()
+                .unwrap()
+                .kind(),
+            io::ErrorKind::AddrInUse
+        );
+    }
+
+    #[test]
+    fn test_accept_timeout() {
+        let listener = MockTcpListener::bind("test_accept_timeout").unwrap();
+
+        assert_eq!(
+            listener.accept().err().unwrap().kind(),
+            io::ErrorKind::WouldBlock
+        );
+
+        let listener = listener.with_timeout(Some(Duration::from_millis(100)));
+
+        assert_eq!(
+            listener.accept().err().unwrap().kind(),
+            io::ErrorKind::TimedOut
+        );
+
+        let client = thread::spawn(|| {
+            thread::sleep(Duration::from_millis(50));
+            MockTcpStream::connect("test_accept_timeout").unwrap()
+        });
+
+        let listener = listener.with_timeout(None);
+        listener.accept().unwrap();
+
+        client.join().unwrap();
+    }
+
+    #[test]
+    fn test_udp_send_recv() {
+        let socket1 = MockUdpSocket::bind("test_udp_send_recv_1").unwrap();
+        let socket2 = MockUdpSocket::bind("test_udp_send_recv_2").unwrap();
+
+        assert_eq!(
+            socket2.recv_from(&mut [0u8; 8]).unwrap_err().kind(),
+            io::ErrorKind::WouldBlock
+        );
+
+        socket1.send_to(b"hello", "test_udp_send_recv_2").unwrap();
+        socket1.send_to(b"world", "test_udp_send_recv_2").unwrap();
+
+        // Sending to an unbound name silently discards the datagram
+        socket1.send_to(b"lost", "test_udp_send_recv_3").unwrap();
+
+        let mut read_data = [0u8; 8];
+        let (len, sender) = socket2.recv_from(&mut read_data).unwrap();
+        assert_eq!(&read_data[0..len], b"hello");
+        assert_eq!(sender, "test_udp_send_recv_1");
+
+        // Datagrams are truncated to the buffer size
+        let mut read_data = [0u8; 3];
+        let (len, _) = socket2.recv_from(&mut read_data).unwrap();
+        assert_eq!(&read_data[0..len], b"wor");
+
+        let socket2 = socket2.with_timeout(Some(Duration::from_millis(50)));
+        assert_eq!(
+            socket2.recv_from(&mut read_data).unwrap_err().kind(),
+            io::ErrorKind::TimedOut
+        );
+    }
+
+    #[test]
+    fn test_udp_loss_and_duplication() {
+        let sender = MockUdpSocket::bind("test_udp_loss_and_duplication_1").unwrap();
+        let receiver = MockUdpSocket::bind("test_udp_loss_and_duplication_2").unwrap();
+
+        let count_received = |loss, duplication, seed| {
+            sender.set_loss(loss);
+            sender.set_duplication(duplication);
+            sender.set_seed(seed);
+
+            for _ in 0..100 {
+                sender
+                    .send_to(b"x", "test_udp_loss_and_duplication_2")
+                    .unwrap();
+            }
+
+            let mut count = 0;
+            while receiver.recv_from(&mut [0u8; 1]).is_ok() {
+                count += 1;
+            }
+            count
+        };
+
+        assert_eq!(count_received(0.0, 0.0, 1), 100);
+        assert_eq!(count_received(1.0, 0.0, 1), 0);
+        assert_eq!(count_received(0.0, 1.0, 1), 200);
+
+        // The same seed produces the same faults
+        let lossy = count_received(0.5, 0.0, 7);
+        assert!(lossy > 0 && lossy < 100);
+        assert_eq!(count_received(0.5, 0.0, 7), lossy);
+    }
+}
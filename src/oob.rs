@@ -0,0 +1,81 @@
+//! Out-of-band sideband channel of a pipe.
+
+use std::io;
+
+use crate::MockPipe;
+
+impl MockPipe {
+    /// Sends an out-of-band message to the other end.
+    ///
+    /// Out-of-band messages travel on a separate lane of the direction, ahead
+    /// of the stream data: they are received with
+    /// [`recv_oob`](MockPipe::recv_oob) regardless of the data still waiting to
+    /// be read, like TCP urgent data or the control-plane messages of some
+    /// device protocols. They don't count against the buffer capacity and
+    /// bypass the simulated link.
+    ///
+    /// Returns a `BrokenPipe` error if the pipe is closed.
+    pub fn send_oob(&self, message: &[u8]) -> io::Result<()> {
+        self.write_buffer.send_oob(message)
+    }
+
+    /// Receives the next out-of-band message sent by the other end into `buf`,
+    /// waiting for it according to the timeout. A message not fitting into
+    /// `buf` is truncated.
+    ///
+    /// Returns the length of the message, or `0` once the pipe is closed and
+    /// no message is left (or when no message is available in non-blocking
+    /// mode).
+    pub fn recv_oob(&self, buf: &mut [u8]) -> io::Result<usize> {
+        self.read_buffer
+            .recv_oob(buf, self.real_timeout(self.timeout()))
+    }
+
+    /// Returns the number of out-of-band messages sent by the other end and
+    /// not received yet.
+    pub fn oob_len(&self) -> usize {
+        self.read_buffer.oob_len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Read, Write},
+        time::Duration,
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_oob_ahead_of_stream() {
+        let (mut host, mut device) = MockPipe::pair(4);
+
+        // The control message overtakes the stream data filling the buffer
+        host.write_all(b"data").unwrap();
+        host.send_oob(b"ABORT").unwrap();
+        assert_eq!(device.oob_len(), 1);
+
+        let mut message = [0u8; 8];
+        assert_eq!(device.recv_oob(&mut message).unwrap(), 5);
+        assert_eq!(&message[0..5], b"ABORT");
+        assert_eq!(device.recv_oob(&mut message).unwrap(), 0);
+
+        let mut data = [0u8; 4];
+        device.read_exact(&mut data).unwrap();
+        assert_eq!(&data, b"data");
+
+        device.set_timeout(Some(Duration::from_millis(10)));
+        assert_eq!(
+            device.recv_oob(&mut message).unwrap_err().kind(),
+            io::ErrorKind::TimedOut
+        );
+
+        host.close();
+        assert_eq!(device.recv_oob(&mut message).unwrap(), 0);
+        assert_eq!(
+            host.send_oob(b"late").unwrap_err().kind(),
+            io::ErrorKind::BrokenPipe
+        );
+    }
+}
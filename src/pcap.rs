@@ -0,0 +1,229 @@
+//! Capture of pipe traffic in the pcapng format.
+//!
+//! [`PcapRecorder`] wraps an endpoint and writes every chunk of data read from
+//! or written to it as an Enhanced Packet Block, tagged with its direction and
+//! a timestamp. The link type is chosen by the user (e.g. one of the
+//! `LINKTYPE_USER*` values mapped to a protocol dissector in Wireshark), so
+//! captured mock sessions can be inspected with existing tools.
+
+use std::{
+    fs::File,
+    io::{self, BufWriter, Read, Write},
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::Direction;
+
+/// The first of the link types reserved for private use (`LINKTYPE_USER0`).
+pub const LINKTYPE_USER0: u16 = 147;
+
+const SECTION_HEADER_BLOCK: u32 = 0x0a0d_0d0a;
+const INTERFACE_DESCRIPTION_BLOCK: u32 = 0x0000_0001;
+const ENHANCED_PACKET_BLOCK: u32 = 0x0000_0006;
+const BYTE_ORDER_MAGIC: u32 = 0x1a2b_3c4d;
+
+const OPT_ENDOFOPT: u16 = 0;
+const OPT_EPB_FLAGS: u16 = 2;
+
+/// Writes pcapng sections with a single interface of the given link type.
+pub struct PcapngWriter<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> PcapngWriter<W> {
+    /// Creates a writer, emitting the section header and interface description
+    /// blocks. Timestamps use the default microsecond resolution.
+    pub fn new(mut writer: W, link_type: u16) -> io::Result<Self> {
+        // Section Header Block: byte-order magic, version 1.0, unknown length
+        let mut body = Vec::with_capacity(16);
+        body.extend_from_slice(&BYTE_ORDER_MAGIC.to_le_bytes());
+        body.extend_from_slice(&1u16.to_le_bytes());
+        body.extend_from_slice(&0u16.to_le_bytes());
+        body.extend_from_slice(&(-1i64).to_le_bytes());
+        write_block(&mut writer, SECTION_HEADER_BLOCK, &body)?;
+
+        // Interface Description Block: link type, reserved, no snapshot limit
+        let mut body = Vec::with_capacity(8);
+        body.extend_from_slice(&link_type.to_le_bytes());
+        body.extend_from_slice(&0u16.to_le_bytes());
+        body.extend_from_slice(&0u32.to_le_bytes());
+        write_block(&mut writer, INTERFACE_DESCRIPTION_BLOCK, &body)?;
+
+        Ok(Self { writer })
+    }
+
+    /// Writes a packet transferred in the given direction, timestamped with the
+    /// current time.
+    pub fn write_packet(&mut self, direction: Direction, data: &[u8]) -> io::Result<()> {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_micros() as u64;
+
+        let padding = (4 - data.len() % 4) % 4;
+
+        let mut body = Vec::with_capacity(20 + data.len() + padding + 12);
+        body.extend_from_slice(&0u32.to_le_bytes());
+        body.extend_from_slice(&((timestamp >> 32) as u32).to_le_bytes());
+        body.extend_from_slice(&(timestamp as u32).to_le_bytes());
+        body.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        body.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        body.extend_from_slice(data);
+        body.resize(body.len() + padding, 0);
+
+        // Direction is stored in the two lowest bits of the flags option
+        let flags: u32 = match direction {
+            Direction::Inbound => 0b01,
+            Direction::Outbound => 0b10,
+        };
+        body.extend_from_slice(&OPT_EPB_FLAGS.to_le_bytes());
+        body.extend_from_slice(&4u16.to_le_bytes());
+        body.extend_from_slice(&flags.to_le_bytes());
+        body.extend_from_slice(&OPT_ENDOFOPT.to_le_bytes());
+        body.extend_from_slice(&0u16.to_le_bytes());
+
+        write_block(&mut self.writer, ENHANCED_PACKET_BLOCK, &body)
+    }
+
+    /// Flushes the underlying writer.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    /// Consumes the pcapng writer, returning the underlying writer.
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+/// Writes a block with the given type and (already padded) body.
+fn write_block<W: Write>(writer: &mut W, block_type: u32, body: &[u8]) -> io::Result<()> {
+    let total_len = (body.len() + 12) as u32;
+
+    writer.write_all(&block_type.to_le_bytes())?;
+    writer.write_all(&total_len.to_le_bytes())?;
+    writer.write_all(body)?;
+    writer.write_all(&total_len.to_le_bytes())
+}
+
+/// An endpoint wrapper recording all transferred data into a pcapng capture.
+///
+/// Data read from the wrapped endpoint is recorded as inbound, data written to
+/// it as outbound.
+pub struct PcapRecorder<T, W: Write> {
+    inner: T,
+    pcap: PcapngWriter<W>,
+}
+
+impl<T> PcapRecorder<T, BufWriter<File>> {
+    /// Creates a recorder writing the capture into a new file at `path`.
+    pub fn create<P: AsRef<Path>>(inner: T, path: P, link_type: u16) -> io::Result<Self> {
+        Self::new(inner, BufWriter::new(File::create(path)?), link_type)
+    }
+}
+
+impl<T, W: Write> PcapRecorder<T, W> {
+    /// Creates a recorder writing the capture into `writer`.
+    pub fn new(inner: T, writer: W, link_type: u16) -> io::Result<Self> {
+        Ok(Self {
+            inner,
+            pcap: PcapngWriter::new(writer, link_type)?,
+        })
+    }
+
+    /// Returns a reference to the wrapped endpoint.
+    pub fn get_ref(&self) -> &T {
+        &self.inner
+    }
+
+    /// Consumes the recorder, flushing the capture and returning the wrapped
+    /// endpoint and the capture writer.
+    pub fn into_inner(mut self) -> io::Result<(T, W)> {
+        self.pcap.flush()?;
+        Ok((self.inner, self.pcap.into_inner()))
+    }
+}
+
+impl<T: Read, W: Write> Read for PcapRecorder<T, W> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        if n > 0 {
+            self.pcap.write_packet(Direction::Inbound, &buf[0..n])?;
+        }
+        Ok(n)
+    }
+}
+
+impl<T: Write, W: Write> Write for PcapRecorder<T, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        if n > 0 {
+            self.pcap.write_packet(Direction::Outbound, &buf[0..n])?;
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()?;
+        self.pcap.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MockPipe;
+
+    fn u32_at(data: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn test_pcap_recorder() {
+        let (pipe1, mut pipe2) = MockPipe::pair(64);
+
+        let mut recorder = PcapRecorder::new(pipe1, Vec::new(), LINKTYPE_USER0).unwrap();
+
+        recorder.write_all(b"hello").unwrap();
+
+        let mut read_data = [0u8; 5];
+        pipe2.read_exact(&mut read_data).unwrap();
+        pipe2.write_all(b"ok").unwrap();
+
+        let mut read_data = [0u8; 2];
+        recorder.read_exact(&mut read_data).unwrap();
+
+        let (_, capture) = recorder.into_inner().unwrap();
+
+        // Section header
+        assert_eq!(u32_at(&capture, 0), SECTION_HEADER_BLOCK);
+        assert_eq!(u32_at(&capture, 8), BYTE_ORDER_MAGIC);
+        let mut offset = u32_at(&capture, 4) as usize;
+
+        // Interface description
+        assert_eq!(u32_at(&capture, offset), INTERFACE_DESCRIPTION_BLOCK);
+        assert_eq!(
+            u16::from_le_bytes([capture[offset + 8], capture[offset + 9]]),
+            LINKTYPE_USER0
+        );
+        offset += u32_at(&capture, offset + 4) as usize;
+
+        // Packets
+        for (data, flags) in [(&b"hello"[..], 0b10), (&b"ok"[..], 0b01)] {
+            assert_eq!(u32_at(&capture, offset), ENHANCED_PACKET_BLOCK);
+            let block_len = u32_at(&capture, offset + 4) as usize;
+            assert_eq!(u32_at(&capture, offset + block_len - 4) as usize, block_len);
+
+            let captured_len = u32_at(&capture, offset + 20) as usize;
+            assert_eq!(&capture[offset + 28..offset + 28 + captured_len], data);
+
+            let options = offset + 28 + (captured_len + 3) / 4 * 4;
+            assert_eq!(u32_at(&capture, options + 4), flags);
+
+            offset += block_len;
+        }
+
+        assert_eq!(offset, capture.len());
+    }
+}
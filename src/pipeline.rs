@@ -0,0 +1,332 @@
+//! Pipelines of pipes with transformer stages between them.
+//!
+//! A [`Transform`] stage reads the data coming out of one pipe endpoint,
+//! transforms it and writes the result into another endpoint, on a background
+//! thread. Stages are connected with [`MockPipe::pipe_through`], or chained
+//! with a [`Pipeline`], so layered encodings can be assembled from small
+//! pieces:
+//!
+//! ```
+//! use std::{
+//!     io::{Read, Write},
+//!     time::Duration,
+//! };
+//!
+//! use mockpipe::pipeline::Pipeline;
+//!
+//! let (mut input, mut output, _stages) = Pipeline::new(64)
+//!     .stage(|data: &[u8]| data.to_ascii_uppercase())
+//!     .stage(|data: &[u8]| data.iter().rev().copied().collect())
+//!     .start();
+//!
+//! input.write_all(b"a").unwrap();
+//! output.set_timeout(Some(Duration::from_secs(1)));
+//!
+//! let mut data = [0u8; 1];
+//! output.read_exact(&mut data).unwrap();
+//! assert_eq!(&data, b"A");
+//! ```
+//!
+//! Ready-made stages include [`LineEndings`], translating line endings like
+//! terminal devices and modems do, the [`Scrambler`], emulating link-layer
+//! whitening or encrypted-looking traffic, and with the `flate2` feature the
+//! `Compress` and `Decompress` stages, simulating compressed transports.
+//!
+//! A [`Splitter`] passes full copies of a stream on to several endpoints, each
+//! with its own backpressure policy.
+
+use std::{
+    io,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+use crate::{
+    bridge::{self, Bridge},
+    MockPipe,
+};
+
+#[cfg(feature = "flate2")]
+mod flate;
+mod line;
+mod scramble;
+mod split;
+
+#[cfg(feature = "flate2")]
+pub use flate::{Compress, Decompress, FlateFormat};
+pub use line::LineEndings;
+pub use scramble::Scrambler;
+pub use split::Splitter;
+
+/// Interval at which the stage threads check whether they were stopped.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Size of the chunks read by the stage threads.
+const CHUNK_SIZE: usize = 4096;
+
+/// A transformer stage of a pipeline.
+///
+/// Implemented for closures transforming each chunk of the stream on its own.
+/// An error fails the stage.
+pub trait Transform: Send + 'static {
+    /// Transforms a chunk of the stream, returning the data to pass on.
+    fn transform(&mut self, data: &[u8]) -> io::Result<Vec<u8>>;
+
+    /// Returns the data to pass on once the stream ends, e.g. the data held
+    /// back by the stage. Nothing by default.
+    fn finish(&mut self) -> io::Result<Vec<u8>> {
+        Ok(Vec::new())
+    }
+
+    /// Returns the data to send back upstream, to the writer of the stream,
+    /// like the local echo of a terminal. Called after each chunk, nothing by
+    /// default.
+    ///
+    /// The writer has to read the echoed data, or the stage blocks once the
+    /// buffer carrying it is full.
+    fn take_echo(&mut self) -> Vec<u8> {
+        Vec::new()
+    }
+}
+
+impl<F> Transform for F
+where
+    F: FnMut(&[u8]) -> Vec<u8> + Send + 'static,
+{
+    fn transform(&mut self, data: &[u8]) -> io::Result<Vec<u8>> {
+        Ok(self(data))
+    }
+}
+
+/// Builder of a chain of transformer stages, each running on its own thread.
+pub struct Pipeline {
+    /// Capacity of the buffers of the pipes between the stages.
+    capacity: usize,
+
+    /// Stages, in the order the data goes through them.
+    stages: Vec<Box<dyn Transform>>,
+}
+
+impl Pipeline {
+    /// Creates a pipeline without stages, connecting the stages with pipes of
+    /// the specified buffer capacity.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            stages: Vec::new(),
+        }
+    }
+
+    /// Appends a stage and returns the modified pipeline.
+    pub fn stage(mut self, transform: impl Transform) -> Self {
+        self.stages.push(Box::new(transform));
+        self
+    }
+
+    /// Starts the stages, returning the input endpoint, the output endpoint
+    /// and the running stages.
+    ///
+    /// The data written to the input endpoint goes through every stage in turn
+    /// and can be read from the output endpoint. Data only flows that way: the
+    /// data written to the output endpoint is never read, and only the first
+    /// stage should echo (see [`Transform::take_echo`]), which can then be read
+    /// from the input endpoint. Closing the input endpoint closes every stage
+    /// in turn, up to the output endpoint. The stages stop when the returned
+    /// [`Bridge`] is stopped or dropped.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the pipeline has no stage.
+    pub fn start(self) -> (MockPipe, MockPipe, Bridge) {
+        assert!(!self.stages.is_empty(), "pipeline without stages");
+
+        let running = Arc::new(AtomicBool::new(true));
+        let (input, mut upstream) = MockPipe::pair(self.capacity);
+        let mut threads = Vec::new();
+
+        let count = self.stages.len();
+        for (index, transform) in self.stages.into_iter().enumerate() {
+            let (downstream, next) = MockPipe::pair(self.capacity);
+            let last = index + 1 == count;
+            threads.push(spawn_stage(upstream, transform, downstream, last, &running));
+            upstream = next;
+        }
+
+        (input, upstream, Bridge::new(running, threads))
+    }
+}
+
+impl MockPipe {
+    /// Starts a transformer stage on a background thread: the data read from
+    /// the endpoint is transformed and written into `downstream`.
+    ///
+    /// When the endpoint reaches end of stream, the data returned by
+    /// [`Transform::finish`] is written and `downstream` is closed. The stage
+    /// stops then, when it fails, or when the returned [`Bridge`] is stopped
+    /// or dropped. No other code should read from the endpoint meanwhile.
+    pub fn pipe_through(&self, transform: impl Transform, downstream: &MockPipe) -> Bridge {
+        let running = Arc::new(AtomicBool::new(true));
+        let thread = spawn_stage(
+            self.clone(),
+            Box::new(transform),
+            downstream.clone(),
+            true,
+            &running,
+        );
+
+        Bridge::new(running, vec![thread])
+    }
+}
+
+/// Spawns the thread running a stage, which clears `running` when the stage
+/// fails, or when it reaches end of stream if it is the `last` one. A failing
+/// stage closes `downstream`.
+fn spawn_stage(
+    upstream: MockPipe,
+    mut transform: Box<dyn Transform>,
+    downstream: MockPipe,
+    last: bool,
+    running: &Arc<AtomicBool>,
+) -> thread::JoinHandle<io::Result<()>> {
+    let running = running.clone();
+
+    thread::spawn(move || {
+        let result = run_stage(&upstream, transform.as_mut(), &downstream, &running);
+        if result.is_err() {
+            downstream.close();
+        }
+        if last || result.is_err() {
+            running.store(false, Ordering::SeqCst);
+        }
+        result
+    })
+}
+
+/// Pumps the data read from `upstream` through `transform` into `downstream`
+/// while the stage is running.
+fn run_stage(
+    upstream: &MockPipe,
+    transform: &mut dyn Transform,
+    downstream: &MockPipe,
+    running: &AtomicBool,
+) -> io::Result<()> {
+    let mut buf = [0u8; CHUNK_SIZE];
+
+    while running.load(Ordering::SeqCst) {
+        let data = match upstream.read_buffer.read(&mut buf, Some(POLL_INTERVAL)) {
+            Ok(0) if upstream.read_buffer.is_closed() => {
+                bridge::write_into_pipe(downstream, &transform.finish()?, running)?;
+                downstream.close();
+                break;
+            }
+            // Woken up by a clear
+            Ok(0) => continue,
+            Ok(n) => transform.transform(&buf[0..n])?,
+            Err(err) if err.kind() == io::ErrorKind::TimedOut => continue,
+            Err(err) => return Err(err),
+        };
+
+        bridge::write_into_pipe(upstream, &transform.take_echo(), running)?;
+        bridge::write_into_pipe(downstream, &data, running)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+
+    use super::*;
+
+    /// Stage holding the data back until the end of the stream.
+    struct Hold(Vec<u8>);
+
+    impl Transform for Hold {
+        fn transform(&mut self, data: &[u8]) -> io::Result<Vec<u8>> {
+            self.0.extend_from_slice(data);
+            Ok(Vec::new())
+        }
+
+        fn finish(&mut self) -> io::Result<Vec<u8>> {
+            Ok(std::mem::take(&mut self.0))
+        }
+    }
+
+    #[test]
+    fn test_pipeline() {
+        let (mut input, mut output, stages) = Pipeline::new(16)
+            .stage(|data: &[u8]| data.iter().map(|byte| byte + 1).collect())
+            .stage(Hold(Vec::new()))
+            .stage(|data: &[u8]| data.to_ascii_lowercase())
+            .start();
+
+        input.set_timeout(None);
+        input.write_all(b"HAL 9000").unwrap();
+        input.write_all(&[b'!'; 40]).unwrap();
+        input.close();
+
+        output.set_timeout(None);
+        let mut data = Vec::new();
+        output.read_to_end(&mut data).unwrap();
+
+        let mut expected = b"ibm!:111".to_vec();
+        expected.extend_from_slice(&[b'"'; 40]);
+        assert_eq!(data, expected);
+        stages.wait().unwrap();
+    }
+
+    #[test]
+    fn test_pipe_through() {
+        let (mut host, device) = MockPipe::pair(16);
+        let (upper, mut reader) = MockPipe::pair(16);
+
+        let stage = device.pipe_through(|data: &[u8]| data.to_ascii_uppercase(), &upper);
+        host.write_all(b"at+cgmi").unwrap();
+
+        reader.set_timeout(None);
+        let mut data = [0u8; 7];
+        reader.read_exact(&mut data).unwrap();
+        assert_eq!(&data, b"AT+CGMI");
+
+        assert!(stage.is_running());
+        stage.stop().unwrap();
+    }
+
+    #[test]
+    fn test_stage_clear_wakeup() {
+        use std::thread;
+
+        use crate::ClearWakeup;
+
+        let (mut host, device) = MockPipe::pair(16);
+        let (upper, mut reader) = MockPipe::pair(16);
+        device.set_clear_wakeup(ClearWakeup::EndOfStream);
+        let stage = device.pipe_through(|data: &[u8]| data.to_ascii_uppercase(), &upper);
+
+        // Clearing the input wakes the stage up without ending the stream
+        for _ in 0..5 {
+            thread::sleep(Duration::from_millis(20));
+            host.clear_write();
+        }
+        assert!(stage.is_running());
+        assert!(!upper.is_closed());
+
+        host.write_all(b"ati").unwrap();
+        reader.set_timeout(None);
+        let mut data = [0u8; 3];
+        reader.read_exact(&mut data).unwrap();
+        assert_eq!(&data, b"ATI");
+        stage.stop().unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "pipeline without stages")]
+    fn test_empty_pipeline() {
+        Pipeline::new(16).start();
+    }
+}
@@ -0,0 +1,198 @@
+//! Compression stages, based on `flate2`.
+
+use std::{
+    io::{self, Write},
+    mem,
+};
+
+use flate2::{
+    write::{DeflateDecoder, DeflateEncoder, GzDecoder, GzEncoder, ZlibDecoder, ZlibEncoder},
+    Compression,
+};
+
+use super::Transform;
+
+/// Default compression level, balancing speed and ratio.
+const DEFAULT_LEVEL: u32 = 6;
+
+/// Format of a compressed stream.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum FlateFormat {
+    /// Raw deflate stream.
+    Deflate,
+
+    /// Deflate stream with a zlib header and checksum.
+    Zlib,
+
+    /// Deflate stream with a gzip header and trailer.
+    Gzip,
+}
+
+/// A `flate2` encoder or decoder writing into a `Vec<u8>`.
+trait Codec: Write + Send {
+    /// Returns the data output so far.
+    fn output(&mut self) -> &mut Vec<u8>;
+
+    /// Completes the stream, outputting the remaining data.
+    fn finish_stream(&mut self) -> io::Result<()>;
+}
+
+macro_rules! impl_codec {
+    ($($codec:ident),*) => {
+        $(
+            impl Codec for $codec<Vec<u8>> {
+                fn output(&mut self) -> &mut Vec<u8> {
+                    self.get_mut()
+                }
+
+                fn finish_stream(&mut self) -> io::Result<()> {
+                    self.try_finish()
+                }
+            }
+        )*
+    };
+}
+
+impl_codec!(
+    DeflateEncoder,
+    ZlibEncoder,
+    GzEncoder,
+    DeflateDecoder,
+    ZlibDecoder,
+    GzDecoder
+);
+
+/// Passes `data` through `codec`, returning the data it outputs.
+fn pass(codec: &mut dyn Codec, data: &[u8]) -> io::Result<Vec<u8>> {
+    codec.write_all(data)?;
+    codec.flush()?;
+    Ok(mem::take(codec.output()))
+}
+
+/// Completes the stream of `codec`, returning the remaining data.
+fn finish(codec: &mut dyn Codec) -> io::Result<Vec<u8>> {
+    codec.finish_stream()?;
+    Ok(mem::take(codec.output()))
+}
+
+/// Stage compressing the stream.
+///
+/// Each chunk is flushed as it passes, so the compressed data flows without
+/// waiting for more input, like on a compressed transport. The stream is
+/// completed when the input reaches end of stream.
+pub struct Compress {
+    format: FlateFormat,
+    encoder: Box<dyn Codec>,
+}
+
+impl Compress {
+    /// Creates a stage compressing into the given format at the default
+    /// level.
+    pub fn new(format: FlateFormat) -> Self {
+        Self::with_level(format, DEFAULT_LEVEL)
+    }
+
+    /// Creates a stage compressing into the given format at the given level,
+    /// from `0` (no compression) to `9` (best compression).
+    pub fn with_level(format: FlateFormat, level: u32) -> Self {
+        let level = Compression::new(level.min(9));
+
+        let encoder: Box<dyn Codec> = match format {
+            FlateFormat::Deflate => Box::new(DeflateEncoder::new(Vec::new(), level)),
+            FlateFormat::Zlib => Box::new(ZlibEncoder::new(Vec::new(), level)),
+            FlateFormat::Gzip => Box::new(GzEncoder::new(Vec::new(), level)),
+        };
+
+        Self { format, encoder }
+    }
+
+    /// Returns the format the stage compresses into.
+    pub fn format(&self) -> FlateFormat {
+        self.format
+    }
+}
+
+impl Transform for Compress {
+    fn transform(&mut self, data: &[u8]) -> io::Result<Vec<u8>> {
+        pass(self.encoder.as_mut(), data)
+    }
+
+    fn finish(&mut self) -> io::Result<Vec<u8>> {
+        finish(self.encoder.as_mut())
+    }
+}
+
+/// Stage decompressing the stream.
+///
+/// Corrupted input fails the stage with an error.
+pub struct Decompress {
+    format: FlateFormat,
+    decoder: Box<dyn Codec>,
+}
+
+impl Decompress {
+    /// Creates a stage decompressing from the given format.
+    pub fn new(format: FlateFormat) -> Self {
+        let decoder: Box<dyn Codec> = match format {
+            FlateFormat::Deflate => Box::new(DeflateDecoder::new(Vec::new())),
+            FlateFormat::Zlib => Box::new(ZlibDecoder::new(Vec::new())),
+            FlateFormat::Gzip => Box::new(GzDecoder::new(Vec::new())),
+        };
+
+        Self { format, decoder }
+    }
+
+    /// Returns the format the stage decompresses from.
+    pub fn format(&self) -> FlateFormat {
+        self.format
+    }
+}
+
+impl Transform for Decompress {
+    fn transform(&mut self, data: &[u8]) -> io::Result<Vec<u8>> {
+        pass(self.decoder.as_mut(), data)
+    }
+
+    fn finish(&mut self) -> io::Result<Vec<u8>> {
+        finish(self.decoder.as_mut())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use super::*;
+    use crate::pipeline::Pipeline;
+
+    #[test]
+    fn test_compression_round_trip() {
+        for format in [FlateFormat::Deflate, FlateFormat::Zlib, FlateFormat::Gzip] {
+            let (mut input, mut compressed, _compress) = Pipeline::new(4096)
+                .stage(Compress::with_level(format, 9))
+                .start();
+            let (mut decompress_input, mut output, decompress) =
+                Pipeline::new(4096).stage(Decompress::new(format)).start();
+
+            let text = b"all work and no play makes jack a dull boy. ".repeat(50);
+            input.set_timeout(None);
+            input.write_all(&text).unwrap();
+            input.close();
+
+            compressed.set_timeout(None);
+            let mut data = Vec::new();
+            compressed.read_to_end(&mut data).unwrap();
+            assert!(data.len() < text.len() / 4);
+
+            decompress_input.set_timeout(None);
+            decompress_input.write_all(&data).unwrap();
+            decompress_input.close();
+
+            output.set_timeout(None);
+            let mut data = Vec::new();
+            output.read_to_end(&mut data).unwrap();
+            assert_eq!(data, text);
+            decompress.wait().unwrap();
+        }
+    }
+}
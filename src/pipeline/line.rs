@@ -0,0 +1,167 @@
+//! Line-ending translation stage.
+
+use std::io;
+
+use super::Transform;
+
+/// Translation performed by a [`LineEndings`] stage.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Translation {
+    /// LF to CRLF.
+    ToCrlf,
+
+    /// CRLF to LF.
+    ToLf,
+}
+
+/// Stage translating line endings, like terminal devices and modems do, so
+/// line-oriented code sees realistic byte sequences. It can also echo the
+/// translated data back to the writer, like a terminal with local echo.
+#[derive(Clone, Debug)]
+pub struct LineEndings {
+    translation: Translation,
+
+    /// Whether the translated data is echoed back.
+    echo: bool,
+
+    /// Translated data not echoed yet.
+    echoed: Vec<u8>,
+
+    /// Whether the last byte seen was a CR, held back when translating to LF.
+    after_cr: bool,
+}
+
+impl LineEndings {
+    fn new(translation: Translation) -> Self {
+        Self {
+            translation,
+            echo: false,
+            echoed: Vec::new(),
+            after_cr: false,
+        }
+    }
+
+    /// Creates a stage translating LF line endings to CRLF, leaving CRLF line
+    /// endings unchanged.
+    pub fn to_crlf() -> Self {
+        Self::new(Translation::ToCrlf)
+    }
+
+    /// Creates a stage translating CRLF line endings to LF, leaving lone CRs
+    /// unchanged.
+    pub fn to_lf() -> Self {
+        Self::new(Translation::ToLf)
+    }
+
+    /// Enables or disables the local echo of the translated data and returns
+    /// the modified stage.
+    pub fn with_echo(mut self, echo: bool) -> Self {
+        self.echo = echo;
+        self
+    }
+
+    /// Translates a chunk of the stream.
+    pub fn translate(&mut self, data: &[u8]) -> Vec<u8> {
+        let mut translated = Vec::with_capacity(data.len());
+
+        for &byte in data {
+            match self.translation {
+                Translation::ToCrlf => {
+                    if (byte == b'\n') && !self.after_cr {
+                        translated.push(b'\r');
+                    }
+                    translated.push(byte);
+                }
+                Translation::ToLf => {
+                    if self.after_cr && (byte != b'\n') {
+                        translated.push(b'\r');
+                    }
+                    if byte != b'\r' {
+                        translated.push(byte);
+                    }
+                }
+            }
+
+            self.after_cr = byte == b'\r';
+        }
+
+        self.echo_back(&translated);
+        translated
+    }
+
+    /// Returns the data held back at the end of the stream: a final CR when
+    /// translating to LF.
+    fn flush(&mut self) -> Vec<u8> {
+        let held_back = if (self.translation == Translation::ToLf) && self.after_cr {
+            vec![b'\r']
+        } else {
+            Vec::new()
+        };
+
+        self.after_cr = false;
+        self.echo_back(&held_back);
+        held_back
+    }
+
+    fn echo_back(&mut self, data: &[u8]) {
+        if self.echo {
+            self.echoed.extend_from_slice(data);
+        }
+    }
+}
+
+impl Transform for LineEndings {
+    fn transform(&mut self, data: &[u8]) -> io::Result<Vec<u8>> {
+        Ok(self.translate(data))
+    }
+
+    fn finish(&mut self) -> io::Result<Vec<u8>> {
+        Ok(self.flush())
+    }
+
+    fn take_echo(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.echoed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+
+    use super::*;
+    use crate::pipeline::Pipeline;
+
+    #[test]
+    fn test_line_endings() {
+        let mut to_crlf = LineEndings::to_crlf();
+        assert_eq!(to_crlf.translate(b"AT\nOK\r"), b"AT\r\nOK\r");
+        assert_eq!(to_crlf.translate(b"\nX\n"), b"\nX\r\n");
+
+        // A CR split from its LF is held back
+        let mut to_lf = LineEndings::to_lf();
+        assert_eq!(to_lf.translate(b"AT\r\nA\rB\r"), b"AT\nA\rB");
+        assert_eq!(to_lf.translate(b"\nOK\r"), b"\nOK");
+        assert_eq!(to_lf.flush(), b"\r");
+    }
+
+    #[test]
+    fn test_local_echo() {
+        let (mut terminal, mut device, stage) = Pipeline::new(64)
+            .stage(LineEndings::to_crlf().with_echo(true))
+            .start();
+
+        terminal.set_timeout(None);
+        device.set_timeout(None);
+
+        terminal.write_all(b"ATZ\n").unwrap();
+        let mut data = [0u8; 5];
+        device.read_exact(&mut data).unwrap();
+        assert_eq!(&data, b"ATZ\r\n");
+
+        let mut echo = [0u8; 5];
+        terminal.read_exact(&mut echo).unwrap();
+        assert_eq!(&echo, b"ATZ\r\n");
+
+        stage.stop().unwrap();
+    }
+}
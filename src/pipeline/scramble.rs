@@ -0,0 +1,98 @@
+//! XOR scrambler stage.
+
+use std::io;
+
+use super::Transform;
+use crate::rng::Rng;
+
+/// Source of the bytes the stream is XORed with.
+#[derive(Clone, Debug)]
+enum Keystream {
+    /// A key repeated over and over, with the position in it.
+    Key(Vec<u8>, usize),
+
+    /// Pseudo-random bytes.
+    Seeded(Rng),
+}
+
+/// Stage XORing the stream with a keystream, like the whitening or the
+/// obfuscation of some link layers.
+///
+/// XORing twice with the same keystream restores the data, so a second
+/// scrambler created the same way descrambles the stream. The keystream runs
+/// on across chunks, so the result does not depend on how the stream is split.
+#[derive(Clone, Debug)]
+pub struct Scrambler {
+    keystream: Keystream,
+}
+
+impl Scrambler {
+    /// Creates a scrambler XORing the stream with `key`, repeated.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the key is empty.
+    pub fn with_key(key: impl Into<Vec<u8>>) -> Self {
+        let key = key.into();
+        assert!(!key.is_empty(), "empty scrambler key");
+
+        Self {
+            keystream: Keystream::Key(key, 0),
+        }
+    }
+
+    /// Creates a scrambler XORing the stream with pseudo-random bytes generated
+    /// from `seed`, making it look encrypted.
+    pub fn seeded(seed: u64) -> Self {
+        Self {
+            keystream: Keystream::Seeded(Rng::new(seed)),
+        }
+    }
+
+    /// XORs `data` with the next bytes of the keystream.
+    pub fn scramble(&mut self, data: &[u8]) -> Vec<u8> {
+        match &mut self.keystream {
+            Keystream::Key(key, position) => data
+                .iter()
+                .map(|byte| {
+                    let scrambled = byte ^ key[*position];
+                    *position = (*position + 1) % key.len();
+                    scrambled
+                })
+                .collect(),
+            Keystream::Seeded(rng) => data
+                .iter()
+                .map(|byte| byte ^ rng.next_u64() as u8)
+                .collect(),
+        }
+    }
+}
+
+impl Transform for Scrambler {
+    fn transform(&mut self, data: &[u8]) -> io::Result<Vec<u8>> {
+        Ok(self.scramble(data))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scrambler() {
+        let data = b"attack at dawn, attack at dawn";
+
+        for scrambler in [Scrambler::with_key(*b"keys"), Scrambler::seeded(9)] {
+            let mut scrambler1 = scrambler.clone();
+            let mut scrambler2 = scrambler;
+
+            // Splitting the stream does not change the result
+            let mut scrambled = scrambler1.scramble(&data[0..7]);
+            scrambled.extend(scrambler1.scramble(&data[7..]));
+            assert_ne!(&scrambled[..], &data[..]);
+            assert_ne!(scrambled[0..15], scrambled[15..30]);
+
+            assert_eq!(&scrambler2.scramble(&scrambled)[..], &data[..]);
+        }
+    }
+}
@@ -0,0 +1,172 @@
+//! Fan-out splitter.
+
+use std::{
+    io,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+};
+
+use super::{CHUNK_SIZE, POLL_INTERVAL};
+use crate::{
+    bridge::{self, Bridge},
+    broadcast::{self, Backpressure},
+    MockPipe,
+};
+
+/// Splitter passing full copies of the data read from one endpoint to several
+/// downstream endpoints, so a single emulated device feed can drive several
+/// consumers under test.
+///
+/// Each output has its own [`Backpressure`] policy: an output with the `Block`
+/// policy holds the feed back until it has room, while the others lose the
+/// newest or the oldest data when their consumer falls behind. Outputs closed
+/// by their consumer are let go, while the others keep receiving the data.
+///
+/// ```
+/// use std::io::{Read, Write};
+///
+/// use mockpipe::{broadcast::Backpressure, pipeline::Splitter, MockPipe};
+///
+/// let (mut device, feed) = MockPipe::pair(64);
+/// let (parser_input, mut parser) = MockPipe::pair(64);
+/// let (logger_input, mut logger) = MockPipe::pair(64);
+///
+/// let splitter = Splitter::new()
+///     .output(&parser_input, Backpressure::Block)
+///     .output(&logger_input, Backpressure::DropOldest)
+///     .start(&feed);
+///
+/// device.write_all(b"$GPGGA").unwrap();
+/// device.close();
+/// splitter.wait().unwrap();
+///
+/// let mut data = Vec::new();
+/// parser.read_to_end(&mut data).unwrap();
+/// assert_eq!(data, b"$GPGGA");
+/// ```
+#[derive(Default)]
+pub struct Splitter {
+    /// Downstream endpoints, with their backpressure policy.
+    outputs: Vec<(MockPipe, Backpressure)>,
+}
+
+impl Splitter {
+    /// Creates a splitter without outputs.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds an output written into `downstream` according to the backpressure
+    /// policy, and returns the modified splitter.
+    pub fn output(mut self, downstream: &MockPipe, backpressure: Backpressure) -> Self {
+        self.outputs.push((downstream.clone(), backpressure));
+        self
+    }
+
+    /// Starts passing the data read from `upstream` on to the outputs on a
+    /// background thread.
+    ///
+    /// When `upstream` reaches end of stream, the outputs are closed. The
+    /// splitter stops then, when every output was closed, when reading
+    /// `upstream` fails, or when the returned [`Bridge`] is stopped or dropped.
+    /// No other code should read from `upstream` meanwhile.
+    pub fn start(self, upstream: &MockPipe) -> Bridge {
+        let running = Arc::new(AtomicBool::new(true));
+        let upstream = upstream.clone();
+        let mut outputs = self.outputs;
+
+        let thread = {
+            let running = running.clone();
+
+            thread::spawn(move || {
+                let result = run_splitter(&upstream, &mut outputs, &running);
+                if result.is_err() {
+                    for (downstream, _) in &outputs {
+                        downstream.close();
+                    }
+                }
+                running.store(false, Ordering::SeqCst);
+                result
+            })
+        };
+
+        Bridge::new(running, vec![thread])
+    }
+}
+
+/// Passes the data read from `upstream` on to the outputs while the splitter
+/// is running and some outputs are left.
+fn run_splitter(
+    upstream: &MockPipe,
+    outputs: &mut Vec<(MockPipe, Backpressure)>,
+    running: &AtomicBool,
+) -> io::Result<()> {
+    let mut buf = [0u8; CHUNK_SIZE];
+
+    while running.load(Ordering::SeqCst) && !outputs.is_empty() {
+        let data = match upstream.read_buffer.read(&mut buf, Some(POLL_INTERVAL)) {
+            Ok(0) => {
+                for (downstream, _) in outputs.iter() {
+                    downstream.close();
+                }
+                break;
+            }
+            Ok(n) => &buf[0..n],
+            Err(err) if err.kind() == io::ErrorKind::TimedOut => continue,
+            Err(err) => return Err(err),
+        };
+
+        outputs.retain(|(downstream, backpressure)| match backpressure {
+            Backpressure::Block => bridge::write_into_pipe(downstream, data, running).is_ok(),
+            _ if downstream.write_buffer.is_closed() => false,
+            _ => {
+                broadcast::offer(&downstream.write_buffer, data, *backpressure);
+                true
+            }
+        });
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+
+    use super::*;
+
+    #[test]
+    fn test_splitter() {
+        let (mut device, feed) = MockPipe::pair(16);
+        let (logger_input, mut logger) = MockPipe::pair(16);
+        let (display_input, mut display) = MockPipe::pair(4);
+        let (monitor_input, mut monitor) = MockPipe::pair(4);
+        let (closed_input, _closed) = MockPipe::pair(4);
+        closed_input.close();
+
+        device.write_all(b"abcdefgh").unwrap();
+        device.close();
+
+        let splitter = Splitter::new()
+            .output(&closed_input, Backpressure::Block)
+            .output(&logger_input, Backpressure::Block)
+            .output(&display_input, Backpressure::DropNewest)
+            .output(&monitor_input, Backpressure::DropOldest)
+            .start(&feed);
+        splitter.wait().unwrap();
+
+        // Every output got the data its policy lets through, and end of stream
+        for (output, expected) in [
+            (&mut logger, &b"abcdefgh"[..]),
+            (&mut display, b"abcd"),
+            (&mut monitor, b"efgh"),
+        ] {
+            let mut data = Vec::new();
+            output.read_to_end(&mut data).unwrap();
+            assert_eq!(data, expected);
+        }
+    }
+}
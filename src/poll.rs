@@ -0,0 +1,267 @@
+//! `mio::event::Source` implementation for `MockPipe`.
+//!
+//! Registering an endpoint in a `mio::Poll` registers a loopback UDP socket in
+//! its place, which the pipe makes readable by sending a datagram to itself
+//! whenever the endpoint becomes readable (data arrives, an error is injected or
+//! the pipe is closed) or writable (space becomes available), so poll-based code
+//! can be tested unchanged:
+//!
+//! ```
+//! use std::{
+//!     io::{self, Read, Write},
+//!     time::Duration,
+//! };
+//!
+//! use mio::{Events, Interest, Poll, Token};
+//! use mockpipe::MockPipe;
+//!
+//! let mut poll = Poll::new().unwrap();
+//! let mut events = Events::with_capacity(8);
+//!
+//! let (mut device, mut host) = MockPipe::pair(64);
+//! poll.registry()
+//!     .register(&mut host, Token(0), Interest::READABLE)
+//!     .unwrap();
+//!
+//! device.write_all(b"ping").unwrap();
+//! poll.poll(&mut events, Some(Duration::from_secs(1))).unwrap();
+//! assert_eq!(events.iter().next().unwrap().token(), Token(0));
+//!
+//! let mut buf = [0u8; 8];
+//! assert_eq!(host.read(&mut buf).unwrap(), 4);
+//! assert_eq!(host.read(&mut buf).unwrap_err().kind(), io::ErrorKind::WouldBlock);
+//! ```
+//!
+//! While an endpoint is registered, its `std::io` operations are non-blocking
+//! like those of a `mio` socket, regardless of the pipe timeout: reads from an
+//! empty pipe and writes into a full pipe fail with `WouldBlock`, an empty read
+//! signals the end of stream only once the pipe is closed, and flushes return
+//! immediately.
+//!
+//! The events are signaled through the readability of the socket, so they are
+//! always reported as readable, whichever readiness changed: handle both
+//! directions on any event for the token.
+
+use std::{
+    io,
+    net::{Ipv4Addr, SocketAddr},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use mio::{event::Source, net::UdpSocket, Interest, Registry, Token};
+
+use crate::{buffer::ReadinessNotify, Error, MockPipe};
+
+/// Loopback UDP socket standing in for a registered endpoint in a `mio::Poll`.
+pub(crate) struct Signal {
+    socket: Mutex<UdpSocket>,
+}
+
+impl Signal {
+    /// Creates a socket connected to itself.
+    fn new() -> io::Result<Self> {
+        let socket = UdpSocket::bind(SocketAddr::from((Ipv4Addr::LOCALHOST, 0)))?;
+        socket.connect(socket.local_addr()?)?;
+
+        Ok(Self {
+            socket: Mutex::new(socket),
+        })
+    }
+
+    /// Makes the socket readable, producing a new event for its token.
+    fn raise(&self) {
+        let socket = self.socket.lock().unwrap();
+
+        // Drain the previous signals until `WouldBlock`, which also rearms the
+        // socket on platforms where `mio` requires it
+        let mut buf = [0u8; 1];
+        while socket.recv(&mut buf).is_ok() {}
+
+        let _ = socket.send(&[0]);
+    }
+}
+
+impl MockPipe {
+    /// Returns `true` if the endpoint is registered in a `mio::Poll`.
+    pub(crate) fn is_registered(&self) -> bool {
+        self.mio_signal.lock().unwrap().is_some()
+    }
+
+    /// Reads data without blocking, reporting an empty open pipe as
+    /// `WouldBlock`.
+    pub(crate) fn read_nonblocking(&self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.read_with_timeout(buf, Some(Duration::ZERO))? {
+            0 if !buf.is_empty() && !self.read_buffer.is_closed() => {
+                Err(io::Error::from(Error::WouldBlock))
+            }
+            n => Ok(n),
+        }
+    }
+
+    /// Writes data without blocking, reporting a full pipe as `WouldBlock`.
+    pub(crate) fn write_nonblocking(&self, buf: &[u8]) -> io::Result<usize> {
+        match self.write_with_timeout(buf, Some(Duration::ZERO))? {
+            0 if !buf.is_empty() => Err(io::Error::from(Error::WouldBlock)),
+            n => Ok(n),
+        }
+    }
+
+    /// Raises `signal` whenever the endpoint becomes ready for the given
+    /// interests (and at once if it already is).
+    fn watch(&self, signal: &Arc<Signal>, interests: Interest) {
+        let notify: ReadinessNotify = {
+            let signal = signal.clone();
+            Arc::new(move || signal.raise())
+        };
+
+        self.read_buffer
+            .set_mio_read_notify(interests.is_readable().then(|| notify.clone()));
+        self.write_buffer
+            .set_mio_write_notify(interests.is_writable().then(|| notify.clone()));
+
+        let readable = (self.read_buffer.len() > 0) || self.read_buffer.is_closed();
+        let writable = self.write_buffer.space() > 0;
+
+        if (interests.is_readable() && readable) || (interests.is_writable() && writable) {
+            signal.raise();
+        }
+    }
+
+    /// Stops raising the readiness signal of the endpoint.
+    fn unwatch(&self) {
+        self.read_buffer.set_mio_read_notify(None);
+        self.write_buffer.set_mio_write_notify(None);
+    }
+}
+
+impl Source for MockPipe {
+    fn register(
+        &mut self,
+        registry: &Registry,
+        token: Token,
+        interests: Interest,
+    ) -> io::Result<()> {
+        let signal = {
+            let mut signal_guard = self.mio_signal.lock().unwrap();
+            if signal_guard.is_some() {
+                return Err(io::Error::from(io::ErrorKind::AlreadyExists));
+            }
+
+            let signal = Arc::new(Signal::new()?);
+            registry.register(
+                &mut *signal.socket.lock().unwrap(),
+                token,
+                Interest::READABLE,
+            )?;
+
+            signal_guard.insert(signal).clone()
+        };
+
+        self.watch(&signal, interests);
+
+        Ok(())
+    }
+
+    fn reregister(
+        &mut self,
+        registry: &Registry,
+        token: Token,
+        interests: Interest,
+    ) -> io::Result<()> {
+        let signal = self
+            .mio_signal
+            .lock()
+            .unwrap()
+            .clone()
+            .ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))?;
+
+        registry.reregister(
+            &mut *signal.socket.lock().unwrap(),
+            token,
+            Interest::READABLE,
+        )?;
+        self.watch(&signal, interests);
+
+        Ok(())
+    }
+
+    fn deregister(&mut self, registry: &Registry) -> io::Result<()> {
+        let signal = self
+            .mio_signal
+            .lock()
+            .unwrap()
+            .take()
+            .ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))?;
+
+        self.unwatch();
+        registry.deregister(&mut *signal.socket.lock().unwrap())?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+
+    use mio::{Events, Poll};
+
+    use super::*;
+
+    /// Polls for events, returning the tokens of the received ones.
+    fn poll_tokens(poll: &mut Poll, timeout: Duration) -> Vec<Token> {
+        let mut events = Events::with_capacity(8);
+        poll.poll(&mut events, Some(timeout)).unwrap();
+        events.iter().map(|event| event.token()).collect()
+    }
+
+    #[test]
+    fn test_mio_source() {
+        let mut poll = Poll::new().unwrap();
+        let (mut device, mut host) = MockPipe::pair(4);
+
+        poll.registry()
+            .register(&mut host, Token(1), Interest::READABLE)
+            .unwrap();
+        assert!(poll_tokens(&mut poll, Duration::from_millis(10)).is_empty());
+
+        let writer = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(10));
+            device.write_all(b"ping").unwrap();
+            device
+        });
+        assert_eq!(poll_tokens(&mut poll, Duration::from_secs(5)), [Token(1)]);
+        let mut device = writer.join().unwrap();
+
+        let mut buf = [0u8; 8];
+        assert_eq!(host.read(&mut buf).unwrap(), 4);
+        assert_eq!(&buf[0..4], b"ping");
+        assert_eq!(
+            host.read(&mut buf).unwrap_err().kind(),
+            io::ErrorKind::WouldBlock
+        );
+
+        // A full pipe becomes writable again once the reader drains it
+        poll.registry()
+            .reregister(&mut host, Token(2), Interest::READABLE | Interest::WRITABLE)
+            .unwrap();
+        assert_eq!(poll_tokens(&mut poll, Duration::from_secs(5)), [Token(2)]);
+
+        assert_eq!(host.write(b"hello").unwrap(), 4);
+        assert_eq!(
+            host.write(b"o").unwrap_err().kind(),
+            io::ErrorKind::WouldBlock
+        );
+        device.read_exact(&mut buf[0..2]).unwrap();
+        assert_eq!(poll_tokens(&mut poll, Duration::from_secs(5)), [Token(2)]);
+        assert_eq!(host.write(b"o").unwrap(), 1);
+
+        device.close();
+        assert_eq!(poll_tokens(&mut poll, Duration::from_secs(5)), [Token(2)]);
+        assert_eq!(host.read(&mut buf).unwrap(), 0);
+
+        poll.registry().deregister(&mut host).unwrap();
+        assert!(!host.is_registered());
+    }
+}
@@ -0,0 +1,240 @@
+//! Round-trip time measurement through a pipe.
+//!
+//! [`measure_rtt`] sends timestamped probes from one end of a pair, echoes them
+//! back from the other end and reports the measured round-trip times, so tests
+//! can check that the simulated link characteristics (see [`link`](crate::link))
+//! produce the intended latency distribution:
+//!
+//! ```
+//! use std::time::Duration;
+//!
+//! use mockpipe::{link::LinkConfig, probe, Direction, MockPipe};
+//!
+//! let (host, device) = MockPipe::pair(64);
+//! let link = LinkConfig::new().with_latency(Duration::from_millis(5));
+//! host.set_link(Direction::Outbound, link);
+//! host.set_link(Direction::Inbound, link);
+//!
+//! let report = probe::measure_rtt(&host, &device, 5, Duration::from_secs(1)).unwrap();
+//! assert!(report.min().unwrap() >= Duration::from_millis(10));
+//! ```
+
+use std::{
+    io,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::MockPipe;
+
+/// Size of a probe: a sequence number followed by the send time.
+const PROBE_SIZE: usize = 16;
+
+/// Interval at which the responder checks whether the measurement is over.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Round-trip times measured by [`measure_rtt`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RttReport {
+    /// Round-trip times of the probes that came back, in the order they were
+    /// sent.
+    pub samples: Vec<Duration>,
+
+    /// Number of probes that did not come back in time, or came back
+    /// corrupted.
+    pub lost: usize,
+}
+
+impl RttReport {
+    /// Returns the shortest round-trip time, if any probe came back.
+    pub fn min(&self) -> Option<Duration> {
+        self.samples.iter().min().copied()
+    }
+
+    /// Returns the longest round-trip time, if any probe came back.
+    pub fn max(&self) -> Option<Duration> {
+        self.samples.iter().max().copied()
+    }
+
+    /// Returns the mean round-trip time, if any probe came back.
+    pub fn mean(&self) -> Option<Duration> {
+        let total: Duration = self.samples.iter().sum();
+        (!self.samples.is_empty()).then(|| total / self.samples.len() as u32)
+    }
+
+    /// Returns the round-trip time below which the given fraction
+    /// (`0.0..=1.0`) of the samples fall, e.g. `0.99` for the 99th percentile.
+    pub fn percentile(&self, fraction: f64) -> Option<Duration> {
+        let mut samples = self.samples.clone();
+        samples.sort();
+
+        let last = samples.len().checked_sub(1)?;
+        let index = (fraction.clamp(0.0, 1.0) * last as f64).round() as usize;
+        Some(samples[index])
+    }
+}
+
+/// Sends `count` probes from `pipe` to its peer `peer`, one at a time, with an
+/// auto-responder echoing them back from `peer`, and reports the round-trip
+/// times.
+///
+/// A probe not echoed back within `timeout` counts as lost. The endpoint
+/// timeouts are not used, and no other code should read from either endpoint
+/// during the measurement.
+pub fn measure_rtt(
+    pipe: &MockPipe,
+    peer: &MockPipe,
+    count: usize,
+    timeout: Duration,
+) -> io::Result<RttReport> {
+    let running = Arc::new(AtomicBool::new(true));
+
+    let responder = {
+        let peer = peer.clone();
+        let running = running.clone();
+        thread::spawn(move || echo_probes(&peer, &running))
+    };
+
+    let result = send_probes(pipe, count, timeout);
+
+    running.store(false, Ordering::SeqCst);
+    let echoed = responder.join().expect("probe responder panicked");
+
+    let report = result?;
+    echoed?;
+
+    Ok(report)
+}
+
+/// Sends the probes and collects their round-trip times.
+fn send_probes(pipe: &MockPipe, count: usize, timeout: Duration) -> io::Result<RttReport> {
+    let start = Instant::now();
+    let mut report = RttReport::default();
+
+    for sequence in 0..count as u64 {
+        let mut probe = [0u8; PROBE_SIZE];
+        probe[0..8].copy_from_slice(&sequence.to_le_bytes());
+        probe[8..16].copy_from_slice(&(start.elapsed().as_nanos() as u64).to_le_bytes());
+
+        write_all(pipe, &probe)?;
+
+        // Skip the late echoes of the probes already counted as lost
+        let deadline = Instant::now() + timeout;
+        let rtt = loop {
+            let echo = match read_probe(pipe, deadline)? {
+                Some(echo) => echo,
+                None => break None,
+            };
+
+            let echo_sequence = u64::from_le_bytes(echo[0..8].try_into().unwrap());
+            let sent_at = u64::from_le_bytes(echo[8..16].try_into().unwrap());
+
+            if echo == probe {
+                break Some(start.elapsed() - Duration::from_nanos(sent_at));
+            } else if echo_sequence >= sequence {
+                // Corrupted on the way
+                break None;
+            }
+        };
+
+        match rtt {
+            Some(rtt) => report.samples.push(rtt),
+            None => report.lost += 1,
+        }
+    }
+
+    Ok(report)
+}
+
+/// Echoes the probes received by `peer` back until the measurement is over.
+fn echo_probes(peer: &MockPipe, running: &AtomicBool) -> io::Result<()> {
+    let mut probe = [0u8; PROBE_SIZE];
+    let mut filled = 0;
+
+    while running.load(Ordering::SeqCst) {
+        match peer.read_with_timeout(&mut probe[filled..], Some(POLL_INTERVAL)) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(err) if err.kind() == io::ErrorKind::TimedOut => continue,
+            Err(err) => return Err(err),
+        }
+
+        if filled == PROBE_SIZE {
+            write_all(peer, &probe)?;
+            filled = 0;
+        }
+    }
+
+    Ok(())
+}
+
+/// Reads a whole probe, or returns `None` if it does not arrive by `deadline`.
+fn read_probe(pipe: &MockPipe, deadline: Instant) -> io::Result<Option<[u8; PROBE_SIZE]>> {
+    let mut probe = [0u8; PROBE_SIZE];
+    let mut filled = 0;
+
+    while filled < PROBE_SIZE {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+
+        match pipe.read_with_timeout(
+            &mut probe[filled..],
+            Some(remaining.max(Duration::from_millis(1))),
+        ) {
+            Ok(0) => return Err(io::Error::from(io::ErrorKind::UnexpectedEof)),
+            Ok(n) => filled += n,
+            Err(err) if err.kind() == io::ErrorKind::TimedOut => {
+                if Instant::now() >= deadline {
+                    return Ok(None);
+                }
+            }
+            Err(err) => return Err(err),
+        }
+    }
+
+    Ok(Some(probe))
+}
+
+/// Writes a whole probe at once, waiting for buffer space if needed.
+fn write_all(pipe: &MockPipe, probe: &[u8]) -> io::Result<()> {
+    match pipe.write_with_timeout(probe, None)? {
+        n if n == probe.len() => Ok(()),
+        _ => Err(io::Error::from(io::ErrorKind::WriteZero)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{link::LinkConfig, Direction};
+
+    #[test]
+    fn test_measure_rtt() {
+        let (host, device) = MockPipe::pair(64);
+
+        let report = measure_rtt(&host, &device, 10, Duration::from_secs(1)).unwrap();
+        assert_eq!(report.samples.len(), 10);
+        assert_eq!(report.lost, 0);
+
+        host.set_link(
+            Direction::Outbound,
+            LinkConfig::new()
+                .with_latency(Duration::from_millis(10))
+                .with_jitter(Duration::from_millis(10)),
+        );
+        host.set_link(
+            Direction::Inbound,
+            LinkConfig::new().with_loss(0.5).with_seed(1),
+        );
+
+        let report = measure_rtt(&host, &device, 10, Duration::from_millis(100)).unwrap();
+        assert_eq!(report.samples.len() + report.lost, 10);
+        assert!(report.lost > 0);
+        assert!(report.min().unwrap() >= Duration::from_millis(10));
+        assert!(report.percentile(0.5).unwrap() <= report.max().unwrap());
+        assert!(report.mean().unwrap() >= report.min().unwrap());
+    }
+}
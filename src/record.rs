@@ -0,0 +1,543 @@
+//! Session recording and replay.
+//!
+//! A [`Recorder`] wraps an endpoint (a `MockPipe` or any real transport) and
+//! records every chunk of data read from or written to it together with its
+//! timing. The resulting [`Session`] can be saved to a file and, in later runs,
+//! a [`Replayer`] can play the remote (device) side of it into a pipe, turning
+//! live captures into deterministic regression fixtures. Parsers can also be
+//! fed the captured data directly through a seekable [`ReplayReader`].
+//!
+//! Sessions are stored in a line-based text format, one event per line:
+//!
+//! ```text
+//! # mockpipe session v1
+//! 0 out 68656c6c6f
+//! 1250 in 6f6b
+//! ```
+//!
+//! where the first column is the time since the start of the recording in
+//! microseconds, the second one the direction relative to the recorded
+//! endpoint and the third one the transferred data in hex.
+
+use std::{
+    fmt::Write as _,
+    fs::File,
+    io::{self, BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    path::Path,
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use crate::{hex::hex_diff, Direction, MockPipe};
+
+const HEADER: &str = "# mockpipe session v1";
+
+/// A chunk of data transferred during a recorded session.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SessionEvent {
+    /// Time since the start of the recording.
+    pub at: Duration,
+
+    /// Direction of the transfer, relative to the recorded endpoint.
+    pub direction: Direction,
+
+    /// The transferred data.
+    pub data: Vec<u8>,
+}
+
+/// A recorded bidirectional session.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Session {
+    /// The recorded events, in chronological order.
+    pub events: Vec<SessionEvent>,
+}
+
+impl Session {
+    /// Returns the concatenation of all data transferred in the given direction.
+    pub fn data(&self, direction: Direction) -> Vec<u8> {
+        self.events
+            .iter()
+            .filter(|event| event.direction == direction)
+            .flat_map(|event| event.data.iter().copied())
+            .collect()
+    }
+
+    /// Returns a reader over the data transferred in the given direction.
+    pub fn reader(&self, direction: Direction) -> ReplayReader {
+        ReplayReader::new(self, direction)
+    }
+
+    /// Writes the session in the text format to `writer`.
+    pub fn write_to<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writeln!(writer, "{}", HEADER)?;
+
+        for event in &self.events {
+            let direction = match event.direction {
+                Direction::Inbound => "in",
+                Direction::Outbound => "out",
+            };
+
+            let mut hex = String::with_capacity(event.data.len() * 2);
+            for byte in &event.data {
+                write!(hex, "{:02x}", byte).unwrap();
+            }
+
+            writeln!(writer, "{} {} {}", event.at.as_micros(), direction, hex)?;
+        }
+
+        writer.flush()
+    }
+
+    /// Reads a session in the text format from `reader`.
+    pub fn read_from<R: Read>(reader: R) -> io::Result<Self> {
+        let invalid = |line: usize| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid session event at line {}", line),
+            )
+        };
+
+        let mut events = Vec::new();
+
+        for (index, line) in BufReader::new(reader).lines().enumerate() {
+            let line = line?;
+            let line = line.trim();
+
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut fields = line.split_whitespace();
+
+            let at = fields
+                .next()
+                .and_then(|at| at.parse().ok())
+                .map(Duration::from_micros)
+                .ok_or_else(|| invalid(index + 1))?;
+
+            let direction = match fields.next() {
+                Some("in") => Direction::Inbound,
+                Some("out") => Direction::Outbound,
+                _ => return Err(invalid(index + 1)),
+            };
+
+            let data = fields
+                .next()
+                .map_or(Some(Vec::new()), decode_hex)
+                .ok_or_else(|| invalid(index + 1))?;
+
+            if fields.next().is_some() {
+                return Err(invalid(index + 1));
+            }
+
+            events.push(SessionEvent {
+                at,
+                direction,
+                data,
+            });
+        }
+
+        Ok(Self { events })
+    }
+
+    /// Saves the session into a file.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        self.write_to(BufWriter::new(File::create(path)?))
+    }
+
+    /// Loads a session from a file.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::read_from(File::open(path)?)
+    }
+}
+
+/// A seekable reader over the data transferred in one direction of a recorded
+/// session.
+///
+/// Parser tests can rewind it to re-read captured traffic without replaying
+/// the session, and [`seek_to_chunk`](Self::seek_to_chunk) positions it at the
+/// start of a recorded chunk to resume from a given message.
+#[derive(Clone, Debug)]
+pub struct ReplayReader {
+    data: io::Cursor<Vec<u8>>,
+
+    /// Offsets of the recorded chunks in the data.
+    chunk_offsets: Vec<u64>,
+}
+
+impl ReplayReader {
+    /// Creates a reader over the data transferred in the given direction.
+    pub fn new(session: &Session, direction: Direction) -> Self {
+        let mut data = Vec::new();
+        let mut chunk_offsets = Vec::new();
+
+        for event in &session.events {
+            if event.direction == direction && !event.data.is_empty() {
+                chunk_offsets.push(data.len() as u64);
+                data.extend_from_slice(&event.data);
+            }
+        }
+
+        Self {
+            data: io::Cursor::new(data),
+            chunk_offsets,
+        }
+    }
+
+    /// Returns the whole data, regardless of the current position.
+    pub fn data(&self) -> &[u8] {
+        self.data.get_ref()
+    }
+
+    /// Returns the current position.
+    pub fn position(&self) -> u64 {
+        self.data.position()
+    }
+
+    /// Returns the offsets of the recorded chunks in the data.
+    pub fn chunk_offsets(&self) -> &[u64] {
+        &self.chunk_offsets
+    }
+
+    /// Seeks to the start of the recorded chunk with the given index, returning
+    /// the new position.
+    ///
+    /// Fails with an `InvalidInput` error if there is no such chunk.
+    pub fn seek_to_chunk(&mut self, index: usize) -> io::Result<u64> {
+        let offset = *self
+            .chunk_offsets
+            .get(index)
+            .ok_or_else(|| io::Error::from(io::ErrorKind::InvalidInput))?;
+
+        self.data.set_position(offset);
+        Ok(offset)
+    }
+}
+
+impl Read for ReplayReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.data.read(buf)
+    }
+}
+
+impl BufRead for ReplayReader {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.data.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.data.consume(amt)
+    }
+}
+
+impl Seek for ReplayReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.data.seek(pos)
+    }
+}
+
+/// Decodes a string of hex digit pairs, returning `None` if it is malformed.
+pub(crate) fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.is_ascii() || (hex.len() % 2 != 0) {
+        return None;
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
+
+/// An endpoint wrapper recording all transferred data and its timing into a
+/// [`Session`].
+///
+/// Data read from the wrapped endpoint is recorded as inbound, data written to
+/// it as outbound.
+pub struct Recorder<T> {
+    inner: T,
+    start: Instant,
+    session: Session,
+}
+
+impl<T> Recorder<T> {
+    /// Starts recording the traffic of the given endpoint.
+    pub fn new(inner: T) -> Self {
+        Self {
+            inner,
+            start: Instant::now(),
+            session: Session::default(),
+        }
+    }
+
+    /// Returns a reference to the wrapped endpoint.
+    pub fn get_ref(&self) -> &T {
+        &self.inner
+    }
+
+    /// Returns the session recorded so far.
+    pub fn session(&self) -> &Session {
+        &self.session
+    }
+
+    /// Consumes the recorder, returning the wrapped endpoint and the recorded
+    /// session.
+    pub fn into_inner(self) -> (T, Session) {
+        (self.inner, self.session)
+    }
+
+    fn record(&mut self, direction: Direction, data: &[u8]) {
+        if !data.is_empty() {
+            self.session.events.push(SessionEvent {
+                // Microsecond resolution, as in the session file format
+                at: Duration::from_micros(self.start.elapsed().as_micros() as u64),
+                direction,
+                data: data.to_vec(),
+            });
+        }
+    }
+}
+
+impl<T: Read> Read for Recorder<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.record(Direction::Inbound, &buf[0..n]);
+        Ok(n)
+    }
+}
+
+impl<T: Write> Write for Recorder<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.record(Direction::Outbound, &buf[0..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Plays the remote side of a recorded [`Session`] into a pipe endpoint.
+///
+/// The endpoint passed to [`run`](Replayer::run) takes the place of the remote
+/// device: inbound data of the session (what the recorded endpoint received) is
+/// written into it, while outbound data (what the recorded endpoint sent) is
+/// expected to be written by the code under test on the other end, and is read
+/// from it before the replay proceeds.
+#[derive(Clone, Debug)]
+pub struct Replayer {
+    session: Session,
+    timing: bool,
+    verify: bool,
+    timeout: Option<Duration>,
+}
+
+impl Replayer {
+    /// Creates a replayer for the given session, reproducing the original
+    /// timing and verifying the outbound data.
+    pub fn new(session: Session) -> Self {
+        Self {
+            session,
+            timing: true,
+            verify: true,
+            timeout: Some(Duration::from_secs(10)),
+        }
+    }
+
+    /// Sets whether the original pauses between events are reproduced (the
+    /// default) or the data is replayed as fast as possible.
+    pub fn with_timing(mut self, timing: bool) -> Self {
+        self.timing = timing;
+        self
+    }
+
+    /// Sets whether the data written by the code under test must match the
+    /// recorded outbound data (the default) or is just consumed.
+    pub fn with_verify(mut self, verify: bool) -> Self {
+        self.verify = verify;
+        self
+    }
+
+    /// Sets how long to wait for the code under test to write the expected
+    /// outbound data. `None` waits indefinitely.
+    pub fn with_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Replays the session into the pipe endpoint, blocking until it is done.
+    ///
+    /// Returns an `InvalidData` error if the data written by the code under
+    /// test does not match the recording (with verification enabled), or a
+    /// `TimedOut` error if it was not written in time.
+    pub fn run(&self, pipe: &MockPipe) -> io::Result<()> {
+        // Real and session time of the previous event
+        let mut previous = (Instant::now(), Duration::ZERO);
+
+        for event in &self.session.events {
+            let due = previous.0 + event.at.saturating_sub(previous.1);
+            previous = (due, event.at);
+
+            match event.direction {
+                Direction::Inbound => {
+                    // The data is scheduled rather than written after a pause,
+                    // so the pacing does not drift
+                    let mut data = &event.data[..];
+                    while !data.is_empty() {
+                        let written = if self.timing {
+                            pipe.write_at_with_timeout(due, data, None)?
+                        } else {
+                            pipe.write_with_timeout(data, None)?
+                        };
+
+                        match written {
+                            0 => return Err(io::Error::from(io::ErrorKind::WriteZero)),
+                            n => data = &data[n..],
+                        }
+                    }
+                }
+                Direction::Outbound => {
+                    let mut received = vec![0u8; event.data.len()];
+                    let mut filled = 0;
+                    while filled < received.len() {
+                        match pipe.read_with_timeout(&mut received[filled..], self.timeout)? {
+                            0 => return Err(io::Error::from(io::ErrorKind::UnexpectedEof)),
+                            n => filled += n,
+                        }
+                    }
+
+                    // The pauses count from when the code under test responded
+                    previous.0 = previous.0.max(Instant::now());
+
+                    if self.verify && (received != event.data) {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!(
+                                "unexpected data at {:?}\n{}",
+                                event.at,
+                                hex_diff(&event.data, &received)
+                            ),
+                        ));
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Replays the session into the pipe endpoint on a background thread.
+    pub fn spawn(self, pipe: MockPipe) -> JoinHandle<io::Result<()>> {
+        thread::spawn(move || self.run(&pipe))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn recorded_session() -> Session {
+        // Code under test talking to a device, with the traffic recorded
+        let (host, mut device) = MockPipe::pair(64);
+        let mut recorder = Recorder::new(host);
+
+        recorder.write_all(b"hello").unwrap();
+
+        let mut request = [0u8; 5];
+        device.read_exact(&mut request).unwrap();
+        device.write_all(b"ok").unwrap();
+
+        let mut response = [0u8; 2];
+        recorder.read_exact(&mut response).unwrap();
+
+        recorder.into_inner().1
+    }
+
+    #[test]
+    fn test_session_text_format() {
+        let session = recorded_session();
+
+        assert_eq!(session.data(Direction::Outbound), b"hello");
+        assert_eq!(session.data(Direction::Inbound), b"ok");
+
+        let mut text = Vec::new();
+        session.write_to(&mut text).unwrap();
+
+        assert_eq!(Session::read_from(&text[..]).unwrap(), session);
+
+        assert_eq!(
+            Session::read_from(&b"0 sideways 00"[..])
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::InvalidData
+        );
+    }
+
+    #[test]
+    fn test_replay_reader() {
+        let mut session = recorded_session();
+        session.events.push(SessionEvent {
+            at: Duration::from_millis(1),
+            direction: Direction::Outbound,
+            data: b" world".to_vec(),
+        });
+
+        let mut reader = session.reader(Direction::Outbound);
+        assert_eq!(reader.chunk_offsets(), &[0, 5]);
+
+        let mut text = String::new();
+        reader.read_to_string(&mut text).unwrap();
+        assert_eq!(text, "hello world");
+
+        // Rewind and re-read parts of the captured traffic
+        assert_eq!(reader.seek(SeekFrom::Start(0)).unwrap(), 0);
+        let mut word = [0u8; 5];
+        reader.read_exact(&mut word).unwrap();
+        assert_eq!(&word, b"hello");
+
+        assert_eq!(reader.seek_to_chunk(1).unwrap(), 5);
+        assert_eq!(reader.seek(SeekFrom::Current(1)).unwrap(), 6);
+        reader.read_exact(&mut word).unwrap();
+        assert_eq!(&word, b"world");
+
+        assert!(reader.seek(SeekFrom::End(-12)).is_err());
+        assert!(reader.seek_to_chunk(2).is_err());
+    }
+
+    #[test]
+    fn test_replay() {
+        let replayer = Replayer::new(recorded_session());
+
+        let (mut host, device) = MockPipe::pair(64);
+        host.set_timeout(Some(Duration::from_secs(1)));
+
+        // The replay goes through the endpoint like any other code
+        let mut tee = MockPipe::loopback(64);
+        device.tee(tee.clone());
+
+        let replay = replayer.clone().spawn(device);
+
+        host.write_all(b"hello").unwrap();
+
+        let mut response = [0u8; 2];
+        host.read_exact(&mut response).unwrap();
+        assert_eq!(&response, b"ok");
+
+        replay.join().unwrap().unwrap();
+
+        let mut mirrored = [0u8; 7];
+        tee.read_exact(&mut mirrored).unwrap();
+        assert_eq!(&mirrored, b"hellook");
+
+        // Unexpected data written by the code under test is reported
+        let (mut host, device) = MockPipe::pair(64);
+        let replay = replayer.spawn(device);
+
+        host.write_all(b"howdy").unwrap();
+
+        assert_eq!(
+            replay.join().unwrap().unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+    }
+}
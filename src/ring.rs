@@ -0,0 +1,329 @@
+//! Storage of the data of the buffers.
+
+#[cfg(not(feature = "ring-buffer"))]
+use std::collections::VecDeque;
+use std::ops::Range;
+
+/// Largest capacity stored inline, like the FIFOs of most UARTs.
+pub(crate) const INLINE_CAPACITY: usize = 64;
+
+/// Slots of a ring.
+enum Slots {
+    Inline([u8; INLINE_CAPACITY]),
+    Heap(Box<[u8]>),
+    Static(&'static mut [u8]),
+}
+
+/// A circular buffer of bytes.
+///
+/// It mirrors the subset of the `VecDeque<u8>` API the buffers use, so either
+/// can store their data. Capacities up to [`INLINE_CAPACITY`] are stored
+/// inline, larger ones are allocated when the ring is created, rounded up to
+/// a power of two, unless the ring uses storage provided by the caller. The
+/// ring is only reallocated if more data than the requested capacity is
+/// pushed, which the buffers never do.
+pub(crate) struct RingBuffer {
+    slots: Slots,
+
+    /// Position of the first byte.
+    head: usize,
+
+    /// Number of bytes stored.
+    len: usize,
+}
+
+impl RingBuffer {
+    /// Creates a ring able to store at least `capacity` bytes.
+    pub(crate) fn with_capacity(capacity: usize) -> Self {
+        let slots = if capacity <= INLINE_CAPACITY {
+            Slots::Inline([0u8; INLINE_CAPACITY])
+        } else {
+            Slots::Heap(vec![0u8; capacity.next_power_of_two()].into_boxed_slice())
+        };
+
+        Self {
+            slots,
+            head: 0,
+            len: 0,
+        }
+    }
+
+    /// Creates a ring storing its bytes in `storage`, without allocating.
+    pub(crate) fn from_static(storage: &'static mut [u8]) -> Self {
+        Self {
+            slots: Slots::Static(storage),
+            head: 0,
+            len: 0,
+        }
+    }
+
+    /// Returns the number of bytes stored.
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns `true` if no bytes are stored.
+    pub(crate) fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the stored bytes, as two slices in order.
+    pub(crate) fn as_slices(&self) -> (&[u8], &[u8]) {
+        let end = self.head + self.len;
+
+        if end <= self.slots().len() {
+            (&self.slots()[self.head..end], &[])
+        } else {
+            let (wrapped, front) = self.slots().split_at(self.head);
+            (front, &wrapped[0..(end - self.slots().len())])
+        }
+    }
+
+    /// Rearranges the stored bytes so they are contiguous, without allocating,
+    /// and returns them.
+    pub(crate) fn make_contiguous(&mut self) -> &mut [u8] {
+        if self.head + self.len > self.slots().len() {
+            let head = self.head;
+            self.slots_mut().rotate_left(head);
+            self.head = 0;
+        }
+
+        let (head, len) = (self.head, self.len);
+        &mut self.slots_mut()[head..(head + len)]
+    }
+
+    /// Removes the bytes in `range`, which must start at the front.
+    pub(crate) fn drain(&mut self, range: Range<usize>) {
+        assert!(
+            (range.start == 0) && (range.end <= self.len),
+            "drain range out of bounds"
+        );
+
+        self.head = self.wrap(self.head + range.end);
+        self.len -= range.end;
+    }
+
+    /// Removes all bytes.
+    pub(crate) fn clear(&mut self) {
+        self.head = 0;
+        self.len = 0;
+    }
+
+    /// Appends `byte`, reallocating if the ring is full.
+    fn push_back(&mut self, byte: u8) {
+        if self.len == self.slots().len() {
+            self.grow(self.len + 1);
+        }
+
+        let position = self.wrap(self.head + self.len);
+        self.slots_mut()[position] = byte;
+        self.len += 1;
+    }
+
+    /// Reallocates the storage to hold at least `capacity` bytes.
+    fn grow(&mut self, capacity: usize) {
+        let mut slots = vec![0u8; capacity.next_power_of_two()].into_boxed_slice();
+
+        let (front, back) = self.as_slices();
+        slots[0..front.len()].copy_from_slice(front);
+        slots[front.len()..self.len].copy_from_slice(back);
+
+        self.slots = Slots::Heap(slots);
+        self.head = 0;
+    }
+
+    fn slots(&self) -> &[u8] {
+        match &self.slots {
+            Slots::Inline(slots) => slots,
+            Slots::Heap(slots) => slots,
+            Slots::Static(slots) => slots,
+        }
+    }
+
+    fn slots_mut(&mut self) -> &mut [u8] {
+        match &mut self.slots {
+            Slots::Inline(slots) => slots,
+            Slots::Heap(slots) => slots,
+            Slots::Static(slots) => slots,
+        }
+    }
+
+    /// Wraps a position less than twice the number of slots around the ring.
+    fn wrap(&self, position: usize) -> usize {
+        position.checked_sub(self.slots().len()).unwrap_or(position)
+    }
+}
+
+impl Extend<u8> for RingBuffer {
+    fn extend<I: IntoIterator<Item = u8>>(&mut self, iter: I) {
+        let mut iter = iter.into_iter();
+
+        let (additional, _) = iter.size_hint();
+        if self.len + additional > self.slots().len() {
+            self.grow(self.len + additional);
+        }
+
+        // Fill the free space in up to two contiguous runs, which compiles to
+        // a tight copy loop, then push whatever the size hint left out
+        for _ in 0..2 {
+            let tail = self.wrap(self.head + self.len);
+            let end = if tail < self.head || self.len == self.slots().len() {
+                self.head
+            } else {
+                self.slots().len()
+            };
+
+            let mut n = 0;
+            for (slot, byte) in self.slots_mut()[tail..end].iter_mut().zip(iter.by_ref()) {
+                *slot = byte;
+                n += 1;
+            }
+            self.len += n;
+
+            if n < end - tail {
+                return;
+            }
+        }
+
+        for byte in iter {
+            self.push_back(byte);
+        }
+    }
+}
+
+/// A `VecDeque<u8>`, stored in a ring for the small capacities of FIFO
+/// simulations, so tests creating many tiny pipes do not allocate their data,
+/// or for storage provided by the caller.
+#[cfg(not(feature = "ring-buffer"))]
+pub(crate) enum SmallDeque {
+    Ring(RingBuffer),
+    Heap(VecDeque<u8>),
+}
+
+/// Evaluates `$expr` with `$deque` bound to the storage of a `SmallDeque`.
+#[cfg(not(feature = "ring-buffer"))]
+macro_rules! with_deque {
+    ($small:expr, $deque:ident => $expr:expr) => {
+        match $small {
+            SmallDeque::Ring($deque) => $expr,
+            SmallDeque::Heap($deque) => $expr,
+        }
+    };
+}
+
+#[cfg(not(feature = "ring-buffer"))]
+impl SmallDeque {
+    /// Creates a deque able to store `capacity` bytes, inline if possible.
+    pub(crate) fn with_capacity(capacity: usize) -> Self {
+        if capacity <= INLINE_CAPACITY {
+            Self::Ring(RingBuffer::with_capacity(capacity))
+        } else {
+            Self::Heap(VecDeque::with_capacity(capacity))
+        }
+    }
+
+    /// Creates a deque storing its bytes in `storage`, without allocating.
+    pub(crate) fn from_static(storage: &'static mut [u8]) -> Self {
+        Self::Ring(RingBuffer::from_static(storage))
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        with_deque!(self, deque => deque.len())
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        with_deque!(self, deque => deque.is_empty())
+    }
+
+    pub(crate) fn as_slices(&self) -> (&[u8], &[u8]) {
+        with_deque!(self, deque => deque.as_slices())
+    }
+
+    pub(crate) fn make_contiguous(&mut self) -> &mut [u8] {
+        with_deque!(self, deque => deque.make_contiguous())
+    }
+
+    /// Removes the bytes in `range`, which must start at the front.
+    pub(crate) fn drain(&mut self, range: Range<usize>) {
+        with_deque!(self, deque => {
+            deque.drain(range);
+        })
+    }
+
+    pub(crate) fn clear(&mut self) {
+        with_deque!(self, deque => deque.clear())
+    }
+}
+
+#[cfg(not(feature = "ring-buffer"))]
+impl Extend<u8> for SmallDeque {
+    fn extend<I: IntoIterator<Item = u8>>(&mut self, iter: I) {
+        with_deque!(self, deque => deque.extend(iter))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ring_buffer() {
+        let data: Vec<u8> = (0..200).collect();
+
+        let mut ring = RingBuffer::with_capacity(100);
+        assert_eq!(ring.slots().len(), 128);
+
+        ring.extend(data[0..120].iter().copied());
+        ring.drain(0..100);
+        ring.extend(data[120..150].iter().copied());
+        assert_eq!(ring.as_slices(), (&data[100..128], &data[128..150]));
+        assert_eq!(ring.slots().len(), 128);
+
+        assert_eq!(ring.make_contiguous(), &data[100..150]);
+        assert_eq!(ring.as_slices(), (&data[100..150], &[][..]));
+
+        // Pushing more than the capacity reallocates
+        ring.drain(0..10);
+        ring.extend(data[150..200].iter().chain(&data[0..50]).copied());
+        assert_eq!(ring.slots().len(), 256);
+        assert_eq!(
+            ring.make_contiguous(),
+            [&data[110..200], &data[0..50]].concat()
+        );
+
+        ring.clear();
+        assert!(ring.is_empty());
+        assert_eq!(ring.len(), 0);
+    }
+
+    #[test]
+    fn test_inline_ring() {
+        let data: Vec<u8> = (0..70).collect();
+
+        let mut ring = RingBuffer::with_capacity(16);
+        ring.extend(data[0..64].iter().copied());
+        assert!(matches!(ring.slots, Slots::Inline(_)));
+
+        // Overflowing the inline slots moves the data to the heap
+        ring.drain(0..2);
+        ring.extend(data[64..70].iter().copied());
+        assert!(matches!(ring.slots, Slots::Heap(_)));
+        assert_eq!(ring.make_contiguous(), &data[2..70]);
+    }
+
+    #[test]
+    fn test_static_ring() {
+        let data: Vec<u8> = (0..20).collect();
+
+        // Any storage length works, and the data wraps around it in place
+        let mut ring = RingBuffer::from_static(Box::leak(Box::new([0u8; 10])));
+        ring.extend(data[0..8].iter().copied());
+        ring.drain(0..6);
+        ring.extend(data[8..16].iter().copied());
+        assert_eq!(ring.as_slices(), (&data[6..10], &data[10..16]));
+        assert!(matches!(ring.slots, Slots::Static(_)));
+
+        ring.drain(0..5);
+        assert_eq!(ring.make_contiguous(), &data[11..16]);
+    }
+}
@@ -0,0 +1,181 @@
+//! A small, dependency-free pseudo-random number generator used by the fault
+//! simulation features.
+//!
+//! The generator is seeded explicitly so that any randomized behavior can be
+//! replayed exactly by reusing the seed. The seeds of the generators of the
+//! pipes created by a builder derive from its master seed (see
+//! [`MockPipeBuilder::with_seed`](crate::MockPipeBuilder::with_seed)), and the
+//! others from a global fallback master seed, see [`set_seed`].
+
+use std::{
+    collections::hash_map::RandomState,
+    env,
+    hash::{BuildHasher, Hasher},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+};
+
+/// Environment variable setting the master seed, in decimal or `0x`-prefixed
+/// hex.
+pub const SEED_ENV_VAR: &str = "MOCKPIPE_SEED";
+
+/// The master seed, once chosen.
+static MASTER_SEED: Mutex<Option<u64>> = Mutex::new(None);
+
+/// Number of seeds derived with [`Rng::derive_unique_seed`] so far.
+static UNIQUE_SEEDS: AtomicUsize = AtomicUsize::new(0);
+
+/// Sets the global master seed from which the randomized behavior not seeded
+/// otherwise (such as the simulated datagram loss and duplication) derives.
+///
+/// Each generator is seeded from the master seed and a stable label (e.g. the
+/// socket name), so the same master seed replays the same behavior regardless
+/// of the order in which the generators are created. Generators created before
+/// the call keep their seeds. The master seed is global to the process, so
+/// tests relying on it should not run in parallel with others changing it:
+/// prefer seeding the pipes of each test with
+/// [`MockPipeBuilder::with_seed`](crate::MockPipeBuilder::with_seed).
+pub fn set_seed(seed: u64) {
+    *MASTER_SEED.lock().unwrap() = Some(seed);
+}
+
+/// Returns the global master seed, e.g. to print it when a test fails so the
+/// failure can be replayed with [`set_seed`] or the `MOCKPIPE_SEED`
+/// environment variable.
+///
+/// Unless set with [`set_seed`], the master seed is taken from `MOCKPIPE_SEED`
+/// on first use, or chosen randomly if the variable is not set.
+///
+/// # Panics
+///
+/// Panics if `MOCKPIPE_SEED` is set but is not a valid seed.
+pub fn seed() -> u64 {
+    *MASTER_SEED
+        .lock()
+        .unwrap()
+        .get_or_insert_with(|| match env::var(SEED_ENV_VAR) {
+            Ok(value) => parse_seed(&value)
+                .unwrap_or_else(|| panic!("invalid {}: {:?}", SEED_ENV_VAR, value)),
+            Err(_) => Rng::random_seed(),
+        })
+}
+
+/// Parses a seed in decimal or `0x`-prefixed hex.
+fn parse_seed(value: &str) -> Option<u64> {
+    let value = value.trim();
+
+    match value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("0X"))
+    {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => value.parse().ok(),
+    }
+}
+
+/// A SplitMix64 pseudo-random number generator.
+#[derive(Clone, Debug)]
+pub(crate) struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    /// Creates a new generator from the given seed.
+    pub(crate) fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    /// Returns a seed which is different on every call.
+    fn random_seed() -> u64 {
+        RandomState::new().build_hasher().finish()
+    }
+
+    /// Returns the seed of the generator with the given label, derived from
+    /// the global master seed.
+    pub(crate) fn derive_seed(label: &str) -> u64 {
+        Self::derive_seed_from(seed(), label)
+    }
+
+    /// Returns the seed of the generator with the given label, derived from
+    /// the given master seed.
+    pub(crate) fn derive_seed_from(master_seed: u64, label: &str) -> u64 {
+        // FNV-1a, which unlike the std hashers is stable across releases
+        let label_hash = label.bytes().fold(0xcbf2_9ce4_8422_2325u64, |hash, byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+        });
+
+        Rng::new(master_seed ^ label_hash).next_u64()
+    }
+
+    /// Returns a seed derived from the master seed and the label like
+    /// [`derive_seed`](Rng::derive_seed), but different on every call, for
+    /// generators which have to be independent of each other. The same
+    /// master seed replays the same seeds as long as the generators are
+    /// created in the same order.
+    pub(crate) fn derive_unique_seed(label: &str) -> u64 {
+        let index = UNIQUE_SEEDS.fetch_add(1, Ordering::Relaxed) as u64;
+        Rng::new(Self::derive_seed(label) ^ index).next_u64()
+    }
+
+    /// Returns the next pseudo-random 64-bit value.
+    pub(crate) fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Returns a pseudo-random value uniformly distributed in `[0, 1)`.
+    pub(crate) fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Returns `true` with the given probability.
+    pub(crate) fn chance(&mut self, probability: f64) -> bool {
+        (probability > 0.0) && (self.next_f64() < probability)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_same_seed_same_sequence() {
+        let mut rng1 = Rng::new(42);
+        let mut rng2 = Rng::new(42);
+
+        for _ in 0..100 {
+            assert_eq!(rng1.next_u64(), rng2.next_u64());
+        }
+    }
+
+    #[test]
+    fn test_derive_seed() {
+        let seed_a = Rng::derive_seed_from(7, "a");
+
+        assert_ne!(seed_a, Rng::derive_seed_from(7, "b"));
+        assert_ne!(seed_a, Rng::derive_seed_from(8, "a"));
+        assert_eq!(seed_a, Rng::derive_seed_from(7, "a"));
+        assert_eq!(Rng::derive_seed_from(seed(), "a"), Rng::derive_seed("a"));
+        assert_ne!(Rng::derive_unique_seed("a"), Rng::derive_unique_seed("a"));
+
+        assert_eq!(parse_seed("42"), Some(42));
+        assert_eq!(parse_seed("0x2a"), Some(42));
+        assert_eq!(parse_seed("forty-two"), None);
+    }
+
+    #[test]
+    fn test_chance_bounds() {
+        let mut rng = Rng::new(1);
+
+        for _ in 0..100 {
+            assert!(!rng.chance(0.0));
+            assert!(rng.chance(1.0));
+        }
+    }
+}
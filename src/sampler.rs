@@ -0,0 +1,257 @@
+//! Sampling of the buffer occupancy over time.
+//!
+//! An [`OccupancySampler`] records the number of bytes waiting to be read in a
+//! buffer, at a fixed interval or on every change, so the backpressure behavior
+//! over a long soak test can be plotted and analyzed after the fact:
+//!
+//! ```
+//! use std::io::{Read, Write};
+//!
+//! use mockpipe::{sampler::SampleMode, Direction, MockPipe};
+//!
+//! let (mut host, mut device) = MockPipe::pair(64);
+//! let sampler = host.sample_occupancy(Direction::Outbound, SampleMode::EveryChange);
+//!
+//! host.write_all(b"ping").unwrap();
+//! device.read_exact(&mut [0u8; 4]).unwrap();
+//!
+//! let occupancy: Vec<_> = sampler.stop().iter().map(|sample| sample.occupancy).collect();
+//! assert_eq!(occupancy, [0, 4, 0]);
+//! ```
+
+use std::{
+    mem,
+    sync::{Arc, Condvar, Mutex},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use crate::{buffer::SyncBuffer, Direction, MockPipe};
+
+/// The occupancy of a buffer at a point in time.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct OccupancySample {
+    /// Time since the sampling started.
+    pub at: Duration,
+
+    /// Number of bytes waiting to be read.
+    pub occupancy: usize,
+}
+
+/// When an [`OccupancySampler`] records the occupancy.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum SampleMode {
+    /// At a fixed, non-zero interval, from a background thread.
+    Interval(Duration),
+
+    /// Whenever the occupancy changes: on every transfer, clear or delivery
+    /// over a simulated link.
+    EveryChange,
+}
+
+/// Samples recorded so far, shared with the buffer or the sampling thread.
+pub(crate) struct OccupancyLog {
+    start: Instant,
+    samples: Mutex<Vec<OccupancySample>>,
+}
+
+impl OccupancyLog {
+    /// Records the occupancy at the current time.
+    pub(crate) fn record(&self, occupancy: usize) {
+        let at = self.start.elapsed();
+        self.samples
+            .lock()
+            .unwrap()
+            .push(OccupancySample { at, occupancy });
+    }
+}
+
+/// Records the occupancy of a buffer over time, until stopped or dropped.
+///
+/// Created with [`MockPipe::sample_occupancy`].
+pub struct OccupancySampler {
+    log: Arc<OccupancyLog>,
+    buffer: Arc<SyncBuffer>,
+
+    /// Whether the sampling thread keeps running, with the condition variable
+    /// waking it up when stopped, and the thread, sampling at a fixed
+    /// interval.
+    running: Arc<(Mutex<bool>, Condvar)>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl OccupancySampler {
+    /// Returns the samples recorded so far.
+    pub fn samples(&self) -> Vec<OccupancySample> {
+        self.log.samples.lock().unwrap().clone()
+    }
+
+    /// Returns the samples recorded so far and removes them, so a long
+    /// sampling can be processed in batches.
+    pub fn take_samples(&self) -> Vec<OccupancySample> {
+        mem::take(&mut *self.log.samples.lock().unwrap())
+    }
+
+    /// Stops the sampling and returns the samples not taken yet.
+    pub fn stop(mut self) -> Vec<OccupancySample> {
+        self.shutdown();
+        self.take_samples()
+    }
+
+    fn shutdown(&mut self) {
+        let (running, stopped) = &*self.running;
+        *running.lock().unwrap() = false;
+        stopped.notify_all();
+
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+
+        self.buffer.remove_occupancy_log(&self.log);
+    }
+}
+
+impl Drop for OccupancySampler {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+impl MockPipe {
+    /// Starts recording the occupancy of the buffer of the given direction:
+    /// of the data to read from the endpoint if `Inbound`, or written to it if
+    /// `Outbound`. The first sample is the occupancy when the sampling starts.
+    ///
+    /// Sampling every change replaces the sampler of the buffer sampled that
+    /// way, if any.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the sampling interval is zero.
+    pub fn sample_occupancy(&self, direction: Direction, mode: SampleMode) -> OccupancySampler {
+        let buffer = match direction {
+            Direction::Inbound => self.read_buffer.clone(),
+            Direction::Outbound => self.write_buffer.clone(),
+        };
+
+        let log = Arc::new(OccupancyLog {
+            start: Instant::now(),
+            samples: Mutex::new(Vec::new()),
+        });
+        let running = Arc::new((Mutex::new(true), Condvar::new()));
+
+        let thread = match mode {
+            SampleMode::Interval(interval) => {
+                assert!(interval > Duration::ZERO, "zero sampling interval");
+                log.record(buffer.len());
+
+                let (log, buffer, running) = (log.clone(), buffer.clone(), running.clone());
+                Some(thread::spawn(move || {
+                    let (running, stopped) = &*running;
+                    let mut running_guard = running.lock().unwrap();
+                    let mut next = log.start;
+
+                    loop {
+                        // Sleep until the next sample, unless stopped meanwhile
+                        next += interval;
+                        let timeout = next.saturating_duration_since(Instant::now());
+                        running_guard = stopped
+                            .wait_timeout_while(running_guard, timeout, |running| *running)
+                            .unwrap()
+                            .0;
+
+                        if !*running_guard {
+                            break;
+                        }
+                        log.record(buffer.len());
+                    }
+                }))
+            }
+            SampleMode::EveryChange => {
+                buffer.set_occupancy_log(log.clone());
+                None
+            }
+        };
+
+        OccupancySampler {
+            log,
+            buffer,
+            running,
+            thread,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+
+    use super::*;
+
+    #[test]
+    fn test_sample_every_change() {
+        let (mut host, mut device) = MockPipe::pair(16);
+        host.write_all(b"ab").unwrap();
+
+        let sampler = device.sample_occupancy(Direction::Inbound, SampleMode::EveryChange);
+        host.write_all(b"cdef").unwrap();
+        device.read_exact(&mut [0u8; 3]).unwrap();
+
+        let samples = sampler.take_samples();
+        let occupancy: Vec<_> = samples.iter().map(|sample| sample.occupancy).collect();
+        assert_eq!(occupancy, [2, 6, 3]);
+        assert!(samples.windows(2).all(|pair| pair[0].at <= pair[1].at));
+
+        host.clear_write();
+        let log = sampler.log.clone();
+        assert_eq!(sampler.stop()[0].occupancy, 0);
+
+        // Nothing is recorded once stopped
+        host.write_all(b"gh").unwrap();
+        assert!(log.samples.lock().unwrap().is_empty());
+
+        // A new sampler starts from the current occupancy
+        let sampler = device.sample_occupancy(Direction::Inbound, SampleMode::EveryChange);
+        assert_eq!(sampler.samples()[0].occupancy, 2);
+    }
+
+    #[test]
+    fn test_sample_interval() {
+        let (mut host, _device) = MockPipe::pair(16);
+        let sampler = host.sample_occupancy(
+            Direction::Outbound,
+            SampleMode::Interval(Duration::from_millis(5)),
+        );
+
+        host.write_all(b"abcd").unwrap();
+        thread::sleep(Duration::from_millis(50));
+
+        let samples = sampler.stop();
+        assert!(samples.len() >= 5);
+        assert_eq!(samples[0].occupancy, 0);
+        assert_eq!(samples.last().unwrap().occupancy, 4);
+        assert!(samples[1].at >= Duration::from_millis(5));
+    }
+
+    #[test]
+    fn test_sample_interval_stop() {
+        let (host, _device) = MockPipe::pair(16);
+        let sampler = host.sample_occupancy(
+            Direction::Outbound,
+            SampleMode::Interval(Duration::from_secs(10)),
+        );
+
+        // Stopping interrupts the wait for the next sample, recording nothing
+        let start = Instant::now();
+        assert_eq!(sampler.stop().len(), 1);
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+
+    #[test]
+    #[should_panic(expected = "zero sampling interval")]
+    fn test_sample_zero_interval() {
+        let (host, _device) = MockPipe::pair(16);
+        host.sample_occupancy(Direction::Outbound, SampleMode::Interval(Duration::ZERO));
+    }
+}
@@ -0,0 +1,883 @@
+//! Scripted exchanges playing the device side of a conversation.
+//!
+//! A [`Script`] is a list of [`Step`]s executed in order against a pipe
+//! endpoint: expecting data from the code under test, replying to it, pausing,
+//! injecting errors and closing the connection.
+//!
+//! ```
+//! use std::io::{Read, Write};
+//!
+//! use mockpipe::{script::Script, MockPipe};
+//!
+//! let (mut host, device) = MockPipe::pair(64);
+//! host.set_timeout(None);
+//!
+//! let script = Script::new().expect(b"AT\r\n").reply(b"OK\r\n").close();
+//! let device = script.spawn(device);
+//!
+//! host.write_all(b"AT\r\n").unwrap();
+//!
+//! let mut response = String::new();
+//! host.read_to_string(&mut response).unwrap();
+//! assert_eq!(response, "OK\r\n");
+//!
+//! device.join().unwrap().unwrap();
+//! ```
+//!
+//! [`Script::start`] runs the script in the background like
+//! [`Script::spawn`], but returns a [`ScriptGuard`] verifying the exchange in
+//! the style of `mockall`: dropping the guard panics if an expected write did
+//! not occur, the code under test wrote unexpected data, or the script failed
+//! otherwise. [`ScriptGuard::checkpoint`] performs the same checks mid-test.
+//!
+//! With the `json`, `yaml` or `toml` features enabled, scripts can also be
+//! loaded from scenario files, so device conversations can be authored and
+//! reviewed without touching Rust code. A scenario has a list of steps and an
+//! optional timeout (in milliseconds) for the `expect` steps:
+//!
+//! ```yaml
+//! timeout_ms: 1000
+//! steps:
+//!   - expect: "AT\r\n"
+//!   - delay: 50
+//!   - reply: { hex: "4f4b0d0a" }
+//!   - error: connection_reset
+//!   - close
+//! ```
+//!
+//! Data is given either as a string or, for binary data, as `{ hex: "..." }`.
+//! Delays are in milliseconds and errors are `std::io::ErrorKind` names in
+//! snake case (e.g. `broken_pipe`, `timed_out`).
+//!
+//! Besides the steps, a script can trigger one-shot [`Action`]s at given times
+//! after its start with [`Script::at`], regardless of how far the steps got, so
+//! failure timelines are expressed declaratively. In scenario files, they are
+//! listed as `events` with their time in milliseconds:
+//!
+//! ```yaml
+//! events:
+//!   - at_ms: 500
+//!     action: { error: connection_reset }
+//!   - at_ms: 2000
+//!     action: { link_down: 300 }
+//!   - at_ms: 3000
+//!     action: { stall: 1500 }
+//! ```
+
+use std::{
+    io,
+    path::Path,
+    sync::{
+        mpsc::{self, Receiver, RecvTimeoutError},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    hex::{hex_diff, hexdump},
+    Direction, MockPipe,
+};
+
+/// Interval at which a [`ScriptGuard`] checks the progress of the script.
+const POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+/// A single step of a [`Script`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Step {
+    /// Reads data from the code under test, failing if it does not match.
+    Expect(Vec<u8>),
+
+    /// Writes data to the code under test.
+    Reply(Vec<u8>),
+
+    /// Pauses for the given duration.
+    Delay(Duration),
+
+    /// Makes the next read of the code under test (after the data replied so
+    /// far) fail with an error of the given kind.
+    Error(io::ErrorKind),
+
+    /// Closes the pipe.
+    Close,
+}
+
+/// A one-shot action triggered at a given time after the start of a
+/// [`Script`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Action {
+    /// Makes the next read of the code under test fail with an error of the
+    /// given kind.
+    Error(io::ErrorKind),
+
+    /// Takes the link down for the given duration: the data written in either
+    /// direction in the meantime is lost.
+    LinkDown(Duration),
+
+    /// Stalls the link for the given duration: the data written in either
+    /// direction in the meantime is held back (see [`MockPipe::stall_for`]).
+    Stall(Duration),
+
+    /// Closes the pipe.
+    Close,
+}
+
+/// A scripted exchange playing the device side of a conversation.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Script {
+    steps: Vec<Step>,
+    timeline: Vec<(Duration, Action)>,
+    timeout: Option<Duration>,
+}
+
+/// An operation of the timeline of a running script.
+enum TimelineOp<'a> {
+    /// Triggers a scheduled action.
+    Trigger(&'a Action),
+
+    /// Brings the link back up at the end of a [`Action::LinkDown`].
+    LinkUp,
+}
+
+impl Default for Script {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Script {
+    /// Creates an empty script.
+    pub fn new() -> Self {
+        Self {
+            steps: Vec::new(),
+            timeline: Vec::new(),
+            timeout: Some(Duration::from_secs(10)),
+        }
+    }
+
+    /// Creates a script consisting of the given steps.
+    pub fn from_steps(steps: Vec<Step>) -> Self {
+        Self {
+            steps,
+            ..Self::new()
+        }
+    }
+
+    /// Returns the steps of the script.
+    pub fn steps(&self) -> &[Step] {
+        &self.steps
+    }
+
+    /// Returns the actions scheduled by the script, with their time after the
+    /// start of the script.
+    pub fn timeline(&self) -> &[(Duration, Action)] {
+        &self.timeline
+    }
+
+    /// Appends a step to the script.
+    pub fn step(mut self, step: Step) -> Self {
+        self.steps.push(step);
+        self
+    }
+
+    /// Appends a step expecting the given data from the code under test.
+    pub fn expect(self, data: impl AsRef<[u8]>) -> Self {
+        self.step(Step::Expect(data.as_ref().to_vec()))
+    }
+
+    /// Appends a step writing the given data to the code under test.
+    pub fn reply(self, data: impl AsRef<[u8]>) -> Self {
+        self.step(Step::Reply(data.as_ref().to_vec()))
+    }
+
+    /// Appends a pause.
+    pub fn delay(self, duration: Duration) -> Self {
+        self.step(Step::Delay(duration))
+    }
+
+    /// Appends a step injecting an error of the given kind.
+    pub fn error(self, kind: io::ErrorKind) -> Self {
+        self.step(Step::Error(kind))
+    }
+
+    /// Appends a step closing the pipe.
+    pub fn close(self) -> Self {
+        self.step(Step::Close)
+    }
+
+    /// Schedules an action at the given time after the start of the script.
+    ///
+    /// The action is triggered concurrently with the steps. Running the script
+    /// completes once both the steps are done and all actions were triggered,
+    /// and the actions not triggered yet are canceled if a step fails.
+    pub fn at(mut self, time: Duration, action: Action) -> Self {
+        self.timeline.push((time, action));
+        self
+    }
+
+    /// Sets how long to wait for the code under test to write the expected
+    /// data. `None` waits indefinitely.
+    pub fn with_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Loads a script from a scenario file.
+    ///
+    /// The format is chosen by the file extension (`.json`, `.yaml`/`.yml` or
+    /// `.toml`). Returns an `Unsupported` error if the extension is unknown or
+    /// the corresponding feature is disabled, and an `InvalidData` error if the
+    /// scenario is malformed.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref();
+
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .unwrap_or("");
+
+        match extension {
+            #[cfg(feature = "json")]
+            "json" => Self::from_json(&std::fs::read_to_string(path)?),
+            #[cfg(feature = "yaml")]
+            "yaml" | "yml" => Self::from_yaml(&std::fs::read_to_string(path)?),
+            #[cfg(feature = "toml")]
+            "toml" => Self::from_toml(&std::fs::read_to_string(path)?),
+            _ => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("unsupported scenario file: {}", path.display()),
+            )),
+        }
+    }
+
+    /// Parses a script from a JSON scenario.
+    #[cfg(feature = "json")]
+    pub fn from_json(scenario: &str) -> io::Result<Self> {
+        serde_json::from_str::<scenario::Scenario>(scenario)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?
+            .into_script()
+    }
+
+    /// Parses a script from a YAML scenario.
+    #[cfg(feature = "yaml")]
+    pub fn from_yaml(scenario: &str) -> io::Result<Self> {
+        // Steps are single-key maps, rather than the YAML tags serde_yaml
+        // uses for enums by default
+        let deserializer = serde_yaml::Deserializer::from_str(scenario);
+        serde_yaml::with::singleton_map_recursive::deserialize::<scenario::Scenario, _>(
+            deserializer,
+        )
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?
+        .into_script()
+    }
+
+    /// Parses a script from a TOML scenario.
+    #[cfg(feature = "toml")]
+    pub fn from_toml(scenario: &str) -> io::Result<Self> {
+        toml::from_str::<scenario::Scenario>(scenario)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?
+            .into_script()
+    }
+
+    /// Runs the script against the pipe endpoint, blocking until it is done.
+    ///
+    /// Returns an `InvalidData` error if the data written by the code under
+    /// test does not match an `expect` step, or a `TimedOut` error if it was
+    /// not written in time.
+    pub fn run(&self, pipe: &MockPipe) -> io::Result<()> {
+        self.execute(pipe, &Mutex::new(Progress::default()))
+    }
+
+    /// Runs the script against the pipe endpoint on a background thread,
+    /// returning a guard verifying the exchange when dropped.
+    pub fn start(self, pipe: MockPipe) -> ScriptGuard {
+        let progress = Arc::new(Mutex::new(Progress::default()));
+
+        let script = self.clone();
+        let script_pipe = pipe.clone();
+        let script_progress = progress.clone();
+        thread::spawn(move || {
+            let result = script.execute(&script_pipe, &script_progress);
+            script_progress.lock().unwrap().result = Some(result);
+        });
+
+        ScriptGuard {
+            script: self,
+            pipe,
+            progress,
+        }
+    }
+
+    /// Runs the script, reporting its progress.
+    fn execute(&self, pipe: &MockPipe, progress: &Mutex<Progress>) -> io::Result<()> {
+        // The timeline is canceled by dropping the sender
+        let (cancel, canceled) = mpsc::channel::<()>();
+
+        let timeline = (!self.timeline.is_empty()).then(|| {
+            let script = self.clone();
+            let pipe = pipe.clone();
+            let start = Instant::now();
+            thread::spawn(move || script.run_timeline(&pipe, start, &canceled))
+        });
+
+        let result = self.run_steps(pipe, progress);
+
+        if let Some(timeline) = timeline {
+            if result.is_err() {
+                drop(cancel);
+            }
+            timeline.join().expect("script timeline panicked");
+        }
+
+        result
+    }
+
+    /// Runs the steps of the script, reporting its progress.
+    fn run_steps(&self, pipe: &MockPipe, progress: &Mutex<Progress>) -> io::Result<()> {
+        for (index, step) in self.steps.iter().enumerate() {
+            progress.lock().unwrap().step = index;
+
+            match step {
+                Step::Expect(expected) => {
+                    let mut received = vec![0u8; expected.len()];
+                    let mut filled = 0;
+                    while filled < received.len() {
+                        progress.lock().unwrap().waiting = true;
+                        let result = pipe.read_with_timeout(&mut received[filled..], self.timeout);
+                        progress.lock().unwrap().waiting = false;
+
+                        match result? {
+                            0 => return Err(io::Error::from(io::ErrorKind::UnexpectedEof)),
+                            n => filled += n,
+                        }
+                    }
+
+                    if &received != expected {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!(
+                                "unexpected data at step {}\n{}",
+                                index,
+                                hex_diff(expected, &received)
+                            ),
+                        ));
+                    }
+                }
+                Step::Reply(data) => {
+                    let mut data = &data[..];
+                    while !data.is_empty() {
+                        match pipe.write_with_timeout(data, None)? {
+                            0 => return Err(io::Error::from(io::ErrorKind::WriteZero)),
+                            n => data = &data[n..],
+                        }
+                    }
+                }
+                Step::Delay(duration) => thread::sleep(*duration),
+                Step::Error(kind) => pipe.inject_error(*kind),
+                Step::Close => pipe.close(),
+            }
+        }
+
+        progress.lock().unwrap().step = self.steps.len();
+
+        Ok(())
+    }
+
+    /// Triggers the scheduled actions in time order, until all of them were
+    /// triggered or the timeline is canceled.
+    fn run_timeline(&self, pipe: &MockPipe, start: Instant, canceled: &Receiver<()>) {
+        let mut ops: Vec<(Duration, TimelineOp)> = Vec::new();
+        for (time, action) in &self.timeline {
+            ops.push((*time, TimelineOp::Trigger(action)));
+            if let Action::LinkDown(duration) = action {
+                ops.push((*time + *duration, TimelineOp::LinkUp));
+            }
+        }
+        ops.sort_by_key(|(time, _)| *time);
+
+        // The link settings to restore once the last outage is over
+        let mut outages = 0;
+        let mut links = [Direction::Inbound, Direction::Outbound].map(|dir| (dir, pipe.link(dir)));
+
+        for (time, op) in ops {
+            let deadline = start + time;
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if let Err(RecvTimeoutError::Disconnected) = canceled.recv_timeout(remaining) {
+                break;
+            }
+
+            match op {
+                TimelineOp::Trigger(Action::Error(kind)) => pipe.inject_error(*kind),
+                TimelineOp::Trigger(Action::Stall(duration)) => pipe.stall_for(*duration),
+                TimelineOp::Trigger(Action::Close) => pipe.close(),
+                TimelineOp::Trigger(Action::LinkDown(_)) => {
+                    if outages == 0 {
+                        links = links.map(|(dir, _)| (dir, pipe.link(dir)));
+                        for (dir, link) in links {
+                            pipe.set_link(dir, link.with_loss(1.0));
+                        }
+                    }
+                    outages += 1;
+                }
+                TimelineOp::LinkUp => {
+                    outages -= 1;
+                    if outages == 0 {
+                        for (dir, link) in links {
+                            pipe.set_link(dir, link);
+                        }
+                    }
+                }
+            }
+        }
+
+        if outages > 0 {
+            for (dir, link) in links {
+                pipe.set_link(dir, link);
+            }
+        }
+    }
+
+    /// Runs the script against the pipe endpoint on a background thread.
+    pub fn spawn(self, pipe: MockPipe) -> JoinHandle<io::Result<()>> {
+        thread::spawn(move || self.run(&pipe))
+    }
+}
+
+/// Progress of a running script.
+#[derive(Default)]
+struct Progress {
+    /// Index of the step being executed.
+    step: usize,
+
+    /// Whether the script is waiting for data from the code under test.
+    waiting: bool,
+
+    /// Result of the script, once it has finished.
+    result: Option<io::Result<()>>,
+}
+
+/// A script running in the background, verifying the exchange when dropped.
+///
+/// Dropping the guard waits for the script to become idle (finished, or
+/// waiting for data that was not written), then panics if it failed, if it did
+/// not finish because an expected write did not occur, or if the code under
+/// test wrote data after the end of the script. The pipe is closed if the
+/// script did not finish, which stops it.
+pub struct ScriptGuard {
+    script: Script,
+    pipe: MockPipe,
+    progress: Arc<Mutex<Progress>>,
+}
+
+impl ScriptGuard {
+    /// Returns `true` if the script has finished.
+    pub fn is_finished(&self) -> bool {
+        self.progress.lock().unwrap().result.is_some()
+    }
+
+    /// Verifies the exchange so far.
+    ///
+    /// Waits for the script to become idle, then panics if it failed or if the
+    /// code under test wrote data after the end of the script.
+    pub fn checkpoint(&self) {
+        let deadline = self.script.timeout.map(|timeout| Instant::now() + timeout);
+
+        loop {
+            {
+                let progress = self.progress.lock().unwrap();
+
+                match &progress.result {
+                    Some(Err(err)) => panic!("script failed at step {}: {}", progress.step, err),
+                    Some(Ok(())) => {
+                        let mut unexpected = vec![0u8; self.pipe.read_buffer_len()];
+                        let n = self
+                            .pipe
+                            .read_with_timeout(&mut unexpected, Some(Duration::ZERO))
+                            .unwrap_or(0);
+
+                        if n > 0 {
+                            panic!(
+                                "unexpected data after the end of the script:\n{}",
+                                hexdump(&unexpected[0..n])
+                            );
+                        }
+
+                        return;
+                    }
+                    None if progress.waiting && (self.pipe.read_buffer_len() == 0) => return,
+                    None => {}
+                }
+            }
+
+            if deadline.map_or(false, |deadline| Instant::now() >= deadline) {
+                panic!(
+                    "script did not become idle in time at step {}",
+                    self.progress.lock().unwrap().step
+                );
+            }
+
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+}
+
+impl Drop for ScriptGuard {
+    fn drop(&mut self) {
+        if !thread::panicking() {
+            self.checkpoint();
+        }
+
+        let unfinished_step = {
+            let progress = self.progress.lock().unwrap();
+            progress.result.is_none().then_some(progress.step)
+        };
+
+        if let Some(step) = unfinished_step {
+            self.pipe.close();
+
+            if !thread::panicking() {
+                panic!(
+                    "script did not finish: step {} was not satisfied: {:02x?}",
+                    step, self.script.steps[step]
+                );
+            }
+        }
+    }
+}
+
+/// The serde representation of scenario files.
+#[cfg(any(feature = "json", feature = "yaml", feature = "toml"))]
+mod scenario {
+    use std::{io, time::Duration};
+
+    use serde::Deserialize;
+
+    use super::{Action, Script, Step};
+    use crate::record::decode_hex;
+
+    #[derive(Deserialize)]
+    #[serde(deny_unknown_fields)]
+    pub(super) struct Scenario {
+        #[serde(default)]
+        timeout_ms: Option<u64>,
+        #[serde(default)]
+        steps: Vec<ScenarioStep>,
+        #[serde(default)]
+        events: Vec<ScenarioEvent>,
+    }
+
+    #[derive(Deserialize)]
+    #[serde(rename_all = "snake_case")]
+    enum ScenarioStep {
+        Expect(Data),
+        Reply(Data),
+        Delay(u64),
+        Error(String),
+        Close,
+    }
+
+    #[derive(Deserialize)]
+    #[serde(deny_unknown_fields)]
+    struct ScenarioEvent {
+        at_ms: u64,
+        action: ScenarioAction,
+    }
+
+    #[derive(Deserialize)]
+    #[serde(rename_all = "snake_case")]
+    enum ScenarioAction {
+        Error(String),
+        LinkDown(u64),
+        Stall(u64),
+        Close,
+    }
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Data {
+        Text(String),
+        Hex { hex: String },
+    }
+
+    impl Data {
+        fn into_bytes(self) -> io::Result<Vec<u8>> {
+            match self {
+                Data::Text(text) => Ok(text.into_bytes()),
+                Data::Hex { hex } => decode_hex(&hex).ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("invalid hex data: {}", hex),
+                    )
+                }),
+            }
+        }
+    }
+
+    impl Scenario {
+        pub(super) fn into_script(self) -> io::Result<Script> {
+            let steps = self
+                .steps
+                .into_iter()
+                .map(|step| {
+                    Ok(match step {
+                        ScenarioStep::Expect(data) => Step::Expect(data.into_bytes()?),
+                        ScenarioStep::Reply(data) => Step::Reply(data.into_bytes()?),
+                        ScenarioStep::Delay(ms) => Step::Delay(Duration::from_millis(ms)),
+                        ScenarioStep::Error(name) => Step::Error(error_kind(&name)?),
+                        ScenarioStep::Close => Step::Close,
+                    })
+                })
+                .collect::<io::Result<_>>()?;
+
+            let script =
+                self.events
+                    .into_iter()
+                    .try_fold(Script::from_steps(steps), |script, event| {
+                        let action = match event.action {
+                            ScenarioAction::Error(name) => Action::Error(error_kind(&name)?),
+                            ScenarioAction::LinkDown(ms) => {
+                                Action::LinkDown(Duration::from_millis(ms))
+                            }
+                            ScenarioAction::Stall(ms) => Action::Stall(Duration::from_millis(ms)),
+                            ScenarioAction::Close => Action::Close,
+                        };
+                        Ok::<_, io::Error>(script.at(Duration::from_millis(event.at_ms), action))
+                    })?;
+
+            Ok(match self.timeout_ms {
+                Some(ms) => script.with_timeout(Some(Duration::from_millis(ms))),
+                None => script,
+            })
+        }
+    }
+
+    /// Maps a snake case `io::ErrorKind` name to the error kind.
+    fn error_kind(name: &str) -> io::Result<io::ErrorKind> {
+        use io::ErrorKind::*;
+
+        Ok(match name {
+            "not_found" => NotFound,
+            "permission_denied" => PermissionDenied,
+            "connection_refused" => ConnectionRefused,
+            "connection_reset" => ConnectionReset,
+            "connection_aborted" => ConnectionAborted,
+            "not_connected" => NotConnected,
+            "addr_in_use" => AddrInUse,
+            "addr_not_available" => AddrNotAvailable,
+            "broken_pipe" => BrokenPipe,
+            "already_exists" => AlreadyExists,
+            "would_block" => WouldBlock,
+            "invalid_input" => InvalidInput,
+            "invalid_data" => InvalidData,
+            "timed_out" => TimedOut,
+            "write_zero" => WriteZero,
+            "interrupted" => Interrupted,
+            "unsupported" => Unsupported,
+            "unexpected_eof" => UnexpectedEof,
+            "out_of_memory" => OutOfMemory,
+            "other" => Other,
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("unknown error kind: {}", name),
+                ))
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+
+    use super::*;
+
+    #[test]
+    fn test_script() {
+        let script = Script::new()
+            .expect(b"ping")
+            .reply(b"pong")
+            .error(io::ErrorKind::ConnectionReset)
+            .close();
+
+        let (mut host, device) = MockPipe::pair(64);
+        host.set_timeout(Some(Duration::from_secs(1)));
+
+        // The script goes through the endpoint like any other code
+        let mut tee = MockPipe::loopback(64);
+        device.tee(tee.clone());
+
+        let device = script.clone().spawn(device);
+
+        host.write_all(b"ping").unwrap();
+
+        let mut response = [0u8; 4];
+        host.read_exact(&mut response).unwrap();
+        assert_eq!(&response, b"pong");
+
+        device.join().unwrap().unwrap();
+
+        let mut mirrored = [0u8; 8];
+        tee.read_exact(&mut mirrored).unwrap();
+        assert_eq!(&mirrored, b"pingpong");
+
+        assert_eq!(
+            host.read(&mut response).unwrap_err().kind(),
+            io::ErrorKind::ConnectionReset
+        );
+        assert_eq!(host.read(&mut response).unwrap(), 0);
+
+        // Unexpected data written by the code under test is reported
+        let (mut host, device) = MockPipe::pair(64);
+        let device = script.spawn(device);
+
+        host.write_all(b"pang").unwrap();
+
+        assert_eq!(
+            device.join().unwrap().unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+    }
+
+    #[test]
+    fn test_script_guard() {
+        let (mut host, device) = MockPipe::pair(64);
+        host.set_timeout(Some(Duration::from_secs(1)));
+
+        let guard = Script::new()
+            .expect(b"ping")
+            .reply(b"pong")
+            .expect(b"bye")
+            .start(device);
+
+        host.write_all(b"ping").unwrap();
+        let mut response = [0u8; 4];
+        host.read_exact(&mut response).unwrap();
+        guard.checkpoint();
+        assert!(!guard.is_finished());
+
+        host.write_all(b"bye").unwrap();
+        drop(guard);
+    }
+
+    #[test]
+    #[should_panic(expected = "unexpected data after the end of the script")]
+    fn test_script_guard_unexpected_data() {
+        let (mut host, device) = MockPipe::pair(64);
+
+        let guard = Script::new().expect(b"ping").start(device);
+
+        host.write_all(b"ping!").unwrap();
+        drop(guard);
+    }
+
+    #[test]
+    fn test_script_timeline() {
+        let (mut host, device) = MockPipe::pair(64);
+
+        let script = Script::new()
+            .at(
+                Duration::from_millis(150),
+                Action::Error(io::ErrorKind::ConnectionReset),
+            )
+            .at(Duration::ZERO, Action::LinkDown(Duration::from_millis(100)));
+        let handle = script.spawn(device.clone());
+
+        thread::sleep(Duration::from_millis(20));
+        host.write_all(b"lost").unwrap();
+        assert_eq!(device.read_buffer_len(), 0);
+
+        // The script completes once the last action is triggered
+        handle.join().unwrap().unwrap();
+        host.write_all(b"kept").unwrap();
+        assert_eq!(device.read_buffer_len(), 4);
+        assert_eq!(
+            host.read(&mut [0u8; 4]).unwrap_err().kind(),
+            io::ErrorKind::ConnectionReset
+        );
+    }
+
+    #[test]
+    fn test_from_file_unsupported() {
+        assert_eq!(
+            Script::from_file("scenario.txt").unwrap_err().kind(),
+            io::ErrorKind::Unsupported
+        );
+    }
+
+    #[cfg(all(feature = "json", feature = "yaml", feature = "toml"))]
+    #[test]
+    fn test_scenario_formats() {
+        let expected = Script::new()
+            .expect(b"AT\r\n")
+            .delay(Duration::from_millis(5))
+            .reply(b"OK\r\n")
+            .error(io::ErrorKind::BrokenPipe)
+            .close()
+            .at(
+                Duration::from_millis(500),
+                Action::Error(io::ErrorKind::ConnectionReset),
+            )
+            .at(
+                Duration::from_millis(2000),
+                Action::LinkDown(Duration::from_millis(300)),
+            )
+            .with_timeout(Some(Duration::from_millis(500)));
+
+        let json = r#"{
+            "timeout_ms": 500,
+            "steps": [
+                {"expect": "AT\r\n"},
+                {"delay": 5},
+                {"reply": {"hex": "4f4b0d0a"}},
+                {"error": "broken_pipe"},
+                "close"
+            ],
+            "events": [
+                {"at_ms": 500, "action": {"error": "connection_reset"}},
+                {"at_ms": 2000, "action": {"link_down": 300}}
+            ]
+        }"#;
+        assert_eq!(Script::from_json(json).unwrap(), expected);
+
+        let yaml = r#"
+timeout_ms: 500
+steps:
+  - expect: "AT\r\n"
+  - delay: 5
+  - reply: { hex: "4f4b0d0a" }
+  - error: broken_pipe
+  - close
+events:
+  - at_ms: 500
+    action: { error: connection_reset }
+  - at_ms: 2000
+    action: { link_down: 300 }
+"#;
+        assert_eq!(Script::from_yaml(yaml).unwrap(), expected);
+
+        let toml = r#"
+timeout_ms = 500
+steps = [
+    { expect = "AT\r\n" },
+    { delay = 5 },
+    { reply = { hex = "4f4b0d0a" } },
+    { error = "broken_pipe" },
+    "close",
+]
+events = [
+    { at_ms = 500, action = { error = "connection_reset" } },
+    { at_ms = 2000, action = { link_down = 300 } },
+]
+"#;
+        assert_eq!(Script::from_toml(toml).unwrap(), expected);
+
+        assert_eq!(
+            Script::from_json(r#"{"steps": [{"error": "sideways"}]}"#)
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::InvalidData
+        );
+    }
+}
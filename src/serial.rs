@@ -0,0 +1,303 @@
+//! Serial line emulation with realistic character timing.
+//!
+//! [`SerialPipe`] wraps a pipe endpoint and paces the data written to it
+//! according to the configured baud rate and character format, so every byte
+//! becomes readable on the other end only once it would have been transmitted
+//! over a real UART.
+//!
+//! On top of that it supports Modbus RTU framing, where frames are delimited by
+//! a silent interval of at least 3.5 character times: [`SerialPipe::write_rtu_frame`]
+//! keeps the line idle for the interval before transmitting a frame, and
+//! [`SerialPipe::read_rtu_frame`] splits the received data into frames on
+//! silence, as an RTU receiver does.
+
+use std::{
+    io::{self, Read, Write},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::MockPipe;
+
+/// Parity bit of a serial character.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Parity {
+    /// No parity bit.
+    #[default]
+    None,
+
+    /// Even parity.
+    Even,
+
+    /// Odd parity.
+    Odd,
+}
+
+/// Serial line settings.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct SerialConfig {
+    /// Baud rate, in bits per second.
+    pub baud_rate: u32,
+
+    /// Number of data bits per character.
+    pub data_bits: u8,
+
+    /// Parity bit.
+    pub parity: Parity,
+
+    /// Number of stop bits.
+    pub stop_bits: u8,
+}
+
+impl SerialConfig {
+    /// Creates settings for the given baud rate with the common 8N1 character
+    /// format (8 data bits, no parity, 1 stop bit).
+    pub fn new(baud_rate: u32) -> Self {
+        Self {
+            baud_rate,
+            data_bits: 8,
+            parity: Parity::None,
+            stop_bits: 1,
+        }
+    }
+
+    /// Sets the number of data bits and returns the modified settings.
+    pub fn with_data_bits(mut self, data_bits: u8) -> Self {
+        self.data_bits = data_bits;
+        self
+    }
+
+    /// Sets the parity and returns the modified settings.
+    pub fn with_parity(mut self, parity: Parity) -> Self {
+        self.parity = parity;
+        self
+    }
+
+    /// Sets the number of stop bits and returns the modified settings.
+    pub fn with_stop_bits(mut self, stop_bits: u8) -> Self {
+        self.stop_bits = stop_bits;
+        self
+    }
+
+    /// Returns the number of bits on the line per character, including the
+    /// start, parity and stop bits.
+    pub fn bits_per_char(&self) -> u32 {
+        let parity_bits = match self.parity {
+            Parity::None => 0,
+            Parity::Even | Parity::Odd => 1,
+        };
+
+        1 + u32::from(self.data_bits) + parity_bits + u32::from(self.stop_bits)
+    }
+
+    /// Returns the time it takes to transmit a single character.
+    pub fn char_time(&self) -> Duration {
+        self.half_chars_time(2)
+    }
+
+    /// Returns the silent interval delimiting Modbus RTU frames: 3.5 character
+    /// times, or a fixed 1.75 ms for baud rates above 19200, as recommended by
+    /// the Modbus over serial line specification.
+    pub fn rtu_silent_interval(&self) -> Duration {
+        if self.baud_rate > 19200 {
+            Duration::from_micros(1750)
+        } else {
+            self.half_chars_time(7)
+        }
+    }
+
+    /// Returns the time it takes to transmit the given number of half
+    /// characters.
+    fn half_chars_time(&self, half_chars: u64) -> Duration {
+        let bits = half_chars * u64::from(self.bits_per_char());
+        Duration::from_nanos(bits * 1_000_000_000 / (2 * u64::from(self.baud_rate)))
+    }
+}
+
+/// A pipe endpoint emulating a serial line with the given settings.
+///
+/// Writes block the caller while the data is being "transmitted", and each
+/// byte is delivered to the other end at the time its transmission completes.
+/// Reads are not affected. Read and write timeouts are those of the wrapped
+/// pipe.
+pub struct SerialPipe {
+    pipe: MockPipe,
+    config: SerialConfig,
+    line_idle_at: Option<Instant>,
+}
+
+impl SerialPipe {
+    /// Wraps the pipe endpoint, emulating a serial line with the given settings.
+    pub fn new(pipe: MockPipe, config: SerialConfig) -> Self {
+        Self {
+            pipe,
+            config,
+            line_idle_at: None,
+        }
+    }
+
+    /// Returns the serial line settings.
+    pub fn config(&self) -> &SerialConfig {
+        &self.config
+    }
+
+    /// Returns a reference to the wrapped pipe endpoint.
+    pub fn pipe(&self) -> &MockPipe {
+        &self.pipe
+    }
+
+    /// Consumes the serial pipe, returning the wrapped pipe endpoint.
+    pub fn into_inner(self) -> MockPipe {
+        self.pipe
+    }
+
+    /// Transmits a Modbus RTU frame.
+    ///
+    /// Keeps the line silent for at least the RTU silent interval since the
+    /// end of the previous transmission before sending the frame, and returns
+    /// once the whole frame has been transmitted.
+    pub fn write_rtu_frame(&mut self, frame: &[u8]) -> io::Result<()> {
+        if let Some(line_idle_at) = self.line_idle_at {
+            sleep_until(line_idle_at + self.config.rtu_silent_interval());
+        }
+
+        self.write_all(frame)
+    }
+
+    /// Receives a Modbus RTU frame.
+    ///
+    /// Waits for the first byte (subject to the pipe timeout), then reads until
+    /// the line stays silent for the RTU silent interval. Returns the length of
+    /// the frame, which is truncated if it does not fit into `buf`, or `0` at
+    /// end of stream.
+    pub fn read_rtu_frame(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut len = self.pipe.read(buf)?;
+
+        if len == 0 {
+            return Ok(0);
+        }
+
+        let silent_interval = Some(self.config.rtu_silent_interval());
+
+        while len < buf.len() {
+            match self
+                .pipe
+                .read_with_timeout(&mut buf[len..], silent_interval)
+            {
+                Ok(0) => break,
+                Ok(n) => len += n,
+                Err(err) if err.kind() == io::ErrorKind::TimedOut => break,
+                Err(err) => return Err(err),
+            }
+        }
+
+        Ok(len)
+    }
+}
+
+/// Sleeps until the given instant, if it is in the future.
+fn sleep_until(deadline: Instant) {
+    let now = Instant::now();
+    if deadline > now {
+        thread::sleep(deadline - now);
+    }
+}
+
+impl Read for SerialPipe {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.pipe.read(buf)
+    }
+}
+
+impl Write for SerialPipe {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let char_time = self.config.char_time();
+
+        // Transmission continues right after the previous one if the line is
+        // still busy
+        let now = Instant::now();
+        let mut transmitted_at = self.line_idle_at.filter(|&at| at > now).unwrap_or(now);
+
+        for (i, byte) in buf.iter().enumerate() {
+            // Deadlines are absolute, so that oversleeping does not accumulate
+            transmitted_at += char_time;
+            sleep_until(transmitted_at);
+
+            match self.pipe.write(&[*byte]) {
+                Ok(0) => return Ok(i),
+                Ok(_) => self.line_idle_at = Some(transmitted_at),
+                Err(err) if i > 0 && err.kind() == io::ErrorKind::TimedOut => return Ok(i),
+                Err(err) => return Err(err),
+            }
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.pipe.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_serial_config_timing() {
+        let config = SerialConfig::new(9600);
+        assert_eq!(config.bits_per_char(), 10);
+        assert_eq!(config.char_time(), Duration::from_nanos(1_041_666));
+        assert_eq!(
+            config.rtu_silent_interval(),
+            Duration::from_nanos(3_645_833)
+        );
+
+        let config = config.with_parity(Parity::Even).with_stop_bits(2);
+        assert_eq!(config.bits_per_char(), 12);
+
+        assert_eq!(
+            SerialConfig::new(115200).rtu_silent_interval(),
+            Duration::from_micros(1750)
+        );
+    }
+
+    #[test]
+    fn test_serial_pacing() {
+        let config = SerialConfig::new(9600);
+        let (pipe1, pipe2) = MockPipe::pair(64);
+
+        let mut port1 = SerialPipe::new(pipe1, config);
+        let mut port2 = SerialPipe::new(pipe2, config);
+
+        let start = Instant::now();
+        port1.write_all(&[0u8; 10]).unwrap();
+        assert!(start.elapsed() >= config.char_time() * 10);
+
+        let mut read_data = [0u8; 10];
+        port2.read_exact(&mut read_data).unwrap();
+    }
+
+    #[test]
+    fn test_rtu_frames() {
+        // A low baud rate keeps the silent interval well above scheduling jitter
+        let config = SerialConfig::new(1200);
+        let (pipe1, pipe2) = MockPipe::pair(64);
+
+        let mut master = SerialPipe::new(pipe1, config);
+        let mut slave = SerialPipe::new(pipe2.with_timeout(Some(Duration::from_secs(1))), config);
+
+        let writer = thread::spawn(move || {
+            master.write_rtu_frame(&[0x11, 0x03, 0x00, 0x6b]).unwrap();
+            master.write_rtu_frame(&[0x11, 0x06]).unwrap();
+        });
+
+        let mut frame = [0u8; 16];
+        let len = slave.read_rtu_frame(&mut frame).unwrap();
+        assert_eq!(&frame[0..len], &[0x11, 0x03, 0x00, 0x6b]);
+        let len = slave.read_rtu_frame(&mut frame).unwrap();
+        assert_eq!(&frame[0..len], &[0x11, 0x06]);
+
+        writer.join().unwrap();
+    }
+}
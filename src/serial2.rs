@@ -0,0 +1,257 @@
+//! Adapter mirroring the `serial2` crate API over `MockPipe`.
+//!
+//! [`SerialPort`] has the same methods as `serial2::SerialPort` for reading
+//! and writing with timeouts, flushing and discarding buffers, so code using
+//! `serial2` can be compiled against the mock in tests by swapping the import:
+//!
+//! ```
+//! use std::{io::Write, time::Duration};
+//!
+//! // use serial2::SerialPort;
+//! use mockpipe::serial2::SerialPort;
+//!
+//! fn query(port: &SerialPort) -> std::io::Result<Vec<u8>> {
+//!     port.write_all(b"ID?\r\n")?;
+//!
+//!     let mut reply = vec![0u8; 6];
+//!     port.read_exact(&mut reply)?;
+//!     Ok(reply)
+//! }
+//!
+//! let (mut device, host) = mockpipe::MockPipe::pair(64);
+//! let mut port = SerialPort::new(host);
+//! port.set_read_timeout(Duration::from_millis(100)).unwrap();
+//!
+//! device.write_all(b"DEV01\n").unwrap();
+//! assert_eq!(query(&port).unwrap(), b"DEV01\n");
+//! ```
+
+use std::{
+    io::{self, Read, Write},
+    time::Duration,
+};
+
+use crate::{Error, MockPipe};
+
+/// Default read and write timeout, as in `serial2`.
+const DEFAULT_TIMEOUT: Duration = Duration::from_millis(50);
+
+/// A serial port backed by a pipe endpoint, with the API of
+/// `serial2::SerialPort`.
+///
+/// As with `serial2`, reads and writes have separate timeouts (independent of
+/// the pipe timeout), and fail with a `TimedOut` error if no data could be
+/// transferred within them. The written data is transmitted at once, so
+/// flushing never waits.
+#[derive(Clone)]
+pub struct SerialPort {
+    pipe: MockPipe,
+    read_timeout: Duration,
+    write_timeout: Duration,
+}
+
+impl SerialPort {
+    /// Creates a serial port over the pipe endpoint, with the default 50 ms
+    /// read and write timeouts.
+    pub fn new(pipe: MockPipe) -> Self {
+        Self {
+            pipe,
+            read_timeout: DEFAULT_TIMEOUT,
+            write_timeout: DEFAULT_TIMEOUT,
+        }
+    }
+
+    /// Returns a reference to the underlying pipe endpoint.
+    pub fn pipe(&self) -> &MockPipe {
+        &self.pipe
+    }
+
+    /// Consumes the port, returning the underlying pipe endpoint.
+    pub fn into_inner(self) -> MockPipe {
+        self.pipe
+    }
+
+    /// Returns a second handle to the same port.
+    pub fn try_clone(&self) -> io::Result<Self> {
+        Ok(self.clone())
+    }
+
+    /// Reads data, waiting at most the read timeout for it to arrive.
+    ///
+    /// Returns `Ok(0)` only once the pipe is closed and drained.
+    pub fn read(&self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.pipe.read_with_timeout(buf, Some(self.read_timeout))? {
+            // A zero timeout does not wait, but must not report end of stream
+            0 if !buf.is_empty() && !self.pipe.read_buffer.is_closed() => {
+                Err(io::Error::from(Error::TimedOut { partial: 0 }))
+            }
+            n => Ok(n),
+        }
+    }
+
+    /// Reads exactly `buf.len()` bytes, applying the read timeout to each
+    /// underlying read. A timeout reports the number of bytes read before it
+    /// (see [`Error::TimedOut`]).
+    pub fn read_exact(&self, buf: &mut [u8]) -> io::Result<()> {
+        let mut partial = 0;
+        while partial < buf.len() {
+            match self.read(&mut buf[partial..]) {
+                Ok(0) => return Err(io::Error::from(io::ErrorKind::UnexpectedEof)),
+                Ok(n) => partial += n,
+                Err(err) if err.kind() == io::ErrorKind::TimedOut => {
+                    return Err(io::Error::from(Error::TimedOut { partial }))
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes data, waiting at most the write timeout for buffer space.
+    pub fn write(&self, buf: &[u8]) -> io::Result<usize> {
+        match self
+            .pipe
+            .write_with_timeout(buf, Some(self.write_timeout))?
+        {
+            0 if !buf.is_empty() => Err(io::Error::from(Error::TimedOut { partial: 0 })),
+            n => Ok(n),
+        }
+    }
+
+    /// Writes all of `buf`, applying the write timeout to each underlying
+    /// write. A timeout reports the number of bytes written before it (see
+    /// [`Error::TimedOut`]).
+    pub fn write_all(&self, buf: &[u8]) -> io::Result<()> {
+        let mut partial = 0;
+        while partial < buf.len() {
+            match self.write(&buf[partial..]) {
+                Ok(n) => partial += n,
+                Err(err) if err.kind() == io::ErrorKind::TimedOut => {
+                    return Err(io::Error::from(Error::TimedOut { partial }))
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    }
+
+    /// Does nothing, as the written data is transmitted at once.
+    pub fn flush(&self) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Sets the read timeout.
+    pub fn set_read_timeout(&mut self, timeout: Duration) -> io::Result<()> {
+        self.read_timeout = timeout;
+        Ok(())
+    }
+
+    /// Returns the read timeout.
+    pub fn get_read_timeout(&self) -> io::Result<Duration> {
+        Ok(self.read_timeout)
+    }
+
+    /// Sets the write timeout.
+    pub fn set_write_timeout(&mut self, timeout: Duration) -> io::Result<()> {
+        self.write_timeout = timeout;
+        Ok(())
+    }
+
+    /// Returns the write timeout.
+    pub fn get_write_timeout(&self) -> io::Result<Duration> {
+        Ok(self.write_timeout)
+    }
+
+    /// Discards the received data not read yet and the written data not
+    /// transmitted yet.
+    pub fn discard_buffers(&self) -> io::Result<()> {
+        self.pipe.clear();
+        Ok(())
+    }
+
+    /// Discards the received data not read yet.
+    pub fn discard_input_buffer(&self) -> io::Result<()> {
+        self.pipe.clear_read();
+        Ok(())
+    }
+
+    /// Discards the written data not transmitted (read by the other end) yet.
+    pub fn discard_output_buffer(&self) -> io::Result<()> {
+        self.pipe.clear_write();
+        Ok(())
+    }
+}
+
+impl From<MockPipe> for SerialPort {
+    fn from(pipe: MockPipe) -> Self {
+        Self::new(pipe)
+    }
+}
+
+impl Read for SerialPort {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        SerialPort::read(self, buf)
+    }
+}
+
+impl Read for &SerialPort {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        SerialPort::read(self, buf)
+    }
+}
+
+impl Write for SerialPort {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        SerialPort::write(self, buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        SerialPort::flush(self)
+    }
+}
+
+impl Write for &SerialPort {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        SerialPort::write(self, buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        SerialPort::flush(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_serial2_port() {
+        let (mut device, host) = MockPipe::pair(4);
+        let mut port = SerialPort::new(host);
+        port.set_read_timeout(Duration::from_millis(10)).unwrap();
+        port.set_write_timeout(Duration::ZERO).unwrap();
+
+        let mut buf = [0u8; 4];
+        let err = port.read(&mut buf).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+
+        port.write_all(b"ping").unwrap();
+        let err = port.write(b"!").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        port.discard_output_buffer().unwrap();
+        assert_eq!(port.write(b"!").unwrap(), 1);
+
+        // A timeout reports the data written before it
+        let err = port.write_all(b"abcd").unwrap_err();
+        assert_eq!(Error::from_io(&err), Some(Error::TimedOut { partial: 3 }));
+        port.discard_output_buffer().unwrap();
+
+        device.write_all(b"pong").unwrap();
+        port.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"pong");
+
+        assert_eq!(device.write(b"stale").unwrap(), 4);
+        port.discard_input_buffer().unwrap();
+        assert_eq!(port.pipe().read_buffer_len(), 0);
+    }
+}
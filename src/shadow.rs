@@ -0,0 +1,181 @@
+//! OS-level readiness signaling for code waiting on raw descriptors.
+//!
+//! With [`MockPipe::enable_os_readiness`], an endpoint is paired with a shadow
+//! loopback UDP socket that is readable exactly when a read from the endpoint
+//! would not block (data, an injected error or the end of stream is pending).
+//! The endpoint exposes the socket through `AsRawFd` (or `AsRawSocket` on
+//! Windows), so event loops built on `select`, `poll` or `epoll` can wait on
+//! the mock as on a real descriptor, and then read from the endpoint itself.
+//!
+//! The socket carries no data and is always writable, so only readability is
+//! signaled.
+
+use std::{
+    io,
+    net::{Ipv4Addr, SocketAddr, UdpSocket},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+#[cfg(unix)]
+use std::os::unix::io::{AsRawFd, RawFd};
+#[cfg(windows)]
+use std::os::windows::io::{AsRawSocket, RawSocket};
+
+use crate::MockPipe;
+
+/// Loopback UDP socket holding a datagram while the endpoint is readable.
+pub(crate) struct Shadow {
+    socket: UdpSocket,
+
+    /// Whether a datagram is queued in the socket.
+    readable: AtomicBool,
+}
+
+impl Shadow {
+    /// Creates a non-blocking socket connected to itself.
+    fn new() -> io::Result<Self> {
+        let socket = UdpSocket::bind(SocketAddr::from((Ipv4Addr::LOCALHOST, 0)))?;
+        socket.connect(socket.local_addr()?)?;
+        socket.set_nonblocking(true)?;
+
+        Ok(Self {
+            socket,
+            readable: AtomicBool::new(false),
+        })
+    }
+
+    /// Queues or drains the datagram making the socket readable.
+    fn set_readable(&self, readable: bool) {
+        if self.readable.swap(readable, Ordering::Relaxed) == readable {
+            return;
+        }
+
+        if readable {
+            let _ = self.socket.send(&[0]);
+        } else {
+            let mut buf = [0u8; 1];
+            while self.socket.recv(&mut buf).is_ok() {}
+        }
+    }
+}
+
+impl MockPipe {
+    /// Enables OS-level readiness signaling for the endpoint, pairing it with a
+    /// shadow socket that is readable whenever a read from the endpoint would
+    /// not block. Does nothing if it is already enabled.
+    ///
+    /// Clones of an endpoint share the shadow socket.
+    pub fn enable_os_readiness(&self) -> io::Result<()> {
+        let mut shadow_guard = self.shadow.lock().unwrap();
+        if shadow_guard.is_some() {
+            return Ok(());
+        }
+
+        let shadow = Arc::new(Shadow::new()?);
+        *shadow_guard = Some(shadow.clone());
+
+        self.read_buffer
+            .set_read_readiness(Some(Arc::new(move |readable| {
+                shadow.set_readable(readable)
+            })));
+
+        Ok(())
+    }
+
+    /// Enables OS-level readiness signaling and returns the modified
+    /// `MockPipe`.
+    pub fn with_os_readiness(self) -> io::Result<Self> {
+        self.enable_os_readiness()?;
+        Ok(self)
+    }
+
+    /// Returns the shadow socket of the endpoint.
+    ///
+    /// # Panics
+    ///
+    /// Panics if OS-level readiness signaling is not enabled.
+    #[cfg(any(unix, windows, test))]
+    fn shadow(&self) -> Arc<Shadow> {
+        self.shadow
+            .lock()
+            .unwrap()
+            .clone()
+            .expect("OS-level readiness signaling is not enabled for the pipe")
+    }
+}
+
+/// Returns the descriptor of the shadow socket, which is readable whenever a
+/// read from the endpoint would not block.
+///
+/// # Panics
+///
+/// Panics if [`MockPipe::enable_os_readiness`] was not called.
+#[cfg(unix)]
+impl AsRawFd for MockPipe {
+    fn as_raw_fd(&self) -> RawFd {
+        self.shadow().socket.as_raw_fd()
+    }
+}
+
+/// Returns the handle of the shadow socket, which is readable whenever a read
+/// from the endpoint would not block.
+///
+/// # Panics
+///
+/// Panics if [`MockPipe::enable_os_readiness`] was not called.
+#[cfg(windows)]
+impl AsRawSocket for MockPipe {
+    fn as_raw_socket(&self) -> RawSocket {
+        self.shadow().socket.as_raw_socket()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Read, Write},
+        thread,
+        time::Duration,
+    };
+
+    use super::*;
+
+    /// Returns `true` if the shadow socket of the endpoint becomes readable
+    /// within a few milliseconds, allowing for asynchronous loopback delivery.
+    fn is_readable(pipe: &MockPipe) -> bool {
+        (0..10).any(|_| {
+            let readable = pipe.shadow().socket.peek(&mut [0u8; 1]).is_ok();
+            if !readable {
+                thread::sleep(Duration::from_millis(1));
+            }
+            readable
+        })
+    }
+
+    #[test]
+    fn test_os_readiness() {
+        let (mut device, host) = MockPipe::pair(16);
+        let mut host = host.with_os_readiness().unwrap();
+        assert!(!is_readable(&host));
+
+        device.write_all(b"ping").unwrap();
+        assert!(is_readable(&host));
+
+        let mut buf = [0u8; 2];
+        host.read_exact(&mut buf).unwrap();
+        assert!(is_readable(&host));
+        host.read_exact(&mut buf).unwrap();
+        assert!(!is_readable(&host));
+
+        device.inject_error(io::ErrorKind::ConnectionReset);
+        assert!(is_readable(&host));
+        assert!(host.read(&mut buf).is_err());
+        assert!(!is_readable(&host));
+
+        device.close();
+        assert!(is_readable(&host));
+    }
+}
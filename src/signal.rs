@@ -0,0 +1,135 @@
+//! Sideband signals conveying device conditions alongside the data stream.
+//!
+//! Besides the data, each direction of a pipe carries named signals with an
+//! integer value, raised with [`MockPipe::raise_signal`]: power state, SIM
+//! removal, carrier detect or any other condition of the emulated device. The
+//! other end either polls the changes with [`MockPipe::poll_signal`], looks up
+//! the current value with [`MockPipe::signal`], or receives the changes on a
+//! channel returned by [`MockPipe::subscribe_signals`]:
+//!
+//! ```
+//! use mockpipe::{signal::Signal, MockPipe};
+//!
+//! let (modem, host) = MockPipe::pair(64);
+//!
+//! modem.raise_signal("carrier_detect", 1);
+//! modem.raise_signal("sim_present", 0);
+//!
+//! assert_eq!(host.signal("sim_present"), Some(0));
+//! assert_eq!(host.poll_signal(), Some(Signal::new("carrier_detect", 1)));
+//! ```
+
+use std::{
+    collections::{BTreeMap, VecDeque},
+    sync::mpsc::{self, Receiver, Sender},
+};
+
+use crate::MockPipe;
+
+/// A change of a sideband signal.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Signal {
+    /// Name of the signal.
+    pub name: String,
+
+    /// New value of the signal.
+    pub value: i64,
+}
+
+impl Signal {
+    /// Creates a signal change.
+    pub fn new(name: impl Into<String>, value: i64) -> Self {
+        Self {
+            name: name.into(),
+            value,
+        }
+    }
+}
+
+/// The signals of one direction of a pipe.
+#[derive(Default)]
+pub(crate) struct SignalLane {
+    /// Current value of every signal raised so far.
+    values: BTreeMap<String, i64>,
+
+    /// Changes not polled yet.
+    pending: VecDeque<Signal>,
+
+    /// Channels of the subscribers to the changes.
+    subscribers: Vec<Sender<Signal>>,
+}
+
+impl MockPipe {
+    /// Sets the value of a sideband signal, observable on the other end.
+    ///
+    /// Every call counts as a change, even if the value stays the same, so
+    /// pulses can be conveyed.
+    pub fn raise_signal(&self, name: &str, value: i64) {
+        let signal = Signal::new(name, value);
+
+        let mut lane = self.write_buffer.signals.lock().unwrap();
+        lane.values.insert(signal.name.clone(), value);
+        lane.subscribers
+            .retain(|subscriber| subscriber.send(signal.clone()).is_ok());
+        lane.pending.push_back(signal);
+    }
+
+    /// Returns the oldest change of the signals raised by the other end not
+    /// polled yet, or `None` if there is none.
+    pub fn poll_signal(&self) -> Option<Signal> {
+        self.read_buffer.signals.lock().unwrap().pending.pop_front()
+    }
+
+    /// Returns the current value of a signal raised by the other end, or
+    /// `None` if it was never raised.
+    pub fn signal(&self, name: &str) -> Option<i64> {
+        self.read_buffer
+            .signals
+            .lock()
+            .unwrap()
+            .values
+            .get(name)
+            .copied()
+    }
+
+    /// Returns a channel receiving every change of the signals raised by the
+    /// other end from now on, independently of
+    /// [`poll_signal`](MockPipe::poll_signal).
+    pub fn subscribe_signals(&self) -> Receiver<Signal> {
+        let (sender, receiver) = mpsc::channel();
+        self.read_buffer
+            .signals
+            .lock()
+            .unwrap()
+            .subscribers
+            .push(sender);
+        receiver
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_signals() {
+        let (device, host) = MockPipe::pair(64);
+        let changes = host.subscribe_signals();
+
+        device.raise_signal("power", 2);
+        device.raise_signal("power", 0);
+        host.raise_signal("reset", 1);
+
+        // Each direction has its own signals
+        assert_eq!(host.signal("power"), Some(0));
+        assert_eq!(host.signal("reset"), None);
+        assert_eq!(device.signal("reset"), Some(1));
+
+        assert_eq!(host.poll_signal(), Some(Signal::new("power", 2)));
+        assert_eq!(host.poll_signal(), Some(Signal::new("power", 0)));
+        assert_eq!(host.poll_signal(), None);
+
+        let received: Vec<_> = changes.try_iter().collect();
+        assert_eq!(received, [Signal::new("power", 2), Signal::new("power", 0)]);
+    }
+}
@@ -0,0 +1,156 @@
+//! Strictly one-directional pipes.
+//!
+//! See [`MockPipe::simplex`](crate::MockPipe::simplex).
+
+use std::{io, time::Duration};
+
+use crate::{stats::PipeStats, MockPipe};
+
+/// The writing end of a simplex pipe created with
+/// [`MockPipe::simplex`](crate::MockPipe::simplex).
+///
+/// It implements only `io::Write`, so the data can't accidentally flow back.
+pub struct MockWriter {
+    pipe: MockPipe,
+}
+
+/// The reading end of a simplex pipe created with
+/// [`MockPipe::simplex`](crate::MockPipe::simplex).
+///
+/// It implements only `io::Read`, so the data can't accidentally flow back.
+pub struct MockReader {
+    pipe: MockPipe,
+}
+
+/// Implements the methods shared by both ends of a simplex pipe.
+macro_rules! impl_simplex_end {
+    ($end:ty) => {
+        impl $end {
+            /// Gets the current timeout duration for the operations.
+            pub fn timeout(&self) -> Option<Duration> {
+                self.pipe.timeout()
+            }
+
+            /// Sets the timeout duration for the operations.
+            ///
+            /// `None` means the operation blocks indefinitely.
+            /// `Some(Duration::ZERO)` means the operation is non-blocking.
+            pub fn set_timeout(&self, timeout: Option<Duration>) {
+                self.pipe.set_timeout(timeout);
+            }
+
+            /// Sets the timeout duration for the operations and returns the
+            /// modified end.
+            pub fn with_timeout(self, timeout: Option<Duration>) -> Self {
+                self.set_timeout(timeout);
+                self
+            }
+
+            /// Returns the number of bytes written and not read yet.
+            pub fn buffer_len(&self) -> usize {
+                self.pipe.read_buffer_len() + self.pipe.write_buffer_len()
+            }
+
+            /// Clears the buffer, discarding all pending data.
+            pub fn clear(&self) {
+                self.pipe.clear();
+            }
+
+            /// Closes the pipe: once the buffered data has been consumed, reads
+            /// return `Ok(0)` (end of stream) and writes fail with a
+            /// `BrokenPipe` error.
+            pub fn close(&self) {
+                self.pipe.close();
+            }
+
+            /// Returns `true` if the pipe was closed (by either end).
+            pub fn is_closed(&self) -> bool {
+                self.pipe.is_closed()
+            }
+
+            /// Returns the transfer statistics of this end.
+            pub fn stats(&self) -> PipeStats {
+                self.pipe.stats()
+            }
+
+            /// Returns the transfer statistics of this end per thread.
+            #[cfg(feature = "stats")]
+            pub fn thread_stats(&self) -> Vec<crate::stats::ThreadStats> {
+                self.pipe.thread_stats()
+            }
+        }
+    };
+}
+
+impl_simplex_end!(MockWriter);
+impl_simplex_end!(MockReader);
+
+impl MockWriter {
+    /// Creates the writing end over the given endpoint.
+    pub(crate) fn new(pipe: MockPipe) -> Self {
+        Self { pipe }
+    }
+
+    /// Injects an error into the data stream: once the data written so far has
+    /// been read, the next read fails with an error of the given kind.
+    pub fn inject_error(&self, kind: io::ErrorKind) {
+        self.pipe.inject_error(kind);
+    }
+}
+
+impl MockReader {
+    /// Creates the reading end over the given endpoint.
+    pub(crate) fn new(pipe: MockPipe) -> Self {
+        Self { pipe }
+    }
+}
+
+impl io::Write for MockWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.pipe.write(buf)
+    }
+
+    /// Waits until the reader has consumed the data written so far.
+    fn flush(&mut self) -> io::Result<()> {
+        self.pipe.flush()
+    }
+}
+
+impl io::Read for MockReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.pipe.read(buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+
+    use super::*;
+
+    #[test]
+    fn test_simplex() {
+        let (mut writer, mut reader) = MockPipe::simplex(4);
+
+        writer.write_all(b"ping").unwrap();
+        assert_eq!(writer.write(b"!").unwrap(), 0);
+        assert_eq!(reader.buffer_len(), 4);
+
+        let mut buf = [0u8; 4];
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"ping");
+
+        writer.inject_error(io::ErrorKind::ConnectionReset);
+        assert_eq!(
+            reader.read(&mut buf).unwrap_err().kind(),
+            io::ErrorKind::ConnectionReset
+        );
+
+        reader.close();
+        assert_eq!(
+            writer.write(b"x").unwrap_err().kind(),
+            io::ErrorKind::BrokenPipe
+        );
+        assert_eq!(reader.read(&mut buf).unwrap(), 0);
+    }
+}
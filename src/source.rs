@@ -0,0 +1,266 @@
+//! Endless generated data sources.
+//!
+//! [`MockPipe::source`] creates an endpoint producing an endless stream of
+//! generated data: a repeating pattern, counter bytes or seeded pseudo-random
+//! bytes, optionally at a limited rate. This allows testing the resync logic of
+//! parsers and the throughput of consumers without a hand-written feeder:
+//!
+//! ```
+//! use std::{io::Read, time::Duration};
+//!
+//! use mockpipe::{source::Generator, MockPipe};
+//!
+//! let mut source = MockPipe::source(Generator::pattern(*b"ab"));
+//! source.set_timeout(Some(Duration::from_secs(1)));
+//!
+//! let mut data = [0u8; 5];
+//! source.read_exact(&mut data).unwrap();
+//! assert_eq!(&data, b"ababa");
+//! ```
+
+use std::{
+    io,
+    sync::{Arc, Weak},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{buffer::SyncBuffer, rng::Rng, MockPipe};
+
+/// Capacity of the buffer of a source endpoint.
+const SOURCE_CAPACITY: usize = 4096;
+
+/// Interval at which the feeder of a source checks whether the endpoint was
+/// dropped while the buffer is full.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Data produced by a generator.
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Kind {
+    /// The given bytes, over and over.
+    Pattern(Vec<u8>),
+
+    /// Bytes counting up from zero, wrapping around.
+    Counter,
+
+    /// Pseudo-random bytes, from the given seed if any.
+    Random(Option<u64>),
+}
+
+/// Description of the data produced by a source endpoint.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Generator {
+    /// Data produced.
+    kind: Kind,
+
+    /// Maximum rate, in bytes per second, if limited.
+    rate: Option<u64>,
+}
+
+impl Generator {
+    /// Creates a generator repeating the given pattern.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the pattern is empty.
+    pub fn pattern(pattern: impl Into<Vec<u8>>) -> Self {
+        let pattern = pattern.into();
+        assert!(!pattern.is_empty(), "empty generator pattern");
+        Self::new(Kind::Pattern(pattern))
+    }
+
+    /// Creates a generator of bytes counting up from zero and wrapping around
+    /// after `0xff`.
+    pub fn counter() -> Self {
+        Self::new(Kind::Counter)
+    }
+
+    /// Creates a generator of pseudo-random bytes, seeded from the master seed
+    /// (see [`set_seed`](crate::set_seed)) unless seeded with
+    /// [`with_seed`](Generator::with_seed). Each source derives its own seed,
+    /// so unseeded sources produce different streams.
+    pub fn random() -> Self {
+        Self::new(Kind::Random(None))
+    }
+
+    fn new(kind: Kind) -> Self {
+        Self { kind, rate: None }
+    }
+
+    /// Sets the seed of a pseudo-random generator and returns the modified
+    /// generator. Has no effect on other generators.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        if let Kind::Random(_) = self.kind {
+            self.kind = Kind::Random(Some(seed));
+        }
+        self
+    }
+
+    /// Limits the rate of the generated data, in bytes per second, and returns
+    /// the modified generator. Unlimited by default, the data is then produced
+    /// as fast as it is read.
+    pub fn with_rate(mut self, bytes_per_second: u64) -> Self {
+        self.rate = Some(bytes_per_second);
+        self
+    }
+
+    /// Returns the rate limit in bytes per second, if any.
+    pub fn rate(&self) -> Option<u64> {
+        self.rate
+    }
+}
+
+/// Generator state of a running source.
+struct Stream {
+    /// Data produced.
+    kind: Kind,
+
+    /// Number of bytes produced so far.
+    produced: u64,
+
+    /// Generator of pseudo-random bytes.
+    rng: Rng,
+}
+
+impl Stream {
+    fn new(kind: Kind) -> Self {
+        let seed = match kind {
+            Kind::Random(seed) => seed.unwrap_or_else(|| Rng::derive_unique_seed("source")),
+            _ => 0,
+        };
+
+        Self {
+            kind,
+            produced: 0,
+            rng: Rng::new(seed),
+        }
+    }
+
+    /// Returns the next `n` bytes of the stream.
+    fn next_bytes(&mut self, n: usize) -> Vec<u8> {
+        let start = self.produced;
+        self.produced += n as u64;
+
+        (start..self.produced)
+            .map(|index| match &self.kind {
+                Kind::Pattern(pattern) => pattern[(index % pattern.len() as u64) as usize],
+                Kind::Counter => index as u8,
+                Kind::Random(_) => self.rng.next_u64() as u8,
+            })
+            .collect()
+    }
+}
+
+impl MockPipe {
+    /// Creates an endpoint whose read side produces an endless stream of data
+    /// from the given generator, while writes are discarded as by a
+    /// [`null`](MockPipe::null) sink.
+    ///
+    /// The data is produced by a background thread into a buffer of 4 KiB,
+    /// which stops once all clones of the endpoint are dropped.
+    pub fn source(generator: Generator) -> Self {
+        let buffer = Arc::new(SyncBuffer::new(SOURCE_CAPACITY));
+        start_feeder(&buffer, generator);
+
+        Self::from_buffers(buffer, Arc::new(SyncBuffer::null()))
+    }
+}
+
+/// Starts the thread writing the data of `generator` into `buffer` until it is
+/// dropped or closed.
+fn start_feeder(buffer: &Arc<SyncBuffer>, generator: Generator) {
+    let buffer: Weak<SyncBuffer> = Arc::downgrade(buffer);
+
+    thread::spawn(move || {
+        let mut stream = Stream::new(generator.kind);
+        let mut pending = Vec::new();
+
+        // Token bucket limiting the rate: the number of bytes that can be
+        // produced, and the time it was last updated
+        let mut tokens = 0.0;
+        let mut updated = Instant::now();
+
+        while let Some(buffer) = buffer.upgrade() {
+            if pending.is_empty() {
+                let n = match generator.rate {
+                    Some(rate) => {
+                        let now = Instant::now();
+                        tokens += rate as f64 * (now - updated).as_secs_f64();
+                        tokens = tokens.min(SOURCE_CAPACITY as f64);
+                        updated = now;
+
+                        if tokens < 1.0 {
+                            drop(buffer);
+                            thread::sleep(POLL_INTERVAL);
+                            continue;
+                        }
+
+                        tokens as usize
+                    }
+                    None => SOURCE_CAPACITY,
+                };
+
+                tokens -= n as f64;
+                pending = stream.next_bytes(n);
+            }
+
+            match buffer.write(&pending, Some(POLL_INTERVAL)) {
+                Ok(n) => {
+                    pending.drain(0..n);
+                }
+                Err(err) if err.kind() == io::ErrorKind::TimedOut => {}
+                Err(_) => break,
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use super::*;
+
+    /// Reads `n` bytes from a source.
+    fn read_source(source: &mut MockPipe, n: usize) -> Vec<u8> {
+        let mut data = vec![0u8; n];
+        source.read_exact(&mut data).unwrap();
+        data
+    }
+
+    #[test]
+    fn test_generators() {
+        let mut counter = MockPipe::source(Generator::counter());
+        counter.set_timeout(None);
+        let data = read_source(&mut counter, 300);
+        assert_eq!(data[255], 0xff);
+        assert_eq!(data[256], 0x00);
+        assert_eq!(data[299], 43);
+
+        let mut random1 = MockPipe::source(Generator::random().with_seed(5));
+        let mut random2 = MockPipe::source(Generator::random().with_seed(5));
+        random1.set_timeout(None);
+        random2.set_timeout(None);
+        assert_eq!(
+            read_source(&mut random1, 5000),
+            read_source(&mut random2, 5000)
+        );
+
+        // Unseeded sources are independent
+        let mut random1 = MockPipe::source(Generator::random());
+        let mut random2 = MockPipe::source(Generator::random());
+        random1.set_timeout(None);
+        random2.set_timeout(None);
+        assert_ne!(read_source(&mut random1, 64), read_source(&mut random2, 64));
+    }
+
+    #[test]
+    fn test_rate_limit() {
+        let mut source = MockPipe::source(Generator::pattern(*b"x").with_rate(1000));
+        source.set_timeout(None);
+
+        let start = Instant::now();
+        assert_eq!(read_source(&mut source, 200), [b'x'; 200]);
+        assert!(start.elapsed() >= Duration::from_millis(150));
+    }
+}
@@ -0,0 +1,461 @@
+//! Transfer statistics of pipe endpoints.
+//!
+//! With the `stats` feature enabled, [`PipeStats`] additionally collects
+//! histograms of the operation sizes and of the time spent waiting in each
+//! operation, which helps to find out e.g. why the code under test does
+//! thousands of 1-byte writes.
+//!
+//! With the `stats` feature, the statistics are also attributed to the threads
+//! making the operations, as `ThreadStats`, to find out which of the threads
+//! sharing an endpoint wrote or consumed what.
+//!
+//! With the `serde` feature enabled, [`PipeStats`] implements
+//! `serde::Serialize`, so statistics can be dumped e.g. by benchmark harnesses.
+
+use std::io;
+
+#[cfg(all(
+    any(feature = "stats", feature = "trace"),
+    not(all(target_arch = "wasm32", target_os = "unknown"))
+))]
+use std::time::Instant;
+#[cfg(feature = "stats")]
+use std::{
+    ops::Range,
+    thread::{self, ThreadId},
+};
+
+/// Counters of the I/O operations performed on a pipe endpoint.
+///
+/// Operations are counted when made through the endpoint's I/O trait
+/// implementations (`std::io` and, with the corresponding features, the
+/// `embedded-hal` and `embedded-io` ones).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct PipeStats {
+    /// Number of read operations.
+    pub reads: u64,
+
+    /// Number of write operations.
+    pub writes: u64,
+
+    /// Total number of bytes read.
+    pub bytes_read: u64,
+
+    /// Total number of bytes written.
+    pub bytes_written: u64,
+
+    /// Number of operations that failed with a `TimedOut` error.
+    pub timeouts: u64,
+
+    /// Number of operations that failed with any other error.
+    pub errors: u64,
+
+    /// Sizes of the successful reads, in bytes.
+    #[cfg(feature = "stats")]
+    pub read_sizes: Histogram,
+
+    /// Sizes of the successful writes, in bytes.
+    #[cfg(feature = "stats")]
+    pub write_sizes: Histogram,
+
+    /// Time spent in read operations (including waiting for data), in
+    /// microseconds.
+    #[cfg(feature = "stats")]
+    pub read_waits: Histogram,
+
+    /// Time spent in write operations (including waiting for space), in
+    /// microseconds.
+    #[cfg(feature = "stats")]
+    pub write_waits: Histogram,
+}
+
+/// Transfer statistics of the operations made by one thread on a pipe
+/// endpoint.
+#[cfg(feature = "stats")]
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct ThreadStats {
+    /// ID of the thread.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub id: ThreadId,
+
+    /// Name of the thread, if named.
+    pub name: Option<String>,
+
+    /// Statistics of the operations of the thread.
+    pub stats: PipeStats,
+}
+
+/// Transfer statistics of a pipe endpoint, in total and, with the `stats`
+/// feature, per thread.
+#[derive(Default)]
+pub(crate) struct EndpointStats {
+    pub(crate) total: PipeStats,
+
+    /// Statistics of the threads, in the order they first used the endpoint.
+    #[cfg(feature = "stats")]
+    pub(crate) threads: Vec<ThreadStats>,
+}
+
+impl EndpointStats {
+    /// Records the result of a read operation started at `timer`.
+    pub(crate) fn record_read(&mut self, result: &io::Result<usize>, timer: OpTimer) {
+        let wait = timer.elapsed_micros();
+        self.total.record_read(result, wait);
+        #[cfg(feature = "stats")]
+        self.current_thread().record_read(result, wait);
+    }
+
+    /// Records the result of a write operation started at `timer`.
+    pub(crate) fn record_write(&mut self, result: &io::Result<usize>, timer: OpTimer) {
+        let wait = timer.elapsed_micros();
+        self.total.record_write(result, wait);
+        #[cfg(feature = "stats")]
+        self.current_thread().record_write(result, wait);
+    }
+
+    /// Records a failed operation.
+    pub(crate) fn record_error(&mut self, err: &io::Error) {
+        self.total.record_error(err);
+        #[cfg(feature = "stats")]
+        self.current_thread().record_error(err);
+    }
+
+    /// Returns the statistics of the calling thread, added if needed.
+    #[cfg(feature = "stats")]
+    fn current_thread(&mut self) -> &mut PipeStats {
+        let current = thread::current();
+
+        let index = match self
+            .threads
+            .iter()
+            .position(|thread| thread.id == current.id())
+        {
+            Some(index) => index,
+            None => {
+                self.threads.push(ThreadStats {
+                    id: current.id(),
+                    name: current.name().map(str::to_owned),
+                    stats: PipeStats::default(),
+                });
+                self.threads.len() - 1
+            }
+        };
+
+        &mut self.threads[index].stats
+    }
+}
+
+impl PipeStats {
+    /// Records the result of a read operation which waited for `wait`
+    /// microseconds.
+    fn record_read(&mut self, result: &io::Result<usize>, wait: u64) {
+        self.reads += 1;
+
+        #[cfg(feature = "stats")]
+        self.read_waits.record(wait);
+        #[cfg(not(feature = "stats"))]
+        let _ = wait;
+
+        match result {
+            Ok(n) => {
+                self.bytes_read += *n as u64;
+                #[cfg(feature = "stats")]
+                self.read_sizes.record(*n as u64);
+            }
+            Err(err) => self.record_error(err),
+        }
+    }
+
+    /// Records the result of a write operation which waited for `wait`
+    /// microseconds.
+    fn record_write(&mut self, result: &io::Result<usize>, wait: u64) {
+        self.writes += 1;
+
+        #[cfg(feature = "stats")]
+        self.write_waits.record(wait);
+        #[cfg(not(feature = "stats"))]
+        let _ = wait;
+
+        match result {
+            Ok(n) => {
+                self.bytes_written += *n as u64;
+                #[cfg(feature = "stats")]
+                self.write_sizes.record(*n as u64);
+            }
+            Err(err) => self.record_error(err),
+        }
+    }
+
+    /// Records a failed operation.
+    fn record_error(&mut self, err: &io::Error) {
+        if err.kind() == io::ErrorKind::TimedOut {
+            self.timeouts += 1;
+        } else {
+            self.errors += 1;
+        }
+    }
+}
+
+/// Measures the duration of an operation when the `stats` or `trace` feature
+/// is enabled (and is a no-op otherwise).
+///
+/// `wasm32-unknown-unknown` has no clock, so durations are always zero there.
+pub(crate) struct OpTimer {
+    #[cfg(all(
+        any(feature = "stats", feature = "trace"),
+        not(all(target_arch = "wasm32", target_os = "unknown"))
+    ))]
+    start: Instant,
+}
+
+impl OpTimer {
+    /// Starts measuring an operation.
+    pub(crate) fn start() -> Self {
+        #[cfg(feature = "trace")]
+        crate::trace::clear_wait();
+
+        Self {
+            #[cfg(all(
+                any(feature = "stats", feature = "trace"),
+                not(all(target_arch = "wasm32", target_os = "unknown"))
+            ))]
+            start: Instant::now(),
+        }
+    }
+
+    /// Returns the time the operation started.
+    #[cfg(feature = "trace")]
+    pub(crate) fn started(&self) -> Instant {
+        self.start
+    }
+
+    #[cfg(all(
+        feature = "stats",
+        not(all(target_arch = "wasm32", target_os = "unknown"))
+    ))]
+    fn elapsed_micros(&self) -> u64 {
+        self.start.elapsed().as_micros() as u64
+    }
+
+    #[cfg(not(all(
+        feature = "stats",
+        not(all(target_arch = "wasm32", target_os = "unknown"))
+    )))]
+    fn elapsed_micros(&self) -> u64 {
+        0
+    }
+}
+
+/// Number of histogram buckets: one for zero and one per power of two.
+#[cfg(feature = "stats")]
+const BUCKETS: usize = 65;
+
+/// A histogram of values with power-of-two buckets.
+///
+/// Bucket `0` counts zero values, and bucket `i > 0` counts values in the range
+/// `2^(i-1)..2^i`.
+#[cfg(feature = "stats")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Histogram {
+    buckets: [u64; BUCKETS],
+    count: u64,
+    sum: u64,
+    max: u64,
+}
+
+#[cfg(feature = "stats")]
+impl Default for Histogram {
+    fn default() -> Self {
+        Self {
+            buckets: [0; BUCKETS],
+            count: 0,
+            sum: 0,
+            max: 0,
+        }
+    }
+}
+
+#[cfg(feature = "stats")]
+impl Histogram {
+    /// Adds a value to the histogram.
+    pub fn record(&mut self, value: u64) {
+        let bucket = (u64::BITS - value.leading_zeros()) as usize;
+
+        self.buckets[bucket] += 1;
+        self.count += 1;
+        self.sum = self.sum.saturating_add(value);
+        self.max = self.max.max(value);
+    }
+
+    /// Returns the number of recorded values.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Returns the sum of the recorded values.
+    pub fn sum(&self) -> u64 {
+        self.sum
+    }
+
+    /// Returns the largest recorded value, or `0` if there are none.
+    pub fn max(&self) -> u64 {
+        self.max
+    }
+
+    /// Returns the mean of the recorded values, or `0.0` if there are none.
+    pub fn mean(&self) -> f64 {
+        match self.count {
+            0 => 0.0,
+            count => self.sum as f64 / count as f64,
+        }
+    }
+
+    /// Returns the value ranges of the non-empty buckets with their counts, in
+    /// ascending order.
+    pub fn buckets(&self) -> impl Iterator<Item = (Range<u64>, u64)> + '_ {
+        self.buckets
+            .iter()
+            .enumerate()
+            .filter(|(_, &count)| count > 0)
+            .map(|(bucket, &count)| {
+                let range = match bucket {
+                    0 => 0..1,
+                    64 => (1 << 63)..u64::MAX,
+                    _ => (1 << (bucket - 1))..(1 << bucket),
+                };
+                (range, count)
+            })
+    }
+}
+
+/// Serializes the statistics of a histogram and its non-empty buckets as pairs
+/// of the bucket lower bound and count.
+#[cfg(all(feature = "stats", feature = "serde"))]
+impl serde::Serialize for Histogram {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+
+        let buckets: Vec<_> = self
+            .buckets()
+            .map(|(range, count)| (range.start, count))
+            .collect();
+
+        let mut state = serializer.serialize_struct("Histogram", 4)?;
+        state.serialize_field("count", &self.count)?;
+        state.serialize_field("sum", &self.sum)?;
+        state.serialize_field("max", &self.max)?;
+        state.serialize_field("buckets", &buckets)?;
+        state.end()
+    }
+}
+
+#[cfg(all(test, any(feature = "stats", feature = "json")))]
+mod tests {
+    #[cfg(feature = "stats")]
+    use std::io::{Read, Write};
+
+    use super::*;
+    #[cfg(feature = "stats")]
+    use crate::MockPipe;
+
+    #[cfg(feature = "stats")]
+    #[test]
+    fn test_histogram() {
+        let mut histogram = Histogram::default();
+
+        for value in [0, 1, 2, 3, 4, 1000] {
+            histogram.record(value);
+        }
+
+        assert_eq!(histogram.count(), 6);
+        assert_eq!(histogram.sum(), 1010);
+        assert_eq!(histogram.max(), 1000);
+
+        assert_eq!(
+            histogram.buckets().collect::<Vec<_>>(),
+            [(0..1, 1), (1..2, 1), (2..4, 2), (4..8, 1), (512..1024, 1)]
+        );
+    }
+
+    #[cfg(feature = "stats")]
+    #[test]
+    fn test_operation_sizes() {
+        let mut pipe = MockPipe::loopback(64);
+
+        for byte in b"hello" {
+            pipe.write_all(&[*byte]).unwrap();
+        }
+
+        let mut read_data = [0u8; 5];
+        pipe.read_exact(&mut read_data).unwrap();
+
+        let stats = pipe.stats();
+        assert_eq!(stats.write_sizes.buckets().collect::<Vec<_>>(), [(1..2, 5)]);
+        assert_eq!(stats.read_sizes.buckets().collect::<Vec<_>>(), [(4..8, 1)]);
+        assert_eq!(stats.read_waits.count(), 1);
+    }
+
+    #[cfg(feature = "stats")]
+    #[test]
+    fn test_thread_stats() {
+        use std::thread;
+
+        let (host, mut device) = MockPipe::pair(64);
+
+        let workers: Vec<_> = (1..=3)
+            .map(|worker| {
+                let mut host = host.clone();
+                thread::Builder::new()
+                    .name(format!("worker-{}", worker))
+                    .spawn(move || host.write_all(&vec![0u8; worker]).unwrap())
+                    .unwrap()
+            })
+            .collect();
+        for worker in workers {
+            worker.join().unwrap();
+        }
+
+        let mut read_data = [0u8; 6];
+        device.read_exact(&mut read_data).unwrap();
+
+        let mut written: Vec<_> = host
+            .thread_stats()
+            .into_iter()
+            .map(|thread| (thread.name.unwrap(), thread.stats.bytes_written))
+            .collect();
+        written.sort();
+        assert_eq!(
+            written,
+            [
+                (String::from("worker-1"), 1),
+                (String::from("worker-2"), 2),
+                (String::from("worker-3"), 3)
+            ]
+        );
+
+        let threads = device.thread_stats();
+        assert_eq!(threads.len(), 1);
+        assert_eq!(threads[0].id, thread::current().id());
+        assert_eq!(threads[0].stats, device.stats());
+
+        host.take_stats();
+        assert!(host.thread_stats().is_empty());
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn test_serialize() {
+        let stats = PipeStats {
+            reads: 1,
+            bytes_read: 5,
+            ..PipeStats::default()
+        };
+
+        let value = serde_json::to_value(stats).unwrap();
+        assert_eq!(value["reads"], 1);
+        assert_eq!(value["bytes_read"], 5);
+        assert_eq!(value["errors"], 0);
+    }
+}
@@ -0,0 +1,132 @@
+//! `proptest` strategies generating sequences of pipe operations.
+//!
+//! [`pipe_ops`] generates random but reproducible sequences of [`PipeOp`]s
+//! (writes, reads, clears, delays and injected faults), which a test applies to
+//! one endpoint of a pipe while the transport layer under test uses the other.
+//!
+//! ```
+//! use mockpipe::{strategy::pipe_ops, MockPipe};
+//! use proptest::{prelude::*, test_runner::TestRunner};
+//!
+//! let mut runner = TestRunner::default();
+//!
+//! runner
+//!     .run(&pipe_ops(16, 8), |ops| {
+//!         let (mut device, _transport) = MockPipe::pair(32);
+//!
+//!         for op in &ops {
+//!             let _ = op.apply(&mut device);
+//!         }
+//!
+//!         prop_assert!(device.write_buffer_len() <= 32);
+//!         Ok(())
+//!     })
+//!     .unwrap();
+//! ```
+
+use std::{
+    io::{self, Read, Write},
+    thread,
+    time::Duration,
+};
+
+use proptest::{collection::vec, prelude::*};
+
+use crate::MockPipe;
+
+/// Longest delay generated by the strategies, keeping test runs short.
+const MAX_DELAY_MS: u64 = 5;
+
+/// An operation performed on a pipe endpoint.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PipeOp {
+    /// Writes the data.
+    Write(Vec<u8>),
+
+    /// Reads up to the given number of bytes.
+    Read(usize),
+
+    /// Clears both buffers of the endpoint.
+    Clear,
+
+    /// Sleeps for the duration.
+    Delay(Duration),
+
+    /// Injects an error of the given kind into the outgoing data stream.
+    Fault(io::ErrorKind),
+}
+
+impl PipeOp {
+    /// Applies the operation to the endpoint, returning the number of bytes
+    /// transferred by reads and writes (and `0` for other operations).
+    pub fn apply(&self, pipe: &mut MockPipe) -> io::Result<usize> {
+        match self {
+            PipeOp::Write(data) => pipe.write(data),
+            PipeOp::Read(len) => pipe.read(&mut vec![0u8; *len]),
+            PipeOp::Clear => {
+                pipe.clear();
+                Ok(0)
+            }
+            PipeOp::Delay(duration) => {
+                thread::sleep(*duration);
+                Ok(0)
+            }
+            PipeOp::Fault(kind) => {
+                pipe.inject_error(*kind);
+                Ok(0)
+            }
+        }
+    }
+}
+
+/// Returns a strategy generating single operations, with reads and writes of
+/// up to `max_len` bytes.
+///
+/// Reads and writes are generated more often than the other operations.
+pub fn pipe_op(max_len: usize) -> impl Strategy<Value = PipeOp> {
+    prop_oneof![
+        4 => vec(any::<u8>(), 1..=max_len.max(1)).prop_map(PipeOp::Write),
+        4 => (1..=max_len.max(1)).prop_map(PipeOp::Read),
+        1 => Just(PipeOp::Clear),
+        1 => (0..=MAX_DELAY_MS).prop_map(|ms| PipeOp::Delay(Duration::from_millis(ms))),
+        1 => prop_oneof![
+            Just(io::ErrorKind::Interrupted),
+            Just(io::ErrorKind::TimedOut),
+            Just(io::ErrorKind::ConnectionReset),
+            Just(io::ErrorKind::BrokenPipe),
+        ]
+        .prop_map(PipeOp::Fault),
+    ]
+}
+
+/// Returns a strategy generating sequences of up to `max_ops` operations, with
+/// reads and writes of up to `max_len` bytes.
+pub fn pipe_ops(max_len: usize, max_ops: usize) -> impl Strategy<Value = Vec<PipeOp>> {
+    vec(pipe_op(max_len), 0..=max_ops)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    proptest! {
+        #[test]
+        fn test_loopback_ops(ops in pipe_ops(8, 32)) {
+            let mut pipe = MockPipe::loopback(16);
+            let mut pending = 0;
+
+            for op in &ops {
+                let result = op.apply(&mut pipe);
+
+                match (op, result) {
+                    (PipeOp::Write(_), Ok(n)) => pending += n,
+                    (PipeOp::Read(_), Ok(n)) => pending -= n,
+                    (PipeOp::Clear, _) => pending = 0,
+                    _ => {}
+                }
+
+                prop_assert_eq!(pipe.read_buffer_len(), pending);
+            }
+        }
+    }
+}
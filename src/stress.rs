@@ -0,0 +1,339 @@
+//! Integrity stress testing of a pipe.
+//!
+//! [`run`] pushes a configurable amount of generated data through a pipe, from
+//! a producer thread to a consumer, optionally over a faulty link, and reports
+//! the throughput and the data lost or damaged on the way. The data is sent
+//! in integrity-checked frames (see [`integrity`](crate::integrity)), and a
+//! rolling checksum of the whole stream is compared on both ends:
+//!
+//! ```
+//! use mockpipe::{
+//!     link::LinkConfig,
+//!     stress::{self, StressConfig},
+//!     MockPipe,
+//! };
+//!
+//! let config = StressConfig::new().with_bytes(1 << 20);
+//! let report = stress::run(MockPipe::pair(4096), config).unwrap();
+//! assert!(report.is_intact());
+//! assert_eq!(report.bytes_received, 1 << 20);
+//!
+//! let faulty = config.with_link(LinkConfig::new().with_corruption(0.001));
+//! let report = stress::run(MockPipe::pair(4096), faulty).unwrap();
+//! assert!(!report.is_intact());
+//! println!(
+//!     "{:.0} bytes/s, {:.1}% lost",
+//!     report.throughput(),
+//!     report.loss_ratio() * 100.0
+//! );
+//! ```
+
+use std::{
+    io::{self, Read, Write},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    integrity::{IntegrityFault, IntegrityReader, IntegrityWriter, MAX_FRAME_DATA},
+    link::LinkConfig,
+    rng::Rng,
+    Direction, MockPipe,
+};
+
+/// Settings of a stress test.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct StressConfig {
+    /// Number of bytes to send.
+    pub bytes: u64,
+
+    /// Size of the writes, at most [`MAX_FRAME_DATA`] bytes.
+    pub chunk_size: usize,
+
+    /// Link simulated from the sending endpoint to the receiving one, with the
+    /// faults to inject. An ideal link leaves the link of the pipe unchanged.
+    pub link: LinkConfig,
+
+    /// Maximum time the consumer waits for data before failing with a
+    /// `TimedOut` error, or `None` to wait until the producer is done.
+    pub timeout: Option<Duration>,
+
+    /// Seed of the generated data, or `None` to derive it from the master
+    /// seed.
+    pub seed: Option<u64>,
+}
+
+impl StressConfig {
+    /// Creates the settings sending 1 MiB in 1 KiB writes over the link of the
+    /// pipe, with a 10 seconds timeout.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the number of bytes to send and returns the modified settings.
+    pub fn with_bytes(mut self, bytes: u64) -> Self {
+        self.bytes = bytes;
+        self
+    }
+
+    /// Sets the size of the writes and returns the modified settings.
+    pub fn with_chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = chunk_size;
+        self
+    }
+
+    /// Sets the simulated link and returns the modified settings.
+    pub fn with_link(mut self, link: LinkConfig) -> Self {
+        self.link = link;
+        self
+    }
+
+    /// Sets the timeout of the consumer and returns the modified settings.
+    pub fn with_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Sets the seed of the generated data and returns the modified settings.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+}
+
+impl Default for StressConfig {
+    fn default() -> Self {
+        Self {
+            bytes: 1 << 20,
+            chunk_size: 1024,
+            link: LinkConfig::default(),
+            timeout: Some(Duration::from_secs(10)),
+            seed: None,
+        }
+    }
+}
+
+/// Outcome of a stress test.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StressReport {
+    /// Number of bytes sent.
+    pub bytes_sent: u64,
+
+    /// Number of bytes received in valid frames.
+    pub bytes_received: u64,
+
+    /// Number of frames sent.
+    pub frames_sent: u64,
+
+    /// Number of valid frames received.
+    pub frames_received: u64,
+
+    /// Faults detected by the consumer, in the order they were found.
+    pub faults: Vec<IntegrityFault>,
+
+    /// Rolling checksum of the data sent.
+    pub checksum_sent: u64,
+
+    /// Rolling checksum of the data received in valid frames.
+    pub checksum_received: u64,
+
+    /// Time from the start of the producer until the consumer got the end of
+    /// the stream.
+    pub elapsed: Duration,
+}
+
+impl StressReport {
+    /// Returns `true` if all the data arrived, intact and in order.
+    pub fn is_intact(&self) -> bool {
+        self.faults.is_empty()
+            && (self.bytes_received == self.bytes_sent)
+            && (self.checksum_received == self.checksum_sent)
+    }
+
+    /// Returns the rate at which the data was received, in bytes per second.
+    pub fn throughput(&self) -> f64 {
+        self.bytes_received as f64 / self.elapsed.as_secs_f64().max(f64::EPSILON)
+    }
+
+    /// Returns the fraction (`0.0..=1.0`) of the data sent that did not arrive
+    /// in valid frames.
+    pub fn loss_ratio(&self) -> f64 {
+        if self.bytes_sent == 0 {
+            return 0.0;
+        }
+
+        self.bytes_sent.saturating_sub(self.bytes_received) as f64 / self.bytes_sent as f64
+    }
+}
+
+/// Pushes the data set by `config` from the first endpoint of `pair` to the
+/// second one, writing from a producer thread and reading on the calling
+/// thread, and reports what arrived.
+///
+/// The pipe is closed once the producer is done or failed, so the consumer gets
+/// the end of the stream after the last data delivered. Fails with the first error of
+/// either side, other than the faults of the link.
+pub fn run(pair: (MockPipe, MockPipe), config: StressConfig) -> io::Result<StressReport> {
+    let (sender, receiver) = pair;
+
+    if !config.link.is_ideal() {
+        sender.set_link(Direction::Outbound, config.link);
+    }
+    receiver.set_timeout(config.timeout);
+
+    let seed = config
+        .seed
+        .unwrap_or_else(|| Rng::derive_seed("stress::run"));
+    let chunk_size = config.chunk_size.clamp(1, MAX_FRAME_DATA);
+    let start = Instant::now();
+
+    let producer = thread::spawn(move || {
+        let result = produce(&sender, seed, chunk_size, config.bytes);
+
+        // The consumer gets the end of the stream even if the producer failed
+        sender.close();
+        result
+    });
+
+    let mut reader = IntegrityReader::new(receiver.clone());
+    let mut checksum = RollingChecksum::new();
+    let mut bytes_received = 0u64;
+    let mut chunk = [0u8; 8192];
+
+    let consumed = loop {
+        match reader.read(&mut chunk) {
+            Ok(0) => break Ok(()),
+            Ok(n) => {
+                checksum.update(&chunk[0..n]);
+                bytes_received += n as u64;
+            }
+            Err(err) => break Err(err),
+        }
+    };
+    let elapsed = start.elapsed();
+
+    // Unblock the producer if the consumer gave up
+    if consumed.is_err() {
+        receiver.close();
+    }
+
+    let produced = producer
+        .join()
+        .unwrap_or_else(|_| Err(io::Error::new(io::ErrorKind::Other, "producer panicked")));
+    consumed?;
+    let (frames_sent, checksum_sent) = produced?;
+
+    Ok(StressReport {
+        bytes_sent: config.bytes,
+        bytes_received,
+        frames_sent,
+        frames_received: reader.frames(),
+        faults: reader.faults().to_vec(),
+        checksum_sent,
+        checksum_received: checksum.value(),
+        elapsed,
+    })
+}
+
+/// Writes `bytes` bytes of data generated from `seed` into `sender`, in chunks
+/// of `chunk_size` bytes. Returns the number of frames sent and the checksum
+/// of the data.
+fn produce(sender: &MockPipe, seed: u64, chunk_size: usize, bytes: u64) -> io::Result<(u64, u64)> {
+    let mut rng = Rng::new(seed);
+    let mut checksum = RollingChecksum::new();
+    let mut writer = IntegrityWriter::new(sender.clone());
+    let mut chunk = vec![0u8; chunk_size];
+
+    let mut remaining = bytes;
+    while remaining > 0 {
+        let len = (remaining.min(chunk_size as u64)) as usize;
+        for byte in &mut chunk[0..len] {
+            *byte = rng.next_u64() as u8;
+        }
+
+        writer.write_all(&chunk[0..len])?;
+        checksum.update(&chunk[0..len]);
+        remaining -= len as u64;
+    }
+
+    Ok((u64::from(writer.sequence()), checksum.value()))
+}
+
+/// FNV-1a checksum of a stream, updated chunk by chunk.
+struct RollingChecksum(u64);
+
+impl RollingChecksum {
+    fn new() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        for &byte in data {
+            self.0 = (self.0 ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    fn value(&self) -> u64 {
+        self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Error, MemoryBudget};
+
+    #[test]
+    fn test_stress_intact() {
+        let config = StressConfig::new()
+            .with_bytes(100_000)
+            .with_chunk_size(1000)
+            .with_seed(7);
+        let report = run(MockPipe::pair(2048), config).unwrap();
+
+        assert!(report.is_intact());
+        assert_eq!(report.frames_sent, 100);
+        assert_eq!(report.frames_received, 100);
+        assert_eq!(report.loss_ratio(), 0.0);
+        assert!(report.throughput() > 0.0);
+
+        // The same seed generates the same data
+        let again = run(MockPipe::pair(64), config.with_chunk_size(333)).unwrap();
+        assert_eq!(again.checksum_received, report.checksum_sent);
+    }
+
+    #[test]
+    fn test_stress_faults() {
+        let link = LinkConfig::new().with_loss(0.2).with_seed(3);
+        let config = StressConfig::new()
+            .with_bytes(50_000)
+            .with_chunk_size(500)
+            .with_link(link);
+        let report = run(MockPipe::pair(4096), config).unwrap();
+
+        assert!(!report.is_intact());
+        assert_eq!(report.frames_sent, 100);
+        assert!(report.frames_received < 100);
+        assert_eq!(report.bytes_received, report.frames_received * 500);
+        assert!(report.loss_ratio() > 0.0);
+        assert!(report
+            .faults
+            .iter()
+            .any(|fault| matches!(fault, IntegrityFault::Lost { .. })));
+        assert!(!report
+            .faults
+            .iter()
+            .any(|fault| matches!(fault, IntegrityFault::OutOfOrder { .. })));
+    }
+
+    #[test]
+    fn test_stress_producer_error() {
+        let (sender, receiver) = MockPipe::pair(4096);
+        sender.set_memory_budget(Some(MemoryBudget::new(100).with_fail_when_exhausted(true)));
+
+        // The consumer does not wait forever for a failed producer
+        let config = StressConfig::new().with_bytes(10_000).with_timeout(None);
+        let err = run((sender, receiver), config).unwrap_err();
+        assert_eq!(Error::from_io(&err), Some(Error::Full));
+    }
+}
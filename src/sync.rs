@@ -0,0 +1,180 @@
+//! Synchronization primitives of the pipe buffers.
+//!
+//! With the `shuttle` feature they are replaced by their `shuttle`
+//! counterparts, so readers and writers blocked on a pipe take part in
+//! shuttle's controlled scheduling. Shuttle does not model time, so timed waits
+//! never time out there: use blocking or non-blocking pipes in shuttle tests.
+
+use std::{sync::LockResult, time::Duration};
+
+#[cfg(feature = "shuttle")]
+pub(crate) use shuttle::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Mutex, MutexGuard,
+};
+#[cfg(feature = "shuttle")]
+use shuttle::sync::{Condvar as RawCondvar, WaitTimeoutResult};
+#[cfg(not(feature = "shuttle"))]
+pub(crate) use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Mutex, MutexGuard,
+};
+#[cfg(not(feature = "shuttle"))]
+use std::sync::{Condvar as RawCondvar, WaitTimeoutResult};
+
+/// A condition variable counting the threads waiting on it, so notifying it
+/// costs nothing when nobody waits, as in single-threaded tests.
+///
+/// The waiters are counted while they hold the mutex, so a thread notifying
+/// after changing the state protected by the mutex sees every thread that
+/// could wait for that change.
+pub(crate) struct Condvar {
+    raw: RawCondvar,
+    waiters: AtomicUsize,
+}
+
+impl Condvar {
+    pub(crate) fn new() -> Self {
+        Self {
+            raw: RawCondvar::new(),
+            waiters: AtomicUsize::new(0),
+        }
+    }
+
+    pub(crate) fn wait_while<'a, T, F>(
+        &self,
+        guard: MutexGuard<'a, T>,
+        condition: F,
+    ) -> LockResult<MutexGuard<'a, T>>
+    where
+        F: FnMut(&mut T) -> bool,
+    {
+        self.waiters.fetch_add(1, Ordering::SeqCst);
+        let result = self.raw.wait_while(guard, condition);
+        self.waiters.fetch_sub(1, Ordering::SeqCst);
+        result
+    }
+
+    pub(crate) fn wait_timeout<'a, T>(
+        &self,
+        guard: MutexGuard<'a, T>,
+        timeout: Duration,
+    ) -> LockResult<(MutexGuard<'a, T>, WaitTimeoutResult)> {
+        self.waiters.fetch_add(1, Ordering::SeqCst);
+        let result = self.raw.wait_timeout(guard, timeout);
+        self.waiters.fetch_sub(1, Ordering::SeqCst);
+        result
+    }
+
+    pub(crate) fn wait_timeout_while<'a, T, F>(
+        &self,
+        guard: MutexGuard<'a, T>,
+        timeout: Duration,
+        condition: F,
+    ) -> LockResult<(MutexGuard<'a, T>, WaitTimeoutResult)>
+    where
+        F: FnMut(&mut T) -> bool,
+    {
+        self.waiters.fetch_add(1, Ordering::SeqCst);
+        let result = self.raw.wait_timeout_while(guard, timeout, condition);
+        self.waiters.fetch_sub(1, Ordering::SeqCst);
+        result
+    }
+
+    pub(crate) fn notify_one(&self) {
+        if self.waiters.load(Ordering::SeqCst) > 0 {
+            #[cfg(test)]
+            tests::count_notification();
+            self.raw.notify_one();
+        }
+    }
+
+    pub(crate) fn notify_all(&self) {
+        if self.waiters.load(Ordering::SeqCst) > 0 {
+            #[cfg(test)]
+            tests::count_notification();
+            self.raw.notify_all();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        cell::Cell,
+        io::{Read, Write},
+    };
+
+    use crate::MockPipe;
+
+    thread_local! {
+        /// Number of notifications actually sent by the current thread.
+        static NOTIFICATIONS: Cell<usize> = const { Cell::new(0) };
+    }
+
+    pub(super) fn count_notification() {
+        NOTIFICATIONS.with(|count| count.set(count.get() + 1));
+    }
+
+    #[cfg(not(feature = "shuttle"))]
+    #[test]
+    fn test_skipped_notifications() {
+        use std::{sync::Arc, thread};
+
+        use super::*;
+
+        let notifications = || NOTIFICATIONS.with(Cell::get);
+
+        // Nobody waits in a single-threaded test, so nothing is notified
+        let (mut pipe1, mut pipe2) = MockPipe::pair(16);
+        let mut read_data = [0u8; 4];
+        for _ in 0..100 {
+            pipe1.write_all(b"ping").unwrap();
+            pipe2.read_exact(&mut read_data).unwrap();
+        }
+        assert_eq!(notifications(), 0);
+
+        // A waiting thread is notified
+        let state = Arc::new((Mutex::new(false), Condvar::new()));
+        let waiter = {
+            let state = state.clone();
+            thread::spawn(move || {
+                let (ready, condvar) = &*state;
+                drop(condvar.wait_while(ready.lock().unwrap(), |ready| !*ready));
+            })
+        };
+
+        let (ready, condvar) = &*state;
+        while condvar.waiters.load(Ordering::SeqCst) == 0 {
+            thread::yield_now();
+        }
+        *ready.lock().unwrap() = true;
+        condvar.notify_all();
+        assert_eq!(notifications(), 1);
+
+        waiter.join().unwrap();
+    }
+
+    #[cfg(feature = "shuttle")]
+    #[test]
+    fn test_shuttle_pair() {
+        shuttle::check_random(
+            || {
+                let (pipe1, pipe2) = MockPipe::pair(2);
+                let mut pipe1 = pipe1.with_timeout(None);
+                let mut pipe2 = pipe2.with_timeout(None);
+
+                let writer = shuttle::thread::spawn(move || {
+                    pipe1.write_all(b"hello").unwrap();
+                });
+
+                let mut read_data = [0u8; 5];
+                pipe2.read_exact(&mut read_data).unwrap();
+                assert_eq!(&read_data, b"hello");
+
+                writer.join().unwrap();
+            },
+            100,
+        );
+    }
+}
@@ -0,0 +1,342 @@
+//! Line-oriented wrapper for testing console interactions.
+//!
+//! A [`MockTextPipe`] drives an endpoint the way a user drives a console (a
+//! u-boot prompt, a REPL, a telnet-style device): it sends lines of text and
+//! waits for lines or prompts in the output, each within a timeout:
+//!
+//! ```
+//! use std::{io::Write, time::Duration};
+//!
+//! use mockpipe::{text::MockTextPipe, MockPipe};
+//!
+//! let (host, mut device) = MockPipe::pair(256);
+//! let mut console = MockTextPipe::new(host);
+//! let timeout = Some(Duration::from_millis(100));
+//!
+//! device.write_all(b"U-Boot 2024.01\r\n=> ").unwrap();
+//! assert_eq!(console.expect_line(timeout).unwrap(), "U-Boot 2024.01");
+//! assert_eq!(console.expect_prompt("=> ", timeout).unwrap(), "");
+//! console.send_line("printenv").unwrap();
+//! ```
+
+use std::{
+    collections::VecDeque,
+    io::{self, Write},
+    time::{Duration, Instant},
+};
+
+use crate::{Error, MockPipe};
+
+/// Size of the chunks read from the endpoint.
+const CHUNK_SIZE: usize = 256;
+
+/// Wrapper of an endpoint exchanging lines of text.
+///
+/// Received lines may end with LF or CRLF, the line ending being stripped.
+/// Invalid UTF-8 is replaced with `U+FFFD`.
+///
+/// Consoles usually echo the lines they are sent. With echo enabled, the
+/// wrapper expects the echo of every line it sends and skips it, so only the
+/// output of the console is returned.
+pub struct MockTextPipe {
+    pipe: MockPipe,
+
+    /// Line ending appended to the sent lines.
+    line_ending: String,
+
+    /// Whether the other end echoes the sent lines.
+    echo: bool,
+
+    /// Data received but not consumed yet.
+    received: Vec<u8>,
+
+    /// Sent lines whose echo was not received yet.
+    echoes: VecDeque<String>,
+}
+
+impl MockTextPipe {
+    /// Wraps `pipe`, sending lines ending with LF, without echo.
+    pub fn new(pipe: MockPipe) -> Self {
+        Self {
+            pipe,
+            line_ending: String::from("\n"),
+            echo: false,
+            received: Vec::new(),
+            echoes: VecDeque::new(),
+        }
+    }
+
+    /// Returns the wrapped endpoint.
+    pub fn pipe(&self) -> &MockPipe {
+        &self.pipe
+    }
+
+    /// Returns the wrapped endpoint, dropping the data received but not
+    /// consumed yet.
+    pub fn into_inner(self) -> MockPipe {
+        self.pipe
+    }
+
+    /// Sets the line ending appended to the sent lines, like `"\r"` for the
+    /// Enter key of a terminal.
+    pub fn set_line_ending(&mut self, line_ending: impl Into<String>) {
+        self.line_ending = line_ending.into();
+    }
+
+    /// Sets the line ending appended to the sent lines and returns the
+    /// modified wrapper.
+    pub fn with_line_ending(mut self, line_ending: impl Into<String>) -> Self {
+        self.set_line_ending(line_ending);
+        self
+    }
+
+    /// Returns the line ending appended to the sent lines.
+    pub fn line_ending(&self) -> &str {
+        &self.line_ending
+    }
+
+    /// Sets whether the other end echoes the sent lines, which are then
+    /// skipped in the received output.
+    pub fn set_echo(&mut self, echo: bool) {
+        self.echo = echo;
+
+        if !echo {
+            self.echoes.clear();
+        }
+    }
+
+    /// Sets whether the other end echoes the sent lines and returns the
+    /// modified wrapper.
+    pub fn with_echo(mut self, echo: bool) -> Self {
+        self.set_echo(echo);
+        self
+    }
+
+    /// Returns `true` if the other end is expected to echo the sent lines.
+    pub fn echo(&self) -> bool {
+        self.echo
+    }
+
+    /// Sends `text` as is, according to the timeout of the endpoint.
+    pub fn send(&mut self, text: &str) -> io::Result<()> {
+        self.pipe.write_all(text.as_bytes())
+    }
+
+    /// Sends `line` followed by the line ending, according to the timeout of
+    /// the endpoint.
+    pub fn send_line(&mut self, line: &str) -> io::Result<()> {
+        let mut data = String::with_capacity(line.len() + self.line_ending.len());
+        data.push_str(line);
+        data.push_str(&self.line_ending);
+        self.send(&data)?;
+
+        if self.echo {
+            self.echoes.push_back(line.to_owned());
+        }
+
+        Ok(())
+    }
+
+    /// Waits for the next line received, skipping echoed lines, and returns
+    /// it without its line ending.
+    ///
+    /// The timeout applies to the whole operation, instead of the timeout of
+    /// the endpoint, `None` meaning no timeout. Fails with a `TimedOut` error
+    /// if no full line is received in time, or an `UnexpectedEof` error if the
+    /// end of stream is reached first. The data received is kept in both
+    /// cases.
+    pub fn expect_line(&mut self, timeout: Option<Duration>) -> io::Result<String> {
+        let deadline = self.deadline(timeout);
+
+        loop {
+            let mut line = self.receive_until(deadline, |data| {
+                data.iter()
+                    .position(|&byte| byte == b'\n')
+                    .map(|position| (position, position + 1))
+            })?;
+
+            if line.last() == Some(&b'\r') {
+                line.pop();
+            }
+
+            let line = String::from_utf8_lossy(&line).into_owned();
+            if self.echoes.front() == Some(&line) {
+                self.echoes.pop_front();
+                continue;
+            }
+
+            return Ok(line);
+        }
+    }
+
+    /// Waits for `prompt` to be received and returns the output received
+    /// before it, as is apart from the echoed lines at its start. The prompt
+    /// does not need to end a line, like the `"=> "` of u-boot.
+    ///
+    /// The timeout and the errors are as for
+    /// [`expect_line`](MockTextPipe::expect_line).
+    pub fn expect_prompt(&mut self, prompt: &str, timeout: Option<Duration>) -> io::Result<String> {
+        let deadline = self.deadline(timeout);
+        let prompt = prompt.as_bytes();
+
+        let output = self.receive_until(deadline, |data| {
+            if prompt.is_empty() {
+                return Some((0, 0));
+            }
+
+            data.windows(prompt.len())
+                .position(|window| window == prompt)
+                .map(|position| (position, position + prompt.len()))
+        })?;
+
+        let mut output = String::from_utf8_lossy(&output).into_owned();
+        while let Some(echo) = self.echoes.front() {
+            let rest = output.strip_prefix(echo.as_str()).and_then(|rest| {
+                rest.strip_prefix("\r\n")
+                    .or_else(|| rest.strip_prefix('\n'))
+            });
+
+            match rest {
+                Some(rest) => output = rest.to_owned(),
+                None => break,
+            }
+            self.echoes.pop_front();
+        }
+
+        Ok(output)
+    }
+
+    /// Returns the data received but not consumed by the expectations yet.
+    pub fn pending(&self) -> &[u8] {
+        &self.received
+    }
+
+    /// Converts a timeout measured on the clock of the endpoint to a deadline
+    /// in real time.
+    fn deadline(&self, timeout: Option<Duration>) -> Option<Instant> {
+        self.pipe
+            .real_timeout(timeout)
+            .map(|timeout| Instant::now() + timeout)
+    }
+
+    /// Receives data until `find` locates a match in the data not consumed
+    /// yet, as a range of positions, then consumes the data up to the end of
+    /// the match and returns the data before it.
+    fn receive_until<F>(&mut self, deadline: Option<Instant>, mut find: F) -> io::Result<Vec<u8>>
+    where
+        F: FnMut(&[u8]) -> Option<(usize, usize)>,
+    {
+        let mut chunk = [0u8; CHUNK_SIZE];
+
+        loop {
+            if let Some((start, end)) = find(&self.received) {
+                let data = self.received[0..start].to_vec();
+                self.received.drain(0..end);
+                return Ok(data);
+            }
+
+            let timeout =
+                deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+
+            match self.pipe.read_with_real_timeout(&mut chunk, timeout) {
+                Ok(0) if self.pipe.read_buffer.is_closed() => {
+                    return Err(io::Error::from(io::ErrorKind::UnexpectedEof))
+                }
+                // Nothing to read right away
+                Ok(0) if timeout == Some(Duration::ZERO) => {
+                    return Err(timed_out(self.received.len()))
+                }
+                // Woken up by a clear of the buffer
+                Ok(0) => {}
+                Ok(n) => self.received.extend_from_slice(&chunk[0..n]),
+                Err(err) if err.kind() == io::ErrorKind::TimedOut => {
+                    return Err(timed_out(self.received.len()))
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+}
+
+/// Returns the error reporting an expectation not met in time, with the
+/// number of bytes received but not consumed.
+fn timed_out(partial: usize) -> io::Error {
+    io::Error::from(Error::TimedOut { partial })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Read, thread};
+
+    use super::*;
+
+    /// Emulates a u-boot console echoing the commands it receives.
+    fn start_console(mut device: MockPipe) -> thread::JoinHandle<()> {
+        thread::spawn(move || {
+            device.set_timeout(None);
+            device.write_all(b"U-Boot 2024.01\r\n\r\n=> ").unwrap();
+
+            let mut command = Vec::new();
+            let mut byte = [0u8; 1];
+            while device.read(&mut byte).unwrap() == 1 {
+                if byte[0] != b'\r' {
+                    command.push(byte[0]);
+                    continue;
+                }
+
+                device.write_all(&command).unwrap();
+                device.write_all(b"\r\n").unwrap();
+                match &command[..] {
+                    b"version" => device.write_all(b"U-Boot 2024.01\r\nmips-gcc 13.2\r\n"),
+                    b"reset" => device.write_all(b"resetting ...\r\n"),
+                    _ => device.write_all(b"Unknown command\r\n"),
+                }
+                .unwrap();
+                device.write_all(b"=> ").unwrap();
+                command.clear();
+            }
+        })
+    }
+
+    #[test]
+    fn test_console_session() {
+        let (host, device) = MockPipe::pair(256);
+        let console = start_console(device);
+        let timeout = Some(Duration::from_secs(5));
+
+        let mut text = MockTextPipe::new(host)
+            .with_line_ending("\r")
+            .with_echo(true);
+
+        assert_eq!(text.expect_line(timeout).unwrap(), "U-Boot 2024.01");
+        assert_eq!(text.expect_prompt("=> ", timeout).unwrap(), "\r\n");
+
+        text.send_line("version").unwrap();
+        assert_eq!(
+            text.expect_prompt("=> ", timeout).unwrap(),
+            "U-Boot 2024.01\r\nmips-gcc 13.2\r\n"
+        );
+
+        text.send_line("reset").unwrap();
+        assert_eq!(text.expect_line(timeout).unwrap(), "resetting ...");
+        assert_eq!(text.expect_prompt("=> ", timeout).unwrap(), "");
+
+        // Without echo, the echoed line is returned
+        text.set_echo(false);
+        text.send_line("help").unwrap();
+        assert_eq!(text.expect_line(timeout).unwrap(), "help");
+        assert_eq!(text.expect_line(timeout).unwrap(), "Unknown command");
+
+        // The data received before a timeout is kept
+        let err = text
+            .expect_line(Some(Duration::from_millis(10)))
+            .unwrap_err();
+        assert_eq!(Error::from_io(&err), Some(Error::TimedOut { partial: 3 }));
+        assert_eq!(text.pending(), b"=> ");
+
+        text.pipe().close();
+        console.join().unwrap();
+        let err = text.expect_line(timeout).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
@@ -0,0 +1,296 @@
+//! Link simulation applied to real sockets, files and other I/O handles.
+//!
+//! [`Throttled`] wraps any `Read + Write` handle and paces its reads and
+//! writes according to a [`LinkConfig`] per direction, with the latency,
+//! jitter and token-bucket bandwidth of the pipe links (see
+//! [`link`](crate::link)), so code can be exercised against a slow real
+//! connection too:
+//!
+//! ```
+//! use std::{
+//!     io::{Cursor, Read},
+//!     time::{Duration, Instant},
+//! };
+//!
+//! use mockpipe::{link::LinkConfig, throttle::Throttled, Direction};
+//!
+//! let mut file = Throttled::new(Cursor::new(vec![0u8; 1000])).with_link(
+//!     Direction::Inbound,
+//!     LinkConfig::new().with_bandwidth(Some(10_000)),
+//! );
+//!
+//! let start = Instant::now();
+//! let mut data = Vec::new();
+//! file.read_to_end(&mut data).unwrap();
+//! assert!(start.elapsed() >= Duration::from_millis(90));
+//! ```
+//!
+//! There is no background thread: an operation blocks until its data has
+//! passed the simulated link, so a write hands its data to the handle and
+//! returns once it could have reached the peer, and a read returns the data it read from the handle once
+//! it could have arrived. Unlike on a pipe, the latency thus adds up over
+//! consecutive operations. The faults and the congestion curve of the
+//! configuration do not apply, as there is no buffer to tell how much data the
+//! peer holds.
+
+use std::{
+    io::{self, Read, Write},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    link::{LinkConfig, TokenBucket},
+    rng::Rng,
+    Direction, Transport,
+};
+
+/// Simulated link of one direction of a [`Throttled`] handle.
+#[derive(Clone, Debug)]
+struct Shaper {
+    link: LinkConfig,
+
+    /// Generator driving the jitter.
+    rng: Rng,
+
+    /// Token bucket of a limited bandwidth.
+    bucket: TokenBucket,
+}
+
+impl Shaper {
+    fn new(link: LinkConfig) -> Self {
+        Self {
+            link,
+            rng: link.rng(),
+            bucket: TokenBucket::default(),
+        }
+    }
+
+    /// Returns the length of the next transfer of up to `len` bytes: 10 ms
+    /// worth of transmission at a limited bandwidth, or the burst size if
+    /// larger, so the data flows progressively.
+    fn transfer_len(&self, len: usize) -> usize {
+        match self.link.bandwidth() {
+            Some(bandwidth) => {
+                let slice_len = (bandwidth / 100).max(self.link.burst).max(1);
+                len.min(usize::try_from(slice_len).unwrap_or(usize::MAX))
+            }
+            None => len,
+        }
+    }
+
+    /// Waits for `len` bytes to pass the link.
+    fn pass(&mut self, len: usize) {
+        if !self.link.delays() {
+            return;
+        }
+
+        let now = Instant::now();
+        let mut wait = self.link.delay(&mut self.rng);
+
+        if let Some(bandwidth) = self.link.bandwidth() {
+            let tokens = self.bucket.refill(bandwidth, self.link.burst, now);
+            wait += Duration::from_secs_f64((len as f64 - tokens).max(0.0) / bandwidth as f64);
+            self.bucket.spend(len);
+        }
+
+        thread::sleep(wait);
+    }
+}
+
+/// A `Read + Write` handle whose reads and writes are paced by simulated
+/// links.
+///
+/// The `Inbound` link applies to the data read from the handle and the
+/// `Outbound` one to the data written to it. Both are ideal by default.
+#[derive(Debug)]
+pub struct Throttled<T> {
+    inner: T,
+    inbound: Shaper,
+    outbound: Shaper,
+}
+
+impl<T: Read + Write> Throttled<T> {
+    /// Wraps `inner` with ideal links in both directions.
+    pub fn new(inner: T) -> Self {
+        Self {
+            inner,
+            inbound: Shaper::new(LinkConfig::new()),
+            outbound: Shaper::new(LinkConfig::new()),
+        }
+    }
+
+    fn shaper(&mut self, direction: Direction) -> &mut Shaper {
+        match direction {
+            Direction::Inbound => &mut self.inbound,
+            Direction::Outbound => &mut self.outbound,
+        }
+    }
+
+    /// Sets the simulated link characteristics of the given direction: of the
+    /// data read from the handle if `Inbound`, or written to it if
+    /// `Outbound`. The jitter generator is reseeded and the token bucket
+    /// refilled.
+    pub fn set_link(&mut self, direction: Direction, config: LinkConfig) {
+        *self.shaper(direction) = Shaper::new(config);
+    }
+
+    /// Sets the simulated link characteristics of the given direction and
+    /// returns the modified handle.
+    pub fn with_link(mut self, direction: Direction, config: LinkConfig) -> Self {
+        self.set_link(direction, config);
+        self
+    }
+
+    /// Returns the simulated link characteristics of the given direction.
+    pub fn link(&self, direction: Direction) -> LinkConfig {
+        match direction {
+            Direction::Inbound => self.inbound.link,
+            Direction::Outbound => self.outbound.link,
+        }
+    }
+
+    /// Returns a reference to the wrapped handle.
+    pub fn get_ref(&self) -> &T {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the wrapped handle. Data transferred
+    /// through it bypasses the simulated links.
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+
+    /// Unwraps the handle.
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+impl<T: Read + Write> Read for Throttled<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.inbound.transfer_len(buf.len());
+        let n = self.inner.read(&mut buf[0..len])?;
+        if n > 0 {
+            self.inbound.pass(n);
+        }
+        Ok(n)
+    }
+}
+
+impl<T: Read + Write> Write for Throttled<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // Only the data the handle took uses the bandwidth
+        let len = self.outbound.transfer_len(buf.len());
+        let n = self.inner.write(&buf[0..len])?;
+        if n > 0 {
+            self.outbound.pass(n);
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<T: Transport> Transport for Throttled<T> {
+    fn set_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        self.inner.set_timeout(timeout)
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        self.inner.close()
+    }
+
+    fn is_end_of_stream(&self) -> bool {
+        self.inner.is_end_of_stream()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::MockPipe;
+
+    #[test]
+    fn test_throttled_bandwidth() {
+        let link = LinkConfig::new()
+            .with_bandwidth(Some(10_000))
+            .with_burst(500);
+        let mut file = Throttled::new(Cursor::new(
+            (0..1500u32).map(|i| i as u8).collect::<Vec<_>>(),
+        ))
+        .with_link(Direction::Inbound, link);
+
+        // The burst is read right away, the rest at the bandwidth rate
+        let start = Instant::now();
+        let mut data = vec![0u8; 500];
+        file.read_exact(&mut data).unwrap();
+        assert!(start.elapsed() < Duration::from_millis(40));
+
+        data.clear();
+        file.read_to_end(&mut data).unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(90));
+        assert_eq!(data.len(), 1000);
+        assert_eq!(data[0], 244);
+
+        // Writes are not throttled
+        let start = Instant::now();
+        file.write_all(&[0u8; 5000]).unwrap();
+        assert!(start.elapsed() < Duration::from_millis(40));
+    }
+
+    #[test]
+    fn test_throttled_latency() {
+        let (host, mut device) = MockPipe::pair(64);
+        let link = LinkConfig::new().with_latency(Duration::from_millis(20));
+        let mut host = Throttled::new(host).with_link(Direction::Outbound, link);
+        assert_eq!(host.link(Direction::Outbound), link);
+
+        let start = Instant::now();
+        host.write_all(b"AT\r\n").unwrap();
+        host.write_all(b"ATI\r\n").unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(40));
+
+        let mut data = [0u8; 9];
+        device.read_exact(&mut data).unwrap();
+        assert_eq!(&data, b"AT\r\nATI\r\n");
+
+        host.close().unwrap();
+        assert_eq!(device.read(&mut data).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_throttled_partial_write() {
+        let (host, mut device) = MockPipe::pair(4);
+        let link = LinkConfig::new()
+            .with_bandwidth(Some(1000))
+            .with_burst(1000);
+        let mut host = Throttled::new(host).with_link(Direction::Outbound, link);
+
+        // Only the bytes the pipe takes are charged, leaving the rest of the
+        // burst for the next writes
+        let start = Instant::now();
+        let mut data = [0u8; 4];
+        for _ in 0..10 {
+            assert_eq!(host.write(&[0x55u8; 1000]).unwrap(), 4);
+            device.read_exact(&mut data).unwrap();
+        }
+        assert!(start.elapsed() < Duration::from_millis(500));
+
+        // Once the burst is spent, a write only waits for the bytes taken
+        let (host, mut device) = MockPipe::pair(4);
+        let link = LinkConfig::new().with_bandwidth(Some(100)).with_burst(100);
+        let mut host = Throttled::new(host).with_link(Direction::Outbound, link);
+
+        let start = Instant::now();
+        for _ in 0..26 {
+            assert_eq!(host.write(&[0x55u8; 100]).unwrap(), 4);
+            device.read_exact(&mut data).unwrap();
+        }
+        assert!(start.elapsed() < Duration::from_millis(500));
+    }
+}
@@ -0,0 +1,369 @@
+//! Async serial port adapter following the `tokio_serial::SerialStream` usage
+//! pattern.
+//!
+//! [`SerialStream`] implements `tokio::io::{AsyncRead, AsyncWrite}` over a pipe
+//! endpoint, with the usual serial port setters. The settings feed the
+//! simulation:
+//!
+//! - The baud rate and character format pace the writes: written data becomes
+//!   readable on the other end once it would have been transmitted, as with
+//!   [`SerialPipe`](crate::serial::SerialPipe).
+//! - With [`FlowControl::Hardware`] or [`FlowControl::Software`], a writer
+//!   waits for the other end to make room in the pipe. Without flow control it
+//!   never waits, and the data not fitting into the pipe is lost, like on a
+//!   real UART with a receiver overrun.
+//!
+//! ```
+//! use mockpipe::{
+//!     serial::SerialConfig,
+//!     tokio_serial::{FlowControl, SerialStream},
+//!     MockPipe,
+//! };
+//! use tokio::io::{AsyncReadExt, AsyncWriteExt};
+//!
+//! # tokio::runtime::Builder::new_current_thread().enable_time().build().unwrap().block_on(async {
+//! let (device, host) = MockPipe::pair(64);
+//! let mut device = SerialStream::new(device, SerialConfig::new(115_200));
+//! let mut host = SerialStream::new(host, SerialConfig::new(115_200));
+//! host.set_flow_control(FlowControl::Hardware).unwrap();
+//!
+//! host.write_all(b"AT\r\n").await.unwrap();
+//!
+//! let mut buf = [0u8; 4];
+//! device.read_exact(&mut buf).await.unwrap();
+//! assert_eq!(&buf, b"AT\r\n");
+//! # });
+//! ```
+
+use std::{
+    future::Future,
+    io,
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    time::{self, Instant, Sleep},
+};
+
+use crate::{
+    serial::{Parity, SerialConfig},
+    stats::OpTimer,
+    MockPipe,
+};
+
+/// Flow control of a serial line.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum FlowControl {
+    /// No flow control: data not fitting into the receiver is lost.
+    #[default]
+    None,
+
+    /// XON/XOFF flow control: the sender waits for the receiver.
+    Software,
+
+    /// RTS/CTS flow control: the sender waits for the receiver.
+    Hardware,
+}
+
+/// Buffers cleared by [`SerialStream::clear`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ClearBuffer {
+    /// The received data not read yet.
+    Input,
+
+    /// The written data not received by the other end yet.
+    Output,
+
+    /// Both buffers.
+    All,
+}
+
+/// A chunk of written data being transmitted.
+struct Transmission {
+    /// Number of bytes being transmitted.
+    len: usize,
+
+    /// Timer elapsing once the transmission completes.
+    done: Pin<Box<Sleep>>,
+
+    /// Timer of the write operation.
+    timer: OpTimer,
+}
+
+/// An async serial port over a pipe endpoint, with the API of
+/// `tokio_serial::SerialStream`.
+pub struct SerialStream {
+    pipe: MockPipe,
+    config: SerialConfig,
+    flow_control: FlowControl,
+    line_idle_at: Option<Instant>,
+    transmission: Option<Transmission>,
+    read_timer: Option<OpTimer>,
+}
+
+impl SerialStream {
+    /// Wraps the pipe endpoint, emulating a serial port with the given
+    /// settings and no flow control.
+    pub fn new(pipe: MockPipe, config: SerialConfig) -> Self {
+        Self {
+            pipe,
+            config,
+            flow_control: FlowControl::None,
+            line_idle_at: None,
+            transmission: None,
+            read_timer: None,
+        }
+    }
+
+    /// Returns a reference to the wrapped pipe endpoint.
+    pub fn pipe(&self) -> &MockPipe {
+        &self.pipe
+    }
+
+    /// Consumes the stream, returning the wrapped pipe endpoint.
+    pub fn into_inner(self) -> MockPipe {
+        self.pipe
+    }
+
+    /// Returns the serial line settings.
+    pub fn config(&self) -> &SerialConfig {
+        &self.config
+    }
+
+    /// Returns the baud rate.
+    pub fn baud_rate(&self) -> io::Result<u32> {
+        Ok(self.config.baud_rate)
+    }
+
+    /// Sets the baud rate.
+    pub fn set_baud_rate(&mut self, baud_rate: u32) -> io::Result<()> {
+        if baud_rate == 0 {
+            return Err(io::Error::from(io::ErrorKind::InvalidInput));
+        }
+
+        self.config.baud_rate = baud_rate;
+        Ok(())
+    }
+
+    /// Returns the number of data bits per character.
+    pub fn data_bits(&self) -> io::Result<u8> {
+        Ok(self.config.data_bits)
+    }
+
+    /// Sets the number of data bits per character.
+    pub fn set_data_bits(&mut self, data_bits: u8) -> io::Result<()> {
+        self.config.data_bits = data_bits;
+        Ok(())
+    }
+
+    /// Returns the parity.
+    pub fn parity(&self) -> io::Result<Parity> {
+        Ok(self.config.parity)
+    }
+
+    /// Sets the parity.
+    pub fn set_parity(&mut self, parity: Parity) -> io::Result<()> {
+        self.config.parity = parity;
+        Ok(())
+    }
+
+    /// Returns the number of stop bits.
+    pub fn stop_bits(&self) -> io::Result<u8> {
+        Ok(self.config.stop_bits)
+    }
+
+    /// Sets the number of stop bits.
+    pub fn set_stop_bits(&mut self, stop_bits: u8) -> io::Result<()> {
+        self.config.stop_bits = stop_bits;
+        Ok(())
+    }
+
+    /// Returns the flow control.
+    pub fn flow_control(&self) -> io::Result<FlowControl> {
+        Ok(self.flow_control)
+    }
+
+    /// Sets the flow control.
+    pub fn set_flow_control(&mut self, flow_control: FlowControl) -> io::Result<()> {
+        self.flow_control = flow_control;
+        Ok(())
+    }
+
+    /// Returns the number of bytes received and not read yet.
+    pub fn bytes_to_read(&self) -> io::Result<u32> {
+        Ok(self.pipe.read_buffer_len() as u32)
+    }
+
+    /// Returns the number of bytes written and not received by the other end
+    /// yet.
+    pub fn bytes_to_write(&self) -> io::Result<u32> {
+        Ok(self.pipe.write_buffer_len() as u32)
+    }
+
+    /// Discards the data in the given buffers.
+    pub fn clear(&self, buffer_to_clear: ClearBuffer) -> io::Result<()> {
+        match buffer_to_clear {
+            ClearBuffer::Input => self.pipe.clear_read(),
+            ClearBuffer::Output => self.pipe.clear_write(),
+            ClearBuffer::All => self.pipe.clear(),
+        }
+        Ok(())
+    }
+
+    /// Starts transmitting up to `len` bytes, right after the previous
+    /// transmission if the line is still busy.
+    fn start_transmission(&mut self, len: usize) {
+        let now = Instant::now();
+        let start = self.line_idle_at.filter(|&at| at > now).unwrap_or(now);
+        let end = start + self.config.char_time() * len as u32;
+        self.line_idle_at = Some(end);
+
+        self.transmission = Some(Transmission {
+            len,
+            done: Box::pin(time::sleep_until(end)),
+            timer: OpTimer::start(),
+        });
+    }
+
+    /// Delivers the transmitted data to the other end, waiting for room in the
+    /// pipe with flow control and dropping what does not fit without it.
+    fn poll_deliver(&self, cx: &mut Context<'_>, data: &[u8]) -> Poll<io::Result<usize>> {
+        let buffer = &self.pipe.write_buffer;
+
+        if self.flow_control != FlowControl::None {
+            return buffer.poll_write(cx, data);
+        }
+
+        match buffer.space() {
+            0 if buffer.is_closed() => Poll::Ready(Err(io::Error::from(io::ErrorKind::BrokenPipe))),
+            0 => Poll::Ready(Ok(data.len())),
+            space => buffer
+                .poll_write(cx, &data[0..data.len().min(space)])
+                .map_ok(|_| data.len()),
+        }
+    }
+}
+
+impl AsyncRead for SerialStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let timer = this.read_timer.take().unwrap_or_else(OpTimer::start);
+
+        let unfilled = buf.initialize_unfilled();
+        let result = match this.pipe.read_buffer.poll_read(cx, unfilled) {
+            Poll::Ready(result) => result,
+            Poll::Pending => {
+                this.read_timer = Some(timer);
+                return Poll::Pending;
+            }
+        };
+
+        this.pipe.finish_read(unfilled, &result, timer);
+        let n = result?;
+        buf.advance(n);
+
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncWrite for SerialStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+
+        if this.transmission.is_none() {
+            // With flow control, transmission starts once the other end has
+            // room for the data
+            let len = match this.flow_control {
+                FlowControl::None => buf.len(),
+                _ => buf
+                    .len()
+                    .min(ready!(this.pipe.write_buffer.poll_space(cx))?),
+            };
+            this.start_transmission(len);
+        }
+
+        let len = {
+            let transmission = this.transmission.as_mut().unwrap();
+            ready!(transmission.done.as_mut().poll(cx));
+            transmission.len
+        };
+
+        let data = &buf[0..len.min(buf.len())];
+        let result = ready!(this.poll_deliver(cx, data));
+
+        let transmission = this.transmission.take().unwrap();
+        this.pipe.finish_write(data, &result, transmission.timer);
+
+        Poll::Ready(result)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_tokio_serial_timing() {
+        let (device, host) = MockPipe::pair(64);
+        let mut device = SerialStream::new(device, SerialConfig::new(9600));
+        let mut host = SerialStream::new(host, SerialConfig::new(9600));
+
+        // 10 characters of 10 bits take about 10.4 ms at 9600 baud
+        let start = Instant::now();
+        host.write_all(b"0123456789").await.unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(10));
+
+        let mut buf = [0u8; 10];
+        device.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"0123456789");
+    }
+
+    #[tokio::test]
+    async fn test_tokio_serial_flow_control() {
+        let (device, host) = MockPipe::pair(4);
+        let mut device = SerialStream::new(device, SerialConfig::new(115_200));
+        let mut host = SerialStream::new(host, SerialConfig::new(115_200));
+
+        // Without flow control the overrun data is lost
+        host.write_all(b"hello").await.unwrap();
+        assert_eq!(device.bytes_to_read().unwrap(), 4);
+        device.clear(ClearBuffer::Input).unwrap();
+
+        // With flow control the writer waits for the reader
+        host.set_flow_control(FlowControl::Hardware).unwrap();
+        let writer = tokio::spawn(async move {
+            host.write_all(b"hello").await.unwrap();
+        });
+
+        let mut buf = [0u8; 5];
+        device.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"hello");
+        writer.await.unwrap();
+    }
+}
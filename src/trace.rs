@@ -0,0 +1,412 @@
+//! Timeline export of pipe operations for Chrome tracing and Perfetto.
+//!
+//! A [`TraceRecorder`] records the operations of the endpoints attached to it:
+//! reads and writes with their durations, the time they spent blocked and the
+//! wakeups ending it, and the faults they reported. The recording is exported
+//! as a Chrome tracing JSON timeline, which `chrome://tracing` and
+//! [Perfetto](https://ui.perfetto.dev) display with one track per endpoint and
+//! thread, making the interleaving of race-dependent test failures visible:
+//!
+//! ```
+//! use std::io::{Read, Write};
+//!
+//! use mockpipe::{trace::TraceRecorder, MockPipe};
+//!
+//! let (mut host, mut device) = MockPipe::pair(64);
+//! let trace = TraceRecorder::new();
+//! trace.attach(&host, "host");
+//! trace.attach(&device, "device");
+//!
+//! host.write_all(b"ping").unwrap();
+//! device.read_exact(&mut [0u8; 4]).unwrap();
+//!
+//! let json = trace.to_json();
+//! assert!(json.contains(r#""name":"write""#));
+//! ```
+
+use std::{
+    cell::Cell,
+    collections::HashMap,
+    fmt::{self, Write as _},
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+    sync::{Arc, Mutex},
+    thread::{self, ThreadId},
+    time::Instant,
+};
+
+use crate::MockPipe;
+
+thread_local! {
+    /// Time the current operation of the thread spent blocked, if it did.
+    static WAIT: Cell<Option<(Instant, Instant)>> = const { Cell::new(None) };
+}
+
+/// Records the operations of the endpoints attached to it, to export them as a
+/// timeline.
+///
+/// Clones share the same recording. Operations are recorded as long as the
+/// endpoints stay attached, or until the recording is cleared.
+#[derive(Clone)]
+pub struct TraceRecorder {
+    inner: Arc<Mutex<Recording>>,
+}
+
+/// Operations recorded so far, with the endpoints and threads involved.
+struct Recording {
+    /// Start of the recording, the origin of the timeline.
+    start: Instant,
+
+    /// Names of the endpoints, indexed by their track.
+    pipes: Vec<String>,
+
+    /// Tracks and names of the threads.
+    threads: HashMap<ThreadId, (usize, String)>,
+
+    events: Vec<TraceEvent>,
+}
+
+/// An event of the timeline.
+struct TraceEvent {
+    name: String,
+    category: &'static str,
+
+    /// Start of the event, in microseconds since the start of the recording.
+    at: f64,
+
+    /// Duration of the event in microseconds, or `None` for an instant event.
+    duration: Option<f64>,
+
+    /// Tracks of the endpoint and the thread.
+    pipe: usize,
+    thread: usize,
+
+    /// Number of bytes requested and transferred, for reads and writes.
+    bytes: Option<(usize, usize)>,
+}
+
+/// Recorder an endpoint is attached to, with its track.
+#[derive(Clone)]
+pub(crate) struct Target {
+    recorder: TraceRecorder,
+    pipe: usize,
+}
+
+impl TraceRecorder {
+    /// Creates an empty recording, starting now.
+    pub fn new() -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(Recording {
+                start: Instant::now(),
+                pipes: Vec::new(),
+                threads: HashMap::new(),
+                events: Vec::new(),
+            })),
+        }
+    }
+
+    /// Records the operations of `pipe` and its clones under `name`, replacing
+    /// the recorder it was attached to, if any.
+    pub fn attach(&self, pipe: &MockPipe, name: &str) {
+        let pipe_track = {
+            let mut recording = self.inner.lock().unwrap();
+            recording.pipes.push(name.to_owned());
+            recording.pipes.len() - 1
+        };
+
+        *pipe.trace.lock().unwrap() = Some(Target {
+            recorder: self.clone(),
+            pipe: pipe_track,
+        });
+    }
+
+    /// Stops recording the operations of `pipe`, if attached to this recorder.
+    pub fn detach(&self, pipe: &MockPipe) {
+        let mut trace = pipe.trace.lock().unwrap();
+
+        if matches!(&*trace, Some(target) if Arc::ptr_eq(&target.recorder.inner, &self.inner)) {
+            *trace = None;
+        }
+    }
+
+    /// Returns the number of events recorded.
+    pub fn len(&self) -> usize {
+        self.inner.lock().unwrap().events.len()
+    }
+
+    /// Returns `true` if no events were recorded.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Discards the events recorded so far, keeping the endpoints attached.
+    pub fn clear(&self) {
+        self.inner.lock().unwrap().events.clear();
+    }
+
+    /// Writes the timeline in the Chrome tracing JSON format to `writer`.
+    pub fn write_json<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_all(self.to_json().as_bytes())
+    }
+
+    /// Saves the timeline in the Chrome tracing JSON format to a file.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write_json(&mut writer)?;
+        writer.flush()
+    }
+
+    /// Returns the timeline in the Chrome tracing JSON format.
+    pub fn to_json(&self) -> String {
+        let recording = self.inner.lock().unwrap();
+        let mut entries = Vec::new();
+
+        // Name the tracks of every endpoint and thread
+        let mut threads: Vec<_> = recording.threads.values().collect();
+        threads.sort();
+        for (pipe, name) in recording.pipes.iter().enumerate() {
+            entries.push(format!(
+                r#"{{"name":"process_name","ph":"M","pid":{},"tid":0,"args":{{"name":"{}"}}}}"#,
+                pipe + 1,
+                Escaped(name)
+            ));
+
+            for (thread, name) in &threads {
+                entries.push(format!(
+                    r#"{{"name":"thread_name","ph":"M","pid":{},"tid":{},"args":{{"name":"{}"}}}}"#,
+                    pipe + 1,
+                    thread + 1,
+                    Escaped(name)
+                ));
+            }
+        }
+
+        for event in &recording.events {
+            let mut entry = format!(
+                r#"{{"name":"{}","cat":"{}","ts":{:.3},"pid":{},"tid":{}"#,
+                Escaped(&event.name),
+                event.category,
+                event.at,
+                event.pipe + 1,
+                event.thread + 1
+            );
+
+            match event.duration {
+                Some(duration) => write!(entry, r#","ph":"X","dur":{:.3}"#, duration).unwrap(),
+                None => entry.push_str(r#","ph":"i","s":"t""#),
+            }
+
+            if let Some((requested, transferred)) = event.bytes {
+                write!(
+                    entry,
+                    r#","args":{{"requested":{},"transferred":{}}}"#,
+                    requested, transferred
+                )
+                .unwrap();
+            }
+
+            entry.push('}');
+            entries.push(entry);
+        }
+
+        format!(
+            "{{\"traceEvents\":[\n{}\n],\"displayTimeUnit\":\"ms\"}}\n",
+            entries.join(",\n")
+        )
+    }
+}
+
+impl Default for TraceRecorder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for TraceRecorder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TraceRecorder")
+            .field("events", &self.len())
+            .finish()
+    }
+}
+
+impl Target {
+    /// Records an operation named `name` started at `start`, which transferred
+    /// the number of bytes in `result` out of the `requested` ones, or failed.
+    pub(crate) fn record_op(
+        &self,
+        name: &str,
+        start: Instant,
+        requested: usize,
+        result: &io::Result<usize>,
+    ) {
+        let end = Instant::now();
+        let wait = WAIT.with(|wait| wait.take());
+
+        let mut recording = self.recorder.inner.lock().unwrap();
+        let thread = recording.thread_track();
+        let event = |name: String, category, from: Instant, to: Option<Instant>| TraceEvent {
+            name,
+            category,
+            at: recording.micros(from),
+            duration: to.map(|to| to.saturating_duration_since(from).as_secs_f64() * 1e6),
+            pipe: self.pipe,
+            thread,
+            bytes: None,
+        };
+
+        let mut events = vec![TraceEvent {
+            bytes: Some((requested, *result.as_ref().unwrap_or(&0))),
+            ..event(name.to_owned(), "io", start, Some(end))
+        }];
+
+        if let Some((blocked, woken)) = wait {
+            events.push(event(String::from("blocked"), "wait", blocked, Some(woken)));
+
+            if !matches!(result, Err(err) if err.kind() == io::ErrorKind::TimedOut) {
+                events.push(event(String::from("wakeup"), "wait", woken, None));
+            }
+        }
+
+        if let Err(err) = result {
+            events.push(event(err.to_string(), "fault", end, None));
+        }
+
+        recording.events.extend(events);
+    }
+}
+
+impl Recording {
+    /// Returns the track of the calling thread, assigning it one if needed.
+    fn thread_track(&mut self) -> usize {
+        let tracks = self.threads.len();
+        let current = thread::current();
+
+        self.threads
+            .entry(current.id())
+            .or_insert_with(|| {
+                let name = current
+                    .name()
+                    .map_or_else(|| format!("thread {}", tracks + 1), str::to_owned);
+                (tracks, name)
+            })
+            .0
+    }
+
+    /// Returns the time of `instant` on the timeline, in microseconds.
+    fn micros(&self, instant: Instant) -> f64 {
+        instant.saturating_duration_since(self.start).as_secs_f64() * 1e6
+    }
+}
+
+/// Forgets the waits of the previous operation of the calling thread.
+pub(crate) fn clear_wait() {
+    WAIT.with(|wait| wait.set(None));
+}
+
+/// A wait of the calling thread, noted as the time its current operation
+/// spent blocked when dropped, merged with its previous waits.
+pub(crate) struct WaitSpan {
+    blocked: Instant,
+}
+
+impl WaitSpan {
+    pub(crate) fn start() -> Self {
+        Self {
+            blocked: Instant::now(),
+        }
+    }
+}
+
+impl Drop for WaitSpan {
+    fn drop(&mut self) {
+        let woken = Instant::now();
+
+        WAIT.with(|wait| {
+            let blocked = match wait.get() {
+                Some((previous, _)) => previous.min(self.blocked),
+                None => self.blocked,
+            };
+            wait.set(Some((blocked, woken)));
+        });
+    }
+}
+
+/// A string escaped for JSON.
+struct Escaped<'a>(&'a str);
+
+impl fmt::Display for Escaped<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for c in self.0.chars() {
+            match c {
+                '"' => f.write_str("\\\"")?,
+                '\\' => f.write_str("\\\\")?,
+                c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+                c => f.write_char(c)?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Read, Write},
+        time::Duration,
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_trace_timeline() {
+        let (mut host, mut device) = MockPipe::pair(4);
+        let trace = TraceRecorder::new();
+        trace.attach(&host, "host");
+        trace.attach(&device, "device \"B\"");
+
+        let reader = thread::Builder::new()
+            .name(String::from("reader"))
+            .spawn(move || {
+                device.set_timeout(Some(Duration::from_secs(5)));
+                let mut data = [0u8; 4];
+                device.read_exact(&mut data).unwrap();
+
+                device.set_timeout(Some(Duration::from_millis(10)));
+                assert!(device.read(&mut data).is_err());
+            })
+            .unwrap();
+
+        thread::sleep(Duration::from_millis(20));
+        host.write_all(b"ping").unwrap();
+        reader.join().unwrap();
+
+        let json = trace.to_json();
+        for expected in [
+            r#""name":"process_name","ph":"M","pid":2,"tid":0,"args":{"name":"device \"B\""}"#,
+            r#""args":{"name":"reader"}"#,
+            r#""name":"blocked","cat":"wait""#,
+            r#""name":"wakeup","cat":"wait""#,
+            r#""args":{"requested":4,"transferred":4}"#,
+            r#""name":"operation timed out after transferring 0 bytes","cat":"fault""#,
+        ] {
+            assert!(
+                json.contains(expected),
+                "{} not found in {}",
+                expected,
+                json
+            );
+        }
+
+        // The reads with their wait, the wakeup or the fault, and the write
+        assert_eq!(trace.len(), 7);
+        assert_eq!(json.matches(r#""name":"wakeup""#).count(), 1);
+
+        trace.clear();
+        trace.detach(&host);
+        host.write_all(b"ping").unwrap();
+        assert!(trace.is_empty());
+    }
+}
@@ -0,0 +1,175 @@
+//! A common interface over real and mock byte-stream transports.
+//!
+//! Code written against [`Transport`] instead of a concrete connection type
+//! can be handed a `TcpStream` (or a `UnixStream`) in production and a
+//! `MockPipe` endpoint in tests:
+//!
+//! ```
+//! use std::{
+//!     io::{self, Write},
+//!     time::Duration,
+//! };
+//!
+//! use mockpipe::{MockPipe, Transport};
+//!
+//! fn ping<T: Transport>(transport: &mut T) -> io::Result<()> {
+//!     transport.set_timeout(Some(Duration::from_millis(100)))?;
+//!     transport.write_all(b"PING\r\n")?;
+//!
+//!     let mut reply = [0u8; 6];
+//!     transport.read_exact(&mut reply)?;
+//!     transport.close()
+//! }
+//!
+//! let (mut device, mut host) = MockPipe::pair(64);
+//! device.write_all(b"PONG\r\n").unwrap();
+//!
+//! ping(&mut host).unwrap();
+//! ```
+//!
+//! The timeout follows the `MockPipe` conventions: `None` blocks indefinitely
+//! and `Some(Duration::ZERO)` makes the transport non-blocking. Note that real
+//! sockets report expired timeouts as `WouldBlock` on some platforms, while
+//! `MockPipe` always reports them as `TimedOut`.
+
+use std::{
+    io::{self, Read, Write},
+    net::{Shutdown, TcpStream},
+    time::Duration,
+};
+
+use crate::MockPipe;
+
+/// A bidirectional byte-stream connection with timeouts that can be closed.
+pub trait Transport: Read + Write {
+    /// Sets the timeout of read and write operations.
+    ///
+    /// `None` means the operations block indefinitely, and
+    /// `Some(Duration::ZERO)` means they are non-blocking.
+    fn set_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()>;
+
+    /// Closes the connection in both directions.
+    fn close(&mut self) -> io::Result<()>;
+
+    /// Returns `true` if a read returning `Ok(0)` reached the end of stream,
+    /// which is always the case by default. A `MockPipe` read also returns it
+    /// when woken up by a clear (see [`ClearWakeup`](crate::ClearWakeup)).
+    fn is_end_of_stream(&self) -> bool {
+        true
+    }
+}
+
+impl Transport for MockPipe {
+    fn set_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        MockPipe::set_timeout(self, timeout);
+        Ok(())
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        MockPipe::close(self);
+        Ok(())
+    }
+
+    fn is_end_of_stream(&self) -> bool {
+        self.read_buffer.is_closed()
+    }
+}
+
+impl<T: Transport + ?Sized> Transport for &mut T {
+    fn set_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        (**self).set_timeout(timeout)
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        (**self).close()
+    }
+
+    fn is_end_of_stream(&self) -> bool {
+        (**self).is_end_of_stream()
+    }
+}
+
+impl<T: Transport + ?Sized> Transport for Box<T> {
+    fn set_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        (**self).set_timeout(timeout)
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        (**self).close()
+    }
+
+    fn is_end_of_stream(&self) -> bool {
+        (**self).is_end_of_stream()
+    }
+}
+
+/// Implements `Transport` for socket types with the `TcpStream` timeout and
+/// shutdown API.
+macro_rules! impl_socket_transport {
+    ($socket:ty) => {
+        impl Transport for $socket {
+            fn set_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+                // Sockets reject zero timeouts, using a non-blocking mode instead
+                let nonblocking = timeout == Some(Duration::ZERO);
+                let timeout = timeout.filter(|timeout| !timeout.is_zero());
+
+                self.set_nonblocking(nonblocking)?;
+                self.set_read_timeout(timeout)?;
+                self.set_write_timeout(timeout)
+            }
+
+            fn close(&mut self) -> io::Result<()> {
+                match self.shutdown(Shutdown::Both) {
+                    // Already closed by the peer
+                    Err(err) if err.kind() == io::ErrorKind::NotConnected => Ok(()),
+                    result => result,
+                }
+            }
+        }
+    };
+}
+
+impl_socket_transport!(TcpStream);
+#[cfg(unix)]
+impl_socket_transport!(std::os::unix::net::UnixStream);
+
+#[cfg(test)]
+mod tests {
+    use std::{net::TcpListener, thread};
+
+    use super::*;
+
+    /// Sends a request and returns the 4-byte reply.
+    fn exchange<T: Transport>(mut transport: T) -> io::Result<[u8; 4]> {
+        transport.set_timeout(Some(Duration::from_secs(5)))?;
+        transport.write_all(b"ping")?;
+
+        let mut reply = [0u8; 4];
+        transport.read_exact(&mut reply)?;
+        transport.close()?;
+
+        Ok(reply)
+    }
+
+    #[test]
+    fn test_transport() {
+        let (mut device, mut host) = MockPipe::pair(16);
+        device.write_all(b"pong").unwrap();
+        assert_eq!(&exchange(&mut host).unwrap(), b"pong");
+        assert!(device.is_closed());
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0u8; 4];
+            stream.read_exact(&mut request).unwrap();
+            stream.write_all(b"pong").unwrap();
+            request
+        });
+
+        let stream: Box<dyn Transport> = Box::new(TcpStream::connect(addr).unwrap());
+        assert_eq!(&exchange(stream).unwrap(), b"pong");
+        assert_eq!(&server.join().unwrap(), b"ping");
+    }
+}
@@ -0,0 +1,412 @@
+//! Pipes carrying owned items instead of bytes.
+//!
+//! A [`TypedMockPipe`] exchanges whole items (frames, structs, enums) with the
+//! timeout and backpressure semantics of a [`MockPipe`](crate::MockPipe): the
+//! buffer of each direction holds up to its capacity of items, senders wait
+//! for room and receivers for items according to the timeout, and closing the
+//! pipe lets the receivers drain the queued items before reporting end of
+//! stream. This mocks message-passing transports above the byte layer:
+//!
+//! ```
+//! use std::time::Duration;
+//!
+//! use mockpipe::typed::TypedMockPipe;
+//!
+//! #[derive(Debug, PartialEq)]
+//! enum Command {
+//!     Reset,
+//!     Move { x: i32, y: i32 },
+//! }
+//!
+//! let (controller, robot) = TypedMockPipe::pair(8);
+//! controller.send(Command::Move { x: 1, y: -2 }).unwrap();
+//! controller.send(Command::Reset).unwrap();
+//!
+//! robot.set_timeout(Some(Duration::from_millis(100)));
+//! assert_eq!(robot.recv().unwrap(), Some(Command::Move { x: 1, y: -2 }));
+//! assert_eq!(robot.recv().unwrap(), Some(Command::Reset));
+//! ```
+//!
+//! With the `serde` feature, a `MessagePipe` carries serialized messages over
+//! a typed pipe, encoded by a pluggable `Codec`: `Json` with the `json`
+//! feature, or `Bincode` with the `bincode` feature.
+
+use std::{collections::VecDeque, error, fmt, io, sync::Arc, time::Duration};
+
+use crate::{
+    sync::{Condvar, Mutex, MutexGuard},
+    Error,
+};
+
+#[cfg(feature = "serde")]
+mod message;
+
+#[cfg(feature = "bincode")]
+pub use message::Bincode;
+#[cfg(feature = "json")]
+pub use message::Json;
+#[cfg(feature = "serde")]
+pub use message::{Codec, MessagePipe};
+
+/// State of the buffer of one direction of a typed pipe.
+struct ItemState<T> {
+    items: VecDeque<T>,
+    capacity: usize,
+    closed: bool,
+}
+
+/// Thread-safe queue of items with a capacity, carrying one direction of a
+/// typed pipe.
+struct ItemBuffer<T> {
+    state: Mutex<ItemState<T>>,
+    can_recv: Condvar,
+    can_send: Condvar,
+}
+
+impl<T> ItemBuffer<T> {
+    fn new(capacity: usize) -> Self {
+        Self {
+            state: Mutex::new(ItemState {
+                items: VecDeque::new(),
+                capacity,
+                closed: false,
+            }),
+            can_recv: Condvar::new(),
+            can_send: Condvar::new(),
+        }
+    }
+
+    /// Waits while the condition holds, according to the timeout. A zero
+    /// timeout does not wait.
+    fn wait_while<'a, F>(
+        state_guard: MutexGuard<'a, ItemState<T>>,
+        condvar: &Condvar,
+        timeout: Option<Duration>,
+        mut condition: F,
+    ) -> Result<MutexGuard<'a, ItemState<T>>, Error>
+    where
+        F: FnMut(&mut ItemState<T>) -> bool,
+    {
+        // Without threads nothing could change the state while waiting
+        #[cfg(all(target_arch = "wasm32", not(target_feature = "atomics")))]
+        let timeout = {
+            let _ = timeout;
+            Some(Duration::ZERO)
+        };
+
+        match timeout {
+            Some(Duration::ZERO) => Ok(state_guard),
+            Some(timeout) => {
+                let (state_guard, timeout_result) = condvar
+                    .wait_timeout_while(state_guard, timeout, &mut condition)
+                    .map_err(|_| Error::Poisoned)?;
+
+                if timeout_result.timed_out() {
+                    Err(Error::TimedOut { partial: 0 })
+                } else {
+                    Ok(state_guard)
+                }
+            }
+            None => condvar
+                .wait_while(state_guard, condition)
+                .map_err(|_| Error::Poisoned),
+        }
+    }
+
+    fn send(&self, item: T, timeout: Option<Duration>) -> Result<(), SendError<T>> {
+        let result = Self::wait_while(
+            self.state.lock().unwrap(),
+            &self.can_send,
+            timeout,
+            |state| (state.items.len() >= state.capacity) && !state.closed,
+        );
+        let mut state_guard = match result {
+            Ok(state_guard) => state_guard,
+            Err(error) => return Err(SendError { item, error }),
+        };
+
+        if state_guard.closed {
+            return Err(SendError {
+                item,
+                error: Error::Closed,
+            });
+        }
+        if state_guard.items.len() >= state_guard.capacity {
+            return Err(SendError {
+                item,
+                error: Error::WouldBlock,
+            });
+        }
+
+        state_guard.items.push_back(item);
+        self.can_recv.notify_one();
+        Ok(())
+    }
+
+    fn recv(&self, timeout: Option<Duration>) -> io::Result<Option<T>> {
+        let mut state_guard = Self::wait_while(
+            self.state.lock().unwrap(),
+            &self.can_recv,
+            timeout,
+            |state| state.items.is_empty() && !state.closed,
+        )?;
+
+        match state_guard.items.pop_front() {
+            Some(item) => {
+                self.can_send.notify_one();
+                Ok(Some(item))
+            }
+            None if state_guard.closed => Ok(None),
+            None => Err(io::Error::from(Error::WouldBlock)),
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.state.lock().unwrap().items.len()
+    }
+
+    fn clear(&self) {
+        self.state.lock().unwrap().items.clear();
+        self.can_send.notify_all();
+    }
+
+    fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+        self.can_recv.notify_all();
+        self.can_send.notify_all();
+    }
+
+    fn is_closed(&self) -> bool {
+        self.state.lock().unwrap().closed
+    }
+}
+
+/// An endpoint of a pipe carrying items of type `T`.
+///
+/// Clones of an endpoint share its buffers and timeout, so several threads
+/// can send and receive through it.
+pub struct TypedMockPipe<T> {
+    timeout: Arc<Mutex<Option<Duration>>>,
+    recv_buffer: Arc<ItemBuffer<T>>,
+    send_buffer: Arc<ItemBuffer<T>>,
+}
+
+impl<T: Send> TypedMockPipe<T> {
+    fn from_buffers(recv_buffer: Arc<ItemBuffer<T>>, send_buffer: Arc<ItemBuffer<T>>) -> Self {
+        Self {
+            // Non-blocking by default, as `MockPipe`
+            timeout: Arc::new(Mutex::new(Some(Duration::ZERO))),
+            recv_buffer,
+            send_buffer,
+        }
+    }
+
+    /// Creates an endpoint in loopback mode, receiving the items it sends,
+    /// with a buffer holding up to `capacity` items.
+    pub fn loopback(capacity: usize) -> Self {
+        let buffer = Arc::new(ItemBuffer::new(capacity));
+        Self::from_buffers(buffer.clone(), buffer)
+    }
+
+    /// Creates a pair of connected endpoints, with buffers holding up to
+    /// `capacity` items in each direction.
+    pub fn pair(capacity: usize) -> (Self, Self) {
+        let buffer1 = Arc::new(ItemBuffer::new(capacity));
+        let buffer2 = Arc::new(ItemBuffer::new(capacity));
+
+        (
+            Self::from_buffers(buffer1.clone(), buffer2.clone()),
+            Self::from_buffers(buffer2, buffer1),
+        )
+    }
+
+    /// Gets the current timeout duration for the operations.
+    pub fn timeout(&self) -> Option<Duration> {
+        *self.timeout.lock().unwrap()
+    }
+
+    /// Sets the timeout duration for the operations.
+    ///
+    /// `None` means the operation blocks indefinitely. `Some(Duration::ZERO)`
+    /// means the operation is non-blocking.
+    pub fn set_timeout(&self, timeout: Option<Duration>) {
+        *self.timeout.lock().unwrap() = timeout;
+    }
+
+    /// Sets the timeout duration for the operations and returns the modified
+    /// endpoint.
+    pub fn with_timeout(self, timeout: Option<Duration>) -> Self {
+        self.set_timeout(timeout);
+        self
+    }
+
+    /// Sends an item, waiting for room in the buffer according to the timeout.
+    ///
+    /// If the item can't be sent, it is returned in a [`SendError`], with a
+    /// `TimedOut` error, a `WouldBlock` error in non-blocking mode, or a
+    /// `Closed` error if the pipe is closed, so it can be sent again.
+    pub fn send(&self, item: T) -> Result<(), SendError<T>> {
+        self.send_buffer.send(item, self.timeout())
+    }
+
+    /// Receives an item, waiting for one according to the timeout.
+    ///
+    /// Returns `None` once the pipe is closed and the queued items are
+    /// received. Returns a `TimedOut` error if no item arrives within the
+    /// timeout, or a `WouldBlock` error in non-blocking mode.
+    pub fn recv(&self) -> io::Result<Option<T>> {
+        self.recv_buffer.recv(self.timeout())
+    }
+
+    /// Returns the number of items waiting to be received.
+    pub fn recv_len(&self) -> usize {
+        self.recv_buffer.len()
+    }
+
+    /// Returns the number of items sent and not received yet by the other end.
+    pub fn send_len(&self) -> usize {
+        self.send_buffer.len()
+    }
+
+    /// Clears both directions, discarding all queued items.
+    pub fn clear(&self) {
+        self.recv_buffer.clear();
+        self.send_buffer.clear();
+    }
+
+    /// Closes the pipe in both directions: once the queued items have been
+    /// received, `recv` returns `None` on either end, and `send` fails with a
+    /// `BrokenPipe` error.
+    pub fn close(&self) {
+        self.recv_buffer.close();
+        self.send_buffer.close();
+    }
+
+    /// Returns `true` if the pipe was closed (by either end).
+    pub fn is_closed(&self) -> bool {
+        self.recv_buffer.is_closed() || self.send_buffer.is_closed()
+    }
+}
+
+impl<T> Clone for TypedMockPipe<T> {
+    fn clone(&self) -> Self {
+        Self {
+            timeout: self.timeout.clone(),
+            recv_buffer: self.recv_buffer.clone(),
+            send_buffer: self.send_buffer.clone(),
+        }
+    }
+}
+
+impl<T> fmt::Debug for TypedMockPipe<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TypedMockPipe")
+            .field("timeout", &*self.timeout.lock().unwrap())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Error of [`TypedMockPipe::send`], returning the item that could not be
+/// sent.
+///
+/// It converts to the `io::Error` of its cause, dropping the item.
+pub struct SendError<T> {
+    /// The item that was not sent.
+    pub item: T,
+
+    /// Why the item was not sent.
+    pub error: Error,
+}
+
+impl<T> SendError<T> {
+    /// Returns the item that was not sent.
+    pub fn into_inner(self) -> T {
+        self.item
+    }
+
+    /// Returns the kind of the `io::Error` the error converts to.
+    pub fn kind(&self) -> io::ErrorKind {
+        self.error.kind()
+    }
+}
+
+impl<T> fmt::Debug for SendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SendError")
+            .field("error", &self.error)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T> fmt::Display for SendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "item not sent: {}", self.error)
+    }
+}
+
+impl<T> error::Error for SendError<T> {}
+
+impl<T> From<SendError<T>> for io::Error {
+    fn from(err: SendError<T>) -> Self {
+        io::Error::from(err.error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+
+    #[test]
+    fn test_typed_pair() {
+        let (pipe1, pipe2) = TypedMockPipe::pair(2);
+
+        pipe1.send(vec![1u8, 2]).unwrap();
+        pipe1.send(vec![3]).unwrap();
+        assert_eq!(pipe1.send_len(), 2);
+
+        // The buffer is full
+        let err = pipe1.send(vec![4]).unwrap_err();
+        assert_eq!(err.error, Error::WouldBlock);
+
+        // The item is returned to be sent again
+        pipe1.set_timeout(Some(Duration::from_millis(10)));
+        let err = pipe1.send(err.into_inner()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert_eq!(err.item, [4]);
+
+        assert_eq!(pipe2.recv_len(), 2);
+        assert_eq!(pipe2.recv().unwrap(), Some(vec![1, 2]));
+        assert_eq!(pipe2.recv().unwrap(), Some(vec![3]));
+        let err = pipe2.recv().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+    }
+
+    #[test]
+    fn test_typed_close() {
+        let (pipe1, pipe2) = TypedMockPipe::pair(1);
+        let pipe1 = pipe1.with_timeout(None);
+
+        let sender = thread::spawn(move || {
+            for i in 0..3 {
+                pipe1.send(i).unwrap();
+            }
+            pipe1.close();
+            pipe1
+        });
+
+        pipe2.set_timeout(None);
+        let mut received = Vec::new();
+        while let Some(item) = pipe2.recv().unwrap() {
+            received.push(item);
+        }
+        assert_eq!(received, [0, 1, 2]);
+
+        let pipe1 = sender.join().unwrap();
+        assert!(pipe2.is_closed());
+        let err = pipe1.send(3).unwrap_err();
+        assert_eq!(err.error, Error::Closed);
+        assert_eq!(io::Error::from(err).kind(), io::ErrorKind::BrokenPipe);
+    }
+}